@@ -0,0 +1,75 @@
+use chrono::Utc;
+
+use crate::models::Alert;
+use crate::search::{SearchOptions, TimeRange, WebSearch};
+use crate::AppState;
+
+/// Run every alert whose schedule has come due since its last run (or its creation).
+/// Returns how many ran; one failing alert doesn't stop the others.
+pub async fn run_due(state: &AppState) -> anyhow::Result<usize> {
+    let now = Utc::now();
+    let mut ran = 0;
+    for alert in state.db.list_alerts(None).await? {
+        let schedule = match crate::scheduler::parse_schedule(&alert.schedule) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                tracing::warn!("Alert {} has an invalid schedule '{}': {}", alert.id, alert.schedule, e);
+                continue;
+            }
+        };
+        let since = alert.last_run_at.unwrap_or(alert.created_at);
+        if schedule.after(&since).next().is_none_or(|due| due > now) {
+            continue;
+        }
+        match run_alert(state, &alert).await {
+            Ok(found) => tracing::info!("Alert {} ('{}'): {} new result(s)", alert.id, alert.query, found),
+            Err(e) => tracing::warn!("Alert {} ('{}') failed: {}", alert.id, alert.query, e),
+        }
+        ran += 1;
+    }
+    Ok(ran)
+}
+
+/// Search for the alert's query and post results not reported before to its thread,
+/// flagging the thread unread. Returns the number of new results.
+///
+/// The attempt counts as a run even if the search fails, so a failing alert waits for
+/// its next slot instead of retrying on every check. An alert whose thread is gone
+/// (deleted, or pruned by `MAX_THREADS`) is deleted.
+pub async fn run_alert(state: &AppState, alert: &Alert) -> anyhow::Result<usize> {
+    if state.db.get_thread(&alert.thread_id).await?.is_none() {
+        state.db.delete_alert(alert.id, None).await?;
+        anyhow::bail!("thread {} no longer exists, alert deleted", alert.thread_id);
+    }
+    state.db.set_alert_last_run(alert.id, Utc::now()).await?;
+
+    let options = SearchOptions {
+        time_range: alert.time_range.as_deref().and_then(TimeRange::parse),
+        ..SearchOptions::default()
+    };
+    let results = WebSearch::search(&state.http, &state.db, &alert.query, alert.search_provider.as_deref(), options).await?;
+
+    let urls: Vec<String> = results.iter().map(|r| r.url.clone()).collect();
+    let fresh = state.db.record_alert_urls(alert.id, &urls).await?;
+    if fresh.is_empty() {
+        return Ok(0);
+    }
+
+    let items = results.iter()
+        .filter(|r| fresh.contains(&r.url))
+        .enumerate()
+        .map(|(i, r)| {
+            let snippet = r.snippet.trim();
+            if snippet.is_empty() {
+                format!("{}. [{}]({})", i + 1, r.title, r.url)
+            } else {
+                format!("{}. [{}]({}) - {}", i + 1, r.title, r.url, snippet)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let message = format!("**New results for \"{}\"** ({})\n\n{}", alert.query, fresh.len(), items);
+    state.db.add_message(&alert.thread_id, "assistant", &message).await?;
+    state.db.set_thread_unread(&alert.thread_id, true).await?;
+    Ok(fresh.len())
+}
@@ -1,5 +1,5 @@
 use axum::{
-    extract::State, 
+    extract::{Query, State}, 
     http::StatusCode, 
     response::{IntoResponse, sse::{Event, Sse}}, 
     Json
@@ -9,19 +9,39 @@ use futures::StreamExt;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use std::convert::Infallible;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-use crate::models::{QueryRequest, QueryResponse};
+use crate::auth::{self, CurrentUser};
+use crate::error::{ApiError, QueryCancelled};
+use crate::llm::ProviderType;
+use crate::models::{Alert, AlertRequest, DomainRule, DomainRuleRequest, QueryEnvelope, QueryParams, QueryRequest, QueryResponse, WebSearchMode};
 use crate::rag::{RAGSystem, StreamEvent};
 use crate::AppState;
 use crate::search::WebSearch;
 
+/// Running streamed queries, keyed by the request ID sent to the client as a
+/// `REQUEST_ID:<id>` status event.
+pub type InFlightQueries = Arc<Mutex<HashMap<String, InFlightQuery>>>;
+
+pub struct InFlightQuery {
+    pub cancel: CancellationToken,
+    /// Empty for dry runs, which have no thread
+    pub thread_id: String,
+    /// Only the user who started a query may cancel it
+    pub user_id: Option<i64>,
+}
+
 pub async fn handle_query_stream(
     State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
     Json(request): Json<QueryRequest>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     tracing::info!(
-        "Received streaming query: '{}' (web_search: {}, model: {:?}, thread: {:?})",
+        "Received streaming query: '{}' (web_search: {:?}, model: {:?}, thread: {:?})",
         request.query,
         request.web_search_enabled,
         request.model,
@@ -30,13 +50,30 @@ pub async fn handle_query_stream(
 
     let (tx, rx) = mpsc::channel(100);
     
-    // Spawn background task to run the query
+    // Spawn background task to run the query, still inside the request's log span
+    let started = std::time::Instant::now();
     tokio::spawn(async move {
+        let generation = request.generation_params();
+        if let Err(e) = generation.validate().and_then(|_| validate_enabled_tools(&state, &request)) {
+            let _ = tx.send(Ok(StreamEvent::Error(e))).await;
+            return;
+        }
+
+        // Dry runs are introspection only: no new thread and nothing persisted
+        let persist = !request.dry_run;
+        
         // 1. Thread Management
         let thread_id = match request.thread_id {
-            Some(id) => id,
+            Some(id) => {
+                if !state.db.thread_visible(&id, user_id).await.unwrap_or(false) {
+                    let _ = tx.send(Ok(StreamEvent::Error(format!("Thread {} not found", id)))).await;
+                    return;
+                }
+                id
+            }
+            None if !persist => String::new(),
             None => {
-                match state.db.create_thread(&request.query).await {
+                match state.db.create_thread(&request.query, user_id).await {
                     Ok(id) => {
                         let _ = tx.send(Ok(StreamEvent::Status(format!("Created new thread: {}", id)))).await;
                         // Send thread ID to client so it can update URL
@@ -51,6 +88,9 @@ pub async fn handle_query_stream(
                 }
             }
         };
+        if !thread_id.is_empty() {
+            tracing::Span::current().record("thread_id", thread_id.as_str());
+        }
 
         // 2. Fetch History
         let history = match state.db.get_thread_messages(&thread_id).await {
@@ -61,64 +101,127 @@ pub async fn handle_query_stream(
             }
         };
 
-        // 3. Save User Message
-        if let Err(e) = state.db.add_message(&thread_id, "user", &request.query).await {
-             tracing::error!("Failed to save user message: {}", e);
-        }
-
-        // 4. Model Selection
-        let requested_model = request.model.clone().unwrap_or_else(|| "auto".to_string());
-        
-        let model = if requested_model == "auto" {
-            // Smart auto-selection
-            let models = state.llm_manager.get_models().await;
-            
-            // Priority list of "smart" models
-            let priority_patterns = [
-                "deepseek-r1",
-                "llama-3.3-70b",
-                "qwen-2.5-72b", 
-                "mixtral-8x22b",
-                "claude-3-opus",
-                "gpt-4"
-            ];
-            
-            let mut selected = None;
-            for pattern in priority_patterns {
-                if let Some(m) = models.iter().find(|m| m.id.to_lowercase().contains(pattern)) {
-                    selected = Some(m.id.clone());
-                    break;
+        // A repeat of the thread's last question within DUPLICATE_WINDOW_SECS is a double
+        // submit: answer it from the original instead of saving and running it again
+        if persist {
+            if let Some(last) = recent_duplicate(&state, &thread_id, &request.query).await {
+                let previous = history.iter().skip_while(|m| m.id != last.id).find(|m| m.role == "assistant");
+                let running = state.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+                    .values()
+                    .any(|q| q.thread_id == thread_id);
+                match previous {
+                    // Re-asking a question that was just cancelled runs it again
+                    Some(previous) if previous.status != "cancelled" => {
+                        let _ = tx.send(Ok(StreamEvent::Status("Duplicate question, returning the previous answer".to_string()))).await;
+                        let _ = tx.send(Ok(StreamEvent::Answer(previous.content.clone()))).await;
+                        let _ = tx.send(Ok(StreamEvent::Done)).await;
+                        return;
+                    }
+                    // No answer yet: only a double submit if the original is still running,
+                    // since a failed query saves no answer and retrying it is legitimate
+                    None if running => {
+                        let _ = tx.send(Ok(StreamEvent::Warning("This question is already being answered".to_string()))).await;
+                        let _ = tx.send(Ok(StreamEvent::Done)).await;
+                        return;
+                    }
+                    _ => {}
                 }
             }
-            
-            // Fallback to default if no smart model found
-            selected.unwrap_or(state.default_model.clone())
-        } else if state.llm_manager.get_model(&requested_model).await.is_some() {
-            requested_model
-        } else {
-             tracing::warn!(
-                "Requested model '{}' not found; using default '{}'",
-                requested_model,
-                state.default_model
-            );
-            state.default_model.clone()
+        }
+
+        // From here on the query can be cancelled by ID, or by the client going away
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let cancel = CancellationToken::new();
+        state.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+            .insert(request_id.clone(), InFlightQuery { cancel: cancel.clone(), thread_id: thread_id.clone(), user_id });
+        let _ = tx.send(Ok(StreamEvent::Status(format!("REQUEST_ID:{}", request_id)))).await;
+        let disconnect_watch = {
+            let (tx, cancel) = (tx.clone(), cancel.clone());
+            tokio::spawn(async move {
+                tx.closed().await;
+                cancel.cancel();
+            })
         };
 
-        let search_provider = request.search_provider
-            .filter(|s| s != "auto");
+        // 3. Save User Message
+        if persist {
+            if let Err(e) = state.db.add_message(&thread_id, "user", &request.query).await {
+                tracing::error!("Failed to save user message: {}", e);
+            }
+        }
 
+        // 4. Model and search provider selection (independent of each other)
+        let requested_model = request.model.clone().unwrap_or_else(|| "auto".to_string());
+        let (model, warning) = select_model(&state, &requested_model, request.research_mode).await;
+        if let Some(warning) = warning {
+            let _ = tx.send(Ok(StreamEvent::Warning(warning))).await;
+        }
+        let (search_provider, warning) = select_search_provider(request.search_provider.as_deref());
+        if let Some(warning) = warning {
+            let _ = tx.send(Ok(StreamEvent::Warning(warning))).await;
+        }
+        
+        let provider = state.llm_manager.get_model(&model).await.map(|m| m.provider.as_str());
+        crate::logging::record_model(&model, provider);
         tracing::info!("Using model '{}' and search provider '{:?}'", model, search_provider);
-        let _ = tx.send(Ok(StreamEvent::Status(format!("Using model: {}", model)))).await;
+        let how = if state.llm_manager.resolve_model_alias(&requested_model) == "auto" { "auto" } else { "requested" };
+        let _ = tx.send(Ok(StreamEvent::Status(format!("Answer model: {} ({})", model, how)))).await;
+        if request.web_search_enabled != WebSearchMode::Off {
+            let engines = WebSearch::engines(search_provider.as_deref()).await;
+            let names: Vec<&str> = engines.iter().map(|e| e.name()).collect();
+            let status = if names.is_empty() {
+                "No search provider available".to_string()
+            } else {
+                let how = if search_provider.is_some() { "requested" } else { "auto" };
+                format!("Search provider: {} ({})", names.join(" + "), how)
+            };
+            let _ = tx.send(Ok(StreamEvent::Status(status))).await;
+        }
 
-        let rag = RAGSystem::new(state.db.clone(), state.llm_manager.clone(), model, search_provider);
+        let rag = RAGSystem::new(state.db.clone(), state.llm_manager.clone(), state.http.clone(), state.tools.clone(), model, search_provider, generation)
+            .with_answer_format(request.answer_format)
+            .with_research_mode(request.research_mode)
+            .with_region(request.region)
+            .with_allowed_domains_only(request.allowed_domains_only)
+            .with_time_range(request.time_range)
+            .with_focus(request.focus)
+            .with_limits(request.max_results, request.max_fetch)
+            .with_dry_run(request.dry_run)
+            .with_user(user_id)
+            .with_thread(Some(thread_id.clone()))
+            .with_enabled_tools(request.enabled_tools.clone())
+            .with_cancellation(cancel);
         
         // 5. Execute RAG with history
         match rag.query(&request.query, request.web_search_enabled, history, Some(tx.clone())).await {
             Ok((answer, _)) => {
+                if simulate_streaming_enabled() {
+                    simulate_streaming(&tx, &answer).await;
+                }
                 let _ = tx.send(Ok(StreamEvent::Answer(answer.clone()))).await;
                 // 6. Save Assistant Message
-                if let Err(e) = state.db.add_message(&thread_id, "assistant", &answer).await {
-                    tracing::error!("Failed to save assistant message: {}", e);
+                let message_id = if persist {
+                    state.db.add_message(&thread_id, "assistant", &answer).await
+                        .inspect_err(|e| tracing::error!("Failed to save assistant message: {}", e))
+                        .ok()
+                } else {
+                    None
+                };
+                // 7. Suggest follow-ups once the answer is out, keeping them with the message
+                let suggestions = rag.suggest_follow_ups(&request.query, &answer, &Some(tx.clone())).await;
+                if let Some(id) = message_id.filter(|_| !suggestions.is_empty()) {
+                    if let Err(e) = state.db.set_message_suggestions(id, &suggestions).await {
+                        tracing::error!("Failed to save follow-up suggestions: {}", e);
+                    }
+                }
+            }
+            Err(e) if e.is::<QueryCancelled>() => {
+                tracing::info!("Query {} cancelled", request_id);
+                let _ = tx.send(Ok(StreamEvent::Cancelled)).await;
+                if persist {
+                    if let Err(e) = state.db.add_message_with_status(&thread_id, "assistant", "Query cancelled.", "cancelled").await {
+                        tracing::error!("Failed to record cancelled query: {}", e);
+                    }
                 }
             }
             Err(e) => {
@@ -127,8 +230,11 @@ pub async fn handle_query_stream(
             }
         }
         
+        disconnect_watch.abort();
+        state.in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(&request_id);
         let _ = tx.send(Ok(StreamEvent::Done)).await;
-    });
+        tracing::info!(latency_ms = started.elapsed().as_millis() as u64, "Streaming query finished");
+    }.instrument(tracing::Span::current()));
 
     // Create stream from channel
     let stream = ReceiverStream::new(rx).map(|result| {
@@ -145,76 +251,747 @@ pub async fn handle_query_stream(
     Sse::new(stream).keep_alive(axum::response::sse::KeepAlive::new().interval(Duration::from_secs(10)))
 }
 
+/// Create an account. Open when `ALLOW_REGISTRATION=true`; the first account can
+/// always be created so a fresh deployment can be bootstrapped.
+pub async fn register(
+    State(state): State<AppState>,
+    Json(credentials): Json<crate::models::Credentials>,
+) -> Result<axum::response::Response, ApiError> {
+    if !auth::auth_enabled() {
+        return Err(ApiError::BadRequest("Accounts are disabled (set AUTH_ENABLED=true)".to_string()));
+    }
+    let username = credentials.username.trim();
+    if username.is_empty() || username.len() > 64 {
+        return Err(ApiError::BadRequest("Username must be 1-64 characters".to_string()));
+    }
+    if credentials.password.len() < 8 {
+        return Err(ApiError::BadRequest("Password must be at least 8 characters".to_string()));
+    }
+    if !auth::registration_open() && state.db.user_count().await? > 0 {
+        return Err(ApiError::Unauthorized("Registration is closed".to_string()));
+    }
+    if state.db.get_user_credentials(username).await?.is_some() {
+        return Err(ApiError::BadRequest(format!("Username '{}' is taken", username)));
+    }
+
+    let hash = auth::hash_password(&credentials.password)?;
+    let user_id = state.db.create_user(username, &hash).await?;
+    tracing::info!("Registered user {} ({})", username, user_id);
+    start_session(&state, user_id).await
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    Json(credentials): Json<crate::models::Credentials>,
+) -> Result<axum::response::Response, ApiError> {
+    if !auth::auth_enabled() {
+        return Err(ApiError::BadRequest("Accounts are disabled (set AUTH_ENABLED=true)".to_string()));
+    }
+    let user = state.db.get_user_credentials(credentials.username.trim()).await?
+        .filter(|(_, hash)| auth::verify_password(&credentials.password, hash))
+        .map(|(user, _)| user)
+        .ok_or_else(|| ApiError::Unauthorized("Invalid username or password".to_string()))?;
+    start_session(&state, user.id).await
+}
+
+/// Issue a session cookie (the token is also returned for API clients using a Bearer header).
+async fn start_session(state: &AppState, user_id: i64) -> Result<axum::response::Response, ApiError> {
+    let token = auth::new_session_token();
+    let ttl = auth::session_ttl();
+    state.db.create_session(&auth::hash_token(&token), user_id, chrono::Utc::now() + ttl).await?;
+    Ok((
+        [(axum::http::header::SET_COOKIE, auth::session_cookie(&token, ttl))],
+        Json(serde_json::json!({ "user_id": user_id, "token": token })),
+    ).into_response())
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, ApiError> {
+    if let Some(token) = auth::session_token(&headers) {
+        state.db.delete_session(&auth::hash_token(&token)).await?;
+    }
+    Ok((
+        [(axum::http::header::SET_COOKIE, auth::session_cookie("", chrono::Duration::zero()))],
+        StatusCode::NO_CONTENT,
+    ).into_response())
+}
+
+pub async fn current_user(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<crate::models::User>, ApiError> {
+    auth::session_user(&state.db, &headers).await
+        .map(Json)
+        .ok_or_else(|| ApiError::Unauthorized("Not logged in".to_string()))
+}
+
+/// Cancel a running streamed query. Its pipeline stops at the next search, fetch
+/// or LLM call and the thread records the query as cancelled.
+pub async fn cancel_query(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    axum::extract::Path(request_id): axum::extract::Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    // Someone else's query is reported as missing rather than revealing that it exists
+    let token = state.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+        .get(&request_id)
+        .filter(|q| q.user_id == user_id)
+        .map(|q| q.cancel.clone())
+        .ok_or_else(|| ApiError::NotFound(format!("No running query with ID {}", request_id)))?;
+    token.cancel();
+    tracing::info!("Cancellation requested for query {}", request_id);
+    Ok(Json(serde_json::json!({ "request_id": request_id, "cancelled": true })))
+}
+
+/// Default patterns for automatic answer-model selection, best first
+const MODEL_PRIORITY: [&str; 6] = [
+    "deepseek-r1",
+    "llama-3.3-70b",
+    "qwen-2.5-72b",
+    "mixtral-8x22b",
+    "claude-3-opus",
+    "gpt-4",
+];
+
+/// `enabled_tools` may only name registered tools.
+fn validate_enabled_tools(state: &AppState, request: &QueryRequest) -> Result<(), String> {
+    let Some(names) = &request.enabled_tools else {
+        return Ok(());
+    };
+    let unknown = state.tools.unknown(names);
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Unknown tools in enabled_tools: {}", unknown.join(", ")))
+    }
+}
+
+/// Pick the answering model. "auto" walks `MODEL_PRIORITY` (comma-separated model ID
+/// substrings, or the built-in list), skipping providers that are failing or out of daily
+/// requests; an unknown model falls back to the default with a warning for the client.
+/// Search provider selection is handled separately.
+async fn select_model(state: &AppState, requested: &str, research_mode: bool) -> (String, Option<String>) {
+    let requested = state.llm_manager.resolve_model_alias(requested);
+    
+    if requested != "auto" {
+        if state.llm_manager.get_model(&requested).await.is_some() {
+            return (requested, None);
+        }
+        tracing::warn!(
+            "Requested model '{}' not found; using default '{}'",
+            requested,
+            state.default_model
+        );
+        let warning = format!("Model '{}' is unavailable, using '{}'", requested, state.default_model);
+        return (state.default_model.clone(), Some(warning));
+    }
+    
+    // Smart auto-selection, leaving out providers that are currently failing or have
+    // no requests left today, so the next match down the priority list is used instead
+    let mut exhausted = Vec::new();
+    for provider in ProviderType::ALL {
+        if !state.llm_manager.has_daily_budget(&provider).await {
+            tracing::info!("Auto-selection skipping {}: daily request limit reached", provider);
+            exhausted.push(provider);
+        }
+    }
+    let models: Vec<_> = state.llm_manager.get_models().await
+        .into_iter()
+        .filter(|m| state.llm_manager.provider_available(&m.provider) && !exhausted.contains(&m.provider))
+        .collect();
+    let configured: Vec<String> = std::env::var("MODEL_PRIORITY")
+        .unwrap_or_default()
+        .split(',')
+        .map(|p| p.trim().to_lowercase())
+        .filter(|p| !p.is_empty())
+        .collect();
+    let priority_patterns: Vec<String> = if configured.is_empty() {
+        MODEL_PRIORITY.iter().map(|p| p.to_string()).collect()
+    } else {
+        configured
+    };
+    
+    let mut selected = None;
+    if research_mode {
+        // Deep research reads many sources, so prefer the largest context window among smart models
+        selected = models.iter()
+            .filter(|m| priority_patterns.iter().any(|p| m.id.to_lowercase().contains(p.as_str())))
+            .max_by_key(|m| m.context_length.unwrap_or(0))
+            .map(|m| m.id.clone());
+    }
+    if selected.is_none() {
+        selected = priority_patterns.iter()
+            .find_map(|p| models.iter().find(|m| m.id.to_lowercase().contains(p.as_str())))
+            .map(|m| m.id.clone());
+    }
+    
+    // Fallback to default if no smart model found
+    (selected.unwrap_or_else(|| state.default_model.clone()), None)
+}
+
+/// Validate the requested search provider; unknown names fall back to automatic selection.
+fn select_search_provider(requested: Option<&str>) -> (Option<String>, Option<String>) {
+    let provider = WebSearch::normalize_provider_name(requested);
+    let warning = match requested.map(str::trim) {
+        Some(name) if provider.is_none() && !name.is_empty() && !name.eq_ignore_ascii_case("auto") => {
+            Some(format!("Unknown search provider '{}', using automatic selection", name))
+        }
+        _ => None,
+    };
+    (provider, warning)
+}
+
+/// The thread's last user message if it equals `query` and was sent within
+/// `DUPLICATE_WINDOW_SECS` (default 10; 0 disables the check).
+async fn recent_duplicate(state: &AppState, thread_id: &str, query: &str) -> Option<crate::models::Message> {
+    let window = std::env::var("DUPLICATE_WINDOW_SECS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(10);
+    if window <= 0 || thread_id.is_empty() {
+        return None;
+    }
+    
+    let last = match state.db.last_user_message(thread_id).await {
+        Ok(last) => last?,
+        Err(e) => {
+            tracing::warn!("Failed to check for duplicate message: {}", e);
+            return None;
+        }
+    };
+    let age = chrono::Utc::now() - last.created_at;
+    if last.content == query && age <= chrono::Duration::seconds(window) {
+        tracing::info!("Ignoring duplicate submission in thread {} ({}s after the original)", thread_id, age.num_seconds());
+        Some(last)
+    } else {
+        None
+    }
+}
+
+fn simulate_streaming_enabled() -> bool {
+    std::env::var("SIMULATE_STREAMING")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// Replay a buffered answer as a series of `Token` events so the UI types it out
+/// even when the provider returned the whole completion at once.
+async fn simulate_streaming(tx: &mpsc::Sender<Result<StreamEvent, anyhow::Error>>, answer: &str) {
+    const WORDS_PER_TOKEN: usize = 4;
+    let delay = Duration::from_millis(
+        std::env::var("SIMULATE_STREAMING_DELAY_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(20),
+    );
+    
+    let words: Vec<&str> = answer.split_inclusive(char::is_whitespace).collect();
+    for chunk in words.chunks(WORDS_PER_TOKEN) {
+        if tx.send(Ok(StreamEvent::Token(chunk.concat()))).await.is_err() {
+            // Client went away
+            return;
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
 pub async fn handle_query(
     State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    Query(params): Query<QueryParams>,
     Json(request): Json<QueryRequest>,
-) -> Result<Json<QueryResponse>, impl IntoResponse> {
+) -> Result<axum::response::Response, ApiError> {
     // Non-streaming endpoint (legacy support, simplified)
     tracing::info!("Received query: '{}'", request.query);
     
-    let requested_model = request.model.clone().unwrap_or_else(|| state.default_model.clone());
-    let model = if state.llm_manager.get_model(&requested_model).await.is_some() {
-        requested_model
-    } else {
-        state.default_model.clone()
-    };
+    if request.query.trim().is_empty() {
+        return Err(ApiError::BadRequest("Query must not be empty".to_string()));
+    }
+    let generation = request.generation_params();
+    generation.validate().map_err(ApiError::BadRequest)?;
+    validate_enabled_tools(&state, &request).map_err(ApiError::BadRequest)?;
     
-    let search_provider = request.search_provider.filter(|s| s != "auto");
-    let rag = RAGSystem::new(state.db.clone(), state.llm_manager.clone(), model, search_provider);
+    let requested_model = request.model.clone().unwrap_or_else(|| state.default_model.clone());
+    let (model, _) = select_model(&state, &requested_model, request.research_mode).await;
+    let provider = state.llm_manager.get_model(&model).await.map(|m| m.provider.as_str());
+    crate::logging::record_model(&model, provider);
+    let (search_provider, _) = select_search_provider(request.search_provider.as_deref());
+    let rag = RAGSystem::new(state.db.clone(), state.llm_manager.clone(), state.http.clone(), state.tools.clone(), model, search_provider, generation)
+        .with_answer_format(request.answer_format)
+        .with_research_mode(request.research_mode)
+        .with_region(request.region)
+        .with_allowed_domains_only(request.allowed_domains_only)
+        .with_time_range(request.time_range)
+        .with_focus(request.focus)
+        .with_limits(request.max_results, request.max_fetch)
+        .with_dry_run(request.dry_run)
+        .with_enabled_tools(request.enabled_tools.clone())
+        .with_user(user_id);
     
     // For simple query, we don't support history yet
-    match rag.query(&request.query, request.web_search_enabled, Vec::new(), None).await {
-        Ok((answer, sources)) => Ok(Json(QueryResponse { answer, sources })),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e))),
+    let started = std::time::Instant::now();
+    let (answer, sources) = rag.query(&request.query, request.web_search_enabled, Vec::new(), None).await?;
+    rag.suggest_follow_ups(&request.query, &answer, &None).await;
+    
+    let stats = rag.stats();
+    if !params.envelope {
+        return Ok(Json(QueryResponse {
+            answer,
+            sources,
+            cited_sources: stats.cited_sources,
+            citations: stats.citations,
+            suggestions: stats.suggestions,
+        }).into_response());
     }
+    
+    let provider = state.llm_manager.get_model(&stats.model).await.map(|m| m.provider.to_string());
+    Ok(Json(QueryEnvelope {
+        answer,
+        sources,
+        cited_sources: stats.cited_sources,
+        citations: stats.citations,
+        suggestions: stats.suggestions,
+        model: stats.model,
+        provider,
+        elapsed_ms: u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        token_usage: stats.usage,
+    }).into_response())
 }
 
 pub async fn get_threads(
     State(state): State<AppState>,
-) -> Result<Json<Vec<crate::models::Thread>>, impl IntoResponse> {
-    match state.db.list_threads(50).await {
-        Ok(threads) => Ok(Json(threads)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e))),
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+) -> Result<Json<Vec<crate::models::Thread>>, ApiError> {
+    Ok(Json(state.db.list_threads(50, user_id).await?))
+}
+
+/// Recreate threads from an exported conversation file (W9, ChatGPT or Open WebUI JSON).
+pub async fn import_threads(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    Json(body): Json<serde_json::Value>,
+) -> Result<Json<crate::models::ThreadImportResponse>, ApiError> {
+    let imported = crate::import::parse(&body).map_err(ApiError::BadRequest)?;
+
+    let mut threads = Vec::new();
+    let mut messages = 0;
+    for thread in &imported {
+        let id = state.db.import_thread(thread, user_id).await?;
+        // Missing when MAX_THREADS pruned it as the oldest thread
+        if let Some(stored) = state.db.get_thread(&id).await? {
+            messages += thread.messages.len();
+            threads.push(stored);
+        }
     }
+    tracing::info!("Imported {} thread(s) with {} message(s)", threads.len(), messages);
+    Ok(Json(crate::models::ThreadImportResponse { threads, messages }))
 }
 
 pub async fn get_thread_messages(
     State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
     axum::extract::Path(thread_id): axum::extract::Path<String>,
-) -> Result<Json<Vec<crate::models::Message>>, impl IntoResponse> {
-    match state.db.get_thread_messages(&thread_id).await {
-        Ok(messages) => Ok(Json(messages)),
-        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e))),
+    axum::extract::Query(page): axum::extract::Query<crate::models::MessagePageParams>,
+) -> Result<Json<Vec<crate::models::Message>>, ApiError> {
+    // Other users' threads look the same as missing ones
+    if !state.db.thread_visible(&thread_id, user_id).await? {
+        return Err(ApiError::NotFound(format!("Thread {} not found", thread_id)));
     }
+
+    // Without a limit, keep returning the full thread for backward compatibility
+    let messages = match page.limit {
+        Some(limit) => {
+            let limit = limit.clamp(1, 500);
+            let offset = page.offset.unwrap_or(0).max(0);
+            state.db.get_thread_messages_page(&thread_id, offset, limit).await?
+        }
+        None => state.db.get_thread_messages(&thread_id).await?,
+    };
+    if page.offset.unwrap_or(0) == 0 {
+        state.db.set_thread_unread(&thread_id, false).await?;
+    }
+
+    Ok(Json(messages))
 }
 
 pub async fn get_sources(
     State(state): State<AppState>,
-) -> Result<Json<Vec<crate::models::Source>>, impl IntoResponse> {
-    match state.db.get_sources(20).await {
-        Ok(sources) => Ok(Json(sources)),
-        Err(e) => {
-            tracing::error!("Get sources error: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Error: {}", e),
-            ))
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+) -> Result<Json<Vec<crate::models::Source>>, ApiError> {
+    Ok(Json(state.db.get_sources(20, user_id).await?))
+}
+
+/// Flat URL listing for external link tooling; paginated, optionally filtered by `?domain=`.
+pub async fn get_source_urls(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    axum::extract::Query(params): axum::extract::Query<crate::models::SourceUrlParams>,
+) -> Result<Json<Vec<crate::models::SourceUrl>>, ApiError> {
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let domain = params.domain.as_deref().filter(|d| !d.trim().is_empty());
+
+    Ok(Json(state.db.list_source_urls(domain, offset, limit, user_id).await?))
+}
+
+/// Domain rules stored in the database; `ALLOWED_DOMAINS`/`BLOCKED_DOMAINS` apply on top.
+pub async fn list_domain_rules(State(state): State<AppState>) -> Result<Json<Vec<DomainRule>>, ApiError> {
+    Ok(Json(state.db.list_domain_rules().await?))
+}
+
+/// Allow or block a domain (and its subdomains). Replaces an existing rule for the domain.
+pub async fn add_domain_rule(
+    State(state): State<AppState>,
+    Json(request): Json<DomainRuleRequest>,
+) -> Result<Json<DomainRule>, ApiError> {
+    let domain = crate::domains::normalize_domain(&request.domain)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid domain: {}", request.domain)))?;
+    let rule = request.rule.trim().to_lowercase();
+    if rule != "allow" && rule != "block" {
+        return Err(ApiError::BadRequest(format!("Invalid rule '{}', expected allow or block", request.rule)));
+    }
+    Ok(Json(state.db.upsert_domain_rule(&domain, &rule).await?))
+}
+
+pub async fn delete_domain_rule(
+    State(state): State<AppState>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    if state.db.delete_domain_rule(id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound(format!("Domain rule {} not found", id)))
+    }
+}
+
+/// The requester's saved-search alerts.
+pub async fn list_alerts(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+) -> Result<Json<Vec<Alert>>, ApiError> {
+    Ok(Json(state.db.list_alerts(user_id).await?))
+}
+
+/// Save a query to be re-searched on `schedule`. New results are posted to the given
+/// thread, or to a new "Alert: ..." thread when none is given.
+pub async fn create_alert(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    Json(request): Json<AlertRequest>,
+) -> Result<Json<Alert>, ApiError> {
+    if request.query.trim().is_empty() {
+        return Err(ApiError::BadRequest("Alert query must not be empty".to_string()));
+    }
+    if let Err(e) = crate::scheduler::parse_schedule(request.schedule.trim()) {
+        return Err(ApiError::BadRequest(format!("Invalid schedule '{}': {}", request.schedule, e)));
+    }
+
+    let thread_id = match &request.thread_id {
+        Some(id) => {
+            if !state.db.thread_visible(id, user_id).await? {
+                return Err(ApiError::NotFound(format!("Thread {} not found", id)));
+            }
+            id.clone()
+        }
+        None => {
+            let title: String = format!("Alert: {}", request.query.trim()).chars().take(80).collect();
+            let id = state.db.create_thread(&title, user_id).await?;
+            state.db.add_message(&id, "user", request.query.trim()).await?;
+            id
         }
+    };
+    let alert = state.db.create_alert(&request, &thread_id, user_id).await?;
+    tracing::info!("Created alert {} for '{}' ({})", alert.id, alert.query, alert.schedule);
+    Ok(Json(alert))
+}
+
+pub async fn delete_alert(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    if state.db.delete_alert(id, user_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound(format!("Alert {} not found", id)))
     }
 }
 
-pub async fn sync_limits(
+/// Run an alert now, outside its schedule. Returns the number of new results posted.
+pub async fn run_alert(
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    // Sync Tavily
-    if let Err(e) = WebSearch::sync_tavily_usage(&state.db).await {
-        tracing::error!("Sync Tavily limits error: {}", e);
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let alert = state.db.get_alert(id, user_id).await?
+        .ok_or_else(|| ApiError::NotFound(format!("Alert {} not found", id)))?;
+    let new_results = crate::alerts::run_alert(&state, &alert).await?;
+    Ok(Json(serde_json::json!({ "new_results": new_results })))
+}
+
+/// Re-run content extraction on a source's stored raw HTML (requires STORE_RAW_HTML).
+pub async fn reextract_source(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    axum::extract::Path(id): axum::extract::Path<i64>,
+) -> Result<Json<crate::models::Source>, ApiError> {
+    let url = state.db.get_source(id, user_id).await?
+        .map(|source| source.url)
+        .ok_or_else(|| ApiError::NotFound(format!("Source {} not found", id)))?;
+    let html = state.db.get_source_raw_html(id).await?
+        .ok_or_else(|| ApiError::NotFound(format!("No stored HTML for source {}", id)))?;
+
+    let content = WebSearch::extract_content_for_url(&url, &html);
+    tracing::info!("Re-extracted source {} ({} chars)", id, content.len());
+
+    state.db.update_source_content(id, &content).await?;
+    if let Err(e) = state.llm_manager.index_source(id, &content).await {
+        tracing::warn!("Failed to re-embed source {}: {}", id, e);
+    }
+
+    let source = state.db.get_source(id, user_id).await?
+        .ok_or_else(|| ApiError::NotFound(format!("Source {} not found", id)))?;
+    Ok(Json(source))
+}
+
+/// Crawl a page and (at depth 1) the pages it links to, storing each as a source.
+pub async fn ingest(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    Json(request): Json<crate::models::IngestRequest>,
+) -> Result<Json<crate::models::IngestResponse>, ApiError> {
+    let root = url::Url::parse(request.url.trim())
+        .ok()
+        .filter(|u| u.scheme() == "http" || u.scheme() == "https")
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid http(s) URL: {}", request.url)))?;
+    crate::http::ensure_public_url(&root).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    
+    let depth = request.depth.unwrap_or(1).min(1);
+    let same_domain = request.same_domain.unwrap_or(true);
+    let page_cap = std::env::var("INGEST_MAX_PAGES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(20)
+        .max(1);
+    let max_pages = request.max_pages.unwrap_or(page_cap).clamp(1, page_cap);
+    let store_raw_html = std::env::var("STORE_RAW_HTML")
+        .map(|v| v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    
+    tracing::info!("Ingesting {} (depth {}, same_domain {}, max {} pages)", root, depth, same_domain, max_pages);
+    
+    let root_page = WebSearch::fetch_public_content(&state.http, root.as_str(), &state.db).await
+        .map_err(|e| ApiError::Upstream(format!("Failed to fetch {}: {}", root, e)))?;
+    
+    let normalize_host = |u: &url::Url| u.host_str().map(|h| h.trim_start_matches("www.").to_lowercase());
+    let root_host = normalize_host(&root);
+    // Only HTML pages have links to follow
+    let links: Vec<url::Url> = match &root_page.html {
+        Some(html) if depth >= 1 => WebSearch::extract_links(&root_page.url, html)
+            .into_iter()
+            .filter(|u| u.as_str() != root_page.url && *u != root)
+            .filter(|u| !same_domain || normalize_host(u) == root_host)
+            // Links are page-controlled, so each one gets the same check as the root
+            .filter(|u| crate::http::ensure_public_url(u).is_ok())
+            .take(max_pages - 1)
+            .collect(),
+        _ => Vec::new(),
+    };
+    
+    let mut sources = Vec::new();
+    let mut failed = 0;
+    
+    let mut pending = vec![Ok(root_page)];
+    for link in &links {
+        pending.push(WebSearch::fetch_public_content(&state.http, link.as_str(), &state.db).await.map_err(|e| (link.to_string(), e)));
     }
     
-    // Sync LLM Providers (OpenRouter, Pollinations, etc.)
-    if let Err(e) = state.llm_manager.refresh_llm_limits().await {
-        tracing::error!("Sync LLM limits error: {}", e);
+    for page in pending {
+        let page = match page {
+            Ok(page) => page,
+            Err((url, e)) => {
+                tracing::warn!("Ingest: failed to fetch {}: {}", url, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if page.content.trim().is_empty() {
+            tracing::warn!("Ingest: no content extracted from {}", page.url);
+            failed += 1;
+            continue;
+        }
+        
+        let title = page.html.as_deref().and_then(WebSearch::extract_title).unwrap_or_else(|| page.url.clone());
+        let raw_html = page.html.as_deref().filter(|_| store_raw_html);
+        match state.db.insert_source(&page.url, &title, &page.content, raw_html, user_id).await {
+            Ok(id) => {
+                if let Err(e) = state.llm_manager.index_source(id, &page.content).await {
+                    tracing::warn!("Ingest: failed to embed {}: {}", page.url, e);
+                }
+                sources.push(crate::models::SourceUrl {
+                    url: page.url,
+                    title,
+                    fetched_at: chrono::Utc::now(),
+                });
+            }
+            Err(e) => {
+                tracing::warn!("Ingest: failed to store {}: {}", page.url, e);
+                failed += 1;
+            }
+        }
     }
     
+    tracing::info!("Ingested {} page(s) from {} ({} failed)", sources.len(), root, failed);
+    Ok(Json(crate::models::IngestResponse {
+        ingested: sources.len(),
+        failed,
+        sources,
+    }))
+}
+
+/// Chunk size and overlap (chars) for uploaded documents; each chunk is stored as its own source
+const DOCUMENT_CHUNK_CHARS: usize = 4000;
+const DOCUMENT_CHUNK_OVERLAP: usize = 200;
+
+/// Largest accepted upload body, from `MAX_UPLOAD_BYTES` (default 20 MiB).
+pub fn max_upload_bytes() -> usize {
+    std::env::var("MAX_UPLOAD_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20 * 1024 * 1024)
+}
+
+/// Store uploaded PDF, TXT and Markdown files as sources (multipart, one or more `file` parts).
+/// Text is split into chunks so each stored source stays a useful retrieval unit.
+pub async fn upload_documents(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    mut multipart: axum::extract::Multipart,
+) -> Result<Json<crate::models::DocumentUploadResponse>, ApiError> {
+    use crate::documents::{self, DocumentKind};
+
+    let mut documents = Vec::new();
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| ApiError::BadRequest(format!("Invalid multipart body: {}", e)))?
+    {
+        let Some(filename) = field.file_name().map(str::to_string) else {
+            continue;
+        };
+        let kind = DocumentKind::detect(&filename, field.content_type())
+            .ok_or_else(|| ApiError::BadRequest(format!("Unsupported file type for {} (expected PDF, TXT or Markdown)", filename)))?;
+        let bytes = field.bytes().await
+            .map_err(|e| ApiError::BadRequest(format!("Failed to read {}: {}", filename, e)))?;
+
+        let text = documents::extract_text(kind, bytes.to_vec()).await
+            .map_err(|e| ApiError::BadRequest(format!("{}: {}", filename, e)))?;
+        if text.trim().is_empty() {
+            return Err(ApiError::BadRequest(format!("No text could be extracted from {}", filename)));
+        }
+
+        let chunks = crate::embeddings::chunk_text(&text, DOCUMENT_CHUNK_CHARS, DOCUMENT_CHUNK_OVERLAP);
+        let total = chunks.len();
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let url = documents::chunk_url(&filename, &text, idx);
+            let title = if total > 1 {
+                format!("{} (part {}/{})", filename, idx + 1, total)
+            } else {
+                filename.clone()
+            };
+            let id = state.db.insert_source(&url, &title, chunk, None, user_id).await?;
+            if let Err(e) = state.llm_manager.index_source(id, chunk).await {
+                tracing::warn!("Upload: failed to embed {}: {}", url, e);
+            }
+        }
+
+        tracing::info!("Stored uploaded {} {} ({} chars, {} chunks)", kind.as_str(), filename, text.len(), total);
+        documents.push(crate::models::UploadedDocument {
+            filename,
+            kind: kind.as_str().to_string(),
+            chars: text.len(),
+            chunks: total,
+        });
+    }
+
+    if documents.is_empty() {
+        return Err(ApiError::BadRequest("No files in upload; send them as multipart 'file' parts".to_string()));
+    }
+    Ok(Json(crate::models::DocumentUploadResponse { documents }))
+}
+
+/// Per-provider performance analytics: requests, errors, latency and token usage.
+pub async fn get_metrics(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<crate::models::ProviderStats>>, ApiError> {
+    Ok(Json(state.db.get_provider_stats().await?))
+}
+
+/// Token usage per day or month, provider and model, plus the threads that used the most.
+/// In multi-user mode only the caller's own usage is counted.
+pub async fn get_usage(
+    State(state): State<AppState>,
+    axum::Extension(CurrentUser(user_id)): axum::Extension<CurrentUser>,
+    axum::extract::Query(params): axum::extract::Query<crate::models::UsageParams>,
+) -> Result<Json<crate::models::UsageReport>, ApiError> {
+    let monthly = match params.period.as_deref().map(str::trim) {
+        None | Some("") | Some("day") => false,
+        Some("month") => true,
+        Some(other) => return Err(ApiError::BadRequest(format!("Invalid period: {} (expected day or month)", other))),
+    };
+    let days = params.days.unwrap_or(if monthly { 365 } else { 30 }).clamp(1, 3650);
+    let lookback = chrono::Duration::days(days);
+    let thread_id = params.thread_id.as_deref().filter(|t| !t.trim().is_empty());
+
+    let usage = state.db.usage_by_period(monthly, lookback, thread_id, user_id).await?;
+    let threads = state.db.usage_by_thread(lookback, 20, user_id).await?;
+    Ok(Json(crate::models::UsageReport {
+        period: if monthly { "month" } else { "day" }.to_string(),
+        since: chrono::Utc::now() - lookback,
+        usage,
+        threads,
+    }))
+}
+
+/// Request limits per provider, including operator overrides.
+pub async fn get_provider_limits(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<crate::models::ProviderLimits>>, ApiError> {
+    Ok(Json(state.db.provider_limits().await?))
+}
+
+/// Override a provider's minute/day/month request limits, e.g. to match a paid tier.
+pub async fn set_provider_limits(
+    State(state): State<AppState>,
+    Json(request): Json<crate::models::ProviderLimitsRequest>,
+) -> Result<Json<crate::models::ProviderLimits>, ApiError> {
+    let provider = request.provider.trim().to_lowercase();
+    let known = state.db.provider_limits().await?;
+    if !known.iter().any(|p| p.provider == provider) {
+        return Err(ApiError::BadRequest(format!("Unknown provider: {}", request.provider)));
+    }
+    for (name, limit) in [("limit_min", request.limit_min), ("limit_day", request.limit_day), ("limit_month", request.limit_month)] {
+        if limit.is_some_and(|l| l < 0) {
+            return Err(ApiError::BadRequest(format!("{} must not be negative", name)));
+        }
+    }
+
+    state.db.set_provider_limit_overrides(&provider, request.limit_min, request.limit_day, request.limit_month).await?;
+    tracing::info!(
+        "Limits for {} overridden: min={:?} day={:?} month={:?}",
+        provider, request.limit_min, request.limit_day, request.limit_month
+    );
+
+    let limits = state.db.provider_limits().await?
+        .into_iter()
+        .find(|p| p.provider == provider)
+        .ok_or_else(|| ApiError::NotFound(format!("Provider {} not found", provider)))?;
+    Ok(Json(limits))
+}
+
+pub async fn sync_limits(
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    crate::scheduler::sync_limits(&state).await;
     StatusCode::OK
 }
@@ -0,0 +1,147 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use crate::db::Database;
+use crate::error::ApiError;
+use crate::models::User;
+use crate::AppState;
+
+pub const SESSION_COOKIE: &str = "w9_session";
+
+/// Multi-user mode, switched on with `AUTH_ENABLED=true`. When off (the default) there
+/// are no accounts and every thread and source is visible, as in a single-user deployment.
+pub fn auth_enabled() -> bool {
+    std::env::var("AUTH_ENABLED").map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+/// Whether `POST /api/auth/register` is open. The first account can always be created.
+pub fn registration_open() -> bool {
+    std::env::var("ALLOW_REGISTRATION").map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+/// Session lifetime from `SESSION_TTL_DAYS` (default 30).
+pub fn session_ttl() -> chrono::Duration {
+    let days = std::env::var("SESSION_TTL_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|d| *d > 0)
+        .unwrap_or(30);
+    chrono::Duration::days(days)
+}
+
+/// The requester's user ID, attached to `/api` requests by `require_session`.
+/// `None` when auth is disabled, which the database layer treats as "no filtering".
+#[derive(Debug, Clone, Copy)]
+pub struct CurrentUser(pub Option<i64>);
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::encode_b64(uuid::Uuid::new_v4().as_bytes())
+        .map_err(|e| anyhow::anyhow!("Failed to generate salt: {}", e))?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
+
+/// A fresh random session token; only its hash is stored.
+pub fn new_session_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn session_cookie(token: &str, max_age: chrono::Duration) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        SESSION_COOKIE,
+        token,
+        max_age.num_seconds().max(0)
+    )
+}
+
+/// Session token from the `w9_session` cookie, or an `Authorization: Bearer` header for API clients.
+pub fn session_token(headers: &HeaderMap) -> Option<String> {
+    let from_cookie = headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string());
+    from_cookie.or_else(|| bearer_token(headers).map(str::to_string))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// The logged-in user for these request headers, if any.
+pub async fn session_user(db: &Database, headers: &HeaderMap) -> Option<User> {
+    let token = session_token(headers)?;
+    match db.session_user(&hash_token(&token)).await {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::warn!("Session lookup failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Middleware for the `/api` routes: resolves the session into a `CurrentUser`
+/// extension, rejecting anonymous requests with 401 when auth is enabled.
+pub async fn require_session(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let user = if auth_enabled() {
+        match session_user(&state.db, req.headers()).await {
+            Some(user) => Some(user.id),
+            None => return ApiError::Unauthorized("Login required".to_string()).into_response(),
+        }
+    } else {
+        None
+    };
+    req.extensions_mut().insert(CurrentUser(user));
+    next.run(req).await
+}
+
+/// Whether the request may use the admin API and pages: it carries `ADMIN_TOKEN` as a
+/// Bearer token, or (with auth enabled) a session for one of the comma-separated
+/// usernames in `ADMIN_USERS`. With neither configured nobody is an admin.
+pub async fn is_admin(db: &Database, headers: &HeaderMap) -> bool {
+    let admin_token = std::env::var("ADMIN_TOKEN").unwrap_or_default();
+    let admin_token = admin_token.trim();
+    if !admin_token.is_empty() && bearer_token(headers).is_some_and(|t| hash_token(t) == hash_token(admin_token)) {
+        return true;
+    }
+    if !auth_enabled() {
+        return false;
+    }
+    let admins = std::env::var("ADMIN_USERS").unwrap_or_default();
+    match session_user(db, headers).await {
+        Some(user) => admins.split(',').any(|name| name.trim() == user.username),
+        None => false,
+    }
+}
+
+/// Middleware for the admin API routes, answering 403 unless `is_admin`.
+pub async fn require_admin(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if !is_admin(&state.db, req.headers()).await {
+        return ApiError::Forbidden("Admin access required".to_string()).into_response();
+    }
+    next.run(req).await
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Consecutive failures that take a provider out of rotation (`CIRCUIT_BREAKER_FAILURES`,
+/// default 3; 0 disables the breaker).
+fn failure_threshold() -> u32 {
+    std::env::var("CIRCUIT_BREAKER_FAILURES")
+        .ok()
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .unwrap_or(3)
+}
+
+/// How long an unhealthy provider is skipped (`CIRCUIT_BREAKER_COOLDOWN_SECS`, default 60).
+fn cooldown() -> Duration {
+    crate::http::env_timeout("CIRCUIT_BREAKER_COOLDOWN_SECS", 60)
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Health of one provider as seen by its circuit breaker, for the `/models` page.
+#[derive(Debug, Clone)]
+pub struct ProviderHealth {
+    pub provider: String,
+    pub consecutive_failures: u32,
+    /// Time left before an unhealthy provider is tried again; `None` while healthy
+    pub retry_in: Option<Duration>,
+}
+
+/// Per-provider circuit breaker. After enough consecutive failures a provider is
+/// unhealthy for the cooldown and auto-selection skips it; the first call after that
+/// is a probe, and one more failure takes it out again.
+#[derive(Default)]
+pub struct CircuitBreaker {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    /// False while `provider` is cooling down after repeated failures.
+    pub fn is_available(&self, provider: &str) -> bool {
+        let circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        circuits
+            .get(provider)
+            .and_then(|c| c.open_until)
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    pub fn record_success(&self, provider: &str) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(circuit) = circuits.get_mut(provider) {
+            if circuit.open_until.is_some() {
+                tracing::info!("{} is healthy again", provider);
+            }
+            *circuit = Circuit::default();
+        }
+    }
+
+    pub fn record_failure(&self, provider: &str) {
+        let threshold = failure_threshold();
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let circuit = circuits.entry(provider.to_string()).or_default();
+        circuit.consecutive_failures += 1;
+        if threshold > 0 && circuit.consecutive_failures >= threshold {
+            let cooldown = cooldown();
+            tracing::warn!(
+                "{} failed {} times in a row; skipping it for {}s",
+                provider,
+                circuit.consecutive_failures,
+                cooldown.as_secs()
+            );
+            circuit.open_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    /// Every provider that has failed since its last success, by name
+    pub fn snapshot(&self) -> Vec<ProviderHealth> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let mut health: Vec<ProviderHealth> = circuits
+            .iter()
+            .filter(|(_, c)| c.consecutive_failures > 0)
+            .map(|(provider, c)| ProviderHealth {
+                provider: provider.clone(),
+                consecutive_failures: c.consecutive_failures,
+                retry_in: c.open_until.filter(|until| *until > now).map(|until| until - now),
+            })
+            .collect();
+        health.sort_by(|a, b| a.provider.cmp(&b.provider));
+        health
+    }
+}
@@ -1,29 +1,90 @@
+use std::borrow::Cow;
+
+use sqlx::postgres::{PgPool, PgPoolOptions};
 use sqlx::sqlite::{SqlitePool, SqliteConnectOptions};
 use sqlx::FromRow;
-use crate::models::Source;
+use crate::models::{Alert, DomainRule, Source, SourceUrl};
 use crate::llm::ProviderType;
-use chrono::{DateTime, Utc, Datelike, TimeZone};
+use chrono::{DateTime, Utc, Datelike, TimeZone, Timelike};
 
 #[derive(FromRow)]
 struct ProviderMetricsRow {
     req_min: Option<i64>,
     req_day: Option<i64>,
     req_month: Option<i64>,
-    last_reset_min: Option<chrono::NaiveDateTime>,
-    last_reset_day: Option<chrono::NaiveDateTime>,
-    last_reset_month: Option<chrono::NaiveDateTime>,
+    last_reset_min: Option<DateTime<Utc>>,
+    last_reset_day: Option<DateTime<Utc>>,
+    last_reset_month: Option<DateTime<Utc>>,
     limit_min: Option<i64>,
     limit_day: Option<i64>,
     limit_month: Option<i64>,
+    override_min: Option<i64>,
+    override_day: Option<i64>,
+    override_month: Option<i64>,
+}
+
+impl ProviderMetricsRow {
+    /// Limits to enforce: operator overrides, then limits the provider reported, then `defaults`
+    fn effective_limits(&self, defaults: (i64, i64, i64)) -> (i64, i64, i64) {
+        (
+            self.override_min.or(self.limit_min).unwrap_or(defaults.0),
+            self.override_day.or(self.limit_day).unwrap_or(defaults.1),
+            self.override_month.or(self.limit_month).unwrap_or(defaults.2),
+        )
+    }
+}
+
+/// A fetched page kept for reuse by `WebSearch::fetch_content`, with the validators
+/// needed to revalidate it.
+#[derive(Debug, Clone, FromRow)]
+pub struct CachedPage {
+    pub final_url: String,
+    /// Raw HTML, or the extracted text for non-HTML pages
+    pub html: String,
+    /// `html`, `pdf`, `json` or `text`; NULL rows predate the column and are HTML
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// Storage backend chosen from `DATABASE_URL`: a SQLite file for single-instance
+/// deployments, or PostgreSQL (`postgres://...`) when several replicas share one database.
+enum Pool {
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+/// Run `$body` against the active backend with `$pool` bound to its concrete pool.
+/// The body is compiled once per backend, so the same sqlx calls serve both.
+macro_rules! with_pool {
+    ($db:expr, $pool:ident => $body:expr) => {
+        match &$db.pool {
+            Pool::Sqlite($pool) => $body,
+            Pool::Postgres($pool) => $body,
+        }
+    };
 }
 
 pub struct Database {
-    pool: SqlitePool,
+    pool: Pool,
 }
 
 impl Database {
     pub async fn new(database_url: &str) -> anyhow::Result<Self> {
-        tracing::info!("Initializing database connection to: {}", database_url);
+        tracing::info!("Initializing database connection to: {}", redact_url(database_url));
+        
+        if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+            let max_connections = std::env::var("DATABASE_MAX_CONNECTIONS")
+                .ok()
+                .and_then(|v| v.parse::<u32>().ok())
+                .unwrap_or(10);
+            let pool = PgPoolOptions::new()
+                .max_connections(max_connections)
+                .connect(database_url)
+                .await?;
+            return Ok(Self { pool: Pool::Postgres(pool) });
+        }
         
         let options = if database_url.starts_with("sqlite:") {
             let path = database_url.strip_prefix("sqlite:").unwrap();
@@ -36,11 +97,35 @@ impl Database {
         };
         
         let pool = SqlitePool::connect_with(options).await?;
-        Ok(Self { pool })
+        Ok(Self { pool: Pool::Sqlite(pool) })
+    }
+
+    /// Queries are written in SQLite's dialect; this rewrites them for PostgreSQL.
+    fn sql<'a>(&self, sql: &'a str) -> Cow<'a, str> {
+        match self.pool {
+            Pool::Sqlite(_) => Cow::Borrowed(sql),
+            Pool::Postgres(_) => Cow::Owned(postgres_sql(sql)),
+        }
+    }
+
+    /// Run schema statements written for SQLite, translating column types for PostgreSQL.
+    async fn execute_ddl(&self, ddl: &str) -> anyhow::Result<()> {
+        match &self.pool {
+            Pool::Sqlite(pool) => {
+                sqlx::query(ddl).execute(pool).await?;
+            }
+            Pool::Postgres(pool) => {
+                // Prepared statements can't hold several commands, so run them one by one
+                for statement in postgres_ddl(ddl).split(';').map(str::trim).filter(|s| !s.is_empty()) {
+                    sqlx::query(statement).execute(pool).await?;
+                }
+            }
+        }
+        Ok(())
     }
 
     pub async fn migrate(&self) -> anyhow::Result<()> {
-        sqlx::query(
+        self.execute_ddl(
             r#"
             CREATE TABLE IF NOT EXISTS sources (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -77,117 +162,1033 @@ impl Database {
             );
             "#,
         )
-        .execute(&self.pool)
         .await?;
 
         // Attempt to add limit columns if they don't exist
-        let _ = sqlx::query("ALTER TABLE provider_metrics ADD COLUMN limit_min INTEGER").execute(&self.pool).await;
-        let _ = sqlx::query("ALTER TABLE provider_metrics ADD COLUMN limit_day INTEGER").execute(&self.pool).await;
-        let _ = sqlx::query("ALTER TABLE provider_metrics ADD COLUMN limit_month INTEGER").execute(&self.pool).await;
+        let _ = self.execute_ddl("ALTER TABLE provider_metrics ADD COLUMN limit_min INTEGER").await;
+        let _ = self.execute_ddl("ALTER TABLE provider_metrics ADD COLUMN limit_day INTEGER").await;
+        let _ = self.execute_ddl("ALTER TABLE provider_metrics ADD COLUMN limit_month INTEGER").await;
 
-        Ok(())
-    }
+        // Operator overrides from /api/admin/limits; win over defaults and provider-reported limits
+        let _ = self.execute_ddl("ALTER TABLE provider_metrics ADD COLUMN override_min INTEGER").await;
+        let _ = self.execute_ddl("ALTER TABLE provider_metrics ADD COLUMN override_day INTEGER").await;
+        let _ = self.execute_ddl("ALTER TABLE provider_metrics ADD COLUMN override_month INTEGER").await;
 
-    pub async fn create_thread(&self, title: &str) -> anyhow::Result<String> {
-        let id = uuid::Uuid::new_v4().to_string();
-        sqlx::query(
-            "INSERT INTO threads (id, title) VALUES (?, ?)"
+        // Performance analytics, separate from the rate-limit counters in provider_metrics
+        self.execute_ddl(
+            r#"
+            CREATE TABLE IF NOT EXISTS provider_stats (
+                provider TEXT PRIMARY KEY,
+                total_requests INTEGER NOT NULL DEFAULT 0,
+                error_count INTEGER NOT NULL DEFAULT 0,
+                total_latency_ms INTEGER NOT NULL DEFAULT 0,
+                total_tokens INTEGER NOT NULL DEFAULT 0,
+                token_requests INTEGER NOT NULL DEFAULT 0
+            )
+            "#,
         )
-        .bind(&id)
-        .bind(title)
-        .execute(&self.pool)
         .await?;
-        Ok(id)
-    }
 
-    pub async fn get_thread(&self, id: &str) -> anyhow::Result<Option<crate::models::Thread>> {
-        let thread = sqlx::query_as::<_, crate::models::Thread>(
-            "SELECT id, title, created_at, updated_at FROM threads WHERE id = ?"
+        // Raw HTML is only populated when STORE_RAW_HTML=true
+        let _ = self.execute_ddl("ALTER TABLE sources ADD COLUMN raw_html TEXT").await;
+
+        // When the content was last fetched; NULL rows predate the column and use created_at
+        let _ = self.execute_ddl("ALTER TABLE sources ADD COLUMN fetched_at DATETIME").await;
+
+        // 'complete' for normal messages, 'cancelled' for the marker left by a cancelled query
+        let _ = self.execute_ddl("ALTER TABLE messages ADD COLUMN status TEXT NOT NULL DEFAULT 'complete'").await;
+
+        // Follow-up questions suggested after an answer, as a JSON array
+        let _ = self.execute_ddl("ALTER TABLE messages ADD COLUMN suggestions TEXT").await;
+
+        // Running summary of turns that no longer fit the history window (SUMMARIZE_HISTORY)
+        let _ = self.execute_ddl("ALTER TABLE threads ADD COLUMN summary TEXT").await;
+        let _ = self.execute_ddl("ALTER TABLE threads ADD COLUMN summary_through INTEGER").await;
+
+        // Chunk embeddings for semantic retrieval; vectors are little-endian f32 BLOBs
+        self.execute_ddl(
+            r#"
+            CREATE TABLE IF NOT EXISTS embeddings (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                source_id INTEGER NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                model TEXT NOT NULL,
+                embedding BLOB NOT NULL,
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY(source_id) REFERENCES sources(id) ON DELETE CASCADE
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_embeddings_model ON embeddings(model);
+            CREATE INDEX IF NOT EXISTS idx_embeddings_source ON embeddings(source_id);
+            "#,
         )
-        .bind(id)
-        .fetch_optional(&self.pool)
         .await?;
-        Ok(thread)
-    }
 
-    pub async fn list_threads(&self, limit: i64) -> anyhow::Result<Vec<crate::models::Thread>> {
-        let threads = sqlx::query_as::<_, crate::models::Thread>(
-            "SELECT id, title, created_at, updated_at FROM threads ORDER BY updated_at DESC LIMIT ?"
+        // Accounts for multi-user mode (AUTH_ENABLED); sessions store a SHA-256 of the token
+        self.execute_ddl(
+            r#"
+            CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                password_hash TEXT NOT NULL,
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username_lower ON users(lower(username));
+
+            CREATE TABLE IF NOT EXISTS sessions (
+                token_hash TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                expires_at DATETIME NOT NULL,
+                FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+            );
+            "#,
         )
-        .bind(limit)
-        .fetch_all(&self.pool)
         .await?;
-        Ok(threads)
-    }
 
-    pub async fn add_message(&self, thread_id: &str, role: &str, content: &str) -> anyhow::Result<i64> {
-        // Update thread updated_at
-        sqlx::query("UPDATE threads SET updated_at = CURRENT_TIMESTAMP WHERE id = ?")
-            .bind(thread_id)
-            .execute(&self.pool)
-            .await?;
+        // Owner of each row; NULL for data created while auth was disabled (visible to everyone)
+        let _ = self.execute_ddl("ALTER TABLE threads ADD COLUMN user_id INTEGER").await;
+        let _ = self.execute_ddl("ALTER TABLE messages ADD COLUMN user_id INTEGER").await;
+        let _ = self.execute_ddl("ALTER TABLE sources ADD COLUMN user_id INTEGER").await;
 
-        let id = sqlx::query_scalar::<_, i64>(
-            "INSERT INTO messages (thread_id, role, content) VALUES (?, ?, ?) RETURNING id"
+        // Raw pages keyed by requested URL, reused within PAGE_CACHE_TTL_SECS and revalidated after
+        self.execute_ddl(
+            r#"
+            CREATE TABLE IF NOT EXISTS page_cache (
+                url TEXT PRIMARY KEY,
+                final_url TEXT NOT NULL,
+                html TEXT NOT NULL,
+                etag TEXT,
+                last_modified TEXT,
+                fetched_at DATETIME NOT NULL
+            )
+            "#,
         )
-        .bind(thread_id)
-        .bind(role)
-        .bind(content)
-        .fetch_one(&self.pool)
         .await?;
-        Ok(id)
-    }
 
-    pub async fn get_thread_messages(&self, thread_id: &str) -> anyhow::Result<Vec<crate::models::Message>> {
-        let messages = sqlx::query_as::<_, crate::models::Message>(
-            "SELECT id, thread_id, role, content, created_at FROM messages WHERE thread_id = ? ORDER BY created_at ASC"
+        // Per-domain allow/block rules, managed through /api/domain-rules
+        self.execute_ddl(
+            r#"
+            CREATE TABLE IF NOT EXISTS domain_rules (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                domain TEXT NOT NULL UNIQUE,
+                rule TEXT NOT NULL CHECK (rule IN ('allow', 'block')),
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
         )
-        .bind(thread_id)
-        .fetch_all(&self.pool)
         .await?;
-        Ok(messages)
-    }
 
-    pub async fn insert_source(&self, url: &str, title: &str, content: &str) -> anyhow::Result<i64> {
-        let id = sqlx::query_scalar::<_, i64>(
+        let _ = self.execute_ddl("ALTER TABLE page_cache ADD COLUMN content_type TEXT").await;
+
+        // Saved searches run by the scheduler; seen URLs keep each run to genuinely new results
+        self.execute_ddl(
             r#"
-            INSERT INTO sources (url, title, content)
-            VALUES (?, ?, ?)
-            ON CONFLICT(url) DO UPDATE SET title = excluded.title, content = excluded.content
-            RETURNING id
+            CREATE TABLE IF NOT EXISTS alerts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                query TEXT NOT NULL,
+                schedule TEXT NOT NULL,
+                thread_id TEXT NOT NULL,
+                search_provider TEXT,
+                time_range TEXT,
+                user_id INTEGER,
+                last_run_at DATETIME,
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+
+            CREATE TABLE IF NOT EXISTS alert_seen_urls (
+                alert_id INTEGER NOT NULL,
+                url TEXT NOT NULL,
+                seen_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (alert_id, url),
+                FOREIGN KEY(alert_id) REFERENCES alerts(id) ON DELETE CASCADE
+            );
             "#,
         )
-        .bind(url)
-        .bind(title)
-        .bind(content)
-        .fetch_one(&self.pool)
         .await?;
 
-        Ok(id)
-    }
+        // Set when an alert posts to the thread, cleared when the thread is opened
+        let _ = self.execute_ddl("ALTER TABLE threads ADD COLUMN unread BOOLEAN NOT NULL DEFAULT FALSE").await;
 
-    pub async fn get_sources(&self, limit: i64) -> anyhow::Result<Vec<Source>> {
-        let sources = sqlx::query_as::<_, Source>(
-            "SELECT id, url, title, content, created_at FROM sources ORDER BY created_at DESC LIMIT ?"
+        // Small key-value store for settings that should survive restarts
+        self.execute_ddl(
+            r#"
+            CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL,
+                updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
         )
-        .bind(limit)
-        .fetch_all(&self.pool)
         .await?;
 
-        Ok(sources)
-    }
+        // Token usage of every completion, for `/api/usage`
+        self.execute_ddl(
+            r#"
+            CREATE TABLE IF NOT EXISTS usage_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                provider TEXT NOT NULL,
+                model TEXT NOT NULL,
+                thread_id TEXT,
+                user_id INTEGER,
+                prompt_tokens INTEGER NOT NULL DEFAULT 0,
+                completion_tokens INTEGER NOT NULL DEFAULT 0,
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
 
-    pub async fn search_sources(&self, query: &str, limit: i64) -> anyhow::Result<Vec<Source>> {
-        let sources = sqlx::query_as::<_, Source>(
-            "SELECT id, url, title, content, created_at FROM sources WHERE content LIKE ? OR title LIKE ? ORDER BY created_at DESC LIMIT ?"
+            CREATE INDEX IF NOT EXISTS idx_usage_log_created ON usage_log(created_at);
+            CREATE INDEX IF NOT EXISTS idx_usage_log_thread ON usage_log(thread_id);
+            "#,
         )
-        .bind(format!("%{}%", query))
-        .bind(format!("%{}%", query))
-        .bind(limit)
-        .fetch_all(&self.pool)
         .await?;
 
-        Ok(sources)
+        Ok(())
+    }
+
+    pub async fn create_user(&self, username: &str, password_hash: &str) -> anyhow::Result<i64> {
+        with_pool!(self, pool => {
+            let id = sqlx::query_scalar::<_, i64>(
+                &self.sql("INSERT INTO users (username, password_hash) VALUES (?, ?) RETURNING id")
+            )
+            .bind(username)
+            .bind(password_hash)
+            .fetch_one(pool)
+            .await?;
+            Ok(id)
+        })
+    }
+
+    pub async fn user_count(&self) -> anyhow::Result<i64> {
+        with_pool!(self, pool => {
+            Ok(sqlx::query_scalar::<_, i64>(&self.sql("SELECT COUNT(*) FROM users")).fetch_one(pool).await?)
+        })
+    }
+
+    /// The user and their password hash, for login.
+    pub async fn get_user_credentials(&self, username: &str) -> anyhow::Result<Option<(crate::models::User, String)>> {
+        with_pool!(self, pool => {
+            let row = sqlx::query_as::<_, (i64, String, DateTime<Utc>, String)>(
+                &self.sql("SELECT id, username, created_at, password_hash FROM users WHERE lower(username) = lower(?)")
+            )
+            .bind(username)
+            .fetch_optional(pool)
+            .await?;
+            Ok(row.map(|(id, username, created_at, hash)| (crate::models::User { id, username, created_at }, hash)))
+        })
+    }
+
+    pub async fn create_session(&self, token_hash: &str, user_id: i64, expires_at: DateTime<Utc>) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            // Expired sessions are cleaned up opportunistically on each login
+            sqlx::query(&self.sql("DELETE FROM sessions WHERE expires_at <= ?"))
+                .bind(Utc::now())
+                .execute(pool)
+                .await?;
+            sqlx::query(&self.sql("INSERT INTO sessions (token_hash, user_id, expires_at) VALUES (?, ?, ?)"))
+                .bind(token_hash)
+                .bind(user_id)
+                .bind(expires_at)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// The user owning an unexpired session.
+    pub async fn session_user(&self, token_hash: &str) -> anyhow::Result<Option<crate::models::User>> {
+        with_pool!(self, pool => {
+            let user = sqlx::query_as::<_, crate::models::User>(
+                &self.sql("SELECT u.id, u.username, u.created_at FROM sessions s JOIN users u ON u.id = s.user_id \
+                 WHERE s.token_hash = ? AND s.expires_at > ?")
+            )
+            .bind(token_hash)
+            .bind(Utc::now())
+            .fetch_optional(pool)
+            .await?;
+            Ok(user)
+        })
+    }
+
+    pub async fn delete_session(&self, token_hash: &str) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("DELETE FROM sessions WHERE token_hash = ?"))
+                .bind(token_hash)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    pub async fn get_setting(&self, key: &str) -> anyhow::Result<Option<String>> {
+        with_pool!(self, pool => {
+            let value = sqlx::query_scalar::<_, String>(&self.sql("SELECT value FROM settings WHERE key = ?"))
+                .bind(key)
+                .fetch_optional(pool)
+                .await?;
+            Ok(value)
+        })
+    }
+
+    pub async fn set_setting(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(
+                &self.sql("INSERT INTO settings (key, value) VALUES (?, ?)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP")
+            )
+            .bind(key)
+            .bind(value)
+            .execute(pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    pub async fn create_thread(&self, title: &str, user_id: Option<i64>) -> anyhow::Result<String> {
+        with_pool!(self, pool => {
+            let id = uuid::Uuid::new_v4().to_string();
+            sqlx::query(
+                &self.sql("INSERT INTO threads (id, title, user_id) VALUES (?, ?, ?)")
+            )
+            .bind(&id)
+            .bind(title)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+        
+            self.enforce_max_threads(user_id).await;
+            Ok(id)
+        })
+    }
+
+    /// Prune the owner's threads down to `MAX_THREADS` after one is added. MAX_THREADS=0
+    /// (default) keeps every thread; failures are logged.
+    async fn enforce_max_threads(&self, user_id: Option<i64>) {
+        let max_threads = std::env::var("MAX_THREADS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(0);
+        if max_threads > 0 {
+            match self.prune_threads(max_threads, user_id).await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!("Pruned {} old thread(s) (MAX_THREADS={})", pruned, max_threads),
+                Err(e) => tracing::warn!("Failed to prune old threads: {}", e),
+            }
+        }
+    }
+
+    /// Store an imported conversation as a new thread, keeping its timestamps. Messages
+    /// without one are placed a second after the previous message. Counts toward
+    /// `MAX_THREADS` like a new thread, so an import older than every kept thread is
+    /// pruned straight away.
+    pub async fn import_thread(&self, thread: &crate::import::ImportedThread, user_id: Option<i64>) -> anyhow::Result<String> {
+        with_pool!(self, pool => {
+            // Whole seconds in UTC, which SQLite stores in the same text format as
+            // CURRENT_TIMESTAMP so imported and new messages sort together
+            let sql_time = |t: DateTime<Utc>| t.naive_utc().with_nanosecond(0).unwrap_or(t.naive_utc());
+            let mut clock = thread.created_at
+                .or_else(|| thread.messages.iter().find_map(|m| m.created_at))
+                .unwrap_or_else(|| Utc::now() - chrono::Duration::seconds(thread.messages.len() as i64));
+            let created_at = clock;
+
+            let id = uuid::Uuid::new_v4().to_string();
+            let mut tx = pool.begin().await?;
+            sqlx::query(&self.sql("INSERT INTO threads (id, title, user_id, created_at, updated_at) VALUES (?, ?, ?, ?, ?)"))
+                .bind(&id)
+                .bind(&thread.title)
+                .bind(user_id)
+                .bind(sql_time(created_at))
+                .bind(sql_time(created_at))
+                .execute(&mut *tx)
+                .await?;
+            for message in &thread.messages {
+                clock = message.created_at.unwrap_or(clock + chrono::Duration::seconds(1));
+                sqlx::query(&self.sql("INSERT INTO messages (thread_id, role, content, user_id, created_at) VALUES (?, ?, ?, ?, ?)"))
+                    .bind(&id)
+                    .bind(&message.role)
+                    .bind(&message.content)
+                    .bind(user_id)
+                    .bind(sql_time(clock))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            sqlx::query(&self.sql("UPDATE threads SET updated_at = ? WHERE id = ?"))
+                .bind(sql_time(clock.max(created_at)))
+                .bind(&id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            self.enforce_max_threads(user_id).await;
+            Ok(id)
+        })
+    }
+
+    /// Delete all but the `keep_latest` most recently active threads of one owner, with
+    /// their messages. Returns the number of threads removed.
+    pub async fn prune_threads(&self, keep_latest: i64, user_id: Option<i64>) -> anyhow::Result<u64> {
+        with_pool!(self, pool => {
+            const STALE_THREADS: &str =
+                "SELECT id FROM threads WHERE user_id = ?1 OR (?1 IS NULL AND user_id IS NULL) \
+                 ORDER BY updated_at DESC, created_at DESC LIMIT -1 OFFSET ?2";
+        
+            let mut tx = pool.begin().await?;
+            sqlx::query(&self.sql(&format!("DELETE FROM messages WHERE thread_id IN ({})", STALE_THREADS)))
+                .bind(user_id)
+                .bind(keep_latest)
+                .execute(&mut *tx)
+                .await?;
+            let result = sqlx::query(&self.sql(&format!("DELETE FROM threads WHERE id IN ({})", STALE_THREADS)))
+                .bind(user_id)
+                .bind(keep_latest)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        
+            Ok(result.rows_affected())
+        })
+    }
+
+    pub async fn get_thread(&self, id: &str) -> anyhow::Result<Option<crate::models::Thread>> {
+        with_pool!(self, pool => {
+            let thread = sqlx::query_as::<_, crate::models::Thread>(
+                &self.sql("SELECT id, title, created_at, updated_at, unread FROM threads WHERE id = ?")
+            )
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+            Ok(thread)
+        })
+    }
+
+    /// Whether `user_id` may read the thread; `None` (auth disabled) sees every thread,
+    /// and threads from before auth was enabled (no owner) are visible to everyone.
+    pub async fn thread_visible(&self, thread_id: &str, user_id: Option<i64>) -> anyhow::Result<bool> {
+        with_pool!(self, pool => {
+            let found = sqlx::query_scalar::<_, i32>(
+                &self.sql("SELECT 1 FROM threads WHERE id = ?1 AND (?2 IS NULL OR user_id IS NULL OR user_id = ?2)")
+            )
+            .bind(thread_id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+            Ok(found.is_some())
+        })
+    }
+
+    pub async fn list_threads(&self, limit: i64, user_id: Option<i64>) -> anyhow::Result<Vec<crate::models::Thread>> {
+        with_pool!(self, pool => {
+            let threads = sqlx::query_as::<_, crate::models::Thread>(
+                &self.sql("SELECT id, title, created_at, updated_at, unread FROM threads WHERE ?1 IS NULL OR user_id IS NULL OR user_id = ?1 ORDER BY updated_at DESC LIMIT ?2")
+            )
+            .bind(user_id)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+            Ok(threads)
+        })
+    }
+
+    pub async fn add_message(&self, thread_id: &str, role: &str, content: &str) -> anyhow::Result<i64> {
+        self.add_message_with_status(thread_id, role, content, "complete").await
+    }
+
+    pub async fn add_message_with_status(&self, thread_id: &str, role: &str, content: &str, status: &str) -> anyhow::Result<i64> {
+        with_pool!(self, pool => {
+            // Update thread updated_at
+            sqlx::query(&self.sql("UPDATE threads SET updated_at = CURRENT_TIMESTAMP WHERE id = ?"))
+                .bind(thread_id)
+                .execute(pool)
+                .await?;
+
+            let id = sqlx::query_scalar::<_, i64>(
+                &self.sql("INSERT INTO messages (thread_id, role, content, status, user_id) \
+                 VALUES (?1, ?2, ?3, ?4, (SELECT user_id FROM threads WHERE id = ?1)) RETURNING id")
+            )
+            .bind(thread_id)
+            .bind(role)
+            .bind(content)
+            .bind(status)
+            .fetch_one(pool)
+            .await?;
+            Ok(id)
+        })
+    }
+
+    /// Stored history summary of a thread and the id of the last message it covers.
+    pub async fn get_thread_summary(&self, thread_id: &str) -> anyhow::Result<Option<(String, i64)>> {
+        with_pool!(self, pool => {
+            let row = sqlx::query_as::<_, (Option<String>, Option<i64>)>(
+                &self.sql("SELECT summary, summary_through FROM threads WHERE id = ?")
+            )
+            .bind(thread_id)
+            .fetch_optional(pool)
+            .await?;
+            Ok(match row {
+                Some((Some(summary), Some(through))) => Some((summary, through)),
+                _ => None,
+            })
+        })
+    }
+
+    pub async fn set_thread_summary(&self, thread_id: &str, summary: &str, through_message_id: i64) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("UPDATE threads SET summary = ?, summary_through = ? WHERE id = ?"))
+                .bind(summary)
+                .bind(through_message_id)
+                .bind(thread_id)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Store the follow-up questions suggested after an assistant message.
+    pub async fn set_message_suggestions(&self, message_id: i64, suggestions: &[String]) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("UPDATE messages SET suggestions = ? WHERE id = ?"))
+                .bind(serde_json::to_string(suggestions)?)
+                .bind(message_id)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    pub async fn get_thread_messages(&self, thread_id: &str) -> anyhow::Result<Vec<crate::models::Message>> {
+        with_pool!(self, pool => {
+            let messages = sqlx::query_as::<_, crate::models::Message>(
+                &self.sql("SELECT id, thread_id, role, content, status, coalesce(suggestions, '[]') AS suggestions, created_at FROM messages WHERE thread_id = ? ORDER BY created_at ASC")
+            )
+            .bind(thread_id)
+            .fetch_all(pool)
+            .await?;
+            Ok(messages)
+        })
+    }
+
+    pub async fn last_user_message(&self, thread_id: &str) -> anyhow::Result<Option<crate::models::Message>> {
+        with_pool!(self, pool => {
+            let message = sqlx::query_as::<_, crate::models::Message>(
+                &self.sql("SELECT id, thread_id, role, content, status, coalesce(suggestions, '[]') AS suggestions, created_at FROM messages WHERE thread_id = ? AND role = 'user' ORDER BY created_at DESC, id DESC LIMIT 1")
+            )
+            .bind(thread_id)
+            .fetch_optional(pool)
+            .await?;
+            Ok(message)
+        })
+    }
+
+    /// Page through a thread's messages newest-first: `offset` skips the most
+    /// recent messages and `limit` caps the page size. The page itself is
+    /// returned in chronological order so it can be rendered directly.
+    pub async fn get_thread_messages_page(&self, thread_id: &str, offset: i64, limit: i64) -> anyhow::Result<Vec<crate::models::Message>> {
+        with_pool!(self, pool => {
+            let mut messages = sqlx::query_as::<_, crate::models::Message>(
+                &self.sql("SELECT id, thread_id, role, content, status, coalesce(suggestions, '[]') AS suggestions, created_at FROM messages WHERE thread_id = ? ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?")
+            )
+            .bind(thread_id)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(pool)
+            .await?;
+            messages.reverse();
+            Ok(messages)
+        })
+    }
+
+    /// Insert or refresh a source. `user_id` records who added it; an existing row keeps its owner.
+    pub async fn insert_source(&self, url: &str, title: &str, content: &str, raw_html: Option<&str>, user_id: Option<i64>) -> anyhow::Result<i64> {
+        with_pool!(self, pool => {
+            let id = sqlx::query_scalar::<_, i64>(
+                &self.sql(r#"
+                INSERT INTO sources (url, title, content, raw_html, user_id, fetched_at)
+                VALUES (?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+                ON CONFLICT(url) DO UPDATE SET
+                    title = excluded.title,
+                    content = excluded.content,
+                    raw_html = coalesce(excluded.raw_html, sources.raw_html),
+                    fetched_at = excluded.fetched_at
+                RETURNING id
+                "#),
+            )
+            .bind(url)
+            .bind(title)
+            .bind(content)
+            .bind(raw_html)
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+
+            Ok(id)
+        })
+    }
+
+    pub async fn get_source(&self, id: i64, user_id: Option<i64>) -> anyhow::Result<Option<Source>> {
+        with_pool!(self, pool => {
+            let source = sqlx::query_as::<_, Source>(
+                &self.sql("SELECT id, url, title, content, created_at FROM sources WHERE id = ?1 AND (?2 IS NULL OR user_id IS NULL OR user_id = ?2)")
+            )
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+
+            Ok(source)
+        })
+    }
+
+    pub async fn list_domain_rules(&self) -> anyhow::Result<Vec<DomainRule>> {
+        with_pool!(self, pool => {
+            let rules = sqlx::query_as::<_, DomainRule>(
+                &self.sql("SELECT id, domain, rule, created_at FROM domain_rules ORDER BY domain")
+            )
+            .fetch_all(pool)
+            .await?;
+            Ok(rules)
+        })
+    }
+
+    /// Add a rule, replacing any existing rule for the same domain.
+    pub async fn upsert_domain_rule(&self, domain: &str, rule: &str) -> anyhow::Result<DomainRule> {
+        with_pool!(self, pool => {
+            let rule = sqlx::query_as::<_, DomainRule>(
+                &self.sql(r#"
+                INSERT INTO domain_rules (domain, rule) VALUES (?, ?)
+                ON CONFLICT(domain) DO UPDATE SET rule = excluded.rule
+                RETURNING id, domain, rule, created_at
+                "#),
+            )
+            .bind(domain)
+            .bind(rule)
+            .fetch_one(pool)
+            .await?;
+            Ok(rule)
+        })
+    }
+
+    /// Returns whether a rule was deleted.
+    pub async fn delete_domain_rule(&self, id: i64) -> anyhow::Result<bool> {
+        with_pool!(self, pool => {
+            let result = sqlx::query(&self.sql("DELETE FROM domain_rules WHERE id = ?"))
+                .bind(id)
+                .execute(pool)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    pub async fn create_alert(&self, request: &crate::models::AlertRequest, thread_id: &str, user_id: Option<i64>) -> anyhow::Result<Alert> {
+        with_pool!(self, pool => {
+            let alert = sqlx::query_as::<_, Alert>(
+                &self.sql("INSERT INTO alerts (query, schedule, thread_id, search_provider, time_range, user_id) VALUES (?, ?, ?, ?, ?, ?) \
+                 RETURNING id, query, schedule, thread_id, search_provider, time_range, last_run_at, created_at")
+            )
+            .bind(request.query.trim())
+            .bind(request.schedule.trim())
+            .bind(thread_id)
+            .bind(&request.search_provider)
+            .bind(request.time_range.map(|r| r.as_str()))
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+            Ok(alert)
+        })
+    }
+
+    /// Alerts owned by `user_id`; `None` (auth disabled, or the scheduler) lists all of them.
+    pub async fn list_alerts(&self, user_id: Option<i64>) -> anyhow::Result<Vec<Alert>> {
+        with_pool!(self, pool => {
+            let alerts = sqlx::query_as::<_, Alert>(
+                &self.sql("SELECT id, query, schedule, thread_id, search_provider, time_range, last_run_at, created_at \
+                 FROM alerts WHERE ?1 IS NULL OR user_id = ?1 ORDER BY id")
+            )
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+            Ok(alerts)
+        })
+    }
+
+    pub async fn get_alert(&self, id: i64, user_id: Option<i64>) -> anyhow::Result<Option<Alert>> {
+        with_pool!(self, pool => {
+            let alert = sqlx::query_as::<_, Alert>(
+                &self.sql("SELECT id, query, schedule, thread_id, search_provider, time_range, last_run_at, created_at \
+                 FROM alerts WHERE id = ?1 AND (?2 IS NULL OR user_id = ?2)")
+            )
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+            Ok(alert)
+        })
+    }
+
+    /// Returns false when no such alert exists for the user.
+    pub async fn delete_alert(&self, id: i64, user_id: Option<i64>) -> anyhow::Result<bool> {
+        with_pool!(self, pool => {
+            let mut tx = pool.begin().await?;
+            let result = sqlx::query(&self.sql("DELETE FROM alerts WHERE id = ?1 AND (?2 IS NULL OR user_id = ?2)"))
+                .bind(id)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&self.sql("DELETE FROM alert_seen_urls WHERE alert_id = ?"))
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    pub async fn set_alert_last_run(&self, id: i64, at: DateTime<Utc>) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("UPDATE alerts SET last_run_at = ? WHERE id = ?"))
+                .bind(at)
+                .bind(id)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Remember `urls` for an alert, returning the ones it hadn't seen before.
+    pub async fn record_alert_urls(&self, alert_id: i64, urls: &[String]) -> anyhow::Result<Vec<String>> {
+        with_pool!(self, pool => {
+            let mut fresh = Vec::new();
+            for url in urls {
+                let inserted = sqlx::query(&self.sql("INSERT INTO alert_seen_urls (alert_id, url) VALUES (?, ?) ON CONFLICT DO NOTHING"))
+                    .bind(alert_id)
+                    .bind(url)
+                    .execute(pool)
+                    .await?
+                    .rows_affected();
+                if inserted > 0 {
+                    fresh.push(url.clone());
+                }
+            }
+            Ok(fresh)
+        })
+    }
+
+    pub async fn set_thread_unread(&self, thread_id: &str, unread: bool) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("UPDATE threads SET unread = ? WHERE id = ?"))
+                .bind(unread)
+                .bind(thread_id)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    pub async fn get_cached_page(&self, url: &str) -> anyhow::Result<Option<CachedPage>> {
+        with_pool!(self, pool => {
+            let page = sqlx::query_as::<_, CachedPage>(
+                &self.sql("SELECT final_url, html, content_type, etag, last_modified, fetched_at FROM page_cache WHERE url = ?")
+            )
+            .bind(url)
+            .fetch_optional(pool)
+            .await?;
+            Ok(page)
+        })
+    }
+
+    /// Store a freshly downloaded page, dropping entries older than `retention`.
+    pub async fn store_cached_page(&self, url: &str, page: &CachedPage, retention: chrono::Duration) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("DELETE FROM page_cache WHERE fetched_at < ?"))
+                .bind(Utc::now() - retention)
+                .execute(pool)
+                .await?;
+            sqlx::query(
+                &self.sql(r#"
+                INSERT INTO page_cache (url, final_url, html, content_type, etag, last_modified, fetched_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(url) DO UPDATE SET
+                    final_url = excluded.final_url,
+                    html = excluded.html,
+                    content_type = excluded.content_type,
+                    etag = excluded.etag,
+                    last_modified = excluded.last_modified,
+                    fetched_at = excluded.fetched_at
+                "#),
+            )
+            .bind(url)
+            .bind(&page.final_url)
+            .bind(&page.html)
+            .bind(&page.content_type)
+            .bind(&page.etag)
+            .bind(&page.last_modified)
+            .bind(page.fetched_at)
+            .execute(pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    /// Mark a cached page as fresh again after a `304 Not Modified`.
+    pub async fn touch_cached_page(&self, url: &str) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("UPDATE page_cache SET fetched_at = ? WHERE url = ?"))
+                .bind(Utc::now())
+                .bind(url)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    pub async fn get_source_raw_html(&self, id: i64) -> anyhow::Result<Option<String>> {
+        with_pool!(self, pool => {
+            let html = sqlx::query_scalar::<_, Option<String>>(
+                &self.sql("SELECT raw_html FROM sources WHERE id = ?")
+            )
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+            Ok(html.flatten())
+        })
+    }
+
+    pub async fn update_source_content(&self, id: i64, content: &str) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql("UPDATE sources SET content = ? WHERE id = ?"))
+                .bind(content)
+                .bind(id)
+                .execute(pool)
+                .await?;
+
+            Ok(())
+        })
+    }
+
+    pub async fn get_sources(&self, limit: i64, user_id: Option<i64>) -> anyhow::Result<Vec<Source>> {
+        with_pool!(self, pool => {
+            let sources = sqlx::query_as::<_, Source>(
+                &self.sql("SELECT id, url, title, content, created_at FROM sources WHERE ?1 IS NULL OR user_id IS NULL OR user_id = ?1 ORDER BY created_at DESC LIMIT ?2")
+            )
+            .bind(user_id)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+
+            Ok(sources)
+        })
+    }
+
+    pub async fn list_source_urls(&self, domain: Option<&str>, offset: i64, limit: i64, user_id: Option<i64>) -> anyhow::Result<Vec<SourceUrl>> {
+        with_pool!(self, pool => {
+            // Match the host exactly or as a subdomain, with or without a path
+            let domain = domain.map(|d| d.trim().trim_start_matches("www.").to_lowercase());
+            let urls = sqlx::query_as::<_, SourceUrl>(
+                &self.sql(r#"
+                SELECT url, title, coalesce(fetched_at, created_at) AS fetched_at FROM sources
+                WHERE (?1 IS NULL
+                   OR lower(url) LIKE '%://' || ?1 || '/%'
+                   OR lower(url) LIKE '%://' || ?1
+                   OR lower(url) LIKE '%.' || ?1 || '/%'
+                   OR lower(url) LIKE '%.' || ?1)
+                  AND (?4 IS NULL OR user_id IS NULL OR user_id = ?4)
+                ORDER BY created_at DESC, id DESC
+                LIMIT ?2 OFFSET ?3
+                "#)
+            )
+            .bind(domain)
+            .bind(limit)
+            .bind(offset)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+            Ok(urls)
+        })
+    }
+
+    pub async fn search_sources(&self, query: &str, limit: i64, user_id: Option<i64>) -> anyhow::Result<Vec<Source>> {
+        with_pool!(self, pool => {
+            let sources = sqlx::query_as::<_, Source>(
+                &self.sql("SELECT id, url, title, content, created_at FROM sources \
+                 WHERE (content LIKE ?1 OR title LIKE ?1) AND (?3 IS NULL OR user_id IS NULL OR user_id = ?3) \
+                 ORDER BY created_at DESC LIMIT ?2")
+            )
+            .bind(format!("%{}%", query))
+            .bind(limit)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+            Ok(sources)
+        })
+    }
+
+    /// Sources with content but no embeddings for `model`, newest first.
+    pub async fn sources_missing_embeddings(&self, model: &str, limit: i64) -> anyhow::Result<Vec<(i64, String)>> {
+        with_pool!(self, pool => {
+            let rows = sqlx::query_as::<_, (i64, String)>(
+                &self.sql("SELECT s.id, s.content FROM sources s \
+                 WHERE s.content != '' AND NOT EXISTS (SELECT 1 FROM embeddings e WHERE e.source_id = s.id AND e.model = ?) \
+                 ORDER BY s.id DESC LIMIT ?")
+            )
+            .bind(model)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+            Ok(rows)
+        })
+    }
+
+    /// Let the database refresh its query planner statistics.
+    pub async fn optimize(&self) -> anyhow::Result<()> {
+        match &self.pool {
+            Pool::Sqlite(pool) => {
+                sqlx::query("PRAGMA optimize").execute(pool).await?;
+            }
+            Pool::Postgres(pool) => {
+                sqlx::query("ANALYZE").execute(pool).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete expired sessions and cached pages older than `page_retention`.
+    /// Returns how many of each were removed.
+    pub async fn prune_expired(&self, page_retention: chrono::Duration) -> anyhow::Result<(u64, u64)> {
+        with_pool!(self, pool => {
+            let sessions = sqlx::query(&self.sql("DELETE FROM sessions WHERE expires_at <= ?"))
+                .bind(Utc::now())
+                .execute(pool)
+                .await?
+                .rows_affected();
+            let pages = sqlx::query(&self.sql("DELETE FROM page_cache WHERE fetched_at < ?"))
+                .bind(Utc::now() - page_retention)
+                .execute(pool)
+                .await?
+                .rows_affected();
+            Ok((sessions, pages))
+        })
+    }
+
+    /// Web sources among `ids` last fetched more than `max_age` ago. Uploaded documents
+    /// can't be refetched and never go stale.
+    pub async fn stale_sources(&self, ids: &[i64], max_age: chrono::Duration) -> anyhow::Result<Vec<i64>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        with_pool!(self, pool => {
+            let mut query = sqlx::QueryBuilder::new(
+                "SELECT id FROM sources WHERE url LIKE 'http%' AND coalesce(fetched_at, created_at) < "
+            );
+            query.push_bind(timestamp_cutoff(max_age)).push(" AND id IN (");
+            let mut list = query.separated(", ");
+            for id in ids {
+                list.push_bind(*id);
+            }
+            query.push(")");
+            Ok(query.build_query_scalar::<i64>().fetch_all(pool).await?)
+        })
+    }
+
+    /// Delete web sources fetched more than `max_age` ago, then all but the `max_rows` most
+    /// recently fetched, with their embeddings. Uploaded documents are kept. Returns the
+    /// number of sources removed.
+    pub async fn prune_sources(&self, max_age: Option<chrono::Duration>, max_rows: Option<i64>) -> anyhow::Result<u64> {
+        with_pool!(self, pool => {
+            const STALE_SOURCES: &str = "SELECT id FROM sources WHERE url LIKE 'http%' AND \
+                 (coalesce(fetched_at, created_at) < ?1 OR id IN (SELECT id FROM sources WHERE url LIKE 'http%' \
+                 ORDER BY coalesce(fetched_at, created_at) DESC, id DESC LIMIT -1 OFFSET ?2))";
+            // A cutoff of the epoch and an offset past every row disable either limit
+            let cutoff = max_age.map(timestamp_cutoff).unwrap_or_default();
+            let keep = max_rows.unwrap_or(i64::MAX);
+
+            let mut tx = pool.begin().await?;
+            sqlx::query(&self.sql(&format!("DELETE FROM embeddings WHERE source_id IN ({})", STALE_SOURCES)))
+                .bind(cutoff)
+                .bind(keep)
+                .execute(&mut *tx)
+                .await?;
+            let result = sqlx::query(&self.sql(&format!("DELETE FROM sources WHERE id IN ({})", STALE_SOURCES)))
+                .bind(cutoff)
+                .bind(keep)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            Ok(result.rows_affected())
+        })
+    }
+
+    /// Replace a source's chunk embeddings for `model`.
+    pub async fn replace_embeddings(&self, source_id: i64, model: &str, chunks: &[String], vectors: &[Vec<f32>]) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            let mut tx = pool.begin().await?;
+            sqlx::query(&self.sql("DELETE FROM embeddings WHERE source_id = ? AND model = ?"))
+                .bind(source_id)
+                .bind(model)
+                .execute(&mut *tx)
+                .await?;
+            for (index, (chunk, vector)) in chunks.iter().zip(vectors).enumerate() {
+                sqlx::query(&self.sql("INSERT INTO embeddings (source_id, chunk_index, content, model, embedding) VALUES (?, ?, ?, ?, ?)"))
+                    .bind(source_id)
+                    .bind(index as i64)
+                    .bind(chunk)
+                    .bind(model)
+                    .bind(crate::embeddings::vector_to_bytes(vector))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    /// Brute-force cosine search over the chunks embedded with `model`. Returns up to
+    /// `limit` sources scoring at least `min_score`, with content set to the best chunk.
+    pub async fn semantic_search(&self, model: &str, query: &[f32], limit: usize, min_score: f32, user_id: Option<i64>) -> anyhow::Result<Vec<Source>> {
+        with_pool!(self, pool => {
+            let rows = sqlx::query_as::<_, (i64, String, Vec<u8>)>(
+                &self.sql("SELECT e.source_id, e.content, e.embedding FROM embeddings e JOIN sources s ON s.id = e.source_id \
+                 WHERE e.model = ?1 AND (?2 IS NULL OR s.user_id IS NULL OR s.user_id = ?2)")
+            )
+            .bind(model)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+            // Best chunk per source
+            let mut best: std::collections::HashMap<i64, (f32, String)> = std::collections::HashMap::new();
+            for (source_id, content, blob) in rows {
+                let score = crate::embeddings::cosine_similarity(query, &crate::embeddings::vector_from_bytes(&blob));
+                if score < min_score {
+                    continue;
+                }
+                if best.get(&source_id).is_none_or(|(s, _)| score > *s) {
+                    best.insert(source_id, (score, content));
+                }
+            }
+            let mut ranked: Vec<(i64, f32, String)> = best.into_iter().map(|(id, (score, chunk))| (id, score, chunk)).collect();
+            ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+            ranked.truncate(limit);
+
+            let mut sources = Vec::with_capacity(ranked.len());
+            for (id, score, chunk) in ranked {
+                let source = sqlx::query_as::<_, Source>(
+                    &self.sql("SELECT id, url, title, content, created_at FROM sources WHERE id = ?")
+                )
+                .bind(id)
+                .fetch_optional(pool)
+                .await?;
+                if let Some(mut source) = source {
+                    tracing::debug!("Semantic match {:.3}: {}", score, source.url);
+                    source.content = chunk;
+                    sources.push(source);
+                }
+            }
+            Ok(sources)
+        })
     }
 
     pub async fn update_provider_limits(
@@ -198,186 +1199,245 @@ impl Database {
         limit_min: Option<i64>, 
         limit_day: Option<i64>
     ) -> anyhow::Result<()> {
-        let provider_str = provider.as_str();
+        with_pool!(self, pool => {
+            let provider_str = provider.as_str();
         
-        let row = sqlx::query_as::<_, ProviderMetricsRow>(
-            "SELECT req_min, req_day, req_month, last_reset_min, last_reset_day, last_reset_month, limit_min, limit_day, limit_month FROM provider_metrics WHERE provider = ?"
-        )
-        .bind(provider_str)
-        .fetch_optional(&self.pool)
-        .await?;
+            let row = sqlx::query_as::<_, ProviderMetricsRow>(
+                &self.sql("SELECT req_min, req_day, req_month, last_reset_min, last_reset_day, last_reset_month, limit_min, limit_day, limit_month, override_min, override_day, override_month FROM provider_metrics WHERE provider = ?")
+            )
+            .bind(provider_str)
+            .fetch_optional(pool)
+            .await?;
         
-        let (default_limit_min, default_limit_day, _) = self.get_default_limits(provider);
+            let (default_limit_min, default_limit_day, _) = Self::default_limits(provider);
         
-        let current_limit_min = limit_min
-            .or(row.as_ref().and_then(|r| r.limit_min))
-            .unwrap_or(default_limit_min);
+            let current_limit_min = limit_min
+                .or(row.as_ref().and_then(|r| r.limit_min))
+                .unwrap_or(default_limit_min);
             
-        let current_limit_day = limit_day
-            .or(row.as_ref().and_then(|r| r.limit_day))
-            .unwrap_or(default_limit_day);
+            let current_limit_day = limit_day
+                .or(row.as_ref().and_then(|r| r.limit_day))
+                .unwrap_or(default_limit_day);
             
-        let new_req_min = requests_remaining_min.map(|rem| current_limit_min.saturating_sub(rem));
-        let new_req_day = requests_remaining_day.map(|rem| current_limit_day.saturating_sub(rem));
+            let new_req_min = requests_remaining_min.map(|rem| current_limit_min.saturating_sub(rem));
+            let new_req_day = requests_remaining_day.map(|rem| current_limit_day.saturating_sub(rem));
         
-        let mut sql = "UPDATE provider_metrics SET ".to_string();
-        let mut updates = Vec::new();
-        
-        if let Some(val) = new_req_min { updates.push(format!("req_min = {}", val)); }
-        if let Some(val) = new_req_day { updates.push(format!("req_day = {}", val)); }
-        if let Some(val) = limit_min { updates.push(format!("limit_min = {}", val)); }
-        if let Some(val) = limit_day { updates.push(format!("limit_day = {}", val)); }
-        
-        if updates.is_empty() {
-            return Ok(());
-        }
+            let updates = [
+                ("req_min", new_req_min),
+                ("req_day", new_req_day),
+                ("limit_min", limit_min),
+                ("limit_day", limit_day),
+            ];
+            if updates.iter().all(|(_, val)| val.is_none()) {
+                return Ok(());
+            }
         
-        sql.push_str(&updates.join(", "));
-        sql.push_str(" WHERE provider = ?");
+            let mut query = sqlx::QueryBuilder::new("UPDATE provider_metrics SET ");
+            let mut set = query.separated(", ");
+            for (column, val) in updates {
+                if let Some(val) = val {
+                    set.push(format!("{} = ", column)).push_bind_unseparated(val);
+                }
+            }
+            query.push(" WHERE provider = ").push_bind(provider_str);
         
-        sqlx::query(&sql)
-            .bind(provider_str)
-            .execute(&self.pool)
-            .await?;
+            query.build().execute(pool).await?;
             
-        Ok(())
+            Ok(())
+        })
     }
 
-    fn get_default_limits(&self, provider: &ProviderType) -> (i64, i64, i64) {
+    /// Built-in (minute, day, month) request limits of each LLM provider's free tier
+    fn default_limits(provider: &ProviderType) -> (i64, i64, i64) {
         match provider {
             ProviderType::OpenRouter => (20, 50, 1000000),
             ProviderType::Groq => (30, 14400, 1000000),
             ProviderType::Cerebras => (1000, 1000, 1000000),
             ProviderType::Cohere => (20, 1000000, 1000),
             ProviderType::Pollinations => (1000, 1000, 1000000), // Defaulting to high daily allowance
+            ProviderType::Custom => (1000000, 1000000, 1000000), // Self-hosted: effectively unlimited
         }
     }
 
+    /// When a provider has used up its daily allowance, the time its day window resets.
+    /// `None` means the provider can still take requests today.
+    pub async fn day_limit_reset(&self, provider: &ProviderType) -> anyhow::Result<Option<DateTime<Utc>>> {
+        with_pool!(self, pool => {
+            let row = sqlx::query_as::<_, ProviderMetricsRow>(
+                &self.sql("SELECT req_min, req_day, req_month, last_reset_min, last_reset_day, last_reset_month, limit_min, limit_day, limit_month, override_min, override_day, override_month FROM provider_metrics WHERE provider = ?")
+            )
+            .bind(provider.as_str())
+            .fetch_optional(pool)
+            .await?;
+
+            let Some(row) = row else { return Ok(None) };
+            let (_, limit_day, _) = row.effective_limits(Self::default_limits(provider));
+            if row.req_day.unwrap_or(0) < limit_day {
+                return Ok(None);
+            }
+
+            // Same windows as check_rate_limit: rolling 24h for Groq/Cerebras, UTC days otherwise
+            let last_reset = row.last_reset_day.unwrap_or_default();
+            let resets_at = match provider {
+                ProviderType::Groq | ProviderType::Cerebras => last_reset + chrono::Duration::hours(24),
+                _ => (last_reset.date_naive() + chrono::Days::new(1))
+                    .and_hms_opt(0, 0, 0)
+                    .map(|t| Utc.from_utc_datetime(&t))
+                    .unwrap_or(last_reset),
+            };
+
+            Ok((resets_at > Utc::now()).then_some(resets_at))
+        })
+    }
+
     pub async fn check_rate_limit(&self, provider: &ProviderType) -> anyhow::Result<bool> {
-        let now = Utc::now();
-        let provider_str = provider.as_str();
+        with_pool!(self, pool => {
+            let now = Utc::now();
+            let provider_str = provider.as_str();
 
-        let row = sqlx::query_as::<_, ProviderMetricsRow>(
-            "SELECT req_min, req_day, req_month, last_reset_min, last_reset_day, last_reset_month, limit_min, limit_day, limit_month FROM provider_metrics WHERE provider = ?"
-        )
-        .bind(provider_str)
-        .fetch_optional(&self.pool)
-        .await?;
+            let row = sqlx::query_as::<_, ProviderMetricsRow>(
+                &self.sql("SELECT req_min, req_day, req_month, last_reset_min, last_reset_day, last_reset_month, limit_min, limit_day, limit_month, override_min, override_day, override_month FROM provider_metrics WHERE provider = ?")
+            )
+            .bind(provider_str)
+            .fetch_optional(pool)
+            .await?;
 
-        let (mut req_min, mut req_day, mut req_month) = if let Some(r) = &row {
-            (r.req_min.unwrap_or(0), r.req_day.unwrap_or(0), r.req_month.unwrap_or(0))
-        } else {
-            (0, 0, 0)
-        };
+            let (mut req_min, mut req_day, mut req_month) = if let Some(r) = &row {
+                (r.req_min.unwrap_or(0), r.req_day.unwrap_or(0), r.req_month.unwrap_or(0))
+            } else {
+                (0, 0, 0)
+            };
         
-        fn to_utc(dt: Option<chrono::NaiveDateTime>) -> DateTime<Utc> {
-             match dt {
-                 Some(t) => Utc.from_utc_datetime(&t),
-                 None => DateTime::from_timestamp(0, 0).unwrap_or_default(),
-             }
-        }
+            fn to_utc(dt: Option<DateTime<Utc>>) -> DateTime<Utc> {
+                 dt.unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default())
+            }
 
-        let last_reset_min = row.as_ref().map(|r| to_utc(r.last_reset_min)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
-        let last_reset_day = row.as_ref().map(|r| to_utc(r.last_reset_day)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
-        let last_reset_month = row.as_ref().map(|r| to_utc(r.last_reset_month)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
+            let last_reset_min = row.as_ref().map(|r| to_utc(r.last_reset_min)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
+            let last_reset_day = row.as_ref().map(|r| to_utc(r.last_reset_day)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
+            let last_reset_month = row.as_ref().map(|r| to_utc(r.last_reset_month)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
 
-        let (def_min, def_day, def_month) = self.get_default_limits(provider);
+            let (def_min, def_day, def_month) = Self::default_limits(provider);
         
-        let limit_min = row.as_ref().and_then(|r| r.limit_min).unwrap_or(def_min);
-        let limit_day = row.as_ref().and_then(|r| r.limit_day).unwrap_or(def_day);
-        let limit_month = row.as_ref().and_then(|r| r.limit_month).unwrap_or(def_month);
+            // Stored limits are what the provider reported; overrides only apply to the check
+            let stored_min = row.as_ref().and_then(|r| r.limit_min).unwrap_or(def_min);
+            let stored_day = row.as_ref().and_then(|r| r.limit_day).unwrap_or(def_day);
+            let stored_month = row.as_ref().and_then(|r| r.limit_month).unwrap_or(def_month);
+            let (limit_min, limit_day, limit_month) = row
+                .as_ref()
+                .map(|r| r.effective_limits((def_min, def_day, def_month)))
+                .unwrap_or((def_min, def_day, def_month));
 
-        let mut needs_reset_min = false;
-        let mut needs_reset_day = false;
-        let mut needs_reset_month = false;
+            let mut needs_reset_min = false;
+            let mut needs_reset_day = false;
+            let mut needs_reset_month = false;
 
-        if now.signed_duration_since(last_reset_min).num_seconds() >= 60 {
-            needs_reset_min = true;
-        }
+            if now.signed_duration_since(last_reset_min).num_seconds() >= 60 {
+                needs_reset_min = true;
+            }
 
-        match provider {
-            ProviderType::OpenRouter | ProviderType::Pollinations => {
-                if now.date_naive() > last_reset_day.date_naive() {
-                    needs_reset_day = true;
+            match provider {
+                ProviderType::OpenRouter | ProviderType::Pollinations => {
+                    if now.date_naive() > last_reset_day.date_naive() {
+                        needs_reset_day = true;
+                    }
                 }
-            }
-            ProviderType::Groq | ProviderType::Cerebras => {
-                 if now.signed_duration_since(last_reset_day).num_hours() >= 24 {
-                    needs_reset_day = true;
+                ProviderType::Groq | ProviderType::Cerebras => {
+                     if now.signed_duration_since(last_reset_day).num_hours() >= 24 {
+                        needs_reset_day = true;
+                    }
                 }
-            }
-            _ => {
-                 if now.date_naive() > last_reset_day.date_naive() {
-                    needs_reset_day = true;
+                _ => {
+                     if now.date_naive() > last_reset_day.date_naive() {
+                        needs_reset_day = true;
+                    }
                 }
             }
-        }
 
-        if provider == &ProviderType::Cohere {
-            if now.month() != last_reset_month.month() || now.year() != last_reset_month.year() {
+            if provider == &ProviderType::Cohere
+                && (now.month() != last_reset_month.month() || now.year() != last_reset_month.year())
+            {
                 needs_reset_month = true;
             }
-        }
 
-        if needs_reset_min { req_min = 0; }
-        if needs_reset_day { req_day = 0; }
-        if needs_reset_month { req_month = 0; }
+            if needs_reset_min { req_min = 0; }
+            if needs_reset_day { req_day = 0; }
+            if needs_reset_month { req_month = 0; }
 
-        if req_min >= limit_min {
-            tracing::warn!("Rate limit exceeded for {} (Minute): {}/{}", provider_str, req_min, limit_min);
-            return Ok(false);
-        }
-        if req_day >= limit_day {
-             tracing::warn!("Rate limit exceeded for {} (Day): {}/{}", provider_str, req_day, limit_day);
-            return Ok(false);
-        }
-        if req_month >= limit_month {
-             tracing::warn!("Rate limit exceeded for {} (Month): {}/{}", provider_str, req_month, limit_month);
-            return Ok(false);
-        }
+            if req_min >= limit_min {
+                tracing::warn!("Rate limit exceeded for {} (Minute): {}/{}", provider_str, req_min, limit_min);
+                return Ok(false);
+            }
+            if req_day >= limit_day {
+                 tracing::warn!("Rate limit exceeded for {} (Day): {}/{}", provider_str, req_day, limit_day);
+                return Ok(false);
+            }
+            if req_month >= limit_month {
+                 tracing::warn!("Rate limit exceeded for {} (Month): {}/{}", provider_str, req_month, limit_month);
+                return Ok(false);
+            }
 
-        req_min += 1;
-        req_day += 1;
-        req_month += 1;
+            req_min += 1;
+            req_day += 1;
+            req_month += 1;
 
-        let new_reset_min = if needs_reset_min { now } else { last_reset_min };
-        let new_reset_day = if needs_reset_day { now } else { last_reset_day };
-        let new_reset_month = if needs_reset_month { now } else { last_reset_month };
+            let new_reset_min = if needs_reset_min { now } else { last_reset_min };
+            let new_reset_day = if needs_reset_day { now } else { last_reset_day };
+            let new_reset_month = if needs_reset_month { now } else { last_reset_month };
 
-        sqlx::query(
-            r#"
-            INSERT INTO provider_metrics (
-                provider, req_min, req_day, req_month, 
-                last_reset_min, last_reset_day, last_reset_month,
-                limit_min, limit_day, limit_month
-            )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT(provider) DO UPDATE SET
-                req_min = excluded.req_min,
-                req_day = excluded.req_day,
-                req_month = excluded.req_month,
-                last_reset_min = excluded.last_reset_min,
-                last_reset_day = excluded.last_reset_day,
-                last_reset_month = excluded.last_reset_month,
-                limit_min = coalesce(excluded.limit_min, provider_metrics.limit_min),
-                limit_day = coalesce(excluded.limit_day, provider_metrics.limit_day),
-                limit_month = coalesce(excluded.limit_month, provider_metrics.limit_month)
-            "#
-        )
-        .bind(provider_str)
-        .bind(req_min)
-        .bind(req_day)
-        .bind(req_month)
-        .bind(new_reset_min)
-        .bind(new_reset_day)
-        .bind(new_reset_month)
-        .bind(limit_min)
-        .bind(limit_day)
-        .bind(limit_month)
-        .execute(&self.pool)
-        .await?;
+            sqlx::query(
+                &self.sql(r#"
+                INSERT INTO provider_metrics (
+                    provider, req_min, req_day, req_month, 
+                    last_reset_min, last_reset_day, last_reset_month,
+                    limit_min, limit_day, limit_month
+                )
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(provider) DO UPDATE SET
+                    req_min = excluded.req_min,
+                    req_day = excluded.req_day,
+                    req_month = excluded.req_month,
+                    last_reset_min = excluded.last_reset_min,
+                    last_reset_day = excluded.last_reset_day,
+                    last_reset_month = excluded.last_reset_month,
+                    limit_min = coalesce(excluded.limit_min, provider_metrics.limit_min),
+                    limit_day = coalesce(excluded.limit_day, provider_metrics.limit_day),
+                    limit_month = coalesce(excluded.limit_month, provider_metrics.limit_month)
+                "#)
+            )
+            .bind(provider_str)
+            .bind(req_min)
+            .bind(req_day)
+            .bind(req_month)
+            .bind(new_reset_min)
+            .bind(new_reset_day)
+            .bind(new_reset_month)
+            .bind(stored_min)
+            .bind(stored_day)
+            .bind(stored_month)
+            .execute(pool)
+            .await?;
 
-        Ok(true)
+            Ok(true)
+        })
+    }
+
+    /// Built-in (minute, day, month) limits of the metered search APIs; a day limit of 0
+    /// leaves days untracked
+    fn search_default_limits(provider_name: &str) -> (i64, i64, i64) {
+        // Brave: 1 req/sec (approx 60/min), 2000/month
+        // Tavily: 1000/month
+        // arXiv: one request every 3 seconds
+        // GitHub search: 10/min anonymous, 30/min with a token
+        // Stack Exchange: 300/day anonymous, 10000/day with an app key
+        let has_var = |name: &str| std::env::var(name).is_ok_and(|v| !v.trim().is_empty());
+        match provider_name {
+            "search:brave" => (60, 0, 2000),
+            "search:tavily" => (1000000, 0, 1000), // No minute limit specified for Tavily, just monthly credits
+            "search:arxiv" => (20, 0, 1000000),
+            "search:github" => (if has_var("GITHUB_TOKEN") { 30 } else { 10 }, 0, 1000000),
+            "search:stackoverflow" => (1000000, if has_var("STACKEXCHANGE_KEY") { 10000 } else { 300 }, 1000000),
+            _ => (1000000, 0, 1000000),
+        }
     }
 
     pub async fn update_search_limits(
@@ -386,145 +1446,455 @@ impl Database {
         used_month: Option<i64>,
         limit_month: Option<i64>,
         used_min: Option<i64>,
+        used_day: Option<i64>,
     ) -> anyhow::Result<()> {
-        let mut sql = "UPDATE provider_metrics SET ".to_string();
-        let mut updates = Vec::new();
-        
-        if let Some(val) = used_month { updates.push(format!("req_month = {}", val)); }
-        if let Some(val) = limit_month { updates.push(format!("limit_month = {}", val)); }
-        if let Some(val) = used_min { updates.push(format!("req_min = {}", val)); }
-        
-        // Also update timestamp to now to prevent immediate local reset if we just got fresh data
-        updates.push("last_reset_month = CASE WHEN last_reset_month IS NULL THEN CURRENT_TIMESTAMP ELSE last_reset_month END".to_string()); 
-        // Actually, if we get fresh usage from API, we trust it. But our local reset logic relies on timestamps.
-        // If API says "100 used this month", we set req_month=100.
-        // If we don't update last_reset_month, our local logic might reset it to 0 if it thinks month changed.
-        // But we shouldn't change last_reset_month unless we know it's a new month.
-        // Let's just update the counters.
+        with_pool!(self, pool => {
+            let mut query = sqlx::QueryBuilder::new("UPDATE provider_metrics SET ");
+            let mut set = query.separated(", ");
+            for (column, val) in [("req_month", used_month), ("limit_month", limit_month), ("req_min", used_min), ("req_day", used_day)] {
+                if let Some(val) = val {
+                    set.push(format!("{} = ", column)).push_bind_unseparated(val);
+                }
+            }
         
-        if updates.is_empty() {
-            return Ok(());
-        }
+            // Also update timestamp to now to prevent immediate local reset if we just got fresh data
+            set.push("last_reset_month = CASE WHEN last_reset_month IS NULL THEN CURRENT_TIMESTAMP ELSE last_reset_month END"); 
+            // Actually, if we get fresh usage from API, we trust it. But our local reset logic relies on timestamps.
+            // If API says "100 used this month", we set req_month=100.
+            // If we don't update last_reset_month, our local logic might reset it to 0 if it thinks month changed.
+            // But we shouldn't change last_reset_month unless we know it's a new month.
+            // Let's just update the counters.
         
-        sql.push_str(&updates.join(", "));
-        sql.push_str(" WHERE provider = ?");
+            query.push(" WHERE provider = ").push_bind(provider_name);
         
-        sqlx::query(&sql)
-            .bind(provider_name)
-            .execute(&self.pool)
-            .await?;
+            query.build().execute(pool).await?;
             
-        Ok(())
+            Ok(())
+        })
     }
 
     pub async fn check_search_rate_limit(&self, provider_name: &str, cost: i64) -> anyhow::Result<bool> {
-        let now = Utc::now();
+        with_pool!(self, pool => {
+            let now = Utc::now();
         
-        let row = sqlx::query_as::<_, ProviderMetricsRow>(
-            "SELECT req_min, req_day, req_month, last_reset_min, last_reset_day, last_reset_month, limit_min, limit_day, limit_month FROM provider_metrics WHERE provider = ?"
-        )
-        .bind(provider_name)
-        .fetch_optional(&self.pool)
-        .await?;
+            let row = sqlx::query_as::<_, ProviderMetricsRow>(
+                &self.sql("SELECT req_min, req_day, req_month, last_reset_min, last_reset_day, last_reset_month, limit_min, limit_day, limit_month, override_min, override_day, override_month FROM provider_metrics WHERE provider = ?")
+            )
+            .bind(provider_name)
+            .fetch_optional(pool)
+            .await?;
 
-        let (mut req_min, mut req_day, mut req_month) = if let Some(r) = &row {
-            (r.req_min.unwrap_or(0), r.req_day.unwrap_or(0), r.req_month.unwrap_or(0))
-        } else {
-            (0, 0, 0)
-        };
+            let (mut req_min, mut req_day, mut req_month) = if let Some(r) = &row {
+                (r.req_min.unwrap_or(0), r.req_day.unwrap_or(0), r.req_month.unwrap_or(0))
+            } else {
+                (0, 0, 0)
+            };
         
-        fn to_utc(dt: Option<chrono::NaiveDateTime>) -> DateTime<Utc> {
-             match dt {
-                 Some(t) => Utc.from_utc_datetime(&t),
-                 None => DateTime::from_timestamp(0, 0).unwrap_or_default(),
-             }
-        }
+            fn to_utc(dt: Option<DateTime<Utc>>) -> DateTime<Utc> {
+                 dt.unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default())
+            }
 
-        let last_reset_min = row.as_ref().map(|r| to_utc(r.last_reset_min)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
-        let last_reset_month = row.as_ref().map(|r| to_utc(r.last_reset_month)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
+            let last_reset_min = row.as_ref().map(|r| to_utc(r.last_reset_min)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
+            let last_reset_day = row.as_ref().map(|r| to_utc(r.last_reset_day)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
+            let last_reset_month = row.as_ref().map(|r| to_utc(r.last_reset_month)).unwrap_or_else(|| DateTime::from_timestamp(0, 0).unwrap_or_default());
 
-        // Define Limits
-        // Brave: 1 req/sec (approx 60/min), 2000/month
-        // Tavily: 1000/month
-        let (limit_min, limit_month) = match provider_name {
-            "search:brave" => (60, 2000), 
-            "search:tavily" => (1000000, 1000), // No minute limit specified for Tavily, just monthly credits
-            _ => (1000000, 1000000),
-        };
+            let (def_min, def_day, def_month) = Self::search_default_limits(provider_name);
+            let limit_min = row.as_ref().and_then(|r| r.override_min).unwrap_or(def_min);
+            let limit_day = row.as_ref().and_then(|r| r.override_day).unwrap_or(def_day);
+            let limit_month = row.as_ref().and_then(|r| r.override_month).unwrap_or(def_month);
 
-        // Reset Logic
-        let mut needs_reset_min = false;
-        let mut needs_reset_month = false;
+            // Reset Logic
+            let mut needs_reset_min = false;
+            let mut needs_reset_month = false;
 
-        // Minute reset (rolling 60s)
-        if now.signed_duration_since(last_reset_min).num_seconds() >= 60 {
-            needs_reset_min = true;
-        }
+            // Minute reset (rolling 60s)
+            if now.signed_duration_since(last_reset_min).num_seconds() >= 60 {
+                needs_reset_min = true;
+            }
+
+            // Day reset (UTC midnight)
+            let needs_reset_day = now.date_naive() > last_reset_day.date_naive();
         
-        // Month Reset (1st of month)
-        if now.month() != last_reset_month.month() || now.year() != last_reset_month.year() {
-            needs_reset_month = true;
-        }
+            // Month Reset (1st of month)
+            if now.month() != last_reset_month.month() || now.year() != last_reset_month.year() {
+                needs_reset_month = true;
+            }
+
+            if needs_reset_min { req_min = 0; }
+            if needs_reset_day { req_day = 0; }
+            if needs_reset_month { req_month = 0; }
+
+            // Check Limits
+            if req_min + cost > limit_min {
+                tracing::warn!("Search rate limit exceeded for {} (Minute): {}/{}", provider_name, req_min, limit_min);
+                return Ok(false);
+            }
+            if limit_day > 0 && req_day + cost > limit_day {
+                tracing::warn!("Search rate limit exceeded for {} (Day): {}/{}", provider_name, req_day, limit_day);
+                return Ok(false);
+            }
+            if req_month + cost > limit_month {
+                 tracing::warn!("Search rate limit exceeded for {} (Month): {}/{}", provider_name, req_month, limit_month);
+                return Ok(false);
+            }
+
+            req_min += cost;
+            req_day += cost;
+            req_month += cost;
 
-        if needs_reset_min { req_min = 0; }
-        if needs_reset_month { req_month = 0; }
+            let new_reset_min = if needs_reset_min { now } else { last_reset_min };
+            let new_reset_day = if needs_reset_day { now } else { last_reset_day };
+            let new_reset_month = if needs_reset_month { now } else { last_reset_month };
 
-        // Check Limits
-        if req_min + cost > limit_min {
-            tracing::warn!("Search rate limit exceeded for {} (Minute): {}/{}", provider_name, req_min, limit_min);
-            return Ok(false);
+            sqlx::query(
+                &self.sql(r#"
+                INSERT INTO provider_metrics (
+                    provider, req_min, req_day, req_month, 
+                    last_reset_min, last_reset_day, last_reset_month,
+                    limit_min, limit_day, limit_month
+                )
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(provider) DO UPDATE SET
+                    req_min = excluded.req_min,
+                    req_day = excluded.req_day,
+                    req_month = excluded.req_month,
+                    last_reset_min = excluded.last_reset_min,
+                    last_reset_day = excluded.last_reset_day,
+                    last_reset_month = excluded.last_reset_month
+                "#)
+            )
+            .bind(provider_name)
+            .bind(req_min)
+            .bind(req_day)
+            .bind(req_month)
+            .bind(new_reset_min)
+            .bind(new_reset_day)
+            .bind(new_reset_month)
+            .bind(def_min)
+            .bind(def_day)
+            .bind(def_month)
+            .execute(pool)
+            .await?;
+
+            Ok(true)
+        })
+    }
+
+    /// Limits in force for every LLM provider and every metered search API seen so far,
+    /// with any operator overrides
+    pub async fn provider_limits(&self) -> anyhow::Result<Vec<crate::models::ProviderLimits>> {
+        #[derive(FromRow)]
+        struct LimitsRow {
+            provider: String,
+            limit_min: Option<i64>,
+            limit_day: Option<i64>,
+            limit_month: Option<i64>,
+            override_min: Option<i64>,
+            override_day: Option<i64>,
+            override_month: Option<i64>,
         }
-        if req_month + cost > limit_month {
-             tracing::warn!("Search rate limit exceeded for {} (Month): {}/{}", provider_name, req_month, limit_month);
-            return Ok(false);
+
+        let rows: Vec<LimitsRow> = with_pool!(self, pool => {
+            sqlx::query_as::<_, LimitsRow>(
+                &self.sql("SELECT provider, limit_min, limit_day, limit_month, override_min, override_day, override_month FROM provider_metrics")
+            )
+            .fetch_all(pool)
+            .await?
+        });
+
+        let mut names: Vec<String> = ProviderType::ALL.iter().map(|p| p.as_str().to_string()).collect();
+        names.extend(["search:brave", "search:tavily", "search:arxiv", "search:github", "search:stackoverflow"].map(String::from));
+        for row in &rows {
+            if !names.contains(&row.provider) {
+                names.push(row.provider.clone());
+            }
         }
 
-        req_min += cost;
-        req_month += cost;
+        Ok(names
+            .into_iter()
+            .map(|name| {
+                let defaults = match ProviderType::from_str(&name) {
+                    Some(provider) => Self::default_limits(&provider),
+                    None => Self::search_default_limits(&name),
+                };
+                let row = rows.iter().find(|r| r.provider == name);
+                let column = |get: fn(&LimitsRow) -> Option<i64>| row.and_then(get);
+                let override_min = column(|r| r.override_min);
+                let override_day = column(|r| r.override_day);
+                let override_month = column(|r| r.override_month);
+                crate::models::ProviderLimits {
+                    limit_min: override_min.or(column(|r| r.limit_min)).unwrap_or(defaults.0),
+                    limit_day: override_day.or(column(|r| r.limit_day)).unwrap_or(defaults.1),
+                    limit_month: override_month.or(column(|r| r.limit_month)).unwrap_or(defaults.2),
+                    override_min,
+                    override_day,
+                    override_month,
+                    provider: name,
+                }
+            })
+            .collect())
+    }
 
-        let new_reset_min = if needs_reset_min { now } else { last_reset_min };
-        let new_reset_month = if needs_reset_month { now } else { last_reset_month };
-        // We don't track daily for search currently, just reuse existing column with no updates or dummy
-        let new_reset_day = row.as_ref().map(|r| to_utc(r.last_reset_day)).unwrap_or(now); 
+    /// Replace a provider's limit overrides; `None` drops an override so the reported or
+    /// default limit applies again
+    pub async fn set_provider_limit_overrides(
+        &self,
+        provider: &str,
+        override_min: Option<i64>,
+        override_day: Option<i64>,
+        override_month: Option<i64>,
+    ) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(
+                &self.sql(r#"
+                INSERT INTO provider_metrics (provider, override_min, override_day, override_month)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT(provider) DO UPDATE SET
+                    override_min = excluded.override_min,
+                    override_day = excluded.override_day,
+                    override_month = excluded.override_month
+                "#)
+            )
+            .bind(provider)
+            .bind(override_min)
+            .bind(override_day)
+            .bind(override_month)
+            .execute(pool)
+            .await?;
 
-        sqlx::query(
-            r#"
-            INSERT INTO provider_metrics (
-                provider, req_min, req_day, req_month, 
-                last_reset_min, last_reset_day, last_reset_month,
-                limit_min, limit_day, limit_month
-            )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT(provider) DO UPDATE SET
-                req_min = excluded.req_min,
-                req_month = excluded.req_month,
-                last_reset_min = excluded.last_reset_min,
-                last_reset_month = excluded.last_reset_month
-            "#
-        )
-        .bind(provider_name)
-        .bind(req_min)
-        .bind(req_day) // Keep existing day count
-        .bind(req_month)
-        .bind(new_reset_min)
-        .bind(new_reset_day)
-        .bind(new_reset_month)
-        .bind(limit_min)
-        .bind(0) // Dummy daily limit
-        .bind(limit_month)
-        .execute(&self.pool)
-        .await?;
+            Ok(())
+        })
+    }
+
+    /// Accumulate one chat completion call. Latency is only counted for successful calls;
+    /// tokens only when the provider reported usage.
+    pub async fn record_provider_call(&self, provider: &ProviderType, latency_ms: i64, tokens: Option<i64>, success: bool) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            let latency_ms = if success { latency_ms } else { 0 };
+            sqlx::query(
+                &self.sql(r#"
+                INSERT INTO provider_stats (provider, total_requests, error_count, total_latency_ms, total_tokens, token_requests)
+                VALUES (?, 1, ?, ?, ?, ?)
+                ON CONFLICT(provider) DO UPDATE SET
+                    total_requests = provider_stats.total_requests + 1,
+                    error_count = provider_stats.error_count + excluded.error_count,
+                    total_latency_ms = provider_stats.total_latency_ms + excluded.total_latency_ms,
+                    total_tokens = provider_stats.total_tokens + excluded.total_tokens,
+                    token_requests = provider_stats.token_requests + excluded.token_requests
+                "#)
+            )
+            .bind(provider.as_str())
+            .bind(i64::from(!success))
+            .bind(latency_ms)
+            .bind(tokens.unwrap_or(0))
+            .bind(i64::from(tokens.is_some()))
+            .execute(pool)
+            .await?;
+
+            Ok(())
+        })
+    }
+
+    pub async fn get_provider_stats(&self) -> anyhow::Result<Vec<crate::models::ProviderStats>> {
+        with_pool!(self, pool => {
+            let stats = sqlx::query_as::<_, crate::models::ProviderStats>(
+                &self.sql(r#"
+                SELECT provider, total_requests, error_count, total_latency_ms, total_tokens,
+                    CAST(total_latency_ms AS DOUBLE PRECISION)
+                        / CASE WHEN total_requests - error_count > 1 THEN total_requests - error_count ELSE 1 END AS avg_latency_ms,
+                    CAST(total_tokens AS DOUBLE PRECISION)
+                        / CASE WHEN token_requests > 1 THEN token_requests ELSE 1 END AS avg_tokens
+                FROM provider_stats
+                ORDER BY provider
+                "#)
+            )
+            .fetch_all(pool)
+            .await?;
+
+            Ok(stats)
+        })
+    }
+
+    /// Log the token usage one completion reported
+    pub async fn record_usage(
+        &self,
+        provider: &str,
+        model: &str,
+        thread_id: Option<&str>,
+        user_id: Option<i64>,
+        prompt_tokens: i64,
+        completion_tokens: i64,
+    ) -> anyhow::Result<()> {
+        with_pool!(self, pool => {
+            sqlx::query(&self.sql(
+                "INSERT INTO usage_log (provider, model, thread_id, user_id, prompt_tokens, completion_tokens) VALUES (?, ?, ?, ?, ?, ?)"
+            ))
+            .bind(provider)
+            .bind(model)
+            .bind(thread_id)
+            .bind(user_id)
+            .bind(prompt_tokens)
+            .bind(completion_tokens)
+            .execute(pool)
+            .await?;
+
+            Ok(())
+        })
+    }
+
+    /// Token usage per day (or month when `monthly`) and provider/model over the last
+    /// `lookback`, newest period first
+    pub async fn usage_by_period(
+        &self,
+        monthly: bool,
+        lookback: chrono::Duration,
+        thread_id: Option<&str>,
+        user_id: Option<i64>,
+    ) -> anyhow::Result<Vec<crate::models::UsageRow>> {
+        // `YYYY-MM-DD` or `YYYY-MM` prefix of the timestamp
+        let period_len: i32 = if monthly { 7 } else { 10 };
+        with_pool!(self, pool => {
+            let rows = sqlx::query_as::<_, crate::models::UsageRow>(
+                &self.sql(r#"
+                SELECT substr(CAST(created_at AS TEXT), 1, ?1) AS period, provider, model,
+                    COUNT(*) AS requests,
+                    CAST(SUM(prompt_tokens) AS BIGINT) AS prompt_tokens,
+                    CAST(SUM(completion_tokens) AS BIGINT) AS completion_tokens,
+                    CAST(SUM(prompt_tokens + completion_tokens) AS BIGINT) AS total_tokens
+                FROM usage_log
+                WHERE created_at >= ?2
+                    AND (?3 IS NULL OR thread_id = ?3)
+                    AND (?4 IS NULL OR user_id = ?4)
+                GROUP BY 1, provider, model
+                ORDER BY 1 DESC, total_tokens DESC
+                "#)
+            )
+            .bind(period_len)
+            .bind(timestamp_cutoff(lookback))
+            .bind(thread_id)
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
 
-        Ok(true)
+            Ok(rows)
+        })
+    }
+
+    /// Threads that used the most tokens over the last `lookback`
+    pub async fn usage_by_thread(&self, lookback: chrono::Duration, limit: i64, user_id: Option<i64>) -> anyhow::Result<Vec<crate::models::ThreadUsage>> {
+        with_pool!(self, pool => {
+            let rows = sqlx::query_as::<_, crate::models::ThreadUsage>(
+                &self.sql(r#"
+                SELECT u.thread_id AS thread_id, t.title AS title,
+                    COUNT(*) AS requests,
+                    CAST(SUM(u.prompt_tokens) AS BIGINT) AS prompt_tokens,
+                    CAST(SUM(u.completion_tokens) AS BIGINT) AS completion_tokens,
+                    CAST(SUM(u.prompt_tokens + u.completion_tokens) AS BIGINT) AS total_tokens
+                FROM usage_log u
+                LEFT JOIN threads t ON t.id = u.thread_id
+                WHERE u.thread_id IS NOT NULL
+                    AND u.created_at >= ?1
+                    AND (?2 IS NULL OR u.user_id = ?2)
+                GROUP BY u.thread_id, t.title
+                ORDER BY total_tokens DESC
+                LIMIT ?3
+                "#)
+            )
+            .bind(timestamp_cutoff(lookback))
+            .bind(user_id)
+            .bind(limit)
+            .fetch_all(pool)
+            .await?;
+
+            Ok(rows)
+        })
     }
 
     pub async fn get_all_provider_metrics(&self) -> anyhow::Result<Vec<crate::models::ProviderMetrics>> {
-        let metrics = sqlx::query_as::<_, crate::models::ProviderMetrics>(
-            "SELECT provider, req_min, req_day, req_month, limit_min, limit_day, limit_month FROM provider_metrics"
-        )
-        .fetch_all(&self.pool)
-        .await?;
+        with_pool!(self, pool => {
+            let metrics = sqlx::query_as::<_, crate::models::ProviderMetrics>(
+                &self.sql(r#"
+                SELECT provider, req_min, req_day, req_month,
+                    coalesce(override_min, limit_min) AS limit_min,
+                    coalesce(override_day, limit_day) AS limit_day,
+                    coalesce(override_month, limit_month) AS limit_month
+                FROM provider_metrics
+                "#)
+            )
+            .fetch_all(pool)
+            .await?;
         
-        Ok(metrics)
+            Ok(metrics)
+        })
     }
-}
\ No newline at end of file
+}
+
+/// `max_age` before now, in whole seconds so SQLite compares it correctly against
+/// `CURRENT_TIMESTAMP` text.
+fn timestamp_cutoff(max_age: chrono::Duration) -> chrono::NaiveDateTime {
+    let cutoff = (Utc::now() - max_age).naive_utc();
+    cutoff.with_nanosecond(0).unwrap_or(cutoff)
+}
+
+/// Rewrite SQLite-flavoured SQL for PostgreSQL: `?`/`?N` placeholders become `$N`,
+/// `LIKE` becomes the case-insensitive `ILIKE` and `LIMIT -1` means no limit.
+fn postgres_sql(sql: &str) -> String {
+    static KEYWORDS: std::sync::OnceLock<[(regex::Regex, &'static str); 2]> = std::sync::OnceLock::new();
+    let keywords = KEYWORDS.get_or_init(|| [
+        (regex::Regex::new(r"\bLIKE\b").unwrap(), "ILIKE"),
+        (regex::Regex::new(r"\bLIMIT -1\b").unwrap(), "LIMIT ALL"),
+    ]);
+
+    let mut out = String::with_capacity(sql.len() + 16);
+    let mut chars = sql.chars().peekable();
+    let mut next_param = 1;
+    let mut in_literal = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_literal = !in_literal;
+                out.push(c);
+            }
+            '?' if !in_literal => {
+                let mut digits = String::new();
+                while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    chars.next();
+                }
+                if digits.is_empty() {
+                    digits = next_param.to_string();
+                    next_param += 1;
+                }
+                out.push('$');
+                out.push_str(&digits);
+            }
+            _ => out.push(c),
+        }
+    }
+    keywords.iter().fold(out, |sql, (pattern, replacement)| pattern.replace_all(&sql, *replacement).into_owned())
+}
+
+/// Map SQLite column types in schema statements to their PostgreSQL equivalents.
+fn postgres_ddl(ddl: &str) -> String {
+    static TYPES: std::sync::OnceLock<[(regex::Regex, &'static str); 6]> = std::sync::OnceLock::new();
+    let types = TYPES.get_or_init(|| [
+        (regex::Regex::new(r"\bINTEGER PRIMARY KEY AUTOINCREMENT\b").unwrap(), "BIGSERIAL PRIMARY KEY"),
+        (regex::Regex::new(r"\bINTEGER\b").unwrap(), "BIGINT"),
+        (regex::Regex::new(r"\bDATETIME\b").unwrap(), "TIMESTAMPTZ"),
+        (regex::Regex::new(r"\bBLOB\b").unwrap(), "BYTEA"),
+        (regex::Regex::new(r"\s+COLLATE NOCASE\b").unwrap(), ""),
+        (regex::Regex::new(r"\bADD COLUMN\b").unwrap(), "ADD COLUMN IF NOT EXISTS"),
+    ]);
+    types.iter().fold(ddl.to_string(), |ddl, (pattern, replacement)| pattern.replace_all(&ddl, *replacement).into_owned())
+}
+
+/// The connection URL with any password masked, for logging.
+pub fn redact_url(database_url: &str) -> String {
+    match url::Url::parse(database_url) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("****"));
+            url.to_string()
+        }
+        _ => database_url.to_string(),
+    }
+}
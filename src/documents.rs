@@ -0,0 +1,58 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+
+/// File types accepted by `POST /api/documents`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    Pdf,
+    Text,
+    Markdown,
+}
+
+impl DocumentKind {
+    /// Detect the kind from the file extension, falling back to the part's content type.
+    pub fn detect(filename: &str, content_type: Option<&str>) -> Option<Self> {
+        let extension = filename.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("pdf") => return Some(Self::Pdf),
+            Some("md" | "markdown") => return Some(Self::Markdown),
+            Some("txt" | "text") => return Some(Self::Text),
+            _ => {}
+        }
+        match content_type.map(|ct| ct.split(';').next().unwrap_or(ct).trim().to_lowercase()).as_deref() {
+            Some("application/pdf") => Some(Self::Pdf),
+            Some("text/markdown" | "text/x-markdown") => Some(Self::Markdown),
+            Some("text/plain") => Some(Self::Text),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pdf => "pdf",
+            Self::Text => "text",
+            Self::Markdown => "markdown",
+        }
+    }
+}
+
+/// Extract plain text from an uploaded file. PDF parsing is CPU-bound and can
+/// panic on malformed files, so it runs on the blocking pool.
+pub async fn extract_text(kind: DocumentKind, bytes: Vec<u8>) -> Result<String> {
+    let text = match kind {
+        DocumentKind::Pdf => tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&bytes))
+            .await
+            .map_err(|_| anyhow::anyhow!("PDF parser crashed on this file"))?
+            .map_err(|e| anyhow::anyhow!("Failed to read PDF: {}", e))?,
+        DocumentKind::Text | DocumentKind::Markdown => String::from_utf8_lossy(&bytes).into_owned(),
+    };
+    Ok(text)
+}
+
+/// Stable source URL for one chunk of an uploaded document. The content hash
+/// makes re-uploading the same file update its rows instead of duplicating them.
+pub fn chunk_url(filename: &str, content: &str, index: usize) -> String {
+    let digest = Sha256::digest(content.as_bytes());
+    let hash: String = digest.iter().take(6).map(|b| format!("{:02x}", b)).collect();
+    format!("document://{}/{}#{}", hash, urlencoding::encode(filename), index + 1)
+}
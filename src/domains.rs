@@ -0,0 +1,75 @@
+use crate::db::Database;
+
+/// Combined allow and block lists from `ALLOWED_DOMAINS`/`BLOCKED_DOMAINS`
+/// (comma-separated) and the `domain_rules` table. A domain also covers its subdomains.
+#[derive(Debug, Clone, Default)]
+pub struct DomainRules {
+    pub allowed: Vec<String>,
+    pub blocked: Vec<String>,
+}
+
+impl DomainRules {
+    pub async fn load(db: &Database) -> Self {
+        let mut rules = Self {
+            allowed: env_domains("ALLOWED_DOMAINS"),
+            blocked: env_domains("BLOCKED_DOMAINS"),
+        };
+        match db.list_domain_rules().await {
+            Ok(stored) => {
+                for rule in stored {
+                    match rule.rule.as_str() {
+                        "allow" => rules.allowed.push(rule.domain),
+                        _ => rules.blocked.push(rule.domain),
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Failed to load domain rules: {}", e),
+        }
+        rules
+    }
+
+    pub fn is_blocked(&self, url: &str) -> bool {
+        host_of(url).is_some_and(|host| self.blocked.iter().any(|d| host_matches(&host, d)))
+    }
+
+    pub fn is_allowed(&self, url: &str) -> bool {
+        host_of(url).is_some_and(|host| self.allowed.iter().any(|d| host_matches(&host, d)))
+    }
+}
+
+/// Canonical form for a rule: lowercase host without scheme, path or leading `www.`.
+/// Accepts bare domains as well as full URLs.
+pub fn normalize_domain(input: &str) -> Option<String> {
+    let input = input.trim().to_lowercase();
+    let host = if input.contains("://") {
+        url::Url::parse(&input).ok()?.host_str()?.to_string()
+    } else {
+        input.split(['/', '?', '#']).next()?.to_string()
+    };
+    let host = host.trim_start_matches("www.").trim_end_matches('.').to_string();
+    let valid = host.contains('.')
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && !host.split('.').any(str::is_empty);
+    valid.then_some(host)
+}
+
+/// Whether `url` is on one of `domains` or their subdomains
+pub fn on_domains(url: &str, domains: &[&str]) -> bool {
+    host_of(url).is_some_and(|host| domains.iter().any(|d| host_matches(&host, d)))
+}
+
+fn env_domains(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(normalize_domain)
+        .collect()
+}
+
+fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url).ok()?.host_str().map(|h| h.trim_start_matches("www.").to_lowercase())
+}
+
+fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
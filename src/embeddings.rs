@@ -0,0 +1,200 @@
+use anyhow::Result;
+use crate::error::ProviderError;
+use crate::http;
+
+/// Embedding backend, configured independently of the chat providers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmbeddingProviderType {
+    /// Any OpenAI-compatible `/embeddings` endpoint (OpenAI, LM Studio, vLLM, ...)
+    OpenAI,
+    Ollama,
+    Cohere,
+}
+
+impl EmbeddingProviderType {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "openai" | "openai-compatible" => Some(Self::OpenAI),
+            "ollama" => Some(Self::Ollama),
+            "cohere" => Some(Self::Cohere),
+            _ => None,
+        }
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            Self::OpenAI => "https://api.openai.com/v1",
+            Self::Ollama => "http://localhost:11434",
+            Self::Cohere => "https://api.cohere.ai/v1",
+        }
+    }
+
+    fn default_model(&self) -> &'static str {
+        match self {
+            Self::OpenAI => "text-embedding-3-small",
+            Self::Ollama => "nomic-embed-text",
+            Self::Cohere => "embed-english-v3.0",
+        }
+    }
+}
+
+/// Turns texts into vectors with the provider selected by `EMBEDDING_PROVIDER`.
+/// `EMBEDDING_MODEL`, `EMBEDDING_BASE_URL` and `EMBEDDING_API_KEY` override the
+/// provider defaults; Cohere falls back to `COHERE_API_KEY`.
+#[derive(Debug, Clone)]
+pub struct Embedder {
+    provider: EmbeddingProviderType,
+    model: String,
+    base_url: String,
+    api_key: Option<String>,
+    /// Shared LLM client; each call sets its own timeout
+    client: reqwest::Client,
+}
+
+impl Embedder {
+    /// `None` when embeddings are not configured or the provider is unknown.
+    pub fn from_env(client: reqwest::Client) -> Option<Self> {
+        let name = std::env::var("EMBEDDING_PROVIDER").ok().filter(|p| !p.trim().is_empty())?;
+        let Some(provider) = EmbeddingProviderType::from_str(&name) else {
+            tracing::warn!("Unknown EMBEDDING_PROVIDER '{}' (expected openai, ollama or cohere); embeddings disabled", name);
+            return None;
+        };
+
+        let env = |var: &str| std::env::var(var).ok().filter(|v| !v.trim().is_empty());
+        let api_key = env("EMBEDDING_API_KEY")
+            .or_else(|| (provider == EmbeddingProviderType::Cohere).then(|| env("COHERE_API_KEY")).flatten());
+        if api_key.is_none() && provider != EmbeddingProviderType::Ollama {
+            tracing::warn!("EMBEDDING_PROVIDER={} has no API key; requests may be rejected", name);
+        }
+
+        Some(Self {
+            model: env("EMBEDDING_MODEL").unwrap_or_else(|| provider.default_model().to_string()),
+            base_url: env("EMBEDDING_BASE_URL")
+                .unwrap_or_else(|| provider.default_base_url().to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            api_key,
+            client,
+            provider,
+        })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{:?} ({} at {})", self.provider, self.model, self.base_url)
+    }
+
+    /// Embed `texts`, returning one vector per input in the same order.
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (url, body) = match self.provider {
+            EmbeddingProviderType::OpenAI => (
+                format!("{}/embeddings", self.base_url),
+                serde_json::json!({ "model": self.model, "input": texts }),
+            ),
+            EmbeddingProviderType::Ollama => (
+                format!("{}/api/embed", self.base_url),
+                serde_json::json!({ "model": self.model, "input": texts }),
+            ),
+            EmbeddingProviderType::Cohere => (
+                format!("{}/embed", self.base_url),
+                serde_json::json!({ "model": self.model, "texts": texts, "input_type": "search_document" }),
+            ),
+        };
+
+        let mut request = self.client.post(&url).timeout(http::env_timeout("EMBEDDING_TIMEOUT_SECS", 30)).json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let resp = request.send().await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            return Err(ProviderError::from_status(&format!("{:?} embeddings", self.provider), status, &text).into());
+        }
+
+        let json: serde_json::Value = resp.json().await?;
+        // OpenAI nests vectors under data[].embedding; Ollama and Cohere return `embeddings` directly
+        let vectors: Vec<&serde_json::Value> = match self.provider {
+            EmbeddingProviderType::OpenAI => json["data"].as_array()
+                .map(|data| data.iter().map(|d| &d["embedding"]).collect())
+                .unwrap_or_default(),
+            EmbeddingProviderType::Ollama | EmbeddingProviderType::Cohere => json["embeddings"].as_array()
+                .map(|e| e.iter().collect())
+                .unwrap_or_default(),
+        };
+
+        let vectors: Vec<Vec<f32>> = vectors.into_iter()
+            .map(|v| serde_json::from_value::<Vec<f32>>(v.clone()))
+            .collect::<Result<_, _>>()?;
+        if vectors.len() != texts.len() {
+            return Err(ProviderError::Upstream(format!(
+                "Embedding provider returned {} vectors for {} inputs", vectors.len(), texts.len()
+            )).into());
+        }
+        Ok(vectors)
+    }
+}
+
+/// Split `text` into chunks of at most about `max_chars`, breaking on whitespace.
+/// Consecutive chunks share roughly `overlap` chars so facts spanning a boundary
+/// stay retrievable.
+pub fn chunk_text(text: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < words.len() {
+        let mut end = start;
+        let mut len = 0;
+        while end < words.len() && (end == start || len + words[end].len() < max_chars) {
+            len += words[end].len() + 1;
+            end += 1;
+        }
+        chunks.push(words[start..end].join(" "));
+        if end >= words.len() {
+            break;
+        }
+
+        // Step back over the tail words that fit in the overlap, always making progress
+        let mut back = end;
+        let mut tail = 0;
+        while back > start + 1 && tail + words[back - 1].len() < overlap {
+            tail += words[back - 1].len() + 1;
+            back -= 1;
+        }
+        start = back;
+    }
+    chunks
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+/// Little-endian f32 encoding used for the `embeddings.embedding` BLOB column
+pub fn vector_to_bytes(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn vector_from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
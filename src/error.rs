@@ -0,0 +1,139 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Failures from upstream LLM providers. These travel inside `anyhow::Error`
+/// through the RAG pipeline and are classified again at the API boundary.
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The provider (or our local budget for it) is out of requests
+    RateLimited(String),
+    /// The requested model isn't available
+    ModelNotFound(String),
+    /// The provider answered with a non-success status or an unusable body
+    Upstream(String),
+    /// The provider failed on its side (5xx); another provider may still succeed
+    Unavailable(String),
+}
+
+impl ProviderError {
+    /// Classify a non-success HTTP response from a provider.
+    pub fn from_status(provider: &str, status: reqwest::StatusCode, body: &str) -> Self {
+        let message = format!("{} Error ({}): {}", provider, status, body);
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            ProviderError::RateLimited(message)
+        } else if status.is_server_error() {
+            ProviderError::Unavailable(message)
+        } else {
+            ProviderError::Upstream(message)
+        }
+    }
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::RateLimited(msg) => write!(f, "{}", msg),
+            ProviderError::ModelNotFound(msg) => write!(f, "{}", msg),
+            ProviderError::Upstream(msg) => write!(f, "{}", msg),
+            ProviderError::Unavailable(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// A query stopped because its cancellation token fired (`POST /api/query/:request_id/cancel`
+/// or the client disconnecting).
+#[derive(Debug)]
+pub struct QueryCancelled;
+
+impl std::fmt::Display for QueryCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Query cancelled")
+    }
+}
+
+impl std::error::Error for QueryCancelled {}
+
+/// Errors returned by the HTTP handlers, mapped to status codes and a JSON body:
+/// `{ "error": { "type": "rate_limited", "message": "..." } }`
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    /// Missing or expired session in multi-user mode
+    Unauthorized(String),
+    /// Authenticated, but without admin rights
+    Forbidden(String),
+    NotFound(String),
+    RateLimited(String),
+    Upstream(String),
+    Internal(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::Forbidden(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::RateLimited(msg)
+            | ApiError::Upstream(msg)
+            | ApiError::Internal(msg) => msg,
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast_ref::<ProviderError>() {
+            Some(ProviderError::RateLimited(msg)) => ApiError::RateLimited(msg.clone()),
+            Some(ProviderError::ModelNotFound(msg)) => ApiError::NotFound(msg.clone()),
+            Some(ProviderError::Upstream(msg) | ProviderError::Unavailable(msg)) => ApiError::Upstream(msg.clone()),
+            None => ApiError::Internal(format!("Error: {}", err)),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("API error ({}): {}", status, self.message());
+        }
+        let body = serde_json::json!({
+            "error": {
+                "type": self.kind(),
+                "message": self.message(),
+            }
+        });
+        (status, Json(body)).into_response()
+    }
+}
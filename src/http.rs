@@ -0,0 +1,177 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Read a timeout in seconds from the environment, falling back to `default_secs`.
+pub fn env_timeout(name: &str, default_secs: u64) -> Duration {
+    let secs = std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(default_secs);
+    Duration::from_secs(secs)
+}
+
+/// Client builder with a short connect timeout (`HTTP_CONNECT_TIMEOUT_SECS`, default 5s)
+/// and the given total request timeout, so dead hosts fail fast while long
+/// generations still have their full budget.
+pub fn client_builder(total: Duration) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .connect_timeout(env_timeout("HTTP_CONNECT_TIMEOUT_SECS", 5).min(total))
+        .timeout(total)
+}
+
+/// Whether `ip` is routable on the public internet rather than private, loopback,
+/// link-local or carrier-grade NAT space
+pub fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => !(v4.is_private() || v4.is_loopback() || v4.is_link_local()
+            || v4.is_unspecified() || v4.is_broadcast() || v4.is_documentation()
+            // "This network" 0.0.0.0/8
+            || v4.octets()[0] == 0
+            // Carrier-grade NAT 100.64.0.0/10
+            || (v4.octets()[0] == 100 && (v4.octets()[1] & 0xC0) == 64)),
+        IpAddr::V6(v6) => {
+            // IPv4-mapped addresses (::ffff:127.0.0.1) reach the IPv4 host
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public_ip(&IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_loopback() || v6.is_unspecified()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xFE00) == 0xFC00
+                || (first & 0xFFC0) == 0xFE80)
+        }
+    }
+}
+
+/// Refuse URLs whose host is this machine or a private network, so user-supplied
+/// URLs can't be used to reach internal services
+pub fn ensure_public_url(url: &url::Url) -> anyhow::Result<()> {
+    let public = match url.host() {
+        Some(url::Host::Domain(host)) => !(host.eq_ignore_ascii_case("localhost") || host.to_lowercase().ends_with(".localhost")),
+        Some(url::Host::Ipv4(ip)) => is_public_ip(&IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => is_public_ip(&IpAddr::V6(ip)),
+        None => false,
+    };
+    if !public {
+        anyhow::bail!("{} is not a public address", url);
+    }
+    Ok(())
+}
+
+/// Resolver for `HttpClients::public_fetch` that drops private addresses, so a public-looking
+/// name (e.g. `127.0.0.1.nip.io`) can't reach internal services, including on redirect hops.
+struct PublicOnlyResolver;
+
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?
+                .filter(|addr| is_public_ip(&addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} does not resolve to a public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Redirect policy for `HttpClients::public_fetch`: each hop must pass `ensure_public_url`
+/// (literal IPs never reach the resolver), with reqwest's default limit of 10 hops.
+fn public_redirect_policy() -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() > 10 {
+            attempt.error("too many redirects")
+        } else if let Err(e) = ensure_public_url(attempt.url()) {
+            attempt.error(e.to_string())
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Browser-like user agent for page fetches and HTML scraping, which some sites require
+pub const BROWSER_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36";
+
+/// Clients shared by every outbound call so connections and TLS sessions are pooled
+/// rather than rebuilt per request. Cloning a `reqwest::Client` shares its pool.
+pub struct HttpClients {
+    /// LLM completions, embeddings and reranking (`LLM_TIMEOUT_SECS`, default 120s);
+    /// shorter calls set their own per-request timeout
+    pub llm: reqwest::Client,
+    /// Search APIs and provider quota endpoints (`SEARCH_TIMEOUT_SECS`, default 10s)
+    pub search: reqwest::Client,
+    /// Fetching result pages, with a browser user agent (`FETCH_TIMEOUT_SECS`, default 10s)
+    pub fetch: reqwest::Client,
+    /// Like `fetch`, for URLs supplied by users or the model: connects only to public
+    /// addresses, checked after DNS resolution and on every redirect
+    pub public_fetch: reqwest::Client,
+}
+
+impl HttpClients {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            llm: client_builder(env_timeout("LLM_TIMEOUT_SECS", 120)).build()?,
+            search: client_builder(env_timeout("SEARCH_TIMEOUT_SECS", 10)).build()?,
+            fetch: client_builder(env_timeout("FETCH_TIMEOUT_SECS", 10))
+                .user_agent(BROWSER_USER_AGENT)
+                .build()?,
+            public_fetch: client_builder(env_timeout("FETCH_TIMEOUT_SECS", 10))
+                .user_agent(BROWSER_USER_AGENT)
+                .dns_resolver(Arc::new(PublicOnlyResolver))
+                .redirect(public_redirect_policy())
+                .build()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn private_and_special_addresses_are_not_public() {
+        for ip in ["127.0.0.1", "10.1.2.3", "192.168.0.1", "169.254.169.254", "100.64.0.1", "0.1.2.3", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public_ip(&ip.parse().unwrap()), "{} should not be public", ip);
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_public_ip(&ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[test]
+    fn ensure_public_url_checks_literal_hosts() {
+        for url in ["http://localhost/", "http://api.localhost:8080/", "http://10.0.0.1/", "http://[::1]/"] {
+            assert!(ensure_public_url(&url::Url::parse(url).unwrap()).is_err(), "{}", url);
+        }
+        assert!(ensure_public_url(&url::Url::parse("https://example.com/page").unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn public_fetch_refuses_names_resolving_to_private_addresses() {
+        let http = HttpClients::from_env().unwrap();
+        let err = http.public_fetch.get("http://localhost:9/").send().await.unwrap_err();
+        assert!(format!("{:?}", err).contains("does not resolve to a public address"), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn public_fetch_refuses_redirects_to_private_addresses() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _ = socket.read(&mut [0u8; 4096]).await;
+            let response = "HTTP/1.1 302 Found\r\nLocation: http://10.0.0.1/admin\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let http = HttpClients::from_env().unwrap();
+        let err = http.public_fetch.get(format!("http://{}/", addr)).send().await.unwrap_err();
+        assert!(err.is_redirect(), "{:?}", err);
+        assert!(format!("{:?}", err).contains("is not a public address"), "{:?}", err);
+    }
+}
@@ -0,0 +1,304 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// A conversation read from an export, ready to be stored as a thread.
+#[derive(Debug, Clone)]
+pub struct ImportedThread {
+    pub title: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub messages: Vec<ImportedMessage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportedMessage {
+    /// `user` or `assistant`; system and tool messages are dropped
+    pub role: String,
+    pub content: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// Read conversations from the body of `POST /api/threads/import`. Accepts a single
+/// conversation, an array of them, or `{ "threads": [...] }`, where each conversation is
+/// one of:
+/// - W9's own shape: `{ title, messages: [{ role, content, created_at }] }`, optionally
+///   with the thread under `thread` as returned by `/api/threads`
+/// - a ChatGPT `conversations.json` entry (`mapping` of message nodes)
+/// - an Open WebUI chat export (`chat.messages` or `chat.history`)
+///
+/// Conversations without any user or assistant text are skipped.
+pub fn parse(body: &Value) -> Result<Vec<ImportedThread>, String> {
+    let conversations = match body {
+        Value::Array(items) => items.iter().collect::<Vec<_>>(),
+        Value::Object(map) => match map.get("threads") {
+            Some(Value::Array(items)) => items.iter().collect(),
+            _ => vec![body],
+        },
+        _ => return Err("Expected a conversation object or an array of conversations".to_string()),
+    };
+
+    let threads: Vec<ImportedThread> = conversations.into_iter()
+        .enumerate()
+        .map(|(i, conversation)| parse_conversation(conversation).map_err(|e| format!("Conversation {}: {}", i + 1, e)))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|thread| !thread.messages.is_empty())
+        .collect();
+    if threads.is_empty() {
+        return Err("No conversations with user or assistant messages found".to_string());
+    }
+    Ok(threads)
+}
+
+fn parse_conversation(value: &Value) -> Result<ImportedThread, String> {
+    if !value.is_object() {
+        return Err("expected an object".to_string());
+    }
+    let messages = if value.get("mapping").is_some_and(Value::is_object) {
+        chatgpt_messages(value)
+    } else if let Some(chat) = value.get("chat").filter(|c| c.is_object()) {
+        open_webui_messages(chat)
+    } else if let Some(Value::Array(items)) = value.get("messages") {
+        items.iter().filter_map(native_message).collect()
+    } else {
+        return Err("unrecognized format (expected messages, mapping or chat)".to_string());
+    };
+
+    let title = [value.get("title"), value.pointer("/thread/title"), value.pointer("/chat/title")]
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::trim)
+        .find(|t| !t.is_empty())
+        .map(str::to_string)
+        .or_else(|| {
+            messages.iter()
+                .find(|m| m.role == "user")
+                .map(|m| m.content.chars().take(60).collect::<String>().trim().to_string())
+        })
+        .unwrap_or_else(|| "Imported conversation".to_string());
+    let title = title.chars().take(100).collect();
+
+    let created_at = ["created_at", "create_time"].iter()
+        .find_map(|key| value.get(*key).and_then(timestamp))
+        .or_else(|| value.pointer("/thread/created_at").and_then(timestamp));
+
+    Ok(ImportedThread { title, created_at, messages })
+}
+
+fn native_message(value: &Value) -> Option<ImportedMessage> {
+    message(
+        value.get("role")?.as_str()?,
+        value.get("content")?.as_str()?,
+        value.get("created_at").or_else(|| value.get("timestamp")).and_then(timestamp),
+    )
+}
+
+/// ChatGPT stores a tree of message nodes; the visible conversation is the path from
+/// `current_node` back to the root (falling back to creation order without one).
+fn chatgpt_messages(conversation: &Value) -> Vec<ImportedMessage> {
+    let Some(mapping) = conversation.get("mapping").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    let mut nodes = Vec::new();
+    let mut seen = HashSet::new();
+    let mut current = conversation.get("current_node").and_then(Value::as_str);
+    while let Some(id) = current {
+        let Some(node) = mapping.get(id) else { break };
+        if !seen.insert(id) {
+            break; // cycle guard
+        }
+        nodes.push(node);
+        current = node.get("parent").and_then(Value::as_str);
+    }
+    nodes.reverse();
+    if nodes.is_empty() {
+        nodes = mapping.values().collect();
+        nodes.sort_by(|a, b| {
+            let time = |n: &Value| n.pointer("/message/create_time").and_then(Value::as_f64).unwrap_or(0.0);
+            time(a).total_cmp(&time(b))
+        });
+    }
+
+    nodes.into_iter()
+        .filter_map(|node| {
+            let msg = node.get("message")?;
+            let text = msg.pointer("/content/parts")?
+                .as_array()?
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("\n");
+            message(msg.pointer("/author/role")?.as_str()?, &text, msg.get("create_time").and_then(timestamp))
+        })
+        .collect()
+}
+
+/// Open WebUI keeps a flat `messages` list and a `history` tree; prefer the list.
+fn open_webui_messages(chat: &Value) -> Vec<ImportedMessage> {
+    if let Some(Value::Array(items)) = chat.get("messages") {
+        return items.iter().filter_map(native_message).collect();
+    }
+    let Some(history) = chat.pointer("/history/messages").and_then(Value::as_object) else {
+        return Vec::new();
+    };
+    let mut nodes = Vec::new();
+    let mut seen = HashSet::new();
+    let mut current = chat.pointer("/history/currentId").and_then(Value::as_str);
+    while let Some(id) = current {
+        let Some(node) = history.get(id) else { break };
+        if !seen.insert(id) {
+            break;
+        }
+        nodes.push(node);
+        current = node.get("parentId").and_then(Value::as_str);
+    }
+    nodes.reverse();
+    nodes.into_iter().filter_map(native_message).collect()
+}
+
+fn message(role: &str, content: &str, created_at: Option<DateTime<Utc>>) -> Option<ImportedMessage> {
+    let role = role.trim().to_lowercase();
+    let content = content.trim();
+    if !matches!(role.as_str(), "user" | "assistant") || content.is_empty() {
+        return None;
+    }
+    Some(ImportedMessage { role, content: content.to_string(), created_at })
+}
+
+/// RFC 3339 or SQLite `YYYY-MM-DD HH:MM:SS` strings, or Unix timestamps in seconds
+/// (milliseconds when implausibly large).
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    if let Some(n) = value.as_f64() {
+        let secs = if n > 1e11 { n / 1000.0 } else { n };
+        return Utc.timestamp_opt(secs.trunc() as i64, (secs.fract() * 1e9) as u32).single();
+    }
+    let s = value.as_str()?.trim();
+    DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.with_timezone(&Utc))
+        .ok()
+        .or_else(|| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").ok().map(|dt| dt.and_utc()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn roles_and_text(thread: &ImportedThread) -> Vec<(&str, &str)> {
+        thread.messages.iter().map(|m| (m.role.as_str(), m.content.as_str())).collect()
+    }
+
+    fn at(secs: i64) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(secs, 0).single()
+    }
+
+    #[test]
+    fn parses_w9_export_and_drops_other_roles() {
+        let body = json!({ "threads": [{
+            "thread": { "title": "Rust releases", "created_at": "2024-05-01 10:00:00" },
+            "messages": [
+                { "role": "system", "content": "You are helpful" },
+                { "role": "user", "content": " Latest Rust? ", "created_at": "2024-05-01 10:00:00" },
+                { "role": "assistant", "content": "1.78 [1]", "created_at": "2024-05-01T10:00:05Z" },
+                { "role": "tool", "content": "{}" }
+            ]
+        }] });
+
+        let threads = parse(&body).unwrap();
+
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].title, "Rust releases");
+        assert_eq!(threads[0].created_at, at(1714557600));
+        assert_eq!(roles_and_text(&threads[0]), [("user", "Latest Rust?"), ("assistant", "1.78 [1]")]);
+        assert_eq!(threads[0].messages[1].created_at, at(1714557605));
+    }
+
+    #[test]
+    fn chatgpt_follows_current_node_branch() {
+        let node = |parent: Option<&str>, role: &str, text: &str, time: f64| json!({
+            "parent": parent,
+            "message": { "author": { "role": role }, "content": { "parts": [text] }, "create_time": time }
+        });
+        let body = json!({
+            "title": "Branches",
+            "create_time": 1714557600.0,
+            "current_node": "edited",
+            "mapping": {
+                "root": { "parent": null, "message": null },
+                "question": node(Some("root"), "user", "Which is faster?", 1714557600.0),
+                "first": node(Some("question"), "assistant", "First draft", 1714557601.0),
+                "edited": node(Some("question"), "assistant", "Regenerated answer", 1714557602.5)
+            }
+        });
+
+        let threads = parse(&body).unwrap();
+
+        assert_eq!(roles_and_text(&threads[0]), [("user", "Which is faster?"), ("assistant", "Regenerated answer")]);
+        assert_eq!(threads[0].created_at, at(1714557600));
+        assert_eq!(threads[0].messages[1].created_at.unwrap().timestamp_millis(), 1714557602500);
+    }
+
+    #[test]
+    fn chatgpt_cycle_visits_each_node_once() {
+        let body = json!({
+            "current_node": "b",
+            "mapping": {
+                "a": { "parent": "b", "message": { "author": { "role": "user" }, "content": { "parts": ["Question"] } } },
+                "b": { "parent": "a", "message": { "author": { "role": "assistant" }, "content": { "parts": ["Answer"] } } }
+            }
+        });
+
+        let threads = parse(&body).unwrap();
+
+        assert_eq!(roles_and_text(&threads[0]), [("user", "Question"), ("assistant", "Answer")]);
+        // No title in the export, so the first question stands in
+        assert_eq!(threads[0].title, "Question");
+    }
+
+    #[test]
+    fn chatgpt_without_current_node_uses_creation_order() {
+        let body = json!({ "mapping": {
+            "x": { "message": { "author": { "role": "assistant" }, "content": { "parts": ["Second"] }, "create_time": 20.0 } },
+            "y": { "message": { "author": { "role": "user" }, "content": { "parts": ["First"] }, "create_time": 10.0 } }
+        } });
+
+        let threads = parse(&body).unwrap();
+
+        assert_eq!(roles_and_text(&threads[0]), [("user", "First"), ("assistant", "Second")]);
+    }
+
+    #[test]
+    fn open_webui_list_and_history() {
+        let list = json!({ "chat": { "title": "Flat", "messages": [
+            { "role": "user", "content": "Hi", "timestamp": 1714557600 },
+            { "role": "assistant", "content": "Hello" }
+        ] } });
+        // Timestamps in milliseconds and in seconds both land on the same instant
+        let history = json!({ "chat": { "title": "Tree", "history": {
+            "currentId": "m3",
+            "messages": {
+                "m1": { "role": "user", "content": "Hi", "timestamp": 1714557600000_i64, "parentId": null },
+                "m2": { "role": "assistant", "content": "Discarded", "timestamp": 1714557601, "parentId": "m1" },
+                "m3": { "role": "assistant", "content": "Hello", "timestamp": 1714557605, "parentId": "m1" }
+            }
+        } } });
+
+        let threads = parse(&json!([list, history])).unwrap();
+
+        assert_eq!(threads[0].title, "Flat");
+        assert_eq!(roles_and_text(&threads[0]), [("user", "Hi"), ("assistant", "Hello")]);
+        assert_eq!(threads[0].messages[0].created_at, at(1714557600));
+        assert_eq!(threads[1].title, "Tree");
+        assert_eq!(roles_and_text(&threads[1]), [("user", "Hi"), ("assistant", "Hello")]);
+        assert_eq!(threads[1].messages[0].created_at, at(1714557600));
+        assert_eq!(threads[1].messages[1].created_at, at(1714557605));
+    }
+
+    #[test]
+    fn rejects_unknown_or_empty_exports() {
+        assert!(parse(&json!("text")).is_err());
+        assert!(parse(&json!([{ "foo": 1 }])).unwrap_err().starts_with("Conversation 1:"));
+        assert!(parse(&json!({ "messages": [{ "role": "system", "content": "x" }] })).is_err());
+    }
+}
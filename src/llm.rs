@@ -1,7 +1,16 @@
 use anyhow::Result;
+use crate::embeddings::{self, Embedder};
+use crate::http::HttpClients;
+use crate::circuit::{CircuitBreaker, ProviderHealth};
+use crate::error::ProviderError;
+use crate::rerank::Reranker;
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -11,6 +20,8 @@ pub enum ProviderType {
     Cerebras,
     Cohere,
     Pollinations,
+    /// Any OpenAI-compatible server (vLLM, LM Studio, llama.cpp, ...) at `CUSTOM_LLM_BASE_URL`
+    Custom,
 }
 
 impl std::fmt::Display for ProviderType {
@@ -21,11 +32,34 @@ impl std::fmt::Display for ProviderType {
             ProviderType::Cerebras => write!(f, "Cerebras"),
             ProviderType::Cohere => write!(f, "Cohere"),
             ProviderType::Pollinations => write!(f, "Pollinations"),
+            ProviderType::Custom => write!(f, "Custom"),
         }
     }
 }
 
+/// Whether a provider (LLM or search) is switched off via `DISABLED_PROVIDERS`,
+/// a comma-separated, case-insensitive list such as `cohere,brave`.
+pub fn provider_disabled(name: &str) -> bool {
+    std::env::var("DISABLED_PROVIDERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|p| p.trim().to_lowercase())
+        .any(|p| match p.as_str() {
+            "ddg" | "duckduckgo" => name.eq_ignore_ascii_case("ddg") || name.eq_ignore_ascii_case("duckduckgo"),
+            _ => !p.is_empty() && p.eq_ignore_ascii_case(name),
+        })
+}
+
 impl ProviderType {
+    pub const ALL: [ProviderType; 6] = [
+        ProviderType::OpenRouter,
+        ProviderType::Groq,
+        ProviderType::Cerebras,
+        ProviderType::Cohere,
+        ProviderType::Pollinations,
+        ProviderType::Custom,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             ProviderType::OpenRouter => "openrouter",
@@ -33,6 +67,7 @@ impl ProviderType {
             ProviderType::Cerebras => "cerebras",
             ProviderType::Cohere => "cohere",
             ProviderType::Pollinations => "pollinations",
+            ProviderType::Custom => "custom",
         }
     }
 
@@ -43,6 +78,7 @@ impl ProviderType {
             "cerebras" => Some(ProviderType::Cerebras),
             "cohere" => Some(ProviderType::Cohere),
             "pollinations" => Some(ProviderType::Pollinations),
+            "custom" => Some(ProviderType::Custom),
             _ => None,
         }
     }
@@ -57,12 +93,6 @@ pub struct Model {
     pub is_free: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModelPricing {
-    pub prompt: String,
-    pub completion: String,
-}
-
 // Internal structures for API responses
 #[derive(Deserialize)]
 struct OpenRouterModel {
@@ -124,53 +154,577 @@ struct CohereModel {
 #[derive(Deserialize)]
 struct PollinationsModel {
     name: String,
-    description: Option<String>,
     #[serde(rename = "context_window")]
     context_window: Option<i64>,
 }
 
+/// One incremental piece of a chat completion, normalized across providers.
+#[derive(Debug, Clone, Default)]
+pub struct ChatDelta {
+    pub content: Option<String>,
+    pub tool_calls: Vec<ToolCallDelta>,
+    pub finish_reason: Option<String>,
+    pub usage: Option<ChatUsage>,
+}
+
+/// Fragment of a tool call. Fragments sharing an `index` are concatenated:
+/// `id` and `name` arrive once, `arguments` is streamed in pieces.
+#[derive(Debug, Clone, Default)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatDelta>> + Send>>;
+
+/// Sampling options for one chat completion. Unset fields are left out of the
+/// request so each provider applies its own default.
+#[derive(Debug, Clone, Default)]
+pub struct GenerationParams {
+    /// Only honored by providers that support it (OpenRouter, Groq, custom); best-effort even there
+    pub seed: Option<u64>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub top_p: Option<f64>,
+    pub stop: Vec<String>,
+}
+
+impl GenerationParams {
+    /// Most OpenAI-compatible providers reject more than four stop sequences
+    pub const MAX_STOP_SEQUENCES: usize = 4;
+
+    /// Check ranges before anything is sent upstream.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(t) = self.temperature {
+            if !(0.0..=2.0).contains(&t) {
+                return Err(format!("temperature must be between 0 and 2, got {}", t));
+            }
+        }
+        if let Some(p) = self.top_p {
+            if !(p > 0.0 && p <= 1.0) {
+                return Err(format!("top_p must be in (0, 1], got {}", p));
+            }
+        }
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens must be at least 1".to_string());
+        }
+        if self.stop.len() > Self::MAX_STOP_SEQUENCES {
+            return Err(format!("At most {} stop sequences are allowed", Self::MAX_STOP_SEQUENCES));
+        }
+        if self.stop.iter().any(|s| s.is_empty()) {
+            return Err("Stop sequences must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Just the sampling knobs, for internal calls (planning, rewriting, summarizing)
+    /// whose output must not be truncated by the caller's `max_tokens` or `stop`.
+    pub fn sampling_only(&self) -> Self {
+        Self { seed: self.seed, temperature: self.temperature, top_p: self.top_p, ..Self::default() }
+    }
+
+    /// Add the set options to an OpenAI-style request body.
+    fn apply_openai(&self, request: &mut serde_json::Value, provider: &ProviderType) {
+        if let (Some(seed), ProviderType::OpenRouter | ProviderType::Groq | ProviderType::Custom) = (self.seed, provider) {
+            request["seed"] = serde_json::json!(seed);
+        }
+        if let Some(temperature) = self.temperature {
+            request["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            request["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        if let Some(top_p) = self.top_p {
+            request["top_p"] = serde_json::json!(top_p);
+        }
+        if !self.stop.is_empty() {
+            request["stop"] = serde_json::json!(self.stop);
+        }
+    }
+
+    /// Cohere's v1 chat API names these `p` and `stop_sequences`.
+    fn apply_cohere(&self, request: &mut serde_json::Value) {
+        if let Some(temperature) = self.temperature {
+            request["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            request["max_tokens"] = serde_json::json!(max_tokens);
+        }
+        if let Some(top_p) = self.top_p {
+            request["p"] = serde_json::json!(top_p);
+        }
+        if !self.stop.is_empty() {
+            request["stop_sequences"] = serde_json::json!(self.stop);
+        }
+    }
+}
+
+impl ChatDelta {
+    fn is_empty(&self) -> bool {
+        self.content.is_none() && self.tool_calls.is_empty() && self.finish_reason.is_none() && self.usage.is_none()
+    }
+
+    /// Parse one OpenAI-style chunk (`choices[0].delta`) or full response (`choices[0].message`).
+    fn from_openai_json(value: &serde_json::Value) -> Result<Self> {
+        if let Some(error) = value.get("error") {
+            return Err(ProviderError::Upstream(format!("Provider API error: {}", error)).into());
+        }
+
+        let choice = &value["choices"][0];
+        let body = if choice.get("delta").is_some() { &choice["delta"] } else { &choice["message"] };
+
+        let tool_calls = body["tool_calls"].as_array()
+            .map(|calls| calls.iter().enumerate().map(|(i, call)| ToolCallDelta {
+                index: call["index"].as_u64().map(|n| n as usize).unwrap_or(i),
+                id: call["id"].as_str().map(String::from),
+                name: call["function"]["name"].as_str().map(String::from),
+                arguments: call["function"]["arguments"].as_str().map(String::from),
+            }).collect())
+            .unwrap_or_default();
+
+        // Groq reports usage of streamed completions under `x_groq`
+        let usage = [&value["usage"], &value["x_groq"]["usage"]].into_iter()
+            .find(|u| u.is_object())
+            .and_then(|u| serde_json::from_value(u.clone()).ok());
+
+        Ok(Self {
+            content: body["content"].as_str().filter(|c| !c.is_empty()).map(String::from),
+            tool_calls,
+            finish_reason: choice["finish_reason"].as_str().map(String::from),
+            usage,
+        })
+    }
+}
+
+/// Read state for an OpenAI-compatible SSE response body.
+struct SseState {
+    resp: reqwest::Response,
+    buf: Vec<u8>,
+    pending: VecDeque<Result<ChatDelta>>,
+    done: bool,
+    stats: StreamCallStats,
+}
+
+/// Provider stats for a streamed call. Tokens only arrive with the final chunk,
+/// so the call is recorded when the stream is dropped (finished or abandoned).
+struct StreamCallStats {
+    db: Arc<crate::db::Database>,
+    provider: ProviderType,
+    latency_ms: i64,
+    tokens: Option<i64>,
+    /// Set when the stream ended in a read or parse error, recording a failed call
+    failed: bool,
+}
+
+impl Drop for StreamCallStats {
+    fn drop(&mut self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let db = self.db.clone();
+        let provider = self.provider.clone();
+        let (latency_ms, tokens, success) = (self.latency_ms, self.tokens, !self.failed);
+        runtime.spawn(async move {
+            if let Err(e) = db.record_provider_call(&provider, latency_ms, tokens, success).await {
+                tracing::warn!("Failed to record stats for {}: {}", provider, e);
+            }
+        });
+    }
+}
+
+impl SseState {
+    fn push_line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        // Lines starting with ':' are keep-alive comments (OpenRouter sends these while routing)
+        let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+            return;
+        };
+        if data == "[DONE]" {
+            self.done = true;
+            return;
+        }
+        let delta = serde_json::from_str::<serde_json::Value>(data)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| ChatDelta::from_openai_json(&value));
+        match delta {
+            Ok(delta) if delta.is_empty() => {}
+            Err(e) => {
+                self.done = true;
+                self.stats.failed = true;
+                self.pending.push_back(Err(e));
+            }
+            Ok(delta) => {
+                if let Some(usage) = &delta.usage {
+                    self.stats.tokens = i64::try_from(usage.total_tokens).ok().filter(|&t| t > 0).or(self.stats.tokens);
+                }
+                self.pending.push_back(Ok(delta));
+            }
+        }
+    }
+
+    async fn next_delta(mut self) -> Option<(Result<ChatDelta>, Self)> {
+        loop {
+            if let Some(delta) = self.pending.pop_front() {
+                return Some((delta, self));
+            }
+            if self.done {
+                return None;
+            }
+            match self.resp.chunk().await {
+                Ok(Some(bytes)) => {
+                    self.buf.extend_from_slice(&bytes);
+                    while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = self.buf.drain(..=pos).collect();
+                        self.push_line(&line);
+                    }
+                }
+                Ok(None) => {
+                    self.done = true;
+                    let rest = std::mem::take(&mut self.buf);
+                    self.push_line(&rest);
+                }
+                Err(e) => {
+                    self.done = true;
+                    self.stats.failed = true;
+                    self.pending.push_back(Err(e.into()));
+                }
+            }
+        }
+    }
+}
+
+/// API keys for one provider, handed out round-robin. A key that gets a 429
+/// is skipped for `KEY_COOLDOWN` while other keys remain available.
+struct KeyPool {
+    keys: Vec<String>,
+    next: AtomicUsize,
+    limited_until: std::sync::Mutex<HashMap<usize, Instant>>,
+}
+
+const KEY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Chunk size and overlap (chars) for embedding stored sources
+const EMBEDDING_CHUNK_CHARS: usize = 1000;
+const EMBEDDING_CHUNK_OVERLAP: usize = 150;
+
+impl KeyPool {
+    /// Read `<PREFIX>_API_KEYS` (comma-separated), falling back to `<PREFIX>_API_KEY`.
+    fn from_env(prefix: &str) -> Option<Self> {
+        let keys: Vec<String> = std::env::var(format!("{}_API_KEYS", prefix))
+            .or_else(|_| std::env::var(format!("{}_API_KEY", prefix)))
+            .ok()?
+            .split(',')
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty())
+            .collect();
+        if keys.is_empty() {
+            return None;
+        }
+        if keys.len() > 1 {
+            tracing::info!("Rotating across {} {} API keys", keys.len(), prefix);
+        }
+        Some(Self::new(keys))
+    }
+
+    fn new(keys: Vec<String>) -> Self {
+        Self {
+            keys,
+            next: AtomicUsize::new(0),
+            limited_until: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Key used for account-level calls (model listing, limits)
+    fn primary(&self) -> &str {
+        &self.keys[0]
+    }
+
+    /// Next key in rotation, skipping rate-limited keys unless all of them are.
+    fn pick(&self) -> (usize, &str) {
+        let now = Instant::now();
+        let limited = self.limited_until.lock().unwrap_or_else(|e| e.into_inner());
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let index = (0..self.keys.len())
+            .map(|offset| (start + offset) % self.keys.len())
+            .find(|i| limited.get(i).is_none_or(|until| *until <= now))
+            .unwrap_or(start % self.keys.len());
+        (index, &self.keys[index])
+    }
+
+    fn mark_rate_limited(&self, index: usize) {
+        let mut limited = self.limited_until.lock().unwrap_or_else(|e| e.into_inner());
+        limited.insert(index, Instant::now() + KEY_COOLDOWN);
+    }
+}
+
 pub struct LLMManager {
     db: Arc<crate::db::Database>,
     models: Arc<RwLock<Vec<Model>>>,
-    api_keys: HashMap<ProviderType, String>,
+    api_keys: HashMap<ProviderType, KeyPool>,
+    /// Logical model names mapped to concrete model IDs (from `PINNED_MODELS`)
+    pinned_models: HashMap<String, String>,
+    /// Embedding backend for semantic retrieval, configured independently of chat providers
+    embedder: Option<Embedder>,
+    /// Cross-encoder used to rerank retrieved passages before the answer step
+    reranker: Option<Reranker>,
+    /// OpenAI-compatible base URL (ending in `/v1`) for `ProviderType::Custom`
+    custom_base_url: Option<String>,
+    /// Providers that keep failing are skipped by auto-selection and failover for a while
+    health: CircuitBreaker,
+    http: Arc<HttpClients>,
+}
+
+fn failover_enabled() -> bool {
+    std::env::var("PROVIDER_FAILOVER").map(|v| v != "false" && v != "0").unwrap_or(true)
+}
+
+/// Failures another provider might not share: rate limits, 5xx, timeouts and connection errors.
+fn should_fail_over(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<ProviderError>() {
+        Some(ProviderError::RateLimited(_) | ProviderError::Unavailable(_)) => true,
+        Some(_) => false,
+        None => err.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect() || e.is_timeout()),
+    }
+}
+
+/// The provider is down or unreachable: 5xx, timeouts and connection errors.
+fn is_outage(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<ProviderError>() {
+        Some(ProviderError::Unavailable(_)) => true,
+        Some(_) => false,
+        None => err.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect() || e.is_timeout()),
+    }
+}
+
+/// Provider-neutral model name used to match the same model across providers:
+/// vendor prefix, `:variant` suffix and serving suffixes like `-instruct` are dropped.
+fn canonical_model_name(id: &str) -> String {
+    let mut name = id.rsplit('/').next().unwrap_or(id).to_lowercase();
+    if let Some((base, _)) = name.split_once(':') {
+        name = base.to_string();
+    }
+    for suffix in ["-versatile", "-instant", "-instruct", "-chat", "-it"] {
+        if let Some(stripped) = name.strip_suffix(suffix) {
+            name = stripped.to_string();
+        }
+    }
+    name
+}
+
+/// Read `CUSTOM_LLM_BASE_URL`, appending `/v1` when only a host is given
+/// (`http://localhost:8000` -> `http://localhost:8000/v1`).
+fn custom_base_url_from_env() -> Option<String> {
+    let raw = std::env::var("CUSTOM_LLM_BASE_URL").ok()?;
+    let base = raw.trim().trim_end_matches('/');
+    if base.is_empty() {
+        return None;
+    }
+    match url::Url::parse(base) {
+        Ok(url) if url.path().is_empty() || url.path() == "/" => Some(format!("{}/v1", base)),
+        Ok(_) => Some(base.to_string()),
+        Err(e) => {
+            tracing::warn!("Ignoring invalid CUSTOM_LLM_BASE_URL '{}': {}", raw, e);
+            None
+        }
+    }
 }
 
 impl LLMManager {
-    pub fn new(db: Arc<crate::db::Database>) -> Self {
+    pub fn new(db: Arc<crate::db::Database>, http: Arc<HttpClients>) -> Self {
         let mut api_keys = HashMap::new();
         
-        if let Ok(key) = std::env::var("OPENROUTER_API_KEY") {
-            api_keys.insert(ProviderType::OpenRouter, key);
+        for (provider, prefix) in [
+            (ProviderType::OpenRouter, "OPENROUTER"),
+            (ProviderType::Groq, "GROQ"),
+            (ProviderType::Cerebras, "CEREBRAS"),
+            (ProviderType::Cohere, "COHERE"),
+            (ProviderType::Pollinations, "POLLINATIONS"),
+        ] {
+            if provider_disabled(provider.as_str()) {
+                tracing::info!("{} is disabled via DISABLED_PROVIDERS", provider);
+                continue;
+            }
+            if let Some(pool) = KeyPool::from_env(prefix) {
+                api_keys.insert(provider, pool);
+            }
         }
-        if let Ok(key) = std::env::var("GROQ_API_KEY") {
-            api_keys.insert(ProviderType::Groq, key);
+
+        // Self-hosted servers often need no key, so the base URL alone enables the provider
+        let custom_base_url = custom_base_url_from_env()
+            .filter(|_| !provider_disabled(ProviderType::Custom.as_str()));
+        if let Some(base) = &custom_base_url {
+            tracing::info!("Custom OpenAI-compatible provider at {}", base);
+            let pool = KeyPool::from_env("CUSTOM_LLM").unwrap_or_else(|| KeyPool::new(vec![String::new()]));
+            api_keys.insert(ProviderType::Custom, pool);
+        } else if std::env::var("CUSTOM_LLM_API_KEY").is_ok_and(|k| !k.trim().is_empty()) {
+            tracing::warn!("CUSTOM_LLM_API_KEY is set but CUSTOM_LLM_BASE_URL is not; custom provider disabled");
         }
-        if let Ok(key) = std::env::var("CEREBRAS_API_KEY") {
-            api_keys.insert(ProviderType::Cerebras, key);
+
+        // PINNED_MODELS=smart=llama-3.3-70b-versatile,fast=llama-3.1-8b-instant
+        let pinned_models: HashMap<String, String> = std::env::var("PINNED_MODELS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|pair| {
+                let (alias, id) = pair.split_once('=')?;
+                let (alias, id) = (alias.trim(), id.trim());
+                if alias.is_empty() || id.is_empty() {
+                    None
+                } else {
+                    Some((alias.to_string(), id.to_string()))
+                }
+            })
+            .collect();
+        if !pinned_models.is_empty() {
+            tracing::info!("Pinned model aliases: {:?}", pinned_models);
         }
-        if let Ok(key) = std::env::var("COHERE_API_KEY") {
-            api_keys.insert(ProviderType::Cohere, key);
+
+        let embedder = Embedder::from_env(http.llm.clone());
+        match &embedder {
+            Some(embedder) => tracing::info!("Embeddings: {}", embedder.describe()),
+            None => tracing::info!("Embeddings: not configured, semantic retrieval disabled"),
         }
-        if let Ok(key) = std::env::var("POLLINATIONS_API_KEY") {
-            api_keys.insert(ProviderType::Pollinations, key);
+
+        let reranker = Reranker::from_env(http.llm.clone());
+        if let Some(reranker) = &reranker {
+            tracing::info!("Reranking: {}", reranker.describe());
         }
 
         Self {
             db,
             models: Arc::new(RwLock::new(Vec::new())),
             api_keys,
+            pinned_models,
+            embedder,
+            reranker,
+            custom_base_url,
+            health: CircuitBreaker::default(),
+            http,
+        }
+    }
+
+    /// False while `provider` is cooling down after repeated outages
+    pub fn provider_available(&self, provider: &ProviderType) -> bool {
+        self.health.is_available(provider.as_str())
+    }
+
+    /// False once `provider` has used up its daily requests, until its day window resets.
+    /// A failed lookup counts as available so one database error can't rule out every model.
+    pub async fn has_daily_budget(&self, provider: &ProviderType) -> bool {
+        match self.db.day_limit_reset(provider).await {
+            Ok(resets_at) => resets_at.is_none(),
+            Err(e) => {
+                tracing::warn!("Could not check the daily budget of {}: {}", provider, e);
+                true
+            }
         }
     }
 
-    pub async fn fetch_available_models(&self) -> Result<()> {
+    /// Providers that have failed since their last successful call
+    pub fn provider_health(&self) -> Vec<ProviderHealth> {
+        self.health.snapshot()
+    }
+
+    /// Feed a call's outcome to the circuit breaker. Only outages count against a
+    /// provider; rate limits and rejected requests say nothing about its health.
+    fn record_health(&self, provider: &ProviderType, error: Option<&anyhow::Error>) {
+        match error {
+            None => self.health.record_success(provider.as_str()),
+            Some(e) if is_outage(e) => self.health.record_failure(provider.as_str()),
+            Some(_) => {}
+        }
+    }
+
+    /// Model of the configured embedding provider, which stored vectors are keyed by.
+    pub fn embedding_model(&self) -> Option<&str> {
+        self.embedder.as_ref().map(Embedder::model)
+    }
+
+    pub fn embeddings_enabled(&self) -> bool {
+        self.embedder.is_some()
+    }
+
+    pub fn reranking_enabled(&self) -> bool {
+        self.reranker.is_some()
+    }
+
+    /// Rank `documents` against `query` with the configured reranker, best first.
+    pub async fn rerank(&self, query: &str, documents: &[String], top_n: usize) -> Result<Vec<(usize, f32)>> {
+        let reranker = self.reranker.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No reranker configured (set RERANK_PROVIDER)"))?;
+        reranker.rerank(query, documents, top_n).await
+    }
+
+    /// Embed `texts` with the configured embedding provider.
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
+        let embedder = self.embedder.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No embedding provider configured (set EMBEDDING_PROVIDER)"))?;
+        embedder.embed(texts).await
+    }
+
+    /// Chunk a stored source, embed the chunks and replace its previous embeddings.
+    /// Returns the number of chunks indexed (0 when embeddings are disabled).
+    pub async fn index_source(&self, source_id: i64, content: &str) -> Result<usize> {
+        let Some(embedder) = &self.embedder else {
+            return Ok(0);
+        };
+        let chunks = embeddings::chunk_text(content, EMBEDDING_CHUNK_CHARS, EMBEDDING_CHUNK_OVERLAP);
+        if chunks.is_empty() {
+            return Ok(0);
+        }
+        let vectors = embedder.embed(chunks.clone()).await?;
+        self.db.replace_embeddings(source_id, embedder.model(), &chunks, &vectors).await?;
+        Ok(chunks.len())
+    }
+
+    /// Stored sources whose chunks are closest to `query` by cosine similarity, best
+    /// first. Each source's content is narrowed to its best-matching chunk.
+    pub async fn semantic_search(&self, query: &str, limit: usize, user_id: Option<i64>) -> Result<Vec<crate::models::Source>> {
+        let Some(embedder) = &self.embedder else {
+            return Ok(Vec::new());
+        };
+        let min_score = std::env::var("SEMANTIC_MIN_SCORE")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(0.3);
+        let query_vector = embedder.embed(vec![query.to_string()]).await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Embedding provider returned no vector for the query"))?;
+        self.db.semantic_search(embedder.model(), &query_vector, limit, min_score, user_id).await
+    }
+
+    /// Resolve a pinned alias (e.g. "smart") to its concrete model ID.
+    /// Names that aren't aliases are returned unchanged.
+    pub fn resolve_model_alias(&self, name: &str) -> String {
+        match self.pinned_models.get(name) {
+            Some(id) => {
+                tracing::debug!("Resolved pinned model '{}' -> '{}'", name, id);
+                id.clone()
+            }
+            None => name.to_string(),
+        }
+    }
+
+    /// Refresh the model list from every configured provider. Provider failures are
+    /// logged and skipped; returns the number of models now available.
+    pub async fn fetch_available_models(&self, timeout: Duration) -> Result<usize> {
         let mut all_models = Vec::new();
         // Use a client with timeout to prevent hanging during startup
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()?;
+        let client = crate::http::client_builder(timeout).build()?;
 
         // 1. OpenRouter (Free models)
-        if let Some(key) = self.api_keys.get(&ProviderType::OpenRouter) {
+        if let Some(key) = self.api_keys.get(&ProviderType::OpenRouter).map(KeyPool::primary) {
             tracing::info!("Fetching OpenRouter models...");
             match self.fetch_openrouter_models(&client, key).await {
                 Ok(mut models) => all_models.append(&mut models),
@@ -184,7 +738,7 @@ impl LLMManager {
         }
 
         // 2. Groq
-        if let Some(key) = self.api_keys.get(&ProviderType::Groq) {
+        if let Some(key) = self.api_keys.get(&ProviderType::Groq).map(KeyPool::primary) {
             tracing::info!("Fetching Groq models...");
             match self.fetch_groq_models(&client, key).await {
                 Ok(mut models) => all_models.append(&mut models),
@@ -193,7 +747,7 @@ impl LLMManager {
         }
 
         // 3. Cerebras
-        if let Some(key) = self.api_keys.get(&ProviderType::Cerebras) {
+        if let Some(key) = self.api_keys.get(&ProviderType::Cerebras).map(KeyPool::primary) {
             tracing::info!("Fetching Cerebras models...");
             match self.fetch_cerebras_models(&client, key).await {
                 Ok(mut models) => all_models.append(&mut models),
@@ -202,7 +756,7 @@ impl LLMManager {
         }
 
         // 4. Cohere
-        if let Some(key) = self.api_keys.get(&ProviderType::Cohere) {
+        if let Some(key) = self.api_keys.get(&ProviderType::Cohere).map(KeyPool::primary) {
             tracing::info!("Fetching Cohere models...");
             match self.fetch_cohere_models(&client, key).await {
                 Ok(mut models) => all_models.append(&mut models),
@@ -211,7 +765,7 @@ impl LLMManager {
         }
 
         // 5. Pollinations
-        if let Some(key) = self.api_keys.get(&ProviderType::Pollinations) {
+        if let Some(key) = self.api_keys.get(&ProviderType::Pollinations).map(KeyPool::primary) {
             tracing::info!("Fetching Pollinations models...");
             match self.fetch_pollinations_models(&client, key).await {
                 Ok(mut models) => all_models.append(&mut models),
@@ -223,27 +777,42 @@ impl LLMManager {
             }
         }
 
+        // 6. Custom OpenAI-compatible server
+        if let (Some(base), Some(key)) = (&self.custom_base_url, self.api_keys.get(&ProviderType::Custom).map(KeyPool::primary)) {
+            tracing::info!("Fetching models from custom provider at {}...", base);
+            match self.fetch_custom_models(&client, base, key).await {
+                Ok(mut models) => all_models.append(&mut models),
+                Err(e) => tracing::error!("Failed to fetch custom provider models: {}", e),
+            }
+        }
+
         let count = all_models.len();
+        // A scheduled refresh during a provider outage shouldn't wipe a working list
+        if count == 0 {
+            let previous = self.models.read().await.len();
+            if previous > 0 {
+                tracing::warn!("Model refresh returned no models; keeping the previous {}", previous);
+                return Ok(previous);
+            }
+        }
         {
             let mut w = self.models.write().await;
             *w = all_models;
         }
         tracing::info!("Successfully updated model list. Total models: {}", count);
         
-        Ok(())
+        Ok(count)
     }
     
     pub async fn refresh_llm_limits(&self) -> Result<()> {
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
-            .build()?;
+        let client = &self.http.search;
 
-        if let Some(key) = self.api_keys.get(&ProviderType::OpenRouter) {
-            let _ = self.fetch_openrouter_limits(&client, key).await;
+        if let Some(key) = self.api_keys.get(&ProviderType::OpenRouter).map(KeyPool::primary) {
+            let _ = self.fetch_openrouter_limits(client, key).await;
         }
 
-        if let Some(key) = self.api_keys.get(&ProviderType::Pollinations) {
-            let _ = self.fetch_pollinations_limits(&client, key).await;
+        if let Some(key) = self.api_keys.get(&ProviderType::Pollinations).map(KeyPool::primary) {
+            let _ = self.fetch_pollinations_limits(client, key).await;
         }
 
         Ok(())
@@ -334,6 +903,33 @@ impl LLMManager {
         Ok(models)
     }
 
+    async fn fetch_custom_models(&self, client: &reqwest::Client, base: &str, key: &str) -> Result<Vec<Model>> {
+        let mut request = client.get(format!("{}/models", base));
+        if !key.is_empty() {
+            request = request.bearer_auth(key);
+        }
+        let resp = request.send().await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            return Err(ProviderError::from_status("Custom", status, &text).into());
+        }
+        let resp: StandardModelResponse = resp.json().await?;
+
+        let models = resp.data.into_iter()
+            .map(|m| Model {
+                id: m.id.clone(),
+                name: m.id,
+                provider: ProviderType::Custom,
+                context_length: m.context_window,
+                // Self-hosted: no per-token pricing
+                is_free: true,
+            })
+            .collect();
+
+        Ok(models)
+    }
+
     async fn fetch_groq_models(&self, client: &reqwest::Client, key: &str) -> Result<Vec<Model>> {
         let resp: StandardModelResponse = client.get("https://api.groq.com/openai/v1/models")
             .header("Authorization", format!("Bearer {}", key))
@@ -390,7 +986,7 @@ impl LLMManager {
                 id: m.name.clone(),
                 name: m.name,
                 provider: ProviderType::Cohere,
-                context_length: m.context_length.map(|l| l as i64),
+                context_length: m.context_length,
                 is_free: false,
             })
             .collect();
@@ -455,111 +1051,363 @@ impl LLMManager {
         self.db.check_rate_limit(&provider).await
     }
 
-    pub async fn chat_completion(&self, model_id: &str, messages: Vec<serde_json::Value>, tools: Option<Vec<serde_json::Value>>) -> Result<serde_json::Value> {
+    /// Run a chat completion against the provider hosting `model_id`.
+    ///
+    /// `params` are passed through to every provider. The seed is forwarded only to
+    /// providers that accept one (OpenRouter, Groq) and omitted otherwise; reproducibility
+    /// is best-effort and provider-dependent, so pair it with `temperature: 0` for the
+    /// most deterministic output.
+    pub async fn chat_completion(&self, model_id: &str, messages: Vec<serde_json::Value>, tools: Option<Vec<serde_json::Value>>, params: &GenerationParams) -> Result<serde_json::Value> {
         let model = self.get_model(model_id).await
-            .ok_or_else(|| anyhow::anyhow!("Model {} not found", model_id))?;
+            .ok_or_else(|| ProviderError::ModelNotFound(format!("Model {} not found", model_id)))?;
         
         let provider = model.provider;
         
         if !self.check_rate_limit(provider.clone()).await? {
-            return Err(anyhow::anyhow!("Rate limit exceeded for provider {}", provider));
+            return Err(ProviderError::RateLimited(format!("Rate limit exceeded for provider {}", provider)).into());
         }
 
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(120))
-            .build()?;
+        // Generations can legitimately take long; only the connect phase is kept short
+        let client = &self.http.llm;
             
-        let key = self.api_keys.get(&provider)
+        let pool = self.api_keys.get(&provider)
             .ok_or_else(|| anyhow::anyhow!("API key not found for provider {}", provider))?;
 
+        // On a 429, retry once per remaining key before giving up
+        let mut attempt = 0;
+        loop {
+            let (key_index, key) = pool.pick();
+            let started = Instant::now();
+            let result = self.send_chat_completion(client, &provider, model_id, key, &messages, tools.as_ref(), params).await;
+            self.record_call_stats(&provider, started.elapsed(), &result).await;
+            
+            match result {
+                Err(e) if matches!(e.downcast_ref::<ProviderError>(), Some(ProviderError::RateLimited(_))) => {
+                    pool.mark_rate_limited(key_index);
+                    attempt += 1;
+                    if attempt >= pool.keys.len() {
+                        return Err(e);
+                    }
+                    tracing::warn!("{} key #{} rate limited, rotating to next key", provider, key_index + 1);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Models on other configured providers that serve the same underlying model as
+    /// `model_id`, e.g. `llama-3.3-70b-versatile` (Groq) for `meta-llama/llama-3.3-70b-instruct:free`.
+    pub async fn equivalent_models(&self, model_id: &str) -> Vec<Model> {
+        let models = self.models.read().await;
+        let Some(current) = models.iter().find(|m| m.id == model_id) else {
+            return Vec::new();
+        };
+        let canonical = canonical_model_name(&current.id);
+        let mut seen = vec![current.provider.clone()];
+        let mut equivalents = Vec::new();
+        for model in models.iter() {
+            if !seen.contains(&model.provider) && canonical_model_name(&model.id) == canonical {
+                seen.push(model.provider.clone());
+                equivalents.push(model.clone());
+            }
+        }
+        equivalents
+    }
+
+    /// Like `chat_completion_stream`, but when the provider is rate limited, failing
+    /// (5xx) or unreachable, retries against equivalent models on the other providers.
+    /// Returns the stream together with the model that actually served it.
+    /// Set `PROVIDER_FAILOVER=false` to disable.
+    pub async fn chat_completion_stream_with_failover(&self, model_id: &str, messages: Vec<serde_json::Value>, tools: Option<Vec<serde_json::Value>>, params: &GenerationParams) -> Result<(ChatStream, String)> {
+        let first = self.chat_completion_stream(model_id, messages.clone(), tools.clone(), params).await;
+        let mut error = match first {
+            Ok(stream) => return Ok((stream, model_id.to_string())),
+            Err(e) if failover_enabled() && should_fail_over(&e) => e,
+            Err(e) => return Err(e),
+        };
+
+        for candidate in self.equivalent_models(model_id).await {
+            if !self.provider_available(&candidate.provider) {
+                tracing::debug!("Skipping failover to {} on unhealthy {}", candidate.id, candidate.provider);
+                continue;
+            }
+            tracing::warn!("{} failed ({}), failing over to {} on {}", model_id, error, candidate.id, candidate.provider);
+            match self.chat_completion_stream(&candidate.id, messages.clone(), tools.clone(), params).await {
+                Ok(stream) => return Ok((stream, candidate.id)),
+                Err(e) if should_fail_over(&e) => error = e,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(error)
+    }
+
+    /// Stream a chat completion as normalized deltas.
+    ///
+    /// OpenRouter, Groq, Cerebras and the custom provider are streamed over their
+    /// OpenAI-compatible SSE endpoints. Cohere and Pollinations fall back to a single buffered delta.
+    pub async fn chat_completion_stream(&self, model_id: &str, messages: Vec<serde_json::Value>, tools: Option<Vec<serde_json::Value>>, params: &GenerationParams) -> Result<ChatStream> {
+        let model = self.get_model(model_id).await
+            .ok_or_else(|| ProviderError::ModelNotFound(format!("Model {} not found", model_id)))?;
+        
+        let provider = model.provider;
+        let url = match provider {
+            ProviderType::OpenRouter | ProviderType::Custom => self.openai_compatible_url(&provider)?,
+            ProviderType::Groq => "https://api.groq.com/openai/v1/chat/completions".to_string(),
+            ProviderType::Cerebras => "https://api.cerebras.ai/v1/chat/completions".to_string(),
+            ProviderType::Cohere | ProviderType::Pollinations => {
+                let resp = self.chat_completion(model_id, messages, tools, params).await?;
+                let delta = ChatDelta::from_openai_json(&resp);
+                return Ok(Box::pin(futures::stream::once(async move { delta })));
+            }
+        };
+        
+        if !self.check_rate_limit(provider.clone()).await? {
+            return Err(ProviderError::RateLimited(format!("Rate limit exceeded for provider {}", provider)).into());
+        }
+
+        let client = &self.http.llm;
+        let pool = self.api_keys.get(&provider)
+            .ok_or_else(|| anyhow::anyhow!("API key not found for provider {}", provider))?;
+
+        let mut request = serde_json::json!({
+            "model": model_id,
+            "messages": messages,
+            "tools": tools,
+            "stream": true,
+            // Without this OpenAI-compatible providers leave usage out of streamed responses
+            "stream_options": { "include_usage": true }
+        });
+        params.apply_openai(&mut request, &provider);
+
+        let mut attempt = 0;
+        loop {
+            let (key_index, key) = pool.pick();
+            let started = Instant::now();
+            let mut builder = client.post(&url)
+                .header("Content-Type", "application/json");
+            if !key.is_empty() {
+                builder = builder.header("Authorization", format!("Bearer {}", key));
+            }
+            if provider == ProviderType::OpenRouter {
+                let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+                builder = builder
+                    .header("HTTP-Referer", format!("http://localhost:{}", port))
+                    .header("X-Title", "W9 Search");
+            }
+
+            let result = match crate::retry::send(&provider.to_string(), builder.json(&request)).await {
+                Ok(resp) if resp.status().is_success() => Ok(resp),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let text = resp.text().await.unwrap_or_default();
+                    Err(ProviderError::from_status(&provider.to_string(), status, &text).into())
+                }
+                Err(e) => Err(anyhow::Error::from(e)),
+            };
+            // Latency is to the first byte; a successful call is recorded with its tokens once the stream ends
+            let latency_ms = i64::try_from(started.elapsed().as_millis()).unwrap_or(i64::MAX);
+            if result.is_err() {
+                if let Err(e) = self.db.record_provider_call(&provider, latency_ms, None, false).await {
+                    tracing::warn!("Failed to record stats for {}: {}", provider, e);
+                }
+            }
+            self.record_health(&provider, result.as_ref().err());
+
+            match result {
+                Ok(resp) => {
+                    self.record_limit_headers(&provider, resp.headers()).await;
+                    let stats = StreamCallStats { db: self.db.clone(), provider: provider.clone(), latency_ms, tokens: None, failed: false };
+                    let state = SseState { resp, buf: Vec::new(), pending: VecDeque::new(), done: false, stats };
+                    return Ok(Box::pin(futures::stream::unfold(state, SseState::next_delta)));
+                }
+                Err(e) if matches!(e.downcast_ref::<ProviderError>(), Some(ProviderError::RateLimited(_))) => {
+                    pool.mark_rate_limited(key_index);
+                    attempt += 1;
+                    if attempt >= pool.keys.len() {
+                        return Err(e);
+                    }
+                    tracing::warn!("{} key #{} rate limited, rotating to next key", provider, key_index + 1);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Drain a delta stream into an OpenAI-shaped response. A stream that yields
+    /// nothing maps to an empty `choices` array, like a provider returning no output.
+    /// `usage` is only present when the provider reported it.
+    pub async fn collect_chat_stream(mut stream: ChatStream) -> Result<serde_json::Value> {
+        let mut content = String::new();
+        let mut tool_calls: BTreeMap<usize, ToolCallDelta> = BTreeMap::new();
+        let mut finish_reason = None;
+        let mut usage = None;
+        let mut received = false;
+
+        while let Some(delta) = stream.next().await {
+            let delta = delta?;
+            received |= delta.content.is_some() || !delta.tool_calls.is_empty() || delta.finish_reason.is_some();
+            if let Some(text) = delta.content {
+                content.push_str(&text);
+            }
+            for call in delta.tool_calls {
+                let entry = tool_calls.entry(call.index).or_default();
+                entry.id = entry.id.take().or(call.id);
+                entry.name = entry.name.take().or(call.name);
+                if let Some(args) = call.arguments {
+                    entry.arguments.get_or_insert_with(String::new).push_str(&args);
+                }
+            }
+            finish_reason = delta.finish_reason.or(finish_reason);
+            usage = delta.usage.or(usage);
+        }
+
+        let mut response = serde_json::json!({ "choices": [] });
+        if let Some(usage) = usage {
+            response["usage"] = serde_json::json!(usage);
+        }
+        if !received {
+            return Ok(response);
+        }
+
+        let mut message = serde_json::json!({ "role": "assistant", "content": content });
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = tool_calls.into_values().map(|call| serde_json::json!({
+                "id": call.id.unwrap_or_default(),
+                "type": "function",
+                "function": {
+                    "name": call.name.unwrap_or_default(),
+                    "arguments": call.arguments.unwrap_or_default()
+                }
+            })).collect();
+        }
+
+        response["choices"] = serde_json::json!([{ "index": 0, "message": message, "finish_reason": finish_reason }]);
+        Ok(response)
+    }
+
+    /// Chat completions endpoint for the providers sharing the OpenRouter request path.
+    fn openai_compatible_url(&self, provider: &ProviderType) -> Result<String> {
+        match provider {
+            ProviderType::Custom => self.custom_base_url.as_ref()
+                .map(|base| format!("{}/chat/completions", base))
+                .ok_or_else(|| anyhow::anyhow!("CUSTOM_LLM_BASE_URL is not set")),
+            _ => Ok("https://openrouter.ai/api/v1/chat/completions".to_string()),
+        }
+    }
+
+    /// Store the request quota headers Groq and Cerebras return with each completion.
+    async fn record_limit_headers(&self, provider: &ProviderType, headers: &reqwest::header::HeaderMap) {
+        let header_i64 = |name: &str| headers.get(name)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.parse::<i64>().ok());
+        let (remaining, limit) = match provider {
+            ProviderType::Groq => (header_i64("x-ratelimit-remaining-requests"), header_i64("x-ratelimit-limit-requests")),
+            ProviderType::Cerebras => (header_i64("x-ratelimit-remaining-requests-day"), header_i64("x-ratelimit-limit-requests-day")),
+            _ => return,
+        };
+        if remaining.is_some() || limit.is_some() {
+            let _ = self.db.update_provider_limits(provider, None, remaining, None, limit).await;
+        }
+    }
+
+    async fn record_call_stats(&self, provider: &ProviderType, latency: Duration, result: &Result<serde_json::Value>) {
+        // Cohere responses are normalized with zeroed usage, so treat 0 as unreported
+        let tokens = result.as_ref().ok()
+            .and_then(|resp| resp["usage"]["total_tokens"].as_i64())
+            .filter(|&t| t > 0);
+        let latency_ms = i64::try_from(latency.as_millis()).unwrap_or(i64::MAX);
+        if let Err(e) = self.db.record_provider_call(provider, latency_ms, tokens, result.is_ok()).await {
+            tracing::warn!("Failed to record stats for {}: {}", provider, e);
+        }
+        self.record_health(provider, result.as_ref().err());
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn send_chat_completion(
+        &self,
+        client: &reqwest::Client,
+        provider: &ProviderType,
+        model_id: &str,
+        key: &str,
+        messages: &[serde_json::Value],
+        tools: Option<&Vec<serde_json::Value>>,
+        params: &GenerationParams,
+    ) -> Result<serde_json::Value> {
         match provider {
-            ProviderType::OpenRouter => {
-                let request = serde_json::json!({
+            ProviderType::OpenRouter | ProviderType::Custom => {
+                let mut request = serde_json::json!({
                     "model": model_id,
                     "messages": messages,
                     "tools": tools
                 });
+                params.apply_openai(&mut request, provider);
                 
-                let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-                let resp = client.post("https://openrouter.ai/api/v1/chat/completions")
-                    .header("Authorization", format!("Bearer {}", key))
-                    .header("Content-Type", "application/json")
-                    .header("HTTP-Referer", format!("http://localhost:{}", port))
-                    .header("X-Title", "W9 Search")
-                    .json(&request)
-                    .send()
-                    .await?;
+                let mut builder = client.post(self.openai_compatible_url(provider)?)
+                    .header("Content-Type", "application/json");
+                if !key.is_empty() {
+                    builder = builder.header("Authorization", format!("Bearer {}", key));
+                }
+                if provider == &ProviderType::OpenRouter {
+                    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+                    builder = builder
+                        .header("HTTP-Referer", format!("http://localhost:{}", port))
+                        .header("X-Title", "W9 Search");
+                }
+                let resp = crate::retry::send(&provider.to_string(), builder.json(&request)).await?;
                     
                 if !resp.status().is_success() {
+                    let status = resp.status();
                     let text = resp.text().await?;
-                    return Err(anyhow::anyhow!("OpenRouter Error: {}", text));
+                    return Err(ProviderError::from_status(&provider.to_string(), status, &text).into());
                 }
                 
                 Ok(resp.json().await?)
             },
             ProviderType::Groq => {
-                let request = serde_json::json!({
+                let mut request = serde_json::json!({
                     "model": model_id,
                     "messages": messages,
                     "tools": tools
                 });
+                params.apply_openai(&mut request, provider);
                 
-                let resp = client.post("https://api.groq.com/openai/v1/chat/completions")
+                let resp = crate::retry::send("Groq", client.post("https://api.groq.com/openai/v1/chat/completions")
                     .header("Authorization", format!("Bearer {}", key))
                     .header("Content-Type", "application/json")
-                    .json(&request)
-                    .send()
+                    .json(&request))
                     .await?;
 
                 if !resp.status().is_success() {
+                    let status = resp.status();
                     let text = resp.text().await?;
-                    return Err(anyhow::anyhow!("Groq Error: {}", text));
+                    return Err(ProviderError::from_status("Groq", status, &text).into());
                 }
                 
-                let headers = resp.headers();
-                let remaining_req = headers.get("x-ratelimit-remaining-requests")
-                    .and_then(|h| h.to_str().ok())
-                    .and_then(|s| s.parse::<i64>().ok());
-                let limit_req = headers.get("x-ratelimit-limit-requests")
-                    .and_then(|h| h.to_str().ok())
-                    .and_then(|s| s.parse::<i64>().ok());
-                    
-                if remaining_req.is_some() || limit_req.is_some() {
-                    let _ = self.db.update_provider_limits(&ProviderType::Groq, None, remaining_req, None, limit_req).await;
-                }
+                self.record_limit_headers(&ProviderType::Groq, resp.headers()).await;
 
                 Ok(resp.json().await?)
             },
             ProviderType::Cerebras => {
-                let request = serde_json::json!({
+                let mut request = serde_json::json!({
                     "model": model_id,
                     "messages": messages,
                     "tools": tools
                 });
+                params.apply_openai(&mut request, provider);
                 
-                let resp = client.post("https://api.cerebras.ai/v1/chat/completions")
+                let resp = crate::retry::send("Cerebras", client.post("https://api.cerebras.ai/v1/chat/completions")
                     .header("Authorization", format!("Bearer {}", key))
                     .header("Content-Type", "application/json")
-                    .json(&request)
-                    .send()
+                    .json(&request))
                     .await?;
 
                 if !resp.status().is_success() {
+                    let status = resp.status();
                     let text = resp.text().await?;
-                    return Err(anyhow::anyhow!("Cerebras Error: {}", text));
+                    return Err(ProviderError::from_status("Cerebras", status, &text).into());
                 }
 
-                let headers = resp.headers();
-                let remaining_day = headers.get("x-ratelimit-remaining-requests-day")
-                    .and_then(|h| h.to_str().ok())
-                    .and_then(|s| s.parse::<i64>().ok());
-                let limit_day = headers.get("x-ratelimit-limit-requests-day")
-                    .and_then(|h| h.to_str().ok())
-                    .and_then(|s| s.parse::<i64>().ok());
-                    
-                if remaining_day.is_some() || limit_day.is_some() {
-                    let _ = self.db.update_provider_limits(&ProviderType::Cerebras, None, remaining_day, None, limit_day).await;
-                }
+                self.record_limit_headers(&ProviderType::Cerebras, resp.headers()).await;
 
                 Ok(resp.json().await?)
             },
@@ -585,23 +1433,24 @@ impl LLMManager {
                     }
                 }
 
-                let request = serde_json::json!({
+                let mut request = serde_json::json!({
                     "model": model_id,
                     "message": last_message,
                     "chat_history": chat_history,
                 });
+                params.apply_cohere(&mut request);
 
-                let resp = client.post("https://api.cohere.ai/v1/chat")
+                let resp = crate::retry::send("Cohere", client.post("https://api.cohere.ai/v1/chat")
                     .header("Authorization", format!("Bearer {}", key))
                     .header("Content-Type", "application/json")
                     .header("X-Client-Name", "w9-search")
-                    .json(&request)
-                    .send()
+                    .json(&request))
                     .await?;
 
                 if !resp.status().is_success() {
+                    let status = resp.status();
                     let text = resp.text().await?;
-                    return Err(anyhow::anyhow!("Cohere Error: {}", text));
+                    return Err(ProviderError::from_status("Cohere", status, &text).into());
                 }
 
                 let cohere_resp: serde_json::Value = resp.json().await?;
@@ -631,22 +1480,23 @@ impl LLMManager {
                 }))
             },
             ProviderType::Pollinations => {
-                let request = serde_json::json!({
+                let mut request = serde_json::json!({
                     "model": model_id,
                     "messages": messages,
                     "tools": tools
                 });
+                params.apply_openai(&mut request, provider);
                 
-                let resp = client.post("https://gen.pollinations.ai/v1/chat/completions")
+                let resp = crate::retry::send("Pollinations", client.post("https://gen.pollinations.ai/v1/chat/completions")
                     .header("Authorization", format!("Bearer {}", key))
                     .header("Content-Type", "application/json")
-                    .json(&request)
-                    .send()
+                    .json(&request))
                     .await?;
 
                 if !resp.status().is_success() {
+                    let status = resp.status();
                     let text = resp.text().await?;
-                    return Err(anyhow::anyhow!("Pollinations Error: {}", text));
+                    return Err(ProviderError::from_status("Pollinations", status, &text).into());
                 }
 
                 Ok(resp.json().await?)
@@ -0,0 +1,60 @@
+use std::time::Instant;
+
+use axum::{
+    extract::Request,
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Run each request in a `request` span carrying its ID (the client's `X-Request-Id`,
+/// or a new one), method and path, and log its status and latency when it completes.
+/// Handlers fill in `thread_id`, `provider` and `model` once they know them.
+pub async fn trace_requests(req: Request, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|id| id.trim().chars().take(64).collect::<String>())
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.uri().path(),
+        thread_id = tracing::field::Empty,
+        provider = tracing::field::Empty,
+        model = tracing::field::Empty,
+    );
+    let quiet = req.uri().path().starts_with("/static") || req.uri().path() == "/health";
+
+    let started = Instant::now();
+    let mut response = next.run(req).instrument(span.clone()).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    span.in_scope(|| {
+        let status = response.status().as_u16();
+        if quiet {
+            tracing::debug!(status, latency_ms, "Request finished");
+        } else {
+            tracing::info!(status, latency_ms, "Request finished");
+        }
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Attach the model answering this request, and its provider, to the current request span
+pub fn record_model(model: &str, provider: Option<&str>) {
+    let span = tracing::Span::current();
+    span.record("model", model);
+    if let Some(provider) = provider {
+        span.record("provider", provider);
+    }
+}
@@ -1,14 +1,31 @@
+mod alerts;
 mod api;
+mod auth;
+mod circuit;
 mod db;
+mod documents;
+mod domains;
+mod embeddings;
+mod error;
+mod http;
+mod import;
 mod llm;
+mod logging;
+mod mcp;
+mod mcp_server;
 mod models;
 mod rag;
+mod ratelimit;
+mod rerank;
+mod retry;
+mod scheduler;
 mod search;
 mod templates;
+mod tokens;
 mod tools;
 
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 use std::sync::Arc;
@@ -23,8 +40,16 @@ use crate::search::WebSearch;
 pub struct AppState {
     pub db: Arc<Database>,
     pub llm_manager: Arc<LLMManager>,
-    /// Default model ID (first in models)
+    /// Default model ID: DEFAULT_MODEL, else the one persisted in `settings`
     pub default_model: String,
+    /// Running streamed queries, keyed by request ID
+    pub in_flight: api::InFlightQueries,
+    /// Per-client query budgets enforced by `ratelimit::limit_queries`
+    pub query_limiter: Arc<ratelimit::QueryRateLimiter>,
+    /// Pooled clients shared by every outbound call
+    pub http: Arc<http::HttpClients>,
+    /// Tools the model may call: built-ins plus those of connected MCP servers
+    pub tools: Arc<tools::ToolRegistry>,
 }
 
 #[tokio::main]
@@ -35,12 +60,23 @@ async fn main() {
         .parse::<tracing::Level>()
         .unwrap_or(tracing::Level::INFO);
     
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_max_level(log_level)
         .with_target(true)
         .with_writer(std::io::stderr)
-        .with_ansi(false) // Disable ANSI colors for Docker logs
-        .init();
+        .with_ansi(false); // Disable ANSI colors for Docker logs
+    // LOG_FORMAT=json: one JSON object per line, with the request span's fields
+    // (request_id, thread_id, provider, model), for Loki/Elastic
+    if std::env::var("LOG_FORMAT").is_ok_and(|v| v.eq_ignore_ascii_case("json")) {
+        subscriber
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init();
+    } else {
+        subscriber.init();
+    }
     
     // Set panic hook to log panics with full backtrace
     std::panic::set_hook(Box::new(|panic_info| {
@@ -83,7 +119,7 @@ async fn run() -> anyhow::Result<()> {
     let database_url = std::env::var("DATABASE_URL")
         .unwrap_or_else(|_| "sqlite:/app/data/w9_search.db".to_string());
     
-    tracing::info!("Database URL: {}", database_url);
+    tracing::info!("Database URL: {}", db::redact_url(&database_url));
     
     // Ensure database directory exists if path contains directories
     if let Some(path) = database_url.strip_prefix("sqlite:") {
@@ -93,26 +129,37 @@ async fn run() -> anyhow::Result<()> {
             // Only create directory if parent is not empty (i.e., path contains directories)
             if !parent.as_os_str().is_empty() {
                 tracing::info!("Creating database directory: {:?}", parent);
-                std::fs::create_dir_all(parent)?;
+                std::fs::create_dir_all(parent).map_err(|e| {
+                    anyhow::anyhow!("Failed to create database directory {:?}: {}. Check permissions on its parent or set DATABASE_URL.", parent, e)
+                })?;
                 
                 // Verify directory is writable
                 let metadata = std::fs::metadata(parent)?;
                 tracing::info!("Directory permissions: {:?}", metadata.permissions());
                 
-                // Test write access by creating a temp file
+                // Test write access by creating a temp file. An existing, writable database
+                // can still be used; otherwise SQLite would fail later with a vaguer error.
                 let test_file = parent.join(".write_test");
                 match std::fs::File::create(&test_file) {
                     Ok(_) => {
                         std::fs::remove_file(&test_file).ok();
                         tracing::info!("Directory is writable");
                     }
-                    Err(e) => {
+                    Err(e) if db_path.exists() && std::fs::OpenOptions::new().write(true).open(db_path).is_ok() => {
                         tracing::warn!(
-                            "Database directory {:?} appears not to be writable: {}. \
-                            SQLite might fail to open the database.",
+                            "Database directory {:?} is not writable ({}), but the database file exists \
+                            and is writable; continuing",
                             parent, e
                         );
                     }
+                    Err(e) => {
+                        return Err(anyhow::anyhow!(
+                            "Database directory {:?} is not writable: {}. The server process needs write \
+                            and execute permission on this directory to create {:?} (e.g. chown it to the \
+                            service user or mount a writable volume), or point DATABASE_URL at a writable path.",
+                            parent, e, db_path
+                        ));
+                    }
                 }
             } else {
                 tracing::info!("Database file is in current directory, no parent directory to create");
@@ -151,22 +198,85 @@ async fn run() -> anyhow::Result<()> {
         }
     }
 
+    // Validate per-domain extraction selectors up front so bad config shows in startup logs
+    WebSearch::domain_selectors();
+
+    let http = Arc::new(http::HttpClients::from_env()?);
+    let tools = Arc::new(tools::ToolRegistry::with_builtins());
+
     // Initialize LLM Manager
-    let llm_manager = Arc::new(LLMManager::new(db.clone()));
+    let llm_manager = Arc::new(LLMManager::new(db.clone(), http.clone()));
+
+    // `--mcp-serve`: act as an MCP server on stdio for desktop hosts instead of serving HTTP
+    if std::env::args().skip(1).any(|arg| arg == "--mcp-serve") {
+        return mcp_server::serve_stdio(db, http, llm_manager, tools).await;
+    }
+
+    // MCP servers connect in the background; their tools appear once the handshake is done
+    tokio::spawn(mcp::connect_configured(tools.clone()));
     
     // Start background initialization task
     // We do this in the background so the server can start up and pass health checks immediately
     // even if external APIs are slow or timing out.
     let manager_clone = llm_manager.clone();
     let db_clone = db.clone();
+    let http_clone = http.clone();
+    // DEFAULT_MODEL wins and is remembered; otherwise reuse the default from a previous run
+    let default_model = match std::env::var("DEFAULT_MODEL").ok().filter(|m| !m.trim().is_empty()) {
+        Some(model) => {
+            let model = model.trim().to_string();
+            if let Err(e) = db.set_setting("default_model", &model).await {
+                tracing::warn!("Failed to persist default model: {}", e);
+            }
+            Some(model)
+        }
+        None => db.get_setting("default_model").await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to load persisted default model: {}", e);
+            None
+        }),
+    };
+    let has_default_model = default_model.is_some();
+
     tokio::spawn(async move {
-        tracing::info!("Background init: Fetching available models...");
-        if let Err(e) = manager_clone.fetch_available_models().await {
-            tracing::error!("Background init: Failed to fetch models: {}", e);
+        // Cold starts on slow networks often fail the first fetch; retry with backoff
+        // and a more generous timeout so a transient blip doesn't leave the app model-less
+        let attempts = std::env::var("MODEL_FETCH_RETRIES")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(3)
+            .max(1);
+        let timeout_secs = std::env::var("MODEL_FETCH_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(60);
+        
+        for attempt in 1..=attempts {
+            tracing::info!("Background init: Fetching available models (attempt {}/{})...", attempt, attempts);
+            match manager_clone.fetch_available_models(std::time::Duration::from_secs(timeout_secs)).await {
+                Ok(count) if count > 0 => {
+                    // First run without DEFAULT_MODEL: remember the first model so the default is stable
+                    if !has_default_model {
+                        if let Some(model) = manager_clone.get_models().await.first() {
+                            tracing::info!("Background init: Persisting '{}' as the default model", model.id);
+                            if let Err(e) = db_clone.set_setting("default_model", &model.id).await {
+                                tracing::warn!("Background init: Failed to persist default model: {}", e);
+                            }
+                        }
+                    }
+                    break;
+                }
+                Ok(_) => tracing::warn!("Background init: No models available yet"),
+                Err(e) => tracing::error!("Background init: Failed to fetch models: {}", e),
+            }
+            if attempt < attempts {
+                let backoff = std::time::Duration::from_secs(2u64.pow(attempt));
+                tracing::info!("Background init: Retrying model fetch in {:?}", backoff);
+                tokio::time::sleep(backoff).await;
+            }
         }
         
         tracing::info!("Background init: Syncing Tavily usage...");
-        if let Err(e) = WebSearch::sync_tavily_usage(&db_clone).await {
+        if let Err(e) = WebSearch::sync_tavily_usage(&http_clone, &db_clone).await {
             tracing::error!("Background init: Failed to sync Tavily usage: {}", e);
         }
         
@@ -175,17 +285,24 @@ async fn run() -> anyhow::Result<()> {
     
     // We don't display models here anymore as they are loaded in background
     // But we still need a default model for the state.
-    // Since models aren't loaded yet, we'll use a placeholder or empty string
+    // Without a configured or persisted one, we'll use a placeholder
     // The frontend should handle fetching models via API or handle empty state.
     
-    let default_model = "loading...".to_string();
+    let default_model = default_model.unwrap_or_else(|| "loading...".to_string());
+    tracing::info!("Default model: {}", default_model);
 
     let state = AppState {
         db,
         llm_manager,
         default_model,
+        in_flight: Default::default(),
+        query_limiter: Default::default(),
+        http,
+        tools,
     };
 
+    scheduler::spawn(state.clone());
+
     // Check if static directory exists
     if !std::path::Path::new("static").exists() {
         tracing::warn!("Static directory not found, creating it...");
@@ -198,16 +315,57 @@ async fn run() -> anyhow::Result<()> {
     }
     
     let app = Router::new()
-        .route("/", get(templates::index))
-        .route("/models", get(templates::models))
-        .route("/health", get(health_check))
-        .route("/api/query", post(api::handle_query))
-        .route("/api/query/stream", post(api::handle_query_stream))
+        .route(
+            "/api/query",
+            post(api::handle_query).layer(axum::middleware::from_fn_with_state(state.clone(), ratelimit::limit_queries)),
+        )
+        .route(
+            "/api/query/stream",
+            post(api::handle_query_stream).layer(axum::middleware::from_fn_with_state(state.clone(), ratelimit::limit_queries)),
+        )
+        .route("/api/query/:request_id/cancel", post(api::cancel_query))
         .route("/api/sources", get(api::get_sources))
+        .route("/api/sources/urls", get(api::get_source_urls))
+        .route("/api/sources/:id/reextract", post(api::reextract_source))
+        .route("/api/metrics", get(api::get_metrics))
+        .route("/api/usage", get(api::get_usage))
+        .route("/api/ingest", post(api::ingest))
+        .route(
+            "/api/documents",
+            post(api::upload_documents).layer(axum::extract::DefaultBodyLimit::max(api::max_upload_bytes())),
+        )
+        .route("/api/domain-rules", get(api::list_domain_rules).post(api::add_domain_rule))
+        .route("/api/domain-rules/:id", delete(api::delete_domain_rule))
+        .route("/api/alerts", get(api::list_alerts).post(api::create_alert))
+        .route("/api/alerts/:id", delete(api::delete_alert))
+        .route("/api/alerts/:id/run", post(api::run_alert))
         .route("/api/sync", post(api::sync_limits))
         .route("/api/threads", get(api::get_threads))
+        .route(
+            "/api/threads/import",
+            post(api::import_threads).layer(axum::extract::DefaultBodyLimit::max(api::max_upload_bytes())),
+        )
         .route("/api/threads/:id/messages", get(api::get_thread_messages))
+        // Everything above needs a session when AUTH_ENABLED=true
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth::require_session))
+        .route("/", get(templates::index))
+        .route("/login", get(templates::login))
+        .route("/models", get(templates::models))
+        .route("/admin/limits", get(templates::admin_limits))
+        // Admin rights come from ADMIN_TOKEN or an ADMIN_USERS session, checked without require_session
+        .route(
+            "/api/admin/limits",
+            get(api::get_provider_limits)
+                .put(api::set_provider_limits)
+                .layer(axum::middleware::from_fn_with_state(state.clone(), auth::require_admin)),
+        )
+        .route("/health", get(health_check))
+        .route("/api/auth/register", post(api::register))
+        .route("/api/auth/login", post(api::login))
+        .route("/api/auth/logout", post(api::logout))
+        .route("/api/auth/me", get(api::current_user))
         .nest_service("/static", ServeDir::new("static"))
+        .layer(axum::middleware::from_fn(logging::trace_requests))
         .layer(CorsLayer::permissive())
         .with_state(state);
     
@@ -250,7 +408,8 @@ async fn run() -> anyhow::Result<()> {
     };
     
     // Start server with error handling
-    match axum::serve(listener, app)
+    // Client addresses are needed to rate-limit queries per IP
+    match axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
         .with_graceful_shutdown(shutdown)
         .await
     {
@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::oneshot;
+
+use crate::tools::{Tool, ToolContext, ToolRegistry};
+
+/// MCP revision this client speaks; servers answer with the one they picked
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Longest tool result handed back to the model, in chars
+const MAX_RESULT_CHARS: usize = 20_000;
+
+/// How long to wait for any MCP response (`MCP_TIMEOUT_SECS`, default 60).
+fn timeout() -> Duration {
+    crate::http::env_timeout("MCP_TIMEOUT_SECS", 60)
+}
+
+/// One entry of `MCP_SERVERS`: a command speaking MCP over stdio, or the SSE endpoint
+/// of a running server.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ServerConfig {
+    Stdio {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+    },
+    Sse {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+}
+
+/// `MCP_SERVERS`: JSON object of server name -> config. The `{"mcpServers": {...}}`
+/// wrapper used by desktop MCP hosts is accepted too, so their config can be pasted as-is.
+fn configured_servers() -> Vec<(String, ServerConfig)> {
+    let raw = match std::env::var("MCP_SERVERS") {
+        Ok(raw) if !raw.trim().is_empty() => raw,
+        _ => return Vec::new(),
+    };
+    let mut value: Value = match serde_json::from_str(&raw) {
+        Ok(value) => value,
+        Err(e) => {
+            tracing::warn!("Ignoring MCP_SERVERS: not valid JSON: {}", e);
+            return Vec::new();
+        }
+    };
+    if let Some(inner) = value.get_mut("mcpServers") {
+        value = inner.take();
+    }
+    let Value::Object(entries) = value else {
+        tracing::warn!("Ignoring MCP_SERVERS: expected a JSON object of server name -> config");
+        return Vec::new();
+    };
+    entries
+        .into_iter()
+        .filter_map(|(name, config)| match serde_json::from_value(config) {
+            Ok(config) => Some((name, config)),
+            Err(e) => {
+                tracing::warn!("Ignoring MCP server '{}': needs a \"command\" or a \"url\": {}", name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value>>>>>;
+
+/// Hand a JSON-RPC response to the request waiting for it. Notifications and requests
+/// from the server are not needed for tool calls and are ignored.
+fn dispatch(server: &str, pending: &Pending, message: &str) {
+    let value: Value = match serde_json::from_str(message) {
+        Ok(value) => value,
+        Err(_) => {
+            tracing::debug!("[mcp:{}] ignoring non-JSON output: {}", server, message);
+            return;
+        }
+    };
+    if value.get("method").is_some() {
+        tracing::debug!("[mcp:{}] ignoring {}", server, value["method"]);
+        return;
+    }
+    let Some(id) = value.get("id").and_then(Value::as_u64) else {
+        return;
+    };
+    let Some(tx) = pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&id) else {
+        return;
+    };
+    let result = match value.get("error") {
+        Some(error) => Err(anyhow!(
+            "{} returned an error: {}",
+            server,
+            error["message"].as_str().unwrap_or("unknown error")
+        )),
+        None => Ok(value.get("result").cloned().unwrap_or(Value::Null)),
+    };
+    let _ = tx.send(result);
+}
+
+/// Where outgoing messages go
+enum Sink {
+    /// Newline-delimited JSON on the child's stdin; the child is killed when dropped
+    Stdio { stdin: tokio::sync::Mutex<ChildStdin>, _child: Mutex<Child> },
+    /// POSTed to the endpoint the server announced on its event stream
+    Sse { client: reqwest::Client, endpoint: String, headers: reqwest::header::HeaderMap },
+}
+
+/// A JSON-RPC session with one MCP server
+struct Connection {
+    server: String,
+    sink: Sink,
+    pending: Pending,
+    next_id: AtomicU64,
+}
+
+impl Connection {
+    async fn spawn_stdio(server: &str, command: &str, args: &[String], env: &HashMap<String, String>) -> Result<Self> {
+        let mut child = Command::new(command)
+            .args(args)
+            .envs(env)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("could not start '{}': {}", command, e))?;
+        let stdin = child.stdin.take().ok_or_else(|| anyhow!("no stdin for '{}'", command))?;
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!("no stdout for '{}'", command))?;
+
+        if let Some(stderr) = child.stderr.take() {
+            let server = server.to_string();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    tracing::debug!("[mcp:{}] {}", server, line);
+                }
+            });
+        }
+
+        let pending = Pending::default();
+        let reader_pending = pending.clone();
+        let reader_server = server.to_string();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                dispatch(&reader_server, &reader_pending, line.trim());
+            }
+            tracing::warn!("MCP server '{}' exited", reader_server);
+            // Dropping the senders fails every call still waiting
+            reader_pending.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        });
+
+        Ok(Self {
+            server: server.to_string(),
+            sink: Sink::Stdio { stdin: tokio::sync::Mutex::new(stdin), _child: Mutex::new(child) },
+            pending,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Open the server's event stream and wait for the `endpoint` event naming where
+    /// requests are POSTed; responses arrive as `message` events on the stream.
+    async fn connect_sse(server: &str, url: &str, headers: &HashMap<String, String>) -> Result<Self> {
+        let headers: reqwest::header::HeaderMap = headers
+            .iter()
+            .map(|(name, value)| Ok((name.parse()?, value.parse()?)))
+            .collect::<Result<_>>()
+            .map_err(|e| anyhow!("invalid header: {}", e))?;
+        // The event stream stays open for the life of the process, so only the connect phase is bounded
+        let client = reqwest::Client::builder()
+            .connect_timeout(crate::http::env_timeout("HTTP_CONNECT_TIMEOUT_SECS", 5))
+            .build()?;
+        let mut resp = client
+            .get(url)
+            .headers(headers.clone())
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .send()
+            .await?
+            .error_for_status()?;
+        let base = url::Url::parse(url)?;
+
+        let pending = Pending::default();
+        let reader_pending = pending.clone();
+        let reader_server = server.to_string();
+        let (endpoint_tx, endpoint_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let mut endpoint_tx = Some(endpoint_tx);
+            let mut events = SseEvents::default();
+            while let Ok(Some(bytes)) = resp.chunk().await {
+                for (event, data) in events.push(&bytes) {
+                    match event.as_str() {
+                        "endpoint" => {
+                            if let (Some(tx), Ok(endpoint)) = (endpoint_tx.take(), base.join(data.trim())) {
+                                let _ = tx.send(endpoint.to_string());
+                            }
+                        }
+                        "message" | "" => dispatch(&reader_server, &reader_pending, &data),
+                        _ => {}
+                    }
+                }
+            }
+            tracing::warn!("MCP server '{}' closed its event stream", reader_server);
+            reader_pending.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        });
+
+        let endpoint = tokio::time::timeout(timeout(), endpoint_rx)
+            .await
+            .map_err(|_| anyhow!("no endpoint event within {}s", timeout().as_secs()))?
+            .map_err(|_| anyhow!("event stream closed before the endpoint event"))?;
+        tracing::debug!("MCP server '{}' takes requests at {}", server, endpoint);
+
+        Ok(Self {
+            server: server.to_string(),
+            sink: Sink::Sse { client, endpoint, headers },
+            pending,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    async fn send(&self, message: &Value) -> Result<()> {
+        match &self.sink {
+            Sink::Stdio { stdin, .. } => {
+                let mut line = serde_json::to_vec(message)?;
+                line.push(b'\n');
+                let mut stdin = stdin.lock().await;
+                stdin.write_all(&line).await?;
+                stdin.flush().await?;
+            }
+            Sink::Sse { client, endpoint, headers } => {
+                client
+                    .post(endpoint)
+                    .headers(headers.clone())
+                    .json(message)
+                    .timeout(timeout())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).insert(id, tx);
+
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(e) = self.send(&message).await {
+            self.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+            return Err(e);
+        }
+        match tokio::time::timeout(timeout(), rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(anyhow!("MCP server '{}' closed the connection", self.server)),
+            Err(_) => {
+                self.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+                Err(anyhow!("MCP server '{}' did not answer {} within {}s", self.server, method, timeout().as_secs()))
+            }
+        }
+    }
+
+    async fn notify(&self, method: &str) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method })).await
+    }
+}
+
+/// Splits a `text/event-stream` body into `(event, data)` pairs.
+#[derive(Default)]
+struct SseEvents {
+    buf: Vec<u8>,
+    event: String,
+    data: Vec<String>,
+}
+
+impl SseEvents {
+    fn push(&mut self, bytes: &[u8]) -> Vec<(String, String)> {
+        self.buf.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push((std::mem::take(&mut self.event), self.data.join("\n")));
+                }
+                self.event.clear();
+                self.data.clear();
+            } else if let Some(event) = line.strip_prefix("event:") {
+                self.event = event.trim().to_string();
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data.push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+        }
+        events
+    }
+}
+
+struct McpServer {
+    name: String,
+    connection: Connection,
+}
+
+impl McpServer {
+    async fn connect(name: String, config: &ServerConfig) -> Result<Arc<Self>> {
+        let connection = match config {
+            ServerConfig::Stdio { command, args, env } => Connection::spawn_stdio(&name, command, args, env).await?,
+            ServerConfig::Sse { url, headers } => Connection::connect_sse(&name, url, headers).await?,
+        };
+
+        let init = connection
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": "w9-search", "version": env!("CARGO_PKG_VERSION") }
+                }),
+            )
+            .await?;
+        connection.notify("notifications/initialized").await?;
+        tracing::debug!(
+            "MCP server '{}' is {} (protocol {})",
+            name,
+            init["serverInfo"]["name"].as_str().unwrap_or("unnamed"),
+            init["protocolVersion"].as_str().unwrap_or("unknown")
+        );
+        Ok(Arc::new(Self { name, connection }))
+    }
+
+    /// Every tool the server offers, following `tools/list` pagination
+    async fn list_tools(self: &Arc<Self>) -> Result<Vec<McpTool>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let page = self.connection.request("tools/list", params).await?;
+            for tool in page["tools"].as_array().into_iter().flatten() {
+                let Some(remote_name) = tool["name"].as_str() else { continue };
+                tools.push(McpTool {
+                    server: self.clone(),
+                    name: exposed_name(&self.name, remote_name),
+                    remote_name: remote_name.to_string(),
+                    description: tool["description"].as_str().unwrap_or_default().to_string(),
+                    input_schema: tool
+                        .get("inputSchema")
+                        .filter(|s| s.is_object())
+                        .cloned()
+                        .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                });
+            }
+            cursor = page["nextCursor"].as_str().map(String::from);
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        Ok(tools)
+    }
+}
+
+/// A tool offered by an MCP server, under the name the model sees
+struct McpTool {
+    server: Arc<McpServer>,
+    /// `<server>__<tool>`, restricted to the characters function names allow
+    name: String,
+    /// Name on the server
+    remote_name: String,
+    description: String,
+    input_schema: Value,
+}
+
+/// Function names may only use `[A-Za-z0-9_-]` and at most 64 chars
+fn exposed_name(server: &str, tool: &str) -> String {
+    format!("{}__{}", server, tool)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(64)
+        .collect()
+}
+
+/// Connect to every server in `MCP_SERVERS` and register their tools. Each server's
+/// tools become available as soon as it is ready; servers that fail to start or
+/// handshake are logged and left out.
+pub async fn connect_configured(registry: Arc<ToolRegistry>) {
+    let connecting = configured_servers().into_iter().map(|(name, config)| {
+        let registry = registry.clone();
+        async move {
+            let tools = match McpServer::connect(name.clone(), &config).await {
+                Ok(server) => server.list_tools().await,
+                Err(e) => Err(e),
+            };
+            match tools {
+                Ok(tools) => {
+                    tracing::info!("MCP server '{}' connected with {} tools", name, tools.len());
+                    for tool in tools {
+                        registry.register(Arc::new(tool));
+                    }
+                }
+                Err(e) => tracing::error!("MCP server '{}' unavailable: {}", name, e),
+            }
+        }
+    });
+    futures::future::join_all(connecting).await;
+}
+
+#[async_trait::async_trait]
+impl Tool for McpTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn definition(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": self.name,
+                "description": format!("[{}] {}", self.server.name, self.description),
+                "parameters": self.input_schema,
+            }
+        })
+    }
+
+    /// Run the tool on its server. Text content is returned joined; other content types
+    /// are summarized, and a result flagged `isError` becomes an error.
+    async fn execute(&self, args: &Value, _ctx: &ToolContext<'_>) -> Result<String> {
+        let arguments = if args.is_object() { args.clone() } else { json!({}) };
+        let result = self
+            .server
+            .connection
+            .request("tools/call", json!({ "name": self.remote_name, "arguments": arguments }))
+            .await?;
+
+        let mut parts = Vec::new();
+        for item in result["content"].as_array().into_iter().flatten() {
+            match item["type"].as_str() {
+                Some("text") => parts.push(item["text"].as_str().unwrap_or_default().to_string()),
+                Some("resource") => {
+                    let resource = &item["resource"];
+                    match resource["text"].as_str() {
+                        Some(text) => parts.push(text.to_string()),
+                        None => parts.push(format!("[resource: {}]", resource["uri"].as_str().unwrap_or("unknown"))),
+                    }
+                }
+                Some(kind) => parts.push(format!("[{} content: {}]", kind, item["mimeType"].as_str().unwrap_or("unknown type"))),
+                None => {}
+            }
+        }
+        let mut output = parts.join("\n");
+        if output.chars().count() > MAX_RESULT_CHARS {
+            output = output.chars().take(MAX_RESULT_CHARS).collect();
+            output.push_str("\n[truncated]");
+        }
+
+        if result["isError"].as_bool() == Some(true) {
+            return Err(anyhow!("{}", if output.is_empty() { "tool reported an error".to_string() } else { output }));
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::db::Database;
+use crate::http::HttpClients;
+use crate::llm::LLMManager;
+use crate::search::WebSearch;
+use crate::tools::{ToolContext, ToolRegistry};
+
+/// Revisions a host may ask for; anything else is answered with `mcp::PROTOCOL_VERSION`
+const SUPPORTED_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
+/// Longest page text returned by `fetch_content` when the caller sets no `max_chars`
+const DEFAULT_FETCH_CHARS: usize = 20_000;
+
+fn fetch_content_definition() -> Value {
+    json!({
+        "name": "fetch_content",
+        "description": "Fetch a web page (or PDF) and return its readable text. Blocked domains are refused.",
+        "inputSchema": {
+            "type": "object",
+            "properties": {
+                "url": {
+                    "type": "string",
+                    "description": "Page to fetch (https:// is assumed when no scheme is given)"
+                },
+                "max_chars": {
+                    "type": "integer",
+                    "description": "Longest text to return (default 20000)"
+                }
+            },
+            "required": ["url"]
+        }
+    })
+}
+
+struct Server {
+    db: Arc<Database>,
+    http: Arc<HttpClients>,
+    llm_manager: Arc<LLMManager>,
+    tools: Arc<ToolRegistry>,
+}
+
+impl Server {
+    /// The registered tools, including `web_search`, plus `fetch_content`, in MCP's shape.
+    fn tool_list(&self) -> Vec<Value> {
+        let mut tools: Vec<Value> = self.tools.definitions(None)
+            .into_iter()
+            .map(|tool| {
+                let function = &tool["function"];
+                json!({
+                    "name": function["name"],
+                    "description": function["description"],
+                    "inputSchema": function["parameters"],
+                })
+            })
+            .collect();
+        tools.push(fetch_content_definition());
+        tools
+    }
+
+    async fn fetch_content(&self, args: &Value) -> Result<String> {
+        let url = args["url"]
+            .as_str()
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: url"))?;
+        let max_chars = args["max_chars"].as_u64().map_or(DEFAULT_FETCH_CHARS, |n| n as usize);
+        // The host's model picks the URL, so it gets the same public-address checks as fetch_url
+        let page = WebSearch::fetch_public_content(&self.http, url, &self.db).await?;
+        let mut content: String = page.content.chars().take(max_chars).collect();
+        if content.len() < page.content.len() {
+            content.push_str("\n[truncated]");
+        }
+        Ok(format!("URL: {}\n\n{}", page.url, content))
+    }
+
+    async fn call_tool(&self, params: &Value) -> Value {
+        let name = params["name"].as_str().unwrap_or_default();
+        let arguments = params.get("arguments").filter(|a| a.is_object()).cloned().unwrap_or_else(|| json!({}));
+        let result = if name == "fetch_content" {
+            self.fetch_content(&arguments).await
+        } else {
+            let ctx = ToolContext {
+                db: &self.db,
+                http: &self.http,
+                llm_manager: &self.llm_manager,
+                user_id: None,
+                search_provider: None,
+                region: None,
+                allowed_domains_only: false,
+                time_range: None,
+                focus: None,
+            };
+            self.tools.execute(name, &arguments, &ctx).await
+        };
+        // Tool failures are results the host's model should see, not protocol errors
+        match result {
+            Ok(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": false }),
+            Err(e) => json!({ "content": [{ "type": "text", "text": e.to_string() }], "isError": true }),
+        }
+    }
+
+    /// Answer one JSON-RPC message; `None` for notifications.
+    async fn handle(&self, message: Value) -> Option<Value> {
+        let id = message.get("id").cloned()?;
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str().unwrap_or_default();
+                let version = SUPPORTED_VERSIONS
+                    .iter()
+                    .find(|v| **v == requested)
+                    .copied()
+                    .unwrap_or(crate::mcp::PROTOCOL_VERSION);
+                tracing::info!(
+                    "MCP host {} connected (protocol {})",
+                    params["clientInfo"]["name"].as_str().unwrap_or("unknown"),
+                    version
+                );
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "w9-search", "version": env!("CARGO_PKG_VERSION") },
+                    "instructions": "Use web_search to find pages and fetch_content to read them, or query_knowledge_base for stored research.",
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": self.tool_list() })),
+            "tools/call" => Ok(self.call_tool(&params).await),
+            _ => Err((-32601, format!("Method not found: {}", method))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } }),
+        })
+    }
+}
+
+/// Serve the built-in tools over MCP on stdin/stdout until the host closes stdin, so
+/// MCP hosts can use W9 Search as a research backend. Logs go to stderr as usual.
+pub async fn serve_stdio(
+    db: Arc<Database>,
+    http: Arc<HttpClients>,
+    llm_manager: Arc<LLMManager>,
+    tools: Arc<ToolRegistry>,
+) -> Result<()> {
+    let server = Arc::new(Server { db, http, llm_manager, tools });
+    tracing::info!("Serving {} tools over MCP on stdio", server.tool_list().len());
+    let stdout = Arc::new(tokio::sync::Mutex::new(tokio::io::stdout()));
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut tasks = tokio::task::JoinSet::new();
+
+    while let Some(line) = lines.next_line().await? {
+        while tasks.try_join_next().is_some() {}
+        if line.trim().is_empty() {
+            continue;
+        }
+        let server = server.clone();
+        let stdout = stdout.clone();
+        // Each message runs on its own task so a slow search doesn't hold up pings
+        tasks.spawn(async move {
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(Value::Array(batch)) => {
+                    let mut responses = Vec::new();
+                    for message in batch {
+                        responses.extend(server.handle(message).await);
+                    }
+                    (!responses.is_empty()).then_some(Value::Array(responses))
+                }
+                Ok(message) => server.handle(message).await,
+                Err(e) => Some(json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": -32700, "message": format!("Parse error: {}", e) }
+                })),
+            };
+            let Some(response) = response else { return };
+            let mut out = serde_json::to_vec(&response).unwrap_or_default();
+            out.push(b'\n');
+            let mut stdout = stdout.lock().await;
+            if let Err(e) = async { stdout.write_all(&out).await?; stdout.flush().await }.await {
+                tracing::error!("Failed to write MCP response: {}", e);
+            }
+        });
+    }
+
+    // Finish the calls still running so their answers reach the host
+    while tasks.join_next().await.is_some() {}
+    tracing::info!("MCP host closed stdin, exiting");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fetch_content_refuses_private_addresses() {
+        let path = std::env::temp_dir().join(format!("w9-search-test-{}.db", uuid::Uuid::new_v4()));
+        let db = Arc::new(Database::new(&format!("sqlite:{}", path.display())).await.unwrap());
+        db.migrate().await.unwrap();
+        let http = Arc::new(HttpClients::from_env().unwrap());
+        let llm_manager = Arc::new(LLMManager::new(db.clone(), http.clone()));
+        let server = Server { db, http, llm_manager, tools: Arc::new(ToolRegistry::with_builtins()) };
+
+        for url in ["http://127.0.0.1:8080/admin", "localhost/", "http://[::1]/", "http://192.168.1.1/"] {
+            let result = server
+                .call_tool(&json!({ "name": "fetch_content", "arguments": { "url": url } }))
+                .await;
+            assert_eq!(result["isError"], true, "{} was fetched", url);
+            let text = result["content"][0]["text"].as_str().unwrap();
+            assert!(text.contains("not a public address"), "{}: {}", url, text);
+        }
+    }
+}
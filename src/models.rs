@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -11,18 +11,289 @@ pub struct Source {
     pub created_at: DateTime<Utc>,
 }
 
+/// Shape of the generated answer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnswerFormat {
+    /// Free-form prose with inline citations
+    #[default]
+    Prose,
+    /// Summary, cited bullet points, then a sources list
+    Structured,
+}
+
+/// Whether to search the web. `auto` lets the server decide per query.
+/// Accepts the legacy booleans as well as `"on"`, `"off"` and `"auto"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebSearchMode {
+    #[default]
+    On,
+    Off,
+    Auto,
+}
+
+impl<'de> Deserialize<'de> for WebSearchMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Str(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bool(true) => Ok(WebSearchMode::On),
+            Raw::Bool(false) => Ok(WebSearchMode::Off),
+            Raw::Str(s) => match s.to_lowercase().as_str() {
+                "on" | "true" => Ok(WebSearchMode::On),
+                "off" | "false" => Ok(WebSearchMode::Off),
+                "auto" => Ok(WebSearchMode::Auto),
+                other => Err(serde::de::Error::custom(format!(
+                    "invalid web_search_enabled value '{}', expected on, off or auto", other
+                ))),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryRequest {
     pub query: String,
-    pub web_search_enabled: bool,
+    pub web_search_enabled: WebSearchMode,
     /// Optional model ID to use (must be one of AppState.models). If None, default_model is used.
     #[serde(default)]
     pub model: Option<String>,
-    /// Optional search provider to use. If None or "auto", automatic selection is used.
+    /// Optional search provider to use. If None or "auto", automatic selection is used;
+    /// "all" searches every configured provider and merges the results.
     #[serde(default)]
     pub search_provider: Option<String>,
     #[serde(default)]
     pub thread_id: Option<String>,
+    /// Optional sampling seed for reproducible outputs. Only honored by providers
+    /// that support it (OpenRouter, Groq); best-effort even there.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub answer_format: AnswerFormat,
+    /// Deep research: more search queries and sources at the cost of latency
+    #[serde(default)]
+    pub research_mode: bool,
+    /// Report planned searches and tool calls without fetching, executing or saving anything
+    #[serde(default)]
+    pub dry_run: bool,
+    /// ISO country code to localize search results. Falls back to `DEFAULT_SEARCH_REGION`;
+    /// unknown codes are ignored.
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Results requested per search query (default 5, capped at 20)
+    #[serde(default)]
+    pub max_results: Option<usize>,
+    /// Pages fetched for context (default 5, or RESEARCH_MAX_SOURCES in research mode; capped at 20)
+    #[serde(default)]
+    pub max_fetch: Option<usize>,
+    /// Only return results from the past day, week, month or year. When unset, news-like
+    /// questions ("latest", "today") get one automatically unless AUTO_TIME_RANGE=false.
+    #[serde(default)]
+    pub time_range: Option<crate::search::TimeRange>,
+    /// Focus mode: "news", "academic", "code" or "social". Picks the search engines
+    /// (e.g. arXiv for academic), filters results and frames the answer accordingly.
+    #[serde(default)]
+    pub focus: Option<crate::search::Focus>,
+    /// Only use search results from allowlisted domains (ALLOWED_DOMAINS and allow rules)
+    #[serde(default)]
+    pub allowed_domains_only: bool,
+    /// Sampling temperature (0-2); lower is more deterministic
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    /// Cap on the answer length in tokens
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// Nucleus sampling cutoff (0-1]
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    /// Up to four sequences that end the answer; a single string is accepted too
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub stop: Vec<String>,
+    /// Tools the model may call, by name (e.g. `["calculate", "web_search"]`); all
+    /// registered tools when omitted, none for an empty list
+    #[serde(default)]
+    pub enabled_tools: Option<Vec<String>>,
+}
+
+impl QueryRequest {
+    pub fn generation_params(&self) -> crate::llm::GenerationParams {
+        crate::llm::GenerationParams {
+            seed: self.seed,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            stop: self.stop.clone(),
+        }
+    }
+}
+
+fn string_or_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<Raw>::deserialize(deserializer)? {
+        Some(Raw::One(s)) => vec![s],
+        Some(Raw::Many(v)) => v,
+        None => Vec::new(),
+    })
+}
+
+/// Query string for `/api/threads/:id/messages`. Without `limit` the full thread is returned.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagePageParams {
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+/// Lightweight source listing entry for `/api/sources/urls` (no content body).
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SourceUrl {
+    pub url: String,
+    pub title: String,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// An allow or block rule for a domain and its subdomains.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DomainRule {
+    pub id: i64,
+    pub domain: String,
+    /// `allow` or `block`
+    pub rule: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body for `POST /api/domain-rules`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DomainRuleRequest {
+    pub domain: String,
+    pub rule: String,
+}
+
+/// A saved search re-run on a schedule, posting new results to its thread.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Alert {
+    pub id: i64,
+    pub query: String,
+    /// Cron expression (5 fields, or 6 with leading seconds), UTC
+    pub schedule: String,
+    pub thread_id: String,
+    pub search_provider: Option<String>,
+    /// `day`, `week`, `month` or `year`
+    pub time_range: Option<String>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body for `POST /api/alerts`. Without `thread_id` a new thread is created for the alert.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRequest {
+    pub query: String,
+    pub schedule: String,
+    #[serde(default)]
+    pub search_provider: Option<String>,
+    #[serde(default)]
+    pub time_range: Option<crate::search::TimeRange>,
+    #[serde(default)]
+    pub thread_id: Option<String>,
+}
+
+/// Query string for `/api/sources/urls`. `domain` also matches subdomains.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceUrlParams {
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+/// Request limits in force for one provider, from `/api/admin/limits`. Search APIs are
+/// named `search:<engine>` and have no daily limit (0).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderLimits {
+    pub provider: String,
+    pub limit_min: i64,
+    pub limit_day: i64,
+    pub limit_month: i64,
+    /// Operator-set values; the others come from provider headers or built-in defaults
+    pub override_min: Option<i64>,
+    pub override_day: Option<i64>,
+    pub override_month: Option<i64>,
+}
+
+/// Body of `PUT /api/admin/limits`. Omitted or null limits drop that override.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderLimitsRequest {
+    pub provider: String,
+    #[serde(default)]
+    pub limit_min: Option<i64>,
+    #[serde(default)]
+    pub limit_day: Option<i64>,
+    #[serde(default)]
+    pub limit_month: Option<i64>,
+}
+
+/// Query string for `/api/usage`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UsageParams {
+    /// `day` (default) or `month`
+    #[serde(default)]
+    pub period: Option<String>,
+    /// How far back to look; defaults to 30 days, or 365 for monthly totals
+    #[serde(default)]
+    pub days: Option<i64>,
+    /// Only count usage from this thread
+    #[serde(default)]
+    pub thread_id: Option<String>,
+}
+
+/// Token usage of one provider and model over one day or month.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UsageRow {
+    /// `YYYY-MM-DD` or `YYYY-MM`
+    pub period: String,
+    pub provider: String,
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+}
+
+/// Token usage of one thread over the report's window.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ThreadUsage {
+    pub thread_id: String,
+    /// `None` once the thread has been deleted
+    pub title: Option<String>,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub total_tokens: i64,
+}
+
+/// Response of `/api/usage`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub period: String,
+    pub since: DateTime<Utc>,
+    pub usage: Vec<UsageRow>,
+    /// Threads that used the most tokens in the window
+    pub threads: Vec<ThreadUsage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -36,61 +307,108 @@ pub struct ProviderMetrics {
     pub limit_month: Option<i64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct QueryResponse {
-    pub answer: String,
-    pub sources: Vec<Source>,
+/// Body of `POST /api/ingest`: a page plus, at depth 1, the pages it links to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IngestRequest {
+    pub url: String,
+    /// 0 ingests only the page itself; 1 (default) also follows its links
+    #[serde(default)]
+    pub depth: Option<u32>,
+    /// Only follow links on the same host (default true)
+    #[serde(default)]
+    pub same_domain: Option<bool>,
+    /// Upper bound on pages fetched, capped by `INGEST_MAX_PAGES`
+    #[serde(default)]
+    pub max_pages: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum OpenRouterMessage {
-    Standard {
-        role: String,
-        content: String,
-    },
-    ToolCall {
-        role: String,
-        content: Option<String>,
-        tool_calls: Vec<ToolCall>,
-    },
-    ToolResponse {
-        role: String,
-        content: String,
-        name: String,
-    },
+pub struct IngestResponse {
+    pub ingested: usize,
+    pub failed: usize,
+    pub sources: Vec<SourceUrl>,
 }
 
+/// One file stored by `POST /api/documents`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ToolCall {
-    pub id: String,
-    #[serde(rename = "type")]
-    pub call_type: String,
-    pub function: ToolFunction,
+pub struct UploadedDocument {
+    pub filename: String,
+    /// `pdf`, `text` or `markdown`
+    pub kind: String,
+    pub chars: usize,
+    /// Number of `sources` rows the text was split into
+    pub chunks: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ToolFunction {
-    pub name: String,
-    pub arguments: String,
+pub struct DocumentUploadResponse {
+    pub documents: Vec<UploadedDocument>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OpenRouterRequest {
-    pub model: String,
-    pub messages: Vec<OpenRouterMessage>,
-    pub tools: Option<Vec<serde_json::Value>>,
+/// Aggregate performance of chat completions per provider, for `/api/metrics`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ProviderStats {
+    pub provider: String,
+    pub total_requests: i64,
+    pub error_count: i64,
+    pub total_latency_ms: i64,
+    pub total_tokens: i64,
+    /// Over successful requests
+    pub avg_latency_ms: f64,
+    /// Over requests where the provider reported usage
+    pub avg_tokens: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OpenRouterResponse {
-    pub choices: Vec<OpenRouterChoice>,
+pub struct QueryResponse {
+    pub answer: String,
+    /// Every source given to the model as context
+    pub sources: Vec<Source>,
+    /// 1-based indices into `sources` that the answer actually cites
+    #[serde(default)]
+    pub cited_sources: Vec<usize>,
+    /// The cited sources with the passage each citation draws on
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    /// Follow-up questions the user might ask next
+    #[serde(default)]
+    pub suggestions: Vec<String>,
 }
 
+/// One source the answer cites, resolved from its `[Source N]` marker.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct OpenRouterChoice {
-    pub message: OpenRouterMessage,
-    pub finish_reason: Option<String>,
+pub struct Citation {
+    /// 1-based position in the query's sources, as used in the marker
+    pub index: usize,
+    pub source_id: i64,
+    pub title: String,
+    pub url: String,
+    /// Passage of the source that best matches the sentences citing it
+    pub snippet: String,
+}
+
+/// Query string for `/api/query`
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueryParams {
+    /// Wrap the response with model, timing and usage metadata
+    #[serde(default)]
+    pub envelope: bool,
+}
+
+/// `/api/query?envelope=true` response: the answer plus request metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryEnvelope {
+    pub answer: String,
+    pub sources: Vec<Source>,
+    pub cited_sources: Vec<usize>,
+    pub citations: Vec<Citation>,
+    pub suggestions: Vec<String>,
+    /// Model that produced the answer (may differ from the requested one after fallback)
+    pub model: String,
+    pub provider: Option<String>,
+    pub elapsed_ms: u64,
+    /// Summed over every LLM call of the query; providers that don't report usage count as 0
+    pub token_usage: crate::llm::ChatUsage,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -99,6 +417,30 @@ pub struct Thread {
     pub title: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// An alert posted results the user hasn't opened yet
+    pub unread: bool,
+}
+
+/// Response of `POST /api/threads/import`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadImportResponse {
+    pub threads: Vec<Thread>,
+    pub messages: usize,
+}
+
+/// An account in multi-user mode (`AUTH_ENABLED=true`).
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Body of `POST /api/auth/register` and `POST /api/auth/login`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -107,10 +449,23 @@ pub struct Message {
     pub thread_id: String,
     pub role: String,
     pub content: String,
+    /// `complete`, or `cancelled` for the marker a cancelled query leaves behind
+    pub status: String,
+    /// Follow-up questions offered after an assistant answer
+    #[sqlx(try_from = "String")]
+    pub suggestions: Suggestions,
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CreateThreadRequest {
-    pub title: Option<String>,
+/// Suggested follow-up questions, stored as a JSON array in `messages.suggestions`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Suggestions(pub Vec<String>);
+
+impl TryFrom<String> for Suggestions {
+    type Error = serde_json::Error;
+
+    fn try_from(json: String) -> Result<Self, Self::Error> {
+        serde_json::from_str(&json).map(Self)
+    }
 }
@@ -1,18 +1,64 @@
-use crate::search::WebSearch;
+use crate::search::{max_results_ceiling, source_max_age, Focus, SearchOptions, SearchResult, TimeRange, WebSearch, DEFAULT_MAX_RESULTS, MAX_RESULTS_LIMIT};
 use crate::db::Database;
-use crate::tools::Tools;
-use crate::llm::LLMManager;
+use crate::http::HttpClients;
+use crate::tools::{ToolContext, ToolRegistry};
+use crate::llm::{ChatUsage, GenerationParams, LLMManager, ProviderType};
+use crate::error::{ProviderError, QueryCancelled};
+use crate::models::{AnswerFormat, Citation, WebSearchMode};
+use crate::tokens::{self, ContextBudget};
 use anyhow::Result;
+use futures::StreamExt;
 use std::sync::Arc;
 use serde_json::{json, Value};
 use std::collections::HashSet;
+use std::sync::OnceLock;
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+
+/// Passage size and overlap (chars) when splitting sources for reranking
+const RERANK_PASSAGE_CHARS: usize = 1000;
+
+/// Answer instructions around the sources, with room for the longest prompt variant
+const PROMPT_INSTRUCTION_TOKENS: usize = 400;
+const RERANK_PASSAGE_OVERLAP: usize = 100;
 
 pub struct RAGSystem {
     db: Arc<Database>,
     llm_manager: Arc<LLMManager>,
+    http: Arc<HttpClients>,
+    tools: Arc<ToolRegistry>,
+    /// Names of the tools the model may call; `None` offers every registered tool
+    enabled_tools: Option<Vec<String>>,
     model: String,
     search_provider: Option<String>,
+    region: Option<String>,
+    allowed_domains_only: bool,
+    time_range: Option<TimeRange>,
+    focus: Option<Focus>,
+    max_results: Option<usize>,
+    max_fetch: Option<usize>,
+    generation: GenerationParams,
+    answer_format: AnswerFormat,
+    research_mode: bool,
+    dry_run: bool,
+    cancel: CancellationToken,
+    /// Owner recorded on stored sources and used to scope knowledge-base lookups
+    user_id: Option<i64>,
+    /// Thread the query belongs to, recorded with its token usage
+    thread_id: Option<String>,
+    stats: std::sync::Mutex<QueryStats>,
+}
+
+/// What a query actually consumed: the model that produced the answer (it can
+/// change mid-query on fallback) and token usage summed over every LLM call.
+#[derive(Debug, Clone, Default)]
+pub struct QueryStats {
+    pub model: String,
+    pub usage: ChatUsage,
+    /// 1-based indices into the returned sources that the answer cites
+    pub cited_sources: Vec<usize>,
+    pub citations: Vec<Citation>,
+    pub suggestions: Vec<String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -20,18 +66,452 @@ pub struct RAGSystem {
 pub enum StreamEvent {
     Status(String),
     Source(crate::models::Source),
+    /// Rough completion fraction (0.0-1.0), emitted at phase boundaries
+    Progress(f32),
+    /// Incremental piece of the answer; the complete text still follows in `Answer`
+    Token(String),
+    /// 1-based positions of the `Source` events the answer actually cites
+    CitedSources(Vec<usize>),
+    /// Title, URL and supporting snippet for each cited source, in source order
+    Citations(Vec<Citation>),
     Answer(String),
+    /// Follow-up questions the user might ask next; sent after the answer
+    Suggestions(Vec<String>),
+    /// Recoverable failure; the query carries on (e.g. one search or fetch failed)
+    Warning(String),
+    /// Terminal failure; no answer follows
     Error(String),
+    /// The query was cancelled before finishing; no answer follows
+    Cancelled,
     Done,
 }
 
+/// What the tool loop does with one provider response
+#[derive(Debug, PartialEq)]
+enum LoopAction {
+    /// Run these tool calls, after echoing the assistant `message` that requested them
+    RunTools { message: Value, calls: Vec<Value> },
+    /// The model keeps returning no choices; switch to a fallback model if there is one
+    SwitchModel,
+    /// Nothing usable came back; try again while iterations remain
+    Retry,
+    /// The final answer, with any content sent alongside earlier tool calls prepended
+    Answer(String),
+    /// Neither content nor tool calls; the finish reason is reported instead of retrying
+    Empty(String),
+    /// The provider returned an error body
+    Error(String),
+}
+
+/// State of the tool calling loop, kept free of I/O so its decisions can be tested
+struct ToolLoop {
+    iterations_left: usize,
+    empty_choices: usize,
+    /// Content that arrived alongside tool calls, prepended to the final answer
+    partial_content: String,
+}
+
+impl ToolLoop {
+    fn new(iterations: usize) -> Self {
+        Self { iterations_left: iterations, empty_choices: 0, partial_content: String::new() }
+    }
+
+    /// Decide the next step for `response`. `switch_on_empty` allows `SwitchModel` after
+    /// repeated empty choices (OpenRouter upstreams that keep returning nothing).
+    fn next(&mut self, response: &Value, switch_on_empty: bool) -> LoopAction {
+        let Some(choices) = response.get("choices").and_then(|c| c.as_array()) else {
+            tracing::warn!("Provider response missing choices field");
+            if let Some(error) = response.get("error") {
+                return LoopAction::Error(serde_json::to_string(error).unwrap_or_default());
+            }
+            return self.retry();
+        };
+
+        if choices.is_empty() {
+            tracing::warn!("Provider returned empty choices array");
+            self.empty_choices += 1;
+            self.iterations_left = self.iterations_left.saturating_sub(1);
+            if switch_on_empty && self.empty_choices >= 2 {
+                return LoopAction::SwitchModel;
+            }
+            return LoopAction::Retry;
+        }
+        self.empty_choices = 0;
+
+        let Some(choice) = choices.first() else {
+            return self.retry();
+        };
+        let Some(message) = choice.get("message") else {
+            tracing::warn!("Choice missing message field");
+            return self.retry();
+        };
+
+        let content = message.get("content")
+            .and_then(|c| c.as_str())
+            .unwrap_or("");
+
+        // Check for tool calls first: some models send partial content alongside
+        // tool_calls, and the tools must still run
+        if let Some(calls) = message.get("tool_calls").and_then(|tc| tc.as_array()).filter(|tc| !tc.is_empty()) {
+            if !content.trim().is_empty() {
+                tracing::info!("Keeping {} chars of content sent with tool calls", content.len());
+                self.partial_content.push_str(content.trim());
+                self.partial_content.push_str("\n\n");
+            }
+            self.iterations_left = self.iterations_left.saturating_sub(1);
+            return LoopAction::RunTools { message: message.clone(), calls: calls.clone() };
+        }
+
+        // Content without tool calls is the final answer
+        if !content.is_empty() {
+            return LoopAction::Answer(format!("{}{}", self.partial_content, content));
+        }
+
+        // Neither content nor tool calls: re-sending the identical request
+        // just burns iterations on the same empty response, so stop here.
+        let finish_reason = choice.get("finish_reason")
+            .and_then(|fr| fr.as_str())
+            .unwrap_or("none");
+        LoopAction::Empty(finish_reason.to_string())
+    }
+
+    /// A fallback model took over, so the empty responses that triggered it don't count
+    fn switched_model(&mut self) {
+        self.empty_choices = 0;
+        self.iterations_left += 1;
+    }
+
+    fn retry(&mut self) -> LoopAction {
+        self.iterations_left = self.iterations_left.saturating_sub(1);
+        tracing::warn!("No valid response extracted, remaining iterations: {}", self.iterations_left);
+        LoopAction::Retry
+    }
+}
+
 impl RAGSystem {
-    pub fn new(db: Arc<Database>, llm_manager: Arc<LLMManager>, model: String, search_provider: Option<String>) -> Self {
+    pub fn new(db: Arc<Database>, llm_manager: Arc<LLMManager>, http: Arc<HttpClients>, tools: Arc<ToolRegistry>, model: String, search_provider: Option<String>, generation: GenerationParams) -> Self {
+        let stats = QueryStats { model: model.clone(), ..QueryStats::default() };
         Self {
             db,
             llm_manager,
+            http,
+            tools,
+            enabled_tools: None,
             model,
             search_provider,
+            region: None,
+            allowed_domains_only: false,
+            time_range: None,
+            focus: None,
+            max_results: None,
+            max_fetch: None,
+            generation,
+            answer_format: AnswerFormat::default(),
+            research_mode: false,
+            dry_run: false,
+            cancel: CancellationToken::new(),
+            user_id: None,
+            thread_id: None,
+            stats: std::sync::Mutex::new(stats),
+        }
+    }
+
+    pub fn with_answer_format(mut self, answer_format: AnswerFormat) -> Self {
+        self.answer_format = answer_format;
+        self
+    }
+
+    /// Country code biasing search results; validated when the search runs
+    pub fn with_region(mut self, region: Option<String>) -> Self {
+        self.region = region;
+        self
+    }
+
+    /// Freshness filter for searches; unset lets news-like questions pick one (see `search_time_range`)
+    pub fn with_time_range(mut self, time_range: Option<TimeRange>) -> Self {
+        self.time_range = time_range;
+        self
+    }
+
+    /// Focus mode steering the search engines, result filtering and answer framing
+    pub fn with_focus(mut self, focus: Option<Focus>) -> Self {
+        self.focus = focus;
+        self
+    }
+
+    /// Keep only search results from allowlisted domains
+    pub fn with_allowed_domains_only(mut self, allowed_domains_only: bool) -> Self {
+        self.allowed_domains_only = allowed_domains_only;
+        self
+    }
+
+    /// Per-query overrides of results per search and pages fetched, clamped to the server ceilings
+    pub fn with_limits(mut self, max_results: Option<usize>, max_fetch: Option<usize>) -> Self {
+        self.max_results = max_results.map(|n| n.clamp(1, max_results_ceiling()));
+        self.max_fetch = max_fetch.map(|n| n.clamp(1, Self::fetch_ceiling()));
+        self
+    }
+
+    /// Server-side cap on pages fetched per query, research mode included (`MAX_FETCH_PER_QUERY`)
+    fn fetch_ceiling() -> usize {
+        Self::env_usize("MAX_FETCH_PER_QUERY", MAX_RESULTS_LIMIT)
+    }
+
+    pub fn with_research_mode(mut self, research_mode: bool) -> Self {
+        self.research_mode = research_mode;
+        self
+    }
+
+    /// Plan only: report intended searches and first-turn tool calls without fetching or executing
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Scope the query to one account in multi-user mode
+    pub fn with_user(mut self, user_id: Option<i64>) -> Self {
+        self.user_id = user_id;
+        self
+    }
+
+    /// Attribute the query's token usage to a thread
+    pub fn with_thread(mut self, thread_id: Option<String>) -> Self {
+        self.thread_id = thread_id.filter(|id| !id.is_empty());
+        self
+    }
+
+    /// Offer the model only the named tools (`None`: every registered tool)
+    pub fn with_enabled_tools(mut self, names: Option<Vec<String>>) -> Self {
+        self.enabled_tools = names;
+        self
+    }
+
+    /// Stop at the next search, fetch or LLM call once `token` is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancel.is_cancelled() {
+            return Err(QueryCancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Run `fut` unless the query is cancelled first; dropping it aborts the in-flight request.
+    async fn cancellable<T>(&self, fut: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+        self.check_cancelled()?;
+        tokio::select! {
+            biased;
+            _ = self.cancel.cancelled() => Err(QueryCancelled.into()),
+            result = fut => result,
+        }
+    }
+
+    /// Fetch one search result and store it as a source. `Ok(None)` when the page
+    /// could not be read or stored; only cancellation is an error.
+    async fn fetch_and_store(
+        &self,
+        result: &SearchResult,
+        store_raw_html: bool,
+        status_sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>,
+    ) -> Result<Option<crate::models::Source>> {
+        tracing::info!("Fetching content from {}", result.url);
+        let (content, html) = if Self::is_paywalled(&result.url) {
+            // A full fetch would only return the paywall stub
+            tracing::info!("Using snippet for paywalled source {}", result.url);
+            self.send_status(status_sender, format!("Paywalled source, using search snippet only: {}", result.title)).await;
+            if result.snippet.trim().is_empty() {
+                return Ok(None);
+            }
+            (result.snippet.clone(), None)
+        } else {
+            match self.cancellable(WebSearch::fetch_content(&self.http, &result.url, &self.db)).await {
+                Ok(page) => {
+                    tracing::info!("Fetched {} bytes from {}", page.content.len(), result.url);
+                    (page.content, page.html)
+                }
+                Err(e) if e.is::<QueryCancelled>() => return Err(e),
+                Err(e) => {
+                    tracing::warn!("Failed to fetch {}: {}", result.url, e);
+                    self.send_warning(status_sender, format!("Could not read {}, skipping", result.title)).await;
+                    return Ok(None);
+                }
+            }
+        };
+        
+        let raw_html = html.as_deref().filter(|_| store_raw_html);
+        match self.db.insert_source(&result.url, &result.title, &content, raw_html, self.user_id).await {
+            Ok(id) => {
+                tracing::info!("Stored source {} in database", id);
+                match self.llm_manager.index_source(id, &content).await {
+                    Ok(0) => {}
+                    Ok(chunks) => tracing::debug!("Embedded {} chunks of source {}", chunks, id),
+                    Err(e) => tracing::warn!("Failed to embed source {}: {}", id, e),
+                }
+                Ok(Some(crate::models::Source {
+                    id,
+                    url: result.url.clone(),
+                    title: result.title.clone(),
+                    content,
+                    created_at: chrono::Utc::now(),
+                }))
+            },
+            Err(e) => {
+                tracing::warn!("Failed to store source {}: {}", result.url, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Refetch the sources last fetched more than `max_age` ago, keeping their order. Ones
+    /// that can no longer be read are dropped rather than served stale.
+    async fn refresh_stale_sources(
+        &self,
+        sources: Vec<crate::models::Source>,
+        max_age: chrono::Duration,
+        status_sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>,
+    ) -> Result<Vec<crate::models::Source>> {
+        let ids: Vec<i64> = sources.iter().map(|s| s.id).collect();
+        let stale: HashSet<i64> = match self.db.stale_sources(&ids, max_age).await {
+            Ok(stale) => stale.into_iter().collect(),
+            Err(e) => {
+                tracing::warn!("Failed to check source ages: {}", e);
+                return Ok(sources);
+            }
+        };
+        if stale.is_empty() {
+            return Ok(sources);
+        }
+        
+        tracing::info!("Refetching {} stale stored sources", stale.len());
+        self.send_status(status_sender, format!("Refreshing {} outdated sources...", stale.len())).await;
+        let store_raw_html = std::env::var("STORE_RAW_HTML")
+            .map(|v| v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        let refreshed = futures::future::join_all(sources.into_iter().map(|source| {
+            let stale = stale.contains(&source.id);
+            async move {
+                if !stale {
+                    return Ok(Some(source));
+                }
+                let result = SearchResult { title: source.title, url: source.url, snippet: String::new() };
+                self.fetch_and_store(&result, store_raw_html, status_sender).await
+            }
+        }))
+        .await;
+        
+        let mut sources = Vec::with_capacity(refreshed.len());
+        for source in refreshed {
+            sources.extend(source?);
+        }
+        Ok(sources)
+    }
+
+    /// Whether `url` is on a `PAYWALLED_DOMAINS` host (comma-separated, subdomains included).
+    fn is_paywalled(url: &str) -> bool {
+        let Some(host) = url::Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_lowercase)) else {
+            return false;
+        };
+        std::env::var("PAYWALLED_DOMAINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|d| d.trim().trim_start_matches("www.").to_lowercase())
+            .filter(|d| !d.is_empty())
+            .any(|d| host == d || host.ends_with(&format!(".{}", d)))
+    }
+
+    pub fn stats(&self) -> QueryStats {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Add a completion's reported usage to the query totals and the usage log. Responses
+    /// without usage, or with the all-zero usage Cohere is normalized to, are skipped.
+    fn record_usage(&self, response: &Value, model: &str) {
+        let Some(usage) = response.get("usage")
+            .and_then(|u| serde_json::from_value::<ChatUsage>(u.clone()).ok())
+            .filter(|u| u.prompt_tokens > 0 || u.completion_tokens > 0 || u.total_tokens > 0)
+        else {
+            tracing::debug!("{} reported no usage, not logging it", model);
+            return;
+        };
+        {
+            let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+            stats.usage.prompt_tokens += usage.prompt_tokens;
+            stats.usage.completion_tokens += usage.completion_tokens;
+            stats.usage.total_tokens += usage.total_tokens;
+        }
+
+        let db = self.db.clone();
+        let llm_manager = self.llm_manager.clone();
+        let model = model.to_string();
+        let thread_id = self.thread_id.clone();
+        let user_id = self.user_id;
+        tokio::spawn(async move {
+            let provider = llm_manager.get_model(&model).await
+                .map(|m| m.provider.as_str())
+                .unwrap_or("unknown");
+            if let Err(e) = db.record_usage(
+                provider,
+                &model,
+                thread_id.as_deref(),
+                user_id,
+                usage.prompt_tokens as i64,
+                usage.completion_tokens as i64,
+            ).await {
+                tracing::warn!("Failed to record usage for {}: {}", model, e);
+            }
+        });
+    }
+
+    /// Read a positive count from the environment, falling back to `default`.
+    fn env_usize(name: &str, default: usize) -> usize {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(default)
+    }
+
+    /// How the answer is framed for a focus mode, appended to the system prompt
+    fn focus_instructions(focus: Focus) -> &'static str {
+        match focus {
+            Focus::News => "FOCUS: News. Lead with the latest developments, give the date of each event, \
+                and prefer the most recent source when sources disagree.",
+            Focus::Academic => "FOCUS: Academic. Prefer papers and preprints over secondary coverage, name \
+                authors and years, and separate established findings from preliminary results.",
+            Focus::Code => "FOCUS: Code. Include working code in fenced blocks, name the language, library \
+                and version it applies to, and prefer official documentation and accepted answers.",
+            Focus::Social => "FOCUS: Social. Summarize what people in the discussions say, separate \
+                consensus from individual opinions, and note where each view was posted.",
+        }
+    }
+
+    /// The requested time range, or one inferred from news-like wording unless `AUTO_TIME_RANGE=false`.
+    fn search_time_range(&self, query: &str) -> Option<TimeRange> {
+        let auto = std::env::var("AUTO_TIME_RANGE").map(|v| !v.eq_ignore_ascii_case("false")).unwrap_or(true);
+        self.time_range.or_else(|| if auto { WebSearch::fresh_time_range(query) } else { None })
+    }
+
+    /// Number of search results to fetch and read (deep research raises this).
+    fn max_fetch(&self) -> usize {
+        let max_fetch = if let Some(max_fetch) = self.max_fetch {
+            max_fetch
+        } else if self.research_mode {
+            Self::env_usize("RESEARCH_MAX_SOURCES", 12)
+        } else {
+            5
+        };
+        max_fetch.min(Self::fetch_ceiling())
+    }
+
+    /// Number of stored sources to pull from the database.
+    fn max_db_sources(&self) -> i64 {
+        if self.research_mode {
+            Self::env_usize("RESEARCH_MAX_DB_SOURCES", 6) as i64
+        } else {
+            3
         }
     }
 
@@ -41,21 +521,296 @@ impl RAGSystem {
         }
     }
 
+    async fn send_warning(&self, sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>, message: impl Into<String>) {
+        if let Some(tx) = sender {
+            let _ = tx.send(Ok(StreamEvent::Warning(message.into()))).await;
+        }
+    }
+
+    /// Heuristic progress: search up to 20%, fetching 20-60%, answering 60-100%
+    async fn send_progress(&self, sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>, fraction: f32) {
+        if let Some(tx) = sender {
+            let _ = tx.send(Ok(StreamEvent::Progress(fraction.clamp(0.0, 1.0)))).await;
+        }
+    }
+
+    fn summarize_history_enabled() -> bool {
+        std::env::var("SUMMARIZE_HISTORY")
+            .map(|v| !v.eq_ignore_ascii_case("false"))
+            .unwrap_or(true)
+    }
+
+    /// Tokens of one chat message, including the per-message framing
+    fn message_tokens(content: &str) -> usize {
+        tokens::count(content) + 4
+    }
+
+    /// Split of the selected model's context window for this query. The fixed prompt is
+    /// the instructions, tool definitions and the question itself.
+    async fn context_budget(&self, user_query: &str) -> ContextBudget {
+        let context_length = self.llm_manager.get_model(&self.model).await.and_then(|m| m.context_length);
+        let tools = serde_json::to_string(&self.tool_definitions()).unwrap_or_default();
+        let prompt_tokens = PROMPT_INSTRUCTION_TOKENS + tokens::count(&tools) + Self::message_tokens(user_query);
+        ContextBudget::new(context_length, self.generation.max_tokens, prompt_tokens)
+    }
+
+    /// Definitions of the tools this query offers the model
+    fn tool_definitions(&self) -> Vec<Value> {
+        self.tools.definitions(self.enabled_tools.as_deref())
+    }
+
+    /// Run a tool the model called, refusing tools this query didn't enable.
+    async fn run_tool(&self, name: &str, arguments: &Value) -> Result<String> {
+        if self.enabled_tools.as_ref().is_some_and(|names| !names.iter().any(|n| n == name)) {
+            return Err(anyhow::anyhow!("Tool {} is not enabled for this query", name));
+        }
+        let ctx = ToolContext {
+            db: &self.db,
+            http: &self.http,
+            llm_manager: &self.llm_manager,
+            user_id: self.user_id,
+            search_provider: self.search_provider.as_deref(),
+            region: self.region.as_deref(),
+            allowed_domains_only: self.allowed_domains_only,
+            time_range: self.time_range,
+            focus: self.focus,
+        };
+        self.tools.execute(name, arguments, &ctx).await
+    }
+
+    /// Index of the oldest message that still fits `budget` tokens when walking back
+    /// from the newest; everything before it has to be summarized or left out.
+    fn history_window_start(history: &[crate::models::Message], budget: usize) -> usize {
+        let mut used = 0;
+        for (i, msg) in history.iter().enumerate().rev() {
+            used += Self::message_tokens(&msg.content);
+            if used > budget {
+                return i + 1;
+            }
+        }
+        0
+    }
+
+    /// Chat messages for the most recent history that fits `budget` tokens, preceded by
+    /// the thread's memory note covering older turns. Returns them with their token count.
+    async fn history_messages(
+        &self,
+        history: Vec<crate::models::Message>,
+        budget: usize,
+        redact_pii: bool,
+        status_sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>,
+    ) -> (Vec<Value>, usize) {
+        // Markers left by cancelled queries aren't part of the conversation, and the
+        // attribution footer is for readers rather than the model
+        let footer = std::env::var("ANSWER_FOOTER").ok().filter(|t| !t.trim().is_empty());
+        let history: Vec<_> = history.into_iter()
+            .filter(|m| m.status != "cancelled")
+            .map(|mut m| {
+                if let Some(template) = footer.as_deref().filter(|_| m.role == "assistant") {
+                    m.content = Self::strip_answer_footer(&m.content, template).to_string();
+                }
+                m
+            })
+            .collect();
+        let mut messages = Vec::new();
+        let mut history_start = Self::history_window_start(&history, budget);
+        if history_start > 0 && Self::summarize_history_enabled() {
+            // Leave room for the note itself (up to ~250 words)
+            history_start = Self::history_window_start(&history, budget.saturating_sub(400)).max(history_start);
+            if let Some((summary, through)) = self.history_summary(&history[..history_start], redact_pii, status_sender).await {
+                // Turns the note already covers aren't repeated verbatim
+                history_start = history_start.max(history.partition_point(|m| m.id <= through));
+                messages.push(json!({
+                    "role": "system",
+                    "content": format!("Summary of the earlier conversation:\n{}", summary)
+                }));
+            }
+        }
+        if history_start > 0 {
+            tracing::info!("Sending {} of {} history messages verbatim ({} token budget)", history.len() - history_start, history.len(), budget);
+        }
+        for msg in &history[history_start..] {
+            let content = if redact_pii {
+                Self::redact_pii(&msg.content).0
+            } else {
+                msg.content.clone()
+            };
+            messages.push(json!({
+                "role": msg.role,
+                "content": content
+            }));
+        }
+        
+        let used = messages.iter()
+            .map(|m| Self::message_tokens(m["content"].as_str().unwrap_or_default()))
+            .sum();
+        (messages, used)
+    }
+
+    /// Running summary of history turns that fall outside the token budget, with the id
+    /// of the last message it covers. The summary is stored on the thread and only
+    /// extended with turns it doesn't cover yet; on failure the last stored summary is used.
+    async fn history_summary(
+        &self,
+        dropped: &[crate::models::Message],
+        redact_pii: bool,
+        status_sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>,
+    ) -> Option<(String, i64)> {
+        let last = dropped.last()?;
+        let stored = self.db.get_thread_summary(&last.thread_id).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to load history summary: {}", e);
+            None
+        });
+        let (previous, through) = match stored {
+            Some((summary, through)) => (Some(summary), through),
+            None => (None, 0),
+        };
+        let previous = previous.map(|summary| (summary, through));
+        
+        let new_turns: Vec<String> = dropped.iter()
+            .filter(|m| m.id > through)
+            .map(|m| {
+                let content = if redact_pii { Self::redact_pii(&m.content).0 } else { m.content.clone() };
+                format!("{}: {}", m.role, content)
+            })
+            .collect();
+        if new_turns.is_empty() {
+            return previous;
+        }
+        
+        self.send_status(status_sender, "Summarizing earlier conversation...").await;
+        tracing::info!("Summarizing {} older message(s) of thread {}", new_turns.len(), last.thread_id);
+        let messages = vec![
+            json!({
+                "role": "system",
+                "content": "You maintain a running summary of a conversation. Merge the existing summary with the new turns. \
+                Preserve key facts, names, numbers, decisions and open questions; drop pleasantries. \
+                Keep it under 250 words and return only the summary."
+            }),
+            json!({
+                "role": "user",
+                "content": format!(
+                    "Existing summary:\n{}\n\nNew turns:\n{}",
+                    previous.as_ref().map_or("(none)", |(summary, _)| summary.as_str()),
+                    new_turns.join("\n\n")
+                )
+            }),
+        ];
+        
+        let summary = match self.cancellable(self.llm_manager.chat_completion(&self.model, messages, None, &self.generation.sampling_only())).await.inspect(|resp| self.record_usage(resp, &self.model)) {
+            Ok(resp) => resp["choices"][0]["message"]["content"].as_str()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            Err(e) => {
+                tracing::warn!("History summarization failed: {}", e);
+                None
+            }
+        };
+        let Some(summary) = summary else {
+            return previous;
+        };
+        
+        if !self.dry_run {
+            if let Err(e) = self.db.set_thread_summary(&last.thread_id, &summary, last.id).await {
+                tracing::warn!("Failed to store history summary: {}", e);
+            }
+        }
+        Some((summary, last.id))
+    }
+
+    fn suggestions_enabled() -> bool {
+        std::env::var("FOLLOW_UP_SUGGESTIONS")
+            .map(|v| !v.eq_ignore_ascii_case("false"))
+            .unwrap_or(true)
+    }
+
+    /// Ask the LLM for three follow-up questions to `answer` and emit them as a
+    /// `Suggestions` event. Best effort: dry runs and failed calls yield none.
+    pub async fn suggest_follow_ups(
+        &self,
+        query: &str,
+        answer: &str,
+        status_sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>,
+    ) -> Vec<String> {
+        if self.dry_run || !Self::suggestions_enabled() || answer.trim().is_empty() {
+            return Vec::new();
+        }
+        
+        let messages = vec![
+            json!({
+                "role": "system",
+                "content": "Suggest exactly 3 follow-up questions the user is likely to ask next, given their question and the answer they received. \
+                Each question must make sense on its own and be under 15 words. \
+                Return ONLY a JSON object with a 'questions' key containing the list of strings."
+            }),
+            json!({
+                "role": "user",
+                "content": format!("Question: {}\n\nAnswer:\n{}", query, answer.chars().take(4000).collect::<String>())
+            }),
+        ];
+        let content = match self.cancellable(self.llm_manager.chat_completion(&self.model, messages, None, &self.generation.sampling_only())).await.inspect(|resp| self.record_usage(resp, &self.model)) {
+            Ok(resp) => resp["choices"][0]["message"]["content"].as_str().unwrap_or_default().to_string(),
+            Err(e) => {
+                tracing::warn!("Follow-up suggestions failed: {}", e);
+                return Vec::new();
+            }
+        };
+        
+        let suggestions = Self::parse_suggestions(&content);
+        tracing::debug!("Suggested follow-ups: {:?}", suggestions);
+        if !suggestions.is_empty() {
+            if let Some(tx) = status_sender {
+                let _ = tx.send(Ok(StreamEvent::Suggestions(suggestions.clone()))).await;
+            }
+            self.stats.lock().unwrap_or_else(|e| e.into_inner()).suggestions = suggestions.clone();
+        }
+        suggestions
+    }
+
+    /// Questions from a `{"questions": [...]}` reply, or from question lines when the
+    /// model ignored the format. At most three, without duplicates.
+    fn parse_suggestions(content: &str) -> Vec<String> {
+        let clean = content.trim()
+            .trim_start_matches("```json")
+            .trim_start_matches("```")
+            .trim_end_matches("```");
+        let candidates: Vec<String> = match serde_json::from_str::<Value>(clean) {
+            Ok(reply) => reply["questions"].as_array()
+                .map(|questions| questions.iter().filter_map(Value::as_str).map(str::to_string).collect())
+                .unwrap_or_default(),
+            Err(_) => clean.lines()
+                .map(|line| line.trim().trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '.' | ')' | '-' | '*' | ' ')))
+                .filter(|line| line.ends_with('?'))
+                .map(str::to_string)
+                .collect(),
+        };
+        
+        let mut seen = HashSet::new();
+        candidates.into_iter()
+            .map(|q| q.trim().chars().take(200).collect::<String>())
+            .filter(|q| !q.is_empty() && seen.insert(q.to_lowercase()))
+            .take(3)
+            .collect()
+    }
+
     /// Ask the LLM to plan the research steps
     async fn plan_search(&self, query: &str) -> Result<Vec<String>> {
         tracing::info!("Planning search for query: {}", query);
         
-        let system_prompt = "You are a simplified research planner. \
-        Given a user query, generate a list of 1-3 specific search queries that would help answer it. \
+        // Deep research expands into more, narrower queries
+        let (query_range, max_queries) = if self.research_mode { ("3-6", 6) } else { ("1-3", 3) };
+        let system_prompt = format!("You are a simplified research planner. \
+        Given a user query, generate a list of {} specific search queries that would help answer it. \
         Return ONLY a JSON object with a 'queries' key containing the list of strings. \
-        Example: {\"queries\": [\"current president of US\", \"US president term length\"]}";
+        Example: {{\"queries\": [\"current president of US\", \"US president term length\"]}}", query_range);
 
         let messages = vec![
             json!({ "role": "system", "content": system_prompt }),
             json!({ "role": "user", "content": query })
         ];
 
-        let json_resp = self.llm_manager.chat_completion(&self.model, messages, None).await?;
+        let json_resp = self.cancellable(self.llm_manager.chat_completion(&self.model, messages, None, &self.generation.sampling_only())).await?;
+        self.record_usage(&json_resp, &self.model);
         
         // Extract content from choice
         let content = json_resp["choices"][0]["message"]["content"]
@@ -72,6 +827,7 @@ impl RAGSystem {
             if let Some(queries) = plan["queries"].as_array() {
                 let strings: Vec<String> = queries.iter()
                     .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .take(max_queries)
                     .collect();
                 tracing::info!("Generated search plan: {:?}", strings);
                 return Ok(strings);
@@ -82,17 +838,90 @@ impl RAGSystem {
         Ok(vec![query.to_string()])
     }
     
-    /// Enhance search query with temporal context for time-sensitive queries
-    fn enhance_query_with_temporal_context(query: &str) -> String {
+    /// Rewrite the user query into a search-engine query with a small, fast model
+    /// (`QUERY_REWRITE_MODEL`, defaults to the answering model). Enabled by `LLM_QUERY_REWRITE=true`.
+    /// Falls back to the temporal-context heuristic if the rewrite call fails.
+    async fn rewrite_query(&self, query: &str) -> Option<String> {
+        let enabled = std::env::var("LLM_QUERY_REWRITE")
+            .map(|v| v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        
+        let model = std::env::var("QUERY_REWRITE_MODEL")
+            .ok()
+            .filter(|m| !m.trim().is_empty())
+            .map(|m| self.llm_manager.resolve_model_alias(m.trim()))
+            .unwrap_or_else(|| self.model.clone());
+        
+        let current_date = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let messages = vec![
+            json!({ "role": "system", "content": format!("Rewrite the user's question into a single concise web search query. \
+            Resolve ambiguity, keep names and key terms, add the year if the question is time-sensitive (today is {}). \
+            Return ONLY the query text, without quotes or explanation.", current_date) }),
+            json!({ "role": "user", "content": query })
+        ];
+        
+        let rewritten = match self.cancellable(self.llm_manager.chat_completion(&model, messages, None, &self.generation.sampling_only())).await.inspect(|resp| self.record_usage(resp, &model)) {
+            Ok(resp) => resp["choices"][0]["message"]["content"]
+                .as_str()
+                .map(|s| s.trim().trim_matches('"').trim().to_string())
+                .filter(|s| !s.is_empty() && s.lines().count() == 1),
+            Err(e) => {
+                tracing::warn!("Query rewrite with {} failed: {}", model, e);
+                None
+            }
+        };
+        
+        Some(rewritten.unwrap_or_else(|| Self::enhance_query_with_temporal_context(query)))
+    }
+    
+    /// Heuristic for `WebSearchMode::Auto`: skip searching for queries the tools or the
+    /// model can answer alone (arithmetic, conversions, code, definitions).
+    /// Returns the decision and a short human-readable reason.
+    fn classify_search_need(query: &str) -> (bool, &'static str) {
         let query_lower = query.to_lowercase();
+        let trimmed = query_lower.trim().trim_end_matches('?');
+        
+        let fresh_keywords = [
+            "current", "today", "now", "latest", "recent", "news", "price", "weather",
+            "score", "released", "announced", "this year", "this week", "update",
+        ];
+        if fresh_keywords.iter().any(|k| trimmed.contains(k)) {
+            return (true, "query needs fresh information");
+        }
+        
+        let expression = trimmed.strip_prefix("what is").unwrap_or(trimmed).trim();
+        let is_arithmetic = expression.chars().any(|c| c.is_ascii_digit())
+            && expression.chars().all(|c| c.is_ascii_digit() || c.is_whitespace() || "+-*/^%().,=x".contains(c));
+        if is_arithmetic || trimmed.starts_with("calculate") {
+            return (false, "arithmetic can be computed locally");
+        }
         
-        // Check if query is time-sensitive
-        let time_sensitive_keywords = [
-            "current", "today", "now", "present", "latest", "recent", 
-            "who is", "what is the current", "who are the current",
-            "president", "leader", "ceo", "chairman", "minister",
-            "happened today", "news", "breaking", "update"
+        if trimmed.starts_with("convert") || (trimmed.contains(" to ") && trimmed.chars().next().is_some_and(|c| c.is_ascii_digit())) {
+            return (false, "unit conversion can be computed locally");
+        }
+        
+        let code_markers = [
+            "```", "write a function", "write a script", "write code", "refactor", "debug this",
+            "regex for", "in python", "in rust", "in javascript", "in typescript", "sql query",
         ];
+        if code_markers.iter().any(|m| query_lower.contains(m)) {
+            return (false, "coding question");
+        }
+        
+        let definition_prefixes = ["define ", "definition of ", "meaning of ", "synonym for ", "what does the word "];
+        if definition_prefixes.iter().any(|p| trimmed.starts_with(p)) {
+            return (false, "definition question");
+        }
+        
+        (true, "factual query")
+    }
+    
+    /// Enhance search query with temporal context for time-sensitive queries
+    fn enhance_query_with_temporal_context(query: &str) -> String {
+        let query_lower = query.to_lowercase();
         
         // Check for comparison queries that might need calculation
         let needs_calculation = query_lower.contains("compare") || 
@@ -109,10 +938,7 @@ impl RAGSystem {
             query_lower.contains("fahrenheit") || query_lower.contains("kg") ||
             query_lower.contains("pounds"));
         
-        let is_time_sensitive = time_sensitive_keywords.iter()
-            .any(|keyword| query_lower.contains(keyword));
-        
-        if is_time_sensitive {
+        if WebSearch::is_time_sensitive(query) {
             // Get current date to add context
             let current_date = chrono::Utc::now().format("%Y-%m-%d").to_string();
             format!("{} as of {}", query, current_date)
@@ -127,147 +953,680 @@ impl RAGSystem {
         }
     }
 
+    /// Markdown summary of what a query would do: planned searches and the
+    /// model's first-turn tool calls (not executed).
+    fn dry_run_report(&self, planned_searches: &[String], message: &Value) -> String {
+        let mut report = String::from("**Dry run**: no pages were fetched and no tools were executed.\n\n");
+        
+        if planned_searches.is_empty() {
+            report.push_str("**Planned searches:** none (web search disabled)\n\n");
+        } else {
+            report.push_str(&format!(
+                "**Planned searches** (provider: {}):\n",
+                self.search_provider.as_deref().unwrap_or("auto")
+            ));
+            for query in planned_searches {
+                report.push_str(&format!("- {}\n", query));
+            }
+            report.push('\n');
+        }
+        
+        let tool_calls = message.get("tool_calls")
+            .and_then(|tc| tc.as_array())
+            .filter(|tc| !tc.is_empty());
+        match tool_calls {
+            Some(tool_calls) => {
+                report.push_str("**First-turn tool calls:**\n");
+                for tool_call in tool_calls {
+                    let function = &tool_call["function"];
+                    report.push_str(&format!(
+                        "- `{}` with arguments `{}`\n",
+                        function["name"].as_str().unwrap_or("unknown"),
+                        function["arguments"].as_str().unwrap_or("{}")
+                    ));
+                }
+            }
+            None => report.push_str("**First-turn tool calls:** none, the model would answer directly\n"),
+        }
+        
+        report
+    }
+
+    /// Canonical citation marker from `CITATION_MARKER`, with `N` standing for the
+    /// source number (default `[N]`). Shared by the prompts, post-processing and the UI.
+    pub fn citation_marker() -> &'static str {
+        static MARKER: OnceLock<String> = OnceLock::new();
+        MARKER.get_or_init(|| {
+            match std::env::var("CITATION_MARKER") {
+                Ok(marker) if marker.matches('N').count() == 1 && !marker.chars().any(|c| c.is_ascii_digit()) => marker,
+                Ok(marker) if !marker.is_empty() => {
+                    tracing::warn!("Ignoring CITATION_MARKER '{}': it must contain exactly one 'N' and no digits", marker);
+                    "[N]".to_string()
+                }
+                _ => "[N]".to_string(),
+            }
+        })
+    }
+
+    /// Rewrite near-variants of citations (`[Source 1]`, `(source 1)`, `[^1]`, `【1】`, `[1]`)
+    /// to `marker`. Multi-source forms like `[1, 2]` or `[Source 1, Source 2]` become one
+    /// marker per source (`[1][2]`). Fenced and inline code is left as written, so
+    /// indexing like `arr[0]` survives.
+    fn normalize_citations(answer: &str, marker: &str) -> String {
+        static VARIANTS: OnceLock<regex::Regex> = OnceLock::new();
+        static NUMBERS: OnceLock<regex::Regex> = OnceLock::new();
+        static CODE: OnceLock<regex::Regex> = OnceLock::new();
+        let variants = VARIANTS.get_or_init(|| {
+            regex::Regex::new(concat!(
+                r"(?i)\[\s*((?:source\s*|\^)?\d+(?:\s*,\s*(?:source\s*|\^)?\d+)*)\s*\]",
+                r"|\(\s*(source\s*\d+(?:\s*,\s*(?:source\s*)?\d+)*)\s*\)",
+                r"|【\s*((?:source\s*)?\d+(?:\s*,\s*(?:source\s*)?\d+)*)\s*】",
+            )).unwrap()
+        });
+        let numbers = NUMBERS.get_or_init(|| regex::Regex::new(r"\d+").unwrap());
+        // Fenced blocks (unterminated ones run to the end) and single or double backtick spans
+        let code = CODE.get_or_init(|| {
+            regex::Regex::new(r"(?s)```.*?(?:```|\z)|~~~.*?(?:~~~|\z)|``[^`]+``|`[^`\n]+`").unwrap()
+        });
+        let rewrite = |text: &str| variants.replace_all(text, |caps: &regex::Captures| {
+            let list = caps.get(1).or_else(|| caps.get(2)).or_else(|| caps.get(3))
+                .map(|m| m.as_str())
+                .unwrap_or_default();
+            numbers.find_iter(list)
+                .map(|n| marker.replace('N', n.as_str()))
+                .collect::<String>()
+        }).into_owned();
+        
+        let mut normalized = String::with_capacity(answer.len());
+        let mut last = 0;
+        for span in code.find_iter(answer) {
+            normalized.push_str(&rewrite(&answer[last..span.start()]));
+            normalized.push_str(span.as_str());
+            last = span.end();
+        }
+        normalized.push_str(&rewrite(&answer[last..]));
+        normalized
+    }
+
+    /// Matches one normalized citation marker, capturing the source number.
+    fn marker_regex() -> &'static regex::Regex {
+        static CITED: OnceLock<regex::Regex> = OnceLock::new();
+        CITED.get_or_init(|| {
+            let pattern = Self::citation_marker()
+                .split('N')
+                .map(regex::escape)
+                .collect::<Vec<_>>()
+                .join(r"(\d+)");
+            regex::Regex::new(&pattern).unwrap()
+        })
+    }
+
+    /// Resolve the cited source numbers into citations. Each snippet is the source
+    /// sentence sharing the most words with the answer sentences that cite it,
+    /// falling back to the opening of the source.
+    fn citations(answer: &str, sources: &[crate::models::Source], cited: &[usize]) -> Vec<Citation> {
+        const SNIPPET_CHARS: usize = 300;
+        let markers = Self::marker_regex();
+        
+        let mut claims: std::collections::HashMap<usize, Vec<String>> = std::collections::HashMap::new();
+        for caps in markers.captures_iter(answer) {
+            let (Some(whole), Some(n)) = (caps.get(0), caps.get(1).and_then(|n| n.as_str().parse::<usize>().ok())) else {
+                continue;
+            };
+            let mut start = whole.start().saturating_sub(600);
+            while !answer.is_char_boundary(start) {
+                start += 1;
+            }
+            // A marker placed after the full stop still belongs to the sentence before it
+            let before = markers.replace_all(&answer[start..whole.start()], "");
+            let before = before.trim_end().trim_end_matches(['.', '!', '?', ':', ';', ',']);
+            let sentence = before.rsplit(['.', '!', '?', '\n']).next().unwrap_or_default().trim();
+            if !sentence.is_empty() {
+                claims.entry(n).or_default().push(sentence.to_lowercase());
+            }
+        }
+        
+        cited.iter()
+            .filter_map(|&n| {
+                let source = sources.get(n.checked_sub(1)?)?;
+                let claim_words: HashSet<&str> = claims.get(&n)
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|c| c.split(|ch: char| !ch.is_alphanumeric()))
+                    .filter(|w| w.chars().count() > 3)
+                    .collect();
+                let best = source.content
+                    .split_inclusive(['.', '!', '?', '\n'])
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(|s| {
+                        let lower = s.to_lowercase();
+                        let words: HashSet<&str> = lower.split(|ch: char| !ch.is_alphanumeric()).collect();
+                        (claim_words.iter().filter(|w| words.contains(**w)).count(), s)
+                    })
+                    .fold((0, None), |best, (score, s)| if score > best.0 { (score, Some(s)) } else { best });
+                let text = best.1.unwrap_or_else(|| source.content.trim());
+                let mut snippet: String = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if snippet.chars().count() > SNIPPET_CHARS {
+                    snippet = snippet.chars().take(SNIPPET_CHARS).collect::<String>().trim_end().to_string() + "...";
+                }
+                Some(Citation {
+                    index: n,
+                    source_id: source.id,
+                    title: source.title.clone(),
+                    url: source.url.clone(),
+                    snippet,
+                })
+            })
+            .collect()
+    }
+
+    /// Source numbers cited in an answer whose markers were already normalized,
+    /// sorted, deduplicated and limited to `1..=source_count`.
+    fn cited_indices(answer: &str, source_count: usize) -> Vec<usize> {
+        let indices: std::collections::BTreeSet<usize> = Self::marker_regex().captures_iter(answer)
+            .filter_map(|caps| caps.get(1)?.as_str().parse().ok())
+            .filter(|n| (1..=source_count).contains(n))
+            .collect();
+        indices.into_iter().collect()
+    }
+
+    /// Model to switch to when the current one keeps failing: `FALLBACK_MODEL` if set,
+    /// otherwise the first available model from another provider (or any other model).
+    async fn fallback_model(&self, current: &str) -> Option<String> {
+        let models = self.llm_manager.get_models().await;
+        
+        if let Ok(name) = std::env::var("FALLBACK_MODEL") {
+            let id = self.llm_manager.resolve_model_alias(name.trim());
+            if id != current && models.iter().any(|m| m.id == id) {
+                return Some(id);
+            }
+        }
+        
+        let current_provider = models.iter().find(|m| m.id == current).map(|m| m.provider.clone());
+        models.iter()
+            .find(|m| m.id != current && Some(&m.provider) != current_provider.as_ref())
+            .or_else(|| models.iter().find(|m| m.id != current))
+            .map(|m| m.id.clone())
+    }
+
+    /// Attribution footer from `ANSWER_FOOTER`, e.g. `Generated by W9 · {model} · {date}`.
+    /// Supports `{model}`, `{provider}` and `{date}`; unset or empty means no footer.
+    async fn answer_footer(&self, model: &str) -> Option<String> {
+        let template = std::env::var("ANSWER_FOOTER").ok().filter(|t| !t.trim().is_empty())?;
+        let provider = self.llm_manager.get_model(model).await
+            .map(|m| m.provider.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        Some(template
+            .replace("{model}", model)
+            .replace("{provider}", &provider)
+            .replace("{date}", &chrono::Utc::now().format("%Y-%m-%d").to_string()))
+    }
+
+    /// `answer` without the footer `answer_footer` rendered from `template`, whatever
+    /// its placeholders were filled with.
+    fn strip_answer_footer<'a>(answer: &'a str, template: &str) -> &'a str {
+        let pattern = regex::escape(template)
+            .replace(r"\{model\}", ".*?")
+            .replace(r"\{provider\}", ".*?")
+            .replace(r"\{date\}", r"\d{4}-\d{2}-\d{2}");
+        match regex::Regex::new(&format!(r"\n\n---\n{}\z", pattern)) {
+            Ok(footer) => footer.find(answer).map_or(answer, |m| &answer[..m.start()]),
+            Err(_) => answer,
+        }
+    }
+
+    /// Split sources into passages, rerank them against the query and keep the
+    /// `RERANK_TOP_N` best (default 8). Each surviving source keeps only its selected
+    /// passages, and sources are ordered by their best passage. On failure the
+    /// sources are returned unchanged.
+    async fn rerank_sources(
+        &self,
+        query: &str,
+        sources: Vec<crate::models::Source>,
+        status_sender: &Option<Sender<Result<StreamEvent, anyhow::Error>>>,
+    ) -> Result<Vec<crate::models::Source>> {
+        let top_n = Self::env_usize("RERANK_TOP_N", 8).max(1);
+        let mut passages = Vec::new();
+        for (source_idx, source) in sources.iter().enumerate() {
+            for chunk in crate::embeddings::chunk_text(&source.content, RERANK_PASSAGE_CHARS, RERANK_PASSAGE_OVERLAP) {
+                passages.push((source_idx, chunk));
+            }
+        }
+        let texts: Vec<String> = passages.iter().map(|(_, text)| text.clone()).collect();
+
+        let ranked = match self.cancellable(self.llm_manager.rerank(query, &texts, top_n)).await {
+            Ok(ranked) if !ranked.is_empty() => ranked,
+            Ok(_) => return Ok(sources),
+            Err(e) if e.is::<QueryCancelled>() => return Err(e),
+            Err(e) => {
+                tracing::warn!("Reranking failed: {}, keeping all sources", e);
+                self.send_warning(status_sender, "Reranking failed, using sources as retrieved").await;
+                return Ok(sources);
+            }
+        };
+
+        // Group kept passages by source, in order of each source's best passage
+        let mut kept: Vec<(usize, Vec<&str>)> = Vec::new();
+        for (passage_idx, _) in &ranked {
+            let (source_idx, text) = &passages[*passage_idx];
+            match kept.iter_mut().find(|(idx, _)| idx == source_idx) {
+                Some((_, texts)) => texts.push(text),
+                None => kept.push((*source_idx, vec![text])),
+            }
+        }
+        tracing::info!("Reranked {} passages from {} sources, kept {} passages from {} sources",
+            passages.len(), sources.len(), ranked.len(), kept.len());
+
+        Ok(kept.into_iter()
+            .map(|(source_idx, texts)| {
+                let mut source = sources[source_idx].clone();
+                source.content = texts.join("\n...\n");
+                source
+            })
+            .collect())
+    }
+
+    /// Order sources before numbering them, per `CONTEXT_ORDER`:
+    /// `search` (default) keeps search order with knowledge-base hits last,
+    /// `recency` puts the newest first, `relevance` ranks by query term overlap.
+    fn order_context_sources(sources: &mut [crate::models::Source], query: &str) {
+        let order = std::env::var("CONTEXT_ORDER").unwrap_or_default().to_lowercase();
+        match order.as_str() {
+            "recency" => sources.sort_by_key(|s| std::cmp::Reverse(s.created_at)),
+            "relevance" => {
+                let terms: Vec<String> = query.to_lowercase()
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|t| t.len() > 2)
+                    .map(str::to_string)
+                    .collect();
+                // Title matches weigh more than body matches; sort is stable so ties keep search order
+                sources.sort_by_cached_key(|s| {
+                    let title = s.title.to_lowercase();
+                    let body = s.content.chars().take(2000).collect::<String>().to_lowercase();
+                    let score: usize = terms.iter()
+                        .map(|t| 3 * usize::from(title.contains(t.as_str())) + usize::from(body.contains(t.as_str())))
+                        .sum();
+                    std::cmp::Reverse(score)
+                });
+            }
+            "" | "search" => {}
+            other => tracing::warn!("Unknown CONTEXT_ORDER '{}', keeping search order", other),
+        }
+    }
+
+    /// Replace emails, card-like numbers and phone numbers with placeholders.
+    /// Returns the redacted text and the number of replacements made.
+    fn redact_pii(text: &str) -> (String, usize) {
+        static PATTERNS: OnceLock<Vec<(regex::Regex, &'static str)>> = OnceLock::new();
+        let patterns = PATTERNS.get_or_init(|| {
+            vec![
+                (regex::Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap(), "[EMAIL]"),
+                // Card numbers before phones so long digit runs aren't split into phone matches
+                (regex::Regex::new(r"\b\d{4}[ -]?\d{4}[ -]?\d{4}[ -]?\d{1,7}\b").unwrap(), "[CARD]"),
+                (regex::Regex::new(r"(?:\+\d{1,3}[\s.-]?)?\(?\d{3}\)?[\s.-]\d{3}[\s.-]\d{4}\b").unwrap(), "[PHONE]"),
+            ]
+        });
+
+        let mut redacted = text.to_string();
+        let mut count = 0;
+        for (pattern, placeholder) in patterns {
+            count += pattern.find_iter(&redacted).count();
+            redacted = pattern.replace_all(&redacted, *placeholder).into_owned();
+        }
+        (redacted, count)
+    }
+
+    fn redact_pii_enabled() -> bool {
+        std::env::var("REDACT_PII")
+            .map(|v| v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    }
+
+    /// Replace any model-written sources section with one built from the sources
+    /// actually provided as context, so structured answers always end the same way.
+    fn append_sources_list(answer: &str, sources: &[crate::models::Source]) -> String {
+        let mut body = answer.trim_end();
+        
+        // Drop a trailing "Sources"/"References" heading the model may have produced
+        let mut offset = 0;
+        for line in answer.split_inclusive('\n') {
+            let heading = line.trim().trim_start_matches('#').trim().trim_matches('*').trim_end_matches(':').to_lowercase();
+            if offset > 0 && (heading == "sources" || heading == "references") {
+                body = answer[..offset].trim_end();
+                break;
+            }
+            offset += line.len();
+        }
+        
+        if sources.is_empty() {
+            return body.to_string();
+        }
+        
+        let list = sources.iter()
+            .enumerate()
+            .map(|(i, s)| format!("{}. [{}]({})", i + 1, s.title, s.url))
+            .collect::<Vec<_>>()
+            .join("\n");
+        
+        format!("{}\n\n## Sources\n{}", body, list)
+    }
+
+    /// Answer `user_query`, streaming progress to `status_sender`, including any provider
+    /// call that is being retried.
     pub async fn query(
+        &self,
+        user_query: &str,
+        web_search: WebSearchMode,
+        history: Vec<crate::models::Message>,
+        status_sender: Option<Sender<Result<StreamEvent, anyhow::Error>>>
+    ) -> Result<(String, Vec<crate::models::Source>)> {
+        let Some(tx) = status_sender.clone() else {
+            return self.run_query(user_query, web_search, history, None).await;
+        };
+        let reporter: crate::retry::RetryReporter = Arc::new(move |notice| {
+            let _ = tx.try_send(Ok(StreamEvent::Status(notice)));
+        });
+        crate::retry::report_retries(reporter, self.run_query(user_query, web_search, history, status_sender)).await
+    }
+
+    async fn run_query(
         &self, 
         user_query: &str, 
-        web_search_enabled: bool,
+        web_search: WebSearchMode,
         history: Vec<crate::models::Message>,
         status_sender: Option<Sender<Result<StreamEvent, anyhow::Error>>>
     ) -> Result<(String, Vec<crate::models::Source>)> {
-        tracing::info!("Starting RAG query: '{}' (web_search: {}, history: {})", user_query, web_search_enabled, history.len());
+        let redact_pii = Self::redact_pii_enabled();
+        
+        // Redact PII before the query leaves the server (LLM planning, search, answering)
+        let redacted_query;
+        let user_query = if redact_pii {
+            let (text, count) = Self::redact_pii(user_query);
+            if count > 0 {
+                tracing::info!("Redacted {} PII match(es) from query", count);
+            }
+            redacted_query = text;
+            redacted_query.as_str()
+        } else {
+            user_query
+        };
+        
+        tracing::info!("Starting RAG query: '{}' (web_search: {:?}, history: {})", user_query, web_search, history.len());
         self.send_status(&status_sender, "Initializing search...").await;
+        self.send_progress(&status_sender, 0.02).await;
+        
+        let web_search_enabled = match web_search {
+            WebSearchMode::On => true,
+            WebSearchMode::Off => false,
+            WebSearchMode::Auto => {
+                let (needed, reason) = Self::classify_search_need(user_query);
+                tracing::info!("Auto web search: {} ({})", needed, reason);
+                let decision = if needed { "searching the web" } else { "skipping web search" };
+                self.send_status(&status_sender, format!("Auto mode: {} ({})", decision, reason)).await;
+                needed
+            }
+        };
         
         let mut context_sources = Vec::new();
+        let mut planned_searches = Vec::new();
         
         // Step 1: Web search if enabled
         if web_search_enabled {
+            if self.research_mode {
+                self.send_status(&status_sender, format!("Deep research mode: reading up to {} sources", self.max_fetch())).await;
+            }
             self.send_status(&status_sender, "Planning research strategy...").await;
             
+            let rewritten_query = self.rewrite_query(user_query).await;
+            if let Some(rewritten) = &rewritten_query {
+                tracing::info!("Rewrote query: '{}' -> '{}'", user_query, rewritten);
+                self.send_status(&status_sender, format!("Rewritten search query: {}", rewritten)).await;
+            }
+            
             // Get search plan
-            let search_queries = match self.plan_search(user_query).await {
+            let mut search_queries = match self.plan_search(user_query).await {
                 Ok(queries) => queries,
+                Err(e) if e.is::<QueryCancelled>() => return Err(e),
                 Err(e) => {
                     tracing::warn!("Planning failed: {}, falling back to single query", e);
-                    vec![Self::enhance_query_with_temporal_context(user_query)]
+                    vec![rewritten_query.clone().unwrap_or_else(|| Self::enhance_query_with_temporal_context(user_query))]
                 }
             };
             
+            // The rewritten query leads the plan
+            if let Some(rewritten) = rewritten_query {
+                if !search_queries.iter().any(|q| q.eq_ignore_ascii_case(&rewritten)) {
+                    search_queries.insert(0, rewritten);
+                }
+            }
+            
             self.send_status(&status_sender, format!("Identified {} search queries", search_queries.len())).await;
+            self.send_progress(&status_sender, 0.1).await;
+            
+            if self.dry_run {
+                self.send_status(&status_sender, "Dry run: not executing searches").await;
+                planned_searches = std::mem::take(&mut search_queries);
+            }
 
             // Execute searches
             let mut all_results = Vec::new();
             let mut seen_urls = HashSet::new();
             
-            for query in search_queries {
+            let search_options = SearchOptions {
+                region: self.region.as_deref(),
+                max_results: self.max_results.unwrap_or(DEFAULT_MAX_RESULTS),
+                allowed_only: self.allowed_domains_only,
+                time_range: self.search_time_range(user_query),
+                focus: self.focus,
+            };
+            if let (None, Some(range)) = (self.time_range, search_options.time_range) {
+                self.send_status(&status_sender, format!("Time-sensitive question: limiting results to the past {}", range.as_str())).await;
+            }
+            let search_total = search_queries.len();
+            for (idx, query) in search_queries.into_iter().enumerate() {
+                self.send_progress(&status_sender, 0.1 + 0.1 * idx as f32 / search_total as f32).await;
                 self.send_status(&status_sender, format!("Searching: {}", query)).await;
                 tracing::info!("Executing search step: {}", query);
-                if let Ok(results) = WebSearch::search(&self.db, &query, self.search_provider.as_deref()).await {
-                    for result in results {
-                        if seen_urls.insert(result.url.clone()) {
-                            all_results.push(result);
+                match self.cancellable(WebSearch::search(&self.http, &self.db, &query, self.search_provider.as_deref(), search_options)).await {
+                    Ok(results) => {
+                        for result in results {
+                            if seen_urls.insert(result.url.clone()) {
+                                all_results.push(result);
+                            }
                         }
                     }
+                    Err(e) if e.is::<QueryCancelled>() => return Err(e),
+                    Err(e) => {
+                        tracing::warn!("Search for '{}' failed: {}", query, e);
+                        self.send_warning(&status_sender, format!("Search failed for '{}', continuing", query)).await;
+                    }
                 }
             }
             
             self.send_status(&status_sender, format!("Found {} potential sources. Reading content...", all_results.len())).await;
+            self.send_progress(&status_sender, 0.2).await;
+            
+            let store_raw_html = std::env::var("STORE_RAW_HTML")
+                .map(|v| v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false);
             
             // Limit and fetch content
-            // We'll take top 5 unique results across all queries
-            for (idx, result) in all_results.iter().take(5).enumerate() {
-                self.send_status(&status_sender, format!("Reading: {}", result.title)).await;
-                tracing::info!("Fetching content from result {}: {}", idx + 1, result.url);
-                match WebSearch::fetch_content(&result.url).await {
-                    Ok(content) => {
-                        tracing::info!("Fetched {} bytes from {}", content.len(), result.url);
-                        match self.db.insert_source(
-                            &result.url,
-                            &result.title,
-                            &content,
-                        ).await {
-                            Ok(id) => {
-                                tracing::info!("Stored source {} in database", id);
-                                let source = crate::models::Source {
-                                    id,
-                                    url: result.url.clone(),
-                                    title: result.title.clone(),
-                                    content,
-                                    created_at: chrono::Utc::now(),
-                                };
-                                
-                                if let Some(tx) = &status_sender {
-                                    let _ = tx.send(Ok(StreamEvent::Source(source.clone()))).await;
-                                }
-                                
-                                context_sources.push(source);
-                            },
-                            Err(e) => {
-                                tracing::warn!("Failed to store source {}: {}", result.url, e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("Failed to fetch {}: {}", result.url, e);
-                    }
+            // We'll take the top unique results across all queries, FETCH_CONCURRENCY at a time
+            let max_fetch = self.max_fetch();
+            let fetch_total = all_results.len().min(max_fetch);
+            if fetch_total > 0 {
+                self.send_status(&status_sender, format!("Reading {} sources...", fetch_total)).await;
+            }
+            let sender = &status_sender;
+            let pending: Vec<_> = all_results.iter().take(max_fetch).enumerate()
+                .map(|(idx, result)| async move {
+                    self.fetch_and_store(result, store_raw_html, sender).await
+                        .map(|source| (idx, result, source))
+                })
+                .collect();
+            let mut fetches = futures::stream::iter(pending)
+                .buffer_unordered(Self::env_usize("FETCH_CONCURRENCY", 4));
+            
+            let mut fetched = Vec::with_capacity(fetch_total);
+            let mut done = 0;
+            while let Some(outcome) = fetches.next().await {
+                let (idx, result, source) = outcome?;
+                done += 1;
+                self.send_progress(&status_sender, 0.2 + 0.4 * done as f32 / fetch_total as f32).await;
+                if let Some(source) = source {
+                    self.send_status(&status_sender, format!("Read ({}/{}): {}", done, fetch_total, result.title)).await;
+                    fetched.push((idx, source));
                 }
             }
+            // Completion order is arbitrary; keep the search ranking
+            fetched.sort_by_key(|(idx, _)| *idx);
+            context_sources.extend(fetched.into_iter().map(|(_, source)| source));
+        }
+        
+        // Searching was requested but produced nothing usable (providers down, pages blocked):
+        // fall back to stored knowledge and training instead of insisting on sources
+        let degraded = web_search_enabled && !self.dry_run && context_sources.is_empty();
+        if degraded {
+            tracing::warn!("Web search yielded no usable sources, answering in degraded mode");
+            self.send_warning(&status_sender, "Web search unavailable: answering from stored knowledge and model training").await;
         }
         
         // Step 2: Retrieve relevant sources from database (always check DB too)
+        self.check_cancelled()?;
         self.send_status(&status_sender, "Checking internal knowledge base...").await;
+        self.send_progress(&status_sender, 0.6).await;
         tracing::info!("Searching database for relevant sources...");
-        let db_sources = match self.db.search_sources(user_query, 3).await {
+        let db_sources = match self.db.search_sources(user_query, self.max_db_sources(), self.user_id).await {
             Ok(sources) => {
                 tracing::info!("Found {} relevant sources in database", sources.len());
                 sources
             },
             Err(e) => {
                 tracing::warn!("Database search failed: {}, continuing without DB sources", e);
+                self.send_warning(&status_sender, "Knowledge base lookup failed, continuing without stored sources").await;
                 Vec::new()
             }
         };
         
+        // Semantic matches find relevant stored sources without keyword overlap
+        let semantic_sources = if self.llm_manager.embeddings_enabled() {
+            match self.llm_manager.semantic_search(user_query, self.max_db_sources() as usize, self.user_id).await {
+                Ok(sources) => {
+                    tracing::info!("Found {} semantically similar sources", sources.len());
+                    sources
+                }
+                Err(e) => {
+                    tracing::warn!("Semantic search failed: {}, continuing with keyword matches", e);
+                    self.send_warning(&status_sender, "Semantic search failed, using keyword matches only").await;
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        
         // Merge and deduplicate
         let mut seen_ids = HashSet::new();
         for s in &context_sources {
             seen_ids.insert(s.id);
         }
-        for s in db_sources {
+        for s in semantic_sources.into_iter().chain(db_sources) {
             if seen_ids.insert(s.id) {
                 context_sources.push(s);
             }
         }
         
+        // Stored pages past SOURCE_MAX_AGE_DAYS are read again instead of answering from stale text
+        if let Some(max_age) = source_max_age().filter(|_| !self.dry_run) {
+            context_sources = self.refresh_stale_sources(context_sources, max_age, &status_sender).await?;
+        }
+        
+        if self.llm_manager.reranking_enabled() && !context_sources.is_empty() {
+            self.send_status(&status_sender, "Reranking passages...").await;
+            context_sources = self.rerank_sources(user_query, context_sources, &status_sender).await?;
+        }
+        
+        Self::order_context_sources(&mut context_sources, user_query);
+        
+        // Fit history first, then as many sources as the rest of the window allows
+        let budget = self.context_budget(user_query).await;
+        let (history_messages, history_tokens) = self.history_messages(history, budget.history_tokens, redact_pii, &status_sender).await;
+        let (source_count, source_tokens) = budget.sources(context_sources.len(), history_tokens);
+        if source_count < context_sources.len() {
+            tracing::info!("Context window ({} tokens) fits {} of {} sources", budget.context_length, source_count, context_sources.len());
+            context_sources.truncate(source_count);
+        }
+        
+        // Sources are announced in final order so citation numbers match the context block
+        if let Some(tx) = &status_sender {
+            for source in &context_sources {
+                let _ = tx.send(Ok(StreamEvent::Source(source.clone()))).await;
+            }
+        }
+        
         // Step 3: Build context
         self.send_status(&status_sender, "Synthesizing answer...").await;
         let context = if context_sources.is_empty() {
             "No relevant sources found.".to_string()
         } else {
-            context_sources.iter()
+            let mut redacted_total = 0;
+            let context = context_sources.iter()
                 .enumerate()
                 .map(|(i, s)| {
+                    let mut content = tokens::truncate(&s.content, source_tokens);
+                    if redact_pii {
+                        let (text, count) = Self::redact_pii(&content);
+                        redacted_total += count;
+                        content = text;
+                    }
                     format!("[Source {}]\nTitle: {}\nURL: {}\nContent: {}\n", 
-                        i + 1, s.title, s.url, 
-                        s.content.chars().take(2000).collect::<String>())
+                        i + 1, s.title, s.url, content)
                 })
                 .collect::<Vec<_>>()
-                .join("\n---\n\n")
+                .join("\n---\n\n");
+            if redacted_total > 0 {
+                tracing::info!("Redacted {} PII match(es) from source content", redacted_total);
+            }
+            context
         };
         
         // Step 4: Query AI with RAG context
-        let system_prompt = if web_search_enabled {
+        let marker = Self::citation_marker();
+        let example_citation = marker.replace('N', "1");
+        let system_prompt = if degraded {
+            format!(
+                "You are a helpful AI assistant. Web search is currently unavailable, so no live sources could be retrieved.\n\
+                \n\
+                TASK: Answer the user's query using the stored knowledge below if relevant, otherwise your training knowledge.\n\
+                \n\
+                GUIDELINES:\n\
+                1. Briefly tell the user that live web search was unavailable and the answer may be out of date.\n\
+                2. Cite stored sources using {} (e.g. {}); clearly mark what comes from your training.\n\
+                3. TEMPORAL AWARENESS: Current date is {}.\n\
+                \n\
+                STORED KNOWLEDGE:\n{}",
+                marker,
+                example_citation,
+                chrono::Utc::now().format("%Y-%m-%d"),
+                context
+            )
+        } else if web_search_enabled {
             format!(
                 "You are an advanced AI assistant with research capabilities.\n\
                 \n\
                 TASK: Answer the user's query using ONLY the provided sources. \n\
                 \n\
                 GUIDELINES:\n\
-                1. CITATIONS: Use [Source N] to cite information. Every fact must be cited.\n\
+                1. CITATIONS: Use {} to cite information (e.g. {} for source 1). Every fact must be cited.\n\
                 2. SYNTHESIS: Combine information from multiple sources to provide a comprehensive answer.\n\
                 3. HONESTY: If the sources do not contain the answer, state that clearly.\n\
                 4. TEMPORAL AWARENESS: Current date is {}.\n\
+                5. FOLLOW-UP SEARCH: If a step of the question isn't covered, call the web_search tool with a targeted query; cite its results by URL.\n\
                 \n\
                 SOURCES:\n{}",
+                marker,
+                example_citation,
                 chrono::Utc::now().format("%Y-%m-%d"),
                 context
             )
@@ -280,13 +1639,32 @@ impl RAGSystem {
                 GUIDELINES:\n\
                 1. Prioritize the provided sources.\n\
                 2. If sources are insufficient, you may use your training knowledge but must clarify what is from sources vs training.\n\
-                3. Cite sources using [Source N].\n\
+                3. Cite sources using {} (e.g. {}).\n\
                 \n\
                 SOURCES:\n{}",
+                marker,
+                example_citation,
                 context
             )
         };
         
+        let system_prompt = match self.answer_format {
+            AnswerFormat::Prose => system_prompt,
+            AnswerFormat::Structured => format!(
+                "{}\n\n\
+                ANSWER FORMAT:\n\
+                1. Start with a short summary (2-3 sentences).\n\
+                2. Follow with bullet points, each with inline citations.\n\
+                3. End with a 'Sources' section listing the sources you cited.",
+                system_prompt
+            ),
+        };
+        
+        let system_prompt = match self.focus {
+            Some(focus) => format!("{}\n\n{}", system_prompt, Self::focus_instructions(focus)),
+            None => system_prompt,
+        };
+        
         let mut messages: Vec<Value> = vec![
             json!({
                 "role": "system",
@@ -294,154 +1672,377 @@ impl RAGSystem {
             })
         ];
         
-        // Append history (limit to last 6 messages to save context)
-        for msg in history.iter().rev().take(6).rev() {
-            messages.push(json!({
-                "role": msg.role,
-                "content": msg.content
-            }));
-        }
+        // Recent history and the memory note for older turns
+        messages.extend(history_messages);
         
         messages.push(json!({
             "role": "user",
             "content": user_query
         }));
         
+        let prompt_tokens: usize = messages.iter()
+            .map(|m| Self::message_tokens(m["content"].as_str().unwrap_or_default()))
+            .sum();
+        if prompt_tokens + budget.answer_tokens > budget.context_length {
+            tracing::warn!("Prompt is ~{} tokens, leaving less than {} for the answer in {}'s {}-token context",
+                prompt_tokens, budget.answer_tokens, self.model, budget.context_length);
+        }
+        
         // Get tools definition
-        let tools = Tools::get_tools_definition();
+        let tools = self.tool_definitions();
         tracing::info!("Starting AI query with {} tools available", tools.len());
         
         // Handle tool calling loop (max 3 iterations)
-        let mut max_iterations = 3;
+        let mut tool_loop = ToolLoop::new(3);
         let mut final_answer = String::new();
+        let mut empty_finish_reason: Option<String> = None;
+        
+        // Tool budget across the whole query, independent of the iteration cap
+        let max_tool_calls = Self::env_usize("MAX_TOTAL_TOOL_CALLS", 8);
+        let mut tool_calls_used = 0;
+        // The model can change mid-query if its route keeps failing
+        let mut model = self.model.clone();
         
-        while max_iterations > 0 {
-            tracing::info!("AI query iteration {} (remaining: {})", 4 - max_iterations, max_iterations - 1);
+        while tool_loop.iterations_left > 0 {
+            tracing::info!("AI query iteration {} (remaining: {})", 4 - tool_loop.iterations_left, tool_loop.iterations_left - 1);
+            self.send_progress(&status_sender, 0.65 + 0.1 * (3 - tool_loop.iterations_left) as f32).await;
+            
+            // Once the budget is spent, stop offering tools so the model must answer
+            let offered_tools = (tool_calls_used < max_tool_calls && !tools.is_empty()).then(|| tools.clone());
             
-            let response_json = self.llm_manager.chat_completion(
-                &self.model, 
+            let (stream, served_by) = self.cancellable(self.llm_manager.chat_completion_stream_with_failover(
+                &model, 
                 messages.clone(), 
-                Some(tools.clone())
-            ).await?;
+                offered_tools,
+                &self.generation
+            )).await?;
+            if served_by != model {
+                let provider = self.llm_manager.get_model(&served_by).await
+                    .map(|m| m.provider.to_string())
+                    .unwrap_or_default();
+                self.send_status(&status_sender, format!("{} is unavailable, falling back to {} on {}", model, served_by, provider)).await;
+                crate::logging::record_model(&served_by, Some(&provider.to_lowercase()));
+                model = served_by;
+            }
+            let response_json = self.cancellable(LLMManager::collect_chat_stream(stream)).await?;
+            self.record_usage(&response_json, &model);
             
             tracing::debug!("Provider response: {}", serde_json::to_string_pretty(&response_json).unwrap_or_default());
             
-            if let Some(choices) = response_json.get("choices").and_then(|c| c.as_array()) {
-                if choices.is_empty() {
-                    tracing::warn!("Provider returned empty choices array");
-                    max_iterations -= 1;
-                    continue;
+            if self.dry_run {
+                if let Some(message) = response_json.pointer("/choices/0/message") {
+                    return Ok((self.dry_run_report(&planned_searches, message), context_sources));
                 }
-                
-                if let Some(choice) = choices.first() {
-                    if let Some(message) = choice.get("message") {
-                        // Check if there's content (final answer)
-                        if let Some(content) = message.get("content").and_then(|c| c.as_str()) {
-                            if !content.is_empty() {
-                                tracing::info!("Received final answer from AI (length: {} chars)", content.len());
-                                final_answer = content.to_string();
-                                break;
-                            }
-                        }
-                        
-                        // Check for tool calls
-                        if let Some(tool_calls) = message.get("tool_calls").and_then(|tc| tc.as_array()) {
-                            if !tool_calls.is_empty() {
-                                self.send_status(&status_sender, "Using calculation tools...").await;
-                                tracing::info!("AI requested {} tool calls", tool_calls.len());
-                                
-                                // Execute tools and add responses
-                                for (idx, tool_call) in tool_calls.iter().enumerate() {
-                                    if let Some(function) = tool_call.get("function") {
-                                        let function_name = function.get("name")
-                                            .and_then(|n| n.as_str())
-                                            .unwrap_or("");
-                                        
-                                        let arguments_str = function.get("arguments")
-                                            .and_then(|a| a.as_str())
-                                            .unwrap_or("{}");
-                                        
-                                        tracing::info!("Tool call {}: {} with args: {}", idx + 1, function_name, arguments_str);
-                                        
-                                        let arguments: Value = match serde_json::from_str(arguments_str) {
-                                            Ok(args) => args,
-                                            Err(e) => {
-                                                tracing::warn!("Failed to parse tool arguments: {}, using empty object", e);
-                                                json!({})
-                                            }
-                                        };
-                                        
-                                        let tool_result = match Tools::execute_tool(function_name, &arguments) {
-                                            Ok(result) => {
-                                                tracing::info!("Tool {} executed successfully, result length: {}", function_name, result.len());
-                                                result
-                                            },
-                                            Err(e) => {
-                                                tracing::warn!("Tool {} execution error: {}", function_name, e);
-                                                format!("Error executing {}: {}", function_name, e)
-                                            }
-                                        };
-                                        
-                                        let tool_call_id = tool_call.get("id")
-                                            .and_then(|id| id.as_str())
-                                            .unwrap_or("");
-                                        
-                                        // Add tool response message
-                                        messages.push(json!({
-                                            "role": "tool",
-                                            "content": tool_result,
-                                            "tool_call_id": tool_call_id
-                                        }));
-                                    } else {
-                                        tracing::warn!("Tool call {} missing function field", idx + 1);
+            }
+            
+            // OpenRouter sometimes routes to an upstream that keeps returning nothing;
+            // retrying the same route rarely helps, so switch models instead
+            let is_openrouter = self.llm_manager.get_model(&model).await
+                .is_some_and(|m| m.provider == ProviderType::OpenRouter);
+            
+            match tool_loop.next(&response_json, is_openrouter) {
+                LoopAction::SwitchModel => {
+                    if let Some(next) = self.fallback_model(&model).await {
+                        tracing::warn!("OpenRouter returned empty choices {} times for {}, switching to {}", tool_loop.empty_choices, model, next);
+                        self.send_status(&status_sender, format!("{} returned no output, switching to {}", model, next)).await;
+                        model = next;
+                        tool_loop.switched_model();
+                    }
+                }
+                LoopAction::Retry => {}
+                LoopAction::Error(error) => {
+                    tracing::error!("Provider API error: {}", error);
+                    return Err(ProviderError::Upstream(format!("Provider API error: {}", error)).into());
+                }
+                LoopAction::Answer(answer) => {
+                    tracing::info!("Received final answer from AI (length: {} chars)", answer.len());
+                    final_answer = answer;
+                    self.stats.lock().unwrap_or_else(|e| e.into_inner()).model = model.clone();
+                    break;
+                }
+                LoopAction::Empty(finish_reason) => {
+                    tracing::warn!("Provider returned an empty message (finish_reason: {}), not retrying", finish_reason);
+                    empty_finish_reason = Some(finish_reason);
+                    break;
+                }
+                LoopAction::RunTools { message, calls: tool_calls } => {
+                    self.send_status(&status_sender, "Using tools...").await;
+                    tracing::info!("AI requested {} tool calls", tool_calls.len());
+                    
+                    // The assistant's tool call message must precede the tool responses
+                    messages.push(message);
+                    
+                    // Execute tools and add responses
+                    for (idx, tool_call) in tool_calls.iter().enumerate() {
+                        if let Some(function) = tool_call.get("function") {
+                            let function_name = function.get("name")
+                                .and_then(|n| n.as_str())
+                                .unwrap_or("");
+                            
+                            let arguments_str = function.get("arguments")
+                                .and_then(|a| a.as_str())
+                                .unwrap_or("{}");
+                            
+                            tracing::info!("Tool call {}: {} with args: {}", idx + 1, function_name, arguments_str);
+                            
+                            let arguments: Value = match serde_json::from_str(arguments_str) {
+                                Ok(args) => args,
+                                Err(e) => {
+                                    tracing::warn!("Failed to parse tool arguments: {}, using empty object", e);
+                                    json!({})
+                                }
+                            };
+                            
+                            let tool_result = if tool_calls_used >= max_tool_calls {
+                                tracing::info!("Skipping tool {}: budget of {} calls exhausted", function_name, max_tool_calls);
+                                "Tool call budget exhausted. Answer using the information already gathered.".to_string()
+                            } else {
+                                tool_calls_used += 1;
+                                if function_name == "web_search" {
+                                    if let Some(query) = arguments.get("query").and_then(|q| q.as_str()) {
+                                        self.send_status(&status_sender, format!("Searching: {}", query)).await;
+                                    }
+                                } else if function_name == "fetch_url" {
+                                    if let Some(url) = arguments.get("url").and_then(|u| u.as_str()) {
+                                        self.send_status(&status_sender, format!("Reading: {}", url)).await;
                                     }
                                 }
-                                
-                                // Add the assistant's tool call message
-                                messages.push(message.clone());
-                                
-                                tracing::info!("Preparing next iteration with {} messages", messages.len());
-                                max_iterations -= 1;
-                                continue;
-                            }
-                        }
-                        
-                        // Check finish_reason
-                        if let Some(finish_reason) = choice.get("finish_reason").and_then(|fr| fr.as_str()) {
-                            tracing::info!("AI finished with reason: {}", finish_reason);
-                            if finish_reason == "stop" {
-                                // Try to get content even if not in message.content
-                                if let Some(content) = message.get("content").and_then(|c| c.as_str()) {
-                                    if !content.is_empty() {
-                                        final_answer = content.to_string();
-                                        break;
+                                match self.cancellable(self.run_tool(function_name, &arguments)).await {
+                                    Ok(result) => {
+                                        tracing::info!("Tool {} executed successfully, result length: {}", function_name, result.len());
+                                        result
+                                    },
+                                    Err(e) => {
+                                        tracing::warn!("Tool {} execution error: {}", function_name, e);
+                                        format!("Error executing {}: {}", function_name, e)
                                     }
                                 }
-                            }
+                            };
+                            
+                            let tool_call_id = tool_call.get("id")
+                                .and_then(|id| id.as_str())
+                                .unwrap_or("");
+                            
+                            // Add tool response message
+                            messages.push(json!({
+                                "role": "tool",
+                                "content": tool_result,
+                                "tool_call_id": tool_call_id
+                            }));
+                        } else {
+                            tracing::warn!("Tool call {} missing function field", idx + 1);
                         }
-                    } else {
-                        tracing::warn!("Choice missing message field");
                     }
-                }
-            } else {
-                tracing::warn!("Provider response missing choices field");
-                if let Some(error) = response_json.get("error") {
-                    tracing::error!("Provider API error: {}", serde_json::to_string(error).unwrap_or_default());
-                    return Err(anyhow::anyhow!("Provider API error: {}", serde_json::to_string(error).unwrap_or_default()));
+                    
+                    if tool_calls_used >= max_tool_calls {
+                        self.send_status(&status_sender, format!("Tool budget reached ({} calls), answering with gathered information", max_tool_calls)).await;
+                    }
+                    
+                    tracing::info!("Preparing next iteration with {} messages", messages.len());
                 }
             }
-            
-            max_iterations -= 1;
-            tracing::warn!("No valid response extracted, remaining iterations: {}", max_iterations);
+        }
+        
+        let partial_content = tool_loop.partial_content;
+        if final_answer.is_empty() && !partial_content.trim().is_empty() {
+            tracing::warn!("No final answer after tool calls, using content sent alongside them");
+            final_answer = partial_content.trim_end().to_string();
         }
         
         if final_answer.is_empty() {
-            tracing::warn!("No answer generated after {} iterations", 3);
-            final_answer = "Sorry, I couldn't generate a response. Please try again.".to_string();
+            if let Some(reason) = empty_finish_reason {
+                self.send_status(&status_sender, format!("Model returned an empty response (finish reason: {})", reason)).await;
+                final_answer = format!(
+                    "Sorry, the model '{}' returned an empty response (finish reason: {}). Please try again or choose a different model.",
+                    model, reason
+                );
+            } else {
+                tracing::warn!("No answer generated after {} iterations", 3);
+                final_answer = "Sorry, I couldn't generate a response. Please try again.".to_string();
+            }
         } else {
             tracing::info!("Successfully generated answer (length: {} chars)", final_answer.len());
+            final_answer = Self::normalize_citations(&final_answer, Self::citation_marker());
+            
+            // Guard the client and the DB against runaway outputs when max_tokens isn't honored
+            let max_answer_chars = Self::env_usize("MAX_ANSWER_CHARS", 50000);
+            if let Some((limit, _)) = final_answer.char_indices().nth(max_answer_chars) {
+                tracing::warn!("Truncating answer from {} to {} chars (MAX_ANSWER_CHARS)", final_answer.chars().count(), max_answer_chars);
+                final_answer.truncate(limit);
+                final_answer.push_str("\n\n[answer truncated]");
+            }
+            
+            // Computed before the structured sources list, which would "cite" everything
+            let cited = Self::cited_indices(&final_answer, context_sources.len());
+            tracing::info!("Answer cites {} of {} sources", cited.len(), context_sources.len());
+            if let Some(tx) = &status_sender {
+                let _ = tx.send(Ok(StreamEvent::CitedSources(cited.clone()))).await;
+            }
+            let citations = Self::citations(&final_answer, &context_sources, &cited);
+            if let Some(tx) = &status_sender {
+                let _ = tx.send(Ok(StreamEvent::Citations(citations.clone()))).await;
+            }
+            {
+                let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+                stats.cited_sources = cited;
+                stats.citations = citations;
+            }
+            
+            if self.answer_format == AnswerFormat::Structured {
+                final_answer = Self::append_sources_list(&final_answer, &context_sources);
+            }
+            if let Some(footer) = self.answer_footer(&model).await {
+                final_answer = format!("{}\n\n---\n{}", final_answer.trim_end(), footer);
+            }
         }
         
+        self.send_progress(&status_sender, 1.0).await;
         Ok((final_answer, context_sources))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed responses through the loop as `run_query` does, assuming a fallback model always
+    /// exists. Returns the action that ended the loop (if any) and how many responses it used.
+    fn drive(responses: &[Value], switch_on_empty: bool) -> (Option<LoopAction>, usize, ToolLoop) {
+        let mut tool_loop = ToolLoop::new(3);
+        let mut used = 0;
+        while tool_loop.iterations_left > 0 && used < responses.len() {
+            let action = tool_loop.next(&responses[used], switch_on_empty);
+            used += 1;
+            match action {
+                LoopAction::SwitchModel => tool_loop.switched_model(),
+                LoopAction::Retry | LoopAction::RunTools { .. } => {}
+                done => return (Some(done), used, tool_loop),
+            }
+        }
+        (None, used, tool_loop)
+    }
+
+    fn message(message: Value) -> Value {
+        json!({ "choices": [{ "message": message, "finish_reason": "stop" }] })
+    }
+
+    #[test]
+    fn empty_message_stops_without_retrying() {
+        let empty = json!({ "choices": [{ "message": { "content": "" }, "finish_reason": "length" }] });
+        let responses = vec![empty.clone(), empty.clone(), empty];
+
+        let (action, used, tool_loop) = drive(&responses, false);
+
+        assert_eq!(action, Some(LoopAction::Empty("length".to_string())));
+        assert_eq!(used, 1);
+        assert_eq!(tool_loop.iterations_left, 3);
+    }
+
+    #[test]
+    fn content_sent_with_tool_calls_precedes_final_answer() {
+        let call = json!({ "id": "c1", "type": "function", "function": { "name": "web_search", "arguments": "{\"query\":\"rust\"}" } });
+        let responses = [
+            message(json!({ "content": "  Let me check.  ", "tool_calls": [call.clone()] })),
+            message(json!({ "content": "Rust 1.80 is current." })),
+        ];
+        let mut tool_loop = ToolLoop::new(3);
+
+        match tool_loop.next(&responses[0], false) {
+            LoopAction::RunTools { message, calls } => {
+                assert_eq!(calls, vec![call]);
+                assert_eq!(message["tool_calls"][0]["id"], "c1");
+            }
+            other => panic!("expected tool calls, got {:?}", other),
+        }
+        assert_eq!(tool_loop.iterations_left, 2);
+        assert_eq!(
+            tool_loop.next(&responses[1], false),
+            LoopAction::Answer("Let me check.\n\nRust 1.80 is current.".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_choices_retries_until_iterations_run_out() {
+        let responses = vec![json!({}), json!({}), json!({}), message(json!({ "content": "late" }))];
+
+        let (action, used, _) = drive(&responses, false);
+
+        assert_eq!(action, None);
+        assert_eq!(used, 3);
+    }
+
+    #[test]
+    fn normalize_citations_splits_multi_source_forms() {
+        assert_eq!(RAGSystem::normalize_citations("Both agree [1, 2].", "[N]"), "Both agree [1][2].");
+        assert_eq!(RAGSystem::normalize_citations("Both agree [1][2].", "[N]"), "Both agree [1][2].");
+        assert_eq!(RAGSystem::normalize_citations("See [Source 3, source 4]", "[N]"), "See [3][4]");
+        assert_eq!(RAGSystem::normalize_citations("Noted (source 1) and 【2】", "(N)"), "Noted (1) and (2)");
+    }
+
+    #[test]
+    fn normalize_citations_leaves_code_alone() {
+        assert_eq!(
+            RAGSystem::normalize_citations("Use `a[1, 2]` as shown [1, 2].", "[N]"),
+            "Use `a[1, 2]` as shown [1][2]."
+        );
+        assert_eq!(
+            RAGSystem::normalize_citations("Index with `arr[0]` [1].", "(N)"),
+            "Index with `arr[0]` (1)."
+        );
+
+        let fenced = "Example [2]:\n```rust\nlet x = arr[0] + grid[1, 2];\n```\nDone [3].";
+        assert_eq!(
+            RAGSystem::normalize_citations(fenced, "(N)"),
+            "Example (2):\n```rust\nlet x = arr[0] + grid[1, 2];\n```\nDone (3)."
+        );
+        // An unterminated fence protects the rest of the answer
+        assert_eq!(RAGSystem::normalize_citations("```\nv[1]", "(N)"), "```\nv[1]");
+    }
+
+    #[test]
+    fn consecutive_empty_choices_switch_models() {
+        let empty = json!({ "choices": [] });
+        let mut tool_loop = ToolLoop::new(3);
+
+        assert_eq!(tool_loop.next(&empty, true), LoopAction::Retry);
+        assert_eq!(tool_loop.next(&empty, true), LoopAction::SwitchModel);
+        tool_loop.switched_model();
+        // The switch refunds the iteration and restarts the count for the new model
+        assert_eq!(tool_loop.iterations_left, 2);
+        assert_eq!(tool_loop.next(&empty, true), LoopAction::Retry);
+        assert_eq!(
+            tool_loop.next(&message(json!({ "content": "Answer" })), true),
+            LoopAction::Answer("Answer".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_choices_only_switch_when_allowed_and_consecutive() {
+        let empty = json!({ "choices": [] });
+
+        // Without a fallback route the empty responses just use up the iterations
+        let mut tool_loop = ToolLoop::new(3);
+        for _ in 0..3 {
+            assert_eq!(tool_loop.next(&empty, false), LoopAction::Retry);
+        }
+        assert_eq!(tool_loop.iterations_left, 0);
+
+        // A tool call in between resets the count
+        let call = json!({ "id": "c1", "type": "function", "function": { "name": "calculator", "arguments": "{}" } });
+        let mut tool_loop = ToolLoop::new(3);
+        assert_eq!(tool_loop.next(&empty, true), LoopAction::Retry);
+        assert!(matches!(tool_loop.next(&message(json!({ "tool_calls": [call] })), true), LoopAction::RunTools { .. }));
+        assert_eq!(tool_loop.next(&empty, true), LoopAction::Retry);
+    }
+
+    #[test]
+    fn strip_answer_footer_removes_rendered_footer_only() {
+        let template = "Generated by W9 · {model} ({provider}) · {date}";
+        let answer = "Rust 1.80 is current [1].\n\n---\nGenerated by W9 · openai/gpt-4o (OpenRouter) · 2026-10-16";
+        assert_eq!(RAGSystem::strip_answer_footer(answer, template), "Rust 1.80 is current [1].");
+
+        // A horizontal rule inside the answer, or a footer from another template, stays
+        let ruled = "Part one\n\n---\nPart two";
+        assert_eq!(RAGSystem::strip_answer_footer(ruled, template), ruled);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::auth::CurrentUser;
+use crate::error::ApiError;
+use crate::AppState;
+
+/// Past this many tracked clients, buckets that have refilled are dropped
+const MAX_IDLE_CLIENTS: usize = 1024;
+
+/// `QUERY_RATE_LIMIT`: queries per minute each client may run. Unset or 0 disables limiting.
+fn queries_per_minute() -> Option<f64> {
+    std::env::var("QUERY_RATE_LIMIT")
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| *v > 0.0)
+}
+
+/// `QUERY_RATE_BURST`: queries a client may send back to back (default: the per-minute limit).
+fn burst(per_minute: f64) -> f64 {
+    std::env::var("QUERY_RATE_BURST")
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| *v >= 1.0)
+        .unwrap_or(per_minute.max(1.0))
+}
+
+/// `TRUST_PROXY_HEADERS=true`: take the client address from `X-Forwarded-For`/`X-Real-IP`,
+/// for deployments behind a reverse proxy. Off by default, since clients can set them.
+fn trust_proxy_headers() -> bool {
+    std::env::var("TRUST_PROXY_HEADERS").map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(false)
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per client for the query endpoints, so one client can't spend the
+/// whole provider quota.
+#[derive(Default)]
+pub struct QueryRateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl QueryRateLimiter {
+    /// Take one token for `client`, or say how long until one is available.
+    fn acquire(&self, client: &str, per_minute: f64, capacity: f64) -> Result<(), Duration> {
+        let rate = per_minute / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_IDLE_CLIENTS && !buckets.contains_key(client) {
+            buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < capacity);
+        }
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Who a request counts against: the signed-in user, else its IP address. Tokens only
+/// count once `require_session` has resolved them to a user; otherwise any made-up
+/// Bearer token would get a fresh bucket.
+fn client_key(req: &Request) -> String {
+    if let Some(CurrentUser(Some(user_id))) = req.extensions().get::<CurrentUser>() {
+        return format!("user:{}", user_id);
+    }
+    let forwarded = trust_proxy_headers().then(|| forwarded_ip(req.headers())).flatten();
+    let ip = forwarded.or_else(|| {
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+    });
+    format!("ip:{}", ip.unwrap_or_default())
+}
+
+fn forwarded_ip(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(',').next())
+        .or_else(|| headers.get("x-real-ip").and_then(|h| h.to_str().ok()))
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+}
+
+/// Middleware for the query routes: answers 429 with `Retry-After` once a client is over
+/// `QUERY_RATE_LIMIT`.
+pub async fn limit_queries(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(per_minute) = queries_per_minute() else {
+        return next.run(req).await;
+    };
+
+    let client = client_key(&req);
+    match state.query_limiter.acquire(&client, per_minute, burst(per_minute)) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            tracing::warn!("Query rate limit exceeded for {}, retry in {}s", client, retry_after);
+            let mut response = ApiError::RateLimited(format!(
+                "Too many queries; try again in {} seconds",
+                retry_after
+            ))
+            .into_response();
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            response
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use crate::error::ProviderError;
+use crate::http;
+
+/// Reranking backend, configured independently of the chat and embedding providers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RerankProviderType {
+    Cohere,
+    /// Self-hosted cross-encoder behind a Cohere-compatible `/rerank` endpoint
+    /// (Infinity, vLLM, LocalAI, text-embeddings-inference, ...)
+    Local,
+}
+
+impl RerankProviderType {
+    fn from_str(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "cohere" => Some(Self::Cohere),
+            "local" => Some(Self::Local),
+            _ => None,
+        }
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        match self {
+            Self::Cohere => "https://api.cohere.ai/v1",
+            Self::Local => "http://localhost:7997",
+        }
+    }
+
+    fn default_model(&self) -> &'static str {
+        match self {
+            Self::Cohere => "rerank-english-v3.0",
+            Self::Local => "BAAI/bge-reranker-base",
+        }
+    }
+}
+
+/// Scores passages against a query with the provider selected by `RERANK_PROVIDER`.
+/// `RERANK_MODEL`, `RERANK_BASE_URL` and `RERANK_API_KEY` override the provider
+/// defaults; Cohere falls back to `COHERE_API_KEY`.
+#[derive(Debug, Clone)]
+pub struct Reranker {
+    provider: RerankProviderType,
+    model: String,
+    base_url: String,
+    api_key: Option<String>,
+    /// Shared LLM client; each call sets its own timeout
+    client: reqwest::Client,
+}
+
+impl Reranker {
+    /// `None` when reranking is not configured or the provider is unknown.
+    pub fn from_env(client: reqwest::Client) -> Option<Self> {
+        let name = std::env::var("RERANK_PROVIDER").ok().filter(|p| !p.trim().is_empty())?;
+        let Some(provider) = RerankProviderType::from_str(&name) else {
+            tracing::warn!("Unknown RERANK_PROVIDER '{}' (expected cohere or local); reranking disabled", name);
+            return None;
+        };
+
+        let env = |var: &str| std::env::var(var).ok().filter(|v| !v.trim().is_empty());
+        let api_key = env("RERANK_API_KEY")
+            .or_else(|| (provider == RerankProviderType::Cohere).then(|| env("COHERE_API_KEY")).flatten());
+        if api_key.is_none() && provider == RerankProviderType::Cohere {
+            tracing::warn!("RERANK_PROVIDER=cohere has no API key; reranking requests will be rejected");
+        }
+
+        Some(Self {
+            model: env("RERANK_MODEL").unwrap_or_else(|| provider.default_model().to_string()),
+            base_url: env("RERANK_BASE_URL")
+                .unwrap_or_else(|| provider.default_base_url().to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            api_key,
+            client,
+            provider,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{:?} ({} at {})", self.provider, self.model, self.base_url)
+    }
+
+    /// Rank `documents` by relevance to `query`, returning up to `top_n`
+    /// `(index into documents, score)` pairs, best first.
+    pub async fn rerank(&self, query: &str, documents: &[String], top_n: usize) -> Result<Vec<(usize, f32)>> {
+        if documents.is_empty() || top_n == 0 {
+            return Ok(Vec::new());
+        }
+
+        let body = serde_json::json!({
+            "model": self.model,
+            "query": query,
+            "documents": documents,
+            "top_n": top_n.min(documents.len()),
+        });
+        let mut request = self.client.post(format!("{}/rerank", self.base_url)).timeout(http::env_timeout("RERANK_TIMEOUT_SECS", 30)).json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        let resp = request.send().await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let text = resp.text().await.unwrap_or_default();
+            return Err(ProviderError::from_status(&format!("{:?} rerank", self.provider), status, &text).into());
+        }
+
+        // Cohere-style `{ results: [{ index, relevance_score }] }`; TEI returns a bare `[{ index, score }]`
+        let json: serde_json::Value = resp.json().await?;
+        let results = json.get("results").unwrap_or(&json).as_array()
+            .ok_or_else(|| ProviderError::Upstream("Rerank response has no results".to_string()))?;
+        let mut ranked: Vec<(usize, f32)> = results.iter()
+            .filter_map(|r| {
+                let index = usize::try_from(r["index"].as_u64()?).ok()?;
+                let score = r.get("relevance_score").or_else(|| r.get("score"))?.as_f64()? as f32;
+                (index < documents.len()).then_some((index, score))
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(top_n);
+        Ok(ranked)
+    }
+}
@@ -0,0 +1,117 @@
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+/// Receives "retrying" and fallback notices for the surrounding query, e.g. to stream them as status events
+pub type RetryReporter = Arc<dyn Fn(String) + Send + Sync>;
+
+tokio::task_local! {
+    static REPORTER: RetryReporter;
+}
+
+/// Run `fut` with `reporter` told about every provider call retried inside it.
+pub async fn report_retries<F: Future>(reporter: RetryReporter, fut: F) -> F::Output {
+    REPORTER.scope(reporter, fut).await
+}
+
+/// Tell the surrounding query's reporter, if any, about `notice`.
+pub fn report(notice: String) {
+    let _ = REPORTER.try_with(|report| report(notice));
+}
+
+/// Extra attempts after the first (`PROVIDER_RETRIES`, default 2; 0 disables retrying).
+fn max_retries() -> u32 {
+    std::env::var("PROVIDER_RETRIES")
+        .ok()
+        .and_then(|v| v.trim().parse::<u32>().ok())
+        .unwrap_or(2)
+}
+
+/// First backoff delay (`PROVIDER_RETRY_BASE_MS`, default 500), doubled on each retry.
+fn base_delay() -> Duration {
+    let ms = std::env::var("PROVIDER_RETRY_BASE_MS")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|ms| *ms > 0)
+        .unwrap_or(500);
+    Duration::from_millis(ms)
+}
+
+/// Longest wait before a retry (`PROVIDER_RETRY_MAX_SECS`, default 30). A `Retry-After`
+/// beyond it, such as a daily quota reset, fails the call straight away instead.
+fn max_delay() -> Duration {
+    crate::http::env_timeout("PROVIDER_RETRY_MAX_SECS", 30)
+}
+
+/// Exponential backoff for retry number `retry` (1-based), with up to half of it
+/// randomly taken off so clients hitting the same limit don't retry in lockstep.
+fn backoff(retry: u32) -> Duration {
+    let delay = base_delay().saturating_mul(2u32.saturating_pow(retry - 1));
+    let jitter = (std::collections::hash_map::RandomState::new().hash_one(retry) % 1000) as f64 / 2000.0;
+    delay.mul_f64(1.0 - jitter)
+}
+
+/// `Retry-After` in either of its forms: delay seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
+
+fn retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Send `request`, retrying connection failures, 429s and 5xx responses with exponential
+/// backoff and jitter, or after the server's `Retry-After`. The last response or error
+/// is returned as-is for the caller's usual status handling. `label` names the provider
+/// in logs and in the status reported to the query.
+pub async fn send(label: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+    let retries = max_retries();
+    let mut retry = 0;
+    loop {
+        // The final attempt (or a body that can't be replayed) consumes the request
+        let Some(attempt) = request.try_clone().filter(|_| retry < retries) else {
+            return request.send().await;
+        };
+        let result = attempt.send().await;
+        retry += 1;
+
+        let (reason, delay) = match &result {
+            Ok(resp) if retryable_status(resp.status()) => {
+                (resp.status().to_string(), retry_after(resp).unwrap_or_else(|| backoff(retry)))
+            }
+            Err(e) if e.is_connect() || (e.is_request() && !e.is_timeout()) => (e.to_string(), backoff(retry)),
+            _ => return result,
+        };
+        if delay > max_delay() {
+            tracing::warn!("{} failed ({}); Retry-After of {}s is too long to wait", label, reason, delay.as_secs());
+            return result;
+        }
+
+        let notice = format!(
+            "{} failed ({}), retrying in {:.1}s (attempt {}/{})",
+            label,
+            reason,
+            delay.as_secs_f64(),
+            retry + 1,
+            retries + 1
+        );
+        tracing::warn!("{}", notice);
+        report(notice);
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,246 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use crate::search::{source_max_age, source_max_rows, WebSearch, PAGE_CACHE_RETENTION_DAYS};
+use crate::AppState;
+
+/// Sources embedded per `rebuild_indexes` run, so a large backlog is worked off gradually
+const REINDEX_BATCH_SIZE: i64 = 100;
+
+/// Periodic maintenance. Each job's schedule is a cron expression read from its env
+/// var (standard 5-field crontab, or 6 fields with leading seconds); `off` disables it.
+#[derive(Debug, Clone, Copy)]
+enum Job {
+    RefreshModels,
+    SyncLimits,
+    Prune,
+    RebuildIndexes,
+    Alerts,
+}
+
+impl Job {
+    const ALL: [Job; 5] = [Job::RefreshModels, Job::SyncLimits, Job::Prune, Job::RebuildIndexes, Job::Alerts];
+
+    fn name(self) -> &'static str {
+        match self {
+            Job::RefreshModels => "refresh_models",
+            Job::SyncLimits => "sync_limits",
+            Job::Prune => "prune",
+            Job::RebuildIndexes => "rebuild_indexes",
+            Job::Alerts => "alerts",
+        }
+    }
+
+    fn env_var(self) -> &'static str {
+        match self {
+            Job::RefreshModels => "SCHEDULE_REFRESH_MODELS",
+            Job::SyncLimits => "SCHEDULE_SYNC_LIMITS",
+            Job::Prune => "SCHEDULE_PRUNE",
+            Job::RebuildIndexes => "SCHEDULE_REBUILD_INDEXES",
+            Job::Alerts => "SCHEDULE_ALERTS",
+        }
+    }
+
+    fn default_schedule(self) -> &'static str {
+        match self {
+            Job::RefreshModels => "0 */6 * * *",
+            Job::SyncLimits => "*/30 * * * *",
+            Job::Prune => "0 3 * * *",
+            Job::RebuildIndexes => "30 3 * * *",
+            // How often due alerts are looked for; each alert has its own schedule
+            Job::Alerts => "* * * * *",
+        }
+    }
+
+    /// `None` when the job is switched off or its expression doesn't parse.
+    fn schedule(self) -> Option<cron::Schedule> {
+        let expr = std::env::var(self.env_var())
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .unwrap_or_else(|| self.default_schedule().to_string());
+        let expr = expr.trim();
+        if matches!(expr.to_lowercase().as_str(), "off" | "false" | "disabled") {
+            tracing::info!("Scheduler: {} disabled ({}={})", self.name(), self.env_var(), expr);
+            return None;
+        }
+        match parse_schedule(expr) {
+            Ok(schedule) => Some(schedule),
+            Err(e) => {
+                tracing::warn!("Scheduler: invalid {} '{}' ({}); {} disabled", self.env_var(), expr, e, self.name());
+                None
+            }
+        }
+    }
+
+    /// Run once, returning a short summary for the log.
+    async fn run(self, state: &AppState) -> anyhow::Result<String> {
+        match self {
+            Job::RefreshModels => {
+                let timeout_secs = std::env::var("MODEL_FETCH_TIMEOUT_SECS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(60);
+                let count = state.llm_manager.fetch_available_models(Duration::from_secs(timeout_secs)).await?;
+                Ok(format!("{} models available", count))
+            }
+            Job::SyncLimits => {
+                sync_limits(state).await;
+                Ok("provider limits synced".to_string())
+            }
+            Job::Prune => {
+                let (sessions, pages) = state.db
+                    .prune_expired(chrono::Duration::days(PAGE_CACHE_RETENTION_DAYS))
+                    .await?;
+                let (max_age, max_rows) = (source_max_age(), source_max_rows());
+                let sources = if max_age.is_some() || max_rows.is_some() {
+                    state.db.prune_sources(max_age, max_rows).await?
+                } else {
+                    0
+                };
+                Ok(format!(
+                    "removed {} expired session(s), {} cached page(s) and {} source(s)",
+                    sessions, pages, sources
+                ))
+            }
+            Job::RebuildIndexes => {
+                let mut indexed = 0;
+                if let Some(model) = state.llm_manager.embedding_model() {
+                    for (id, content) in state.db.sources_missing_embeddings(model, REINDEX_BATCH_SIZE).await? {
+                        match state.llm_manager.index_source(id, &content).await {
+                            Ok(_) => indexed += 1,
+                            Err(e) => tracing::warn!("Scheduler: failed to embed source {}: {}", id, e),
+                        }
+                    }
+                }
+                state.db.optimize().await?;
+                Ok(format!("embedded {} source(s), optimized database", indexed))
+            }
+            Job::Alerts => {
+                let ran = crate::alerts::run_due(state).await?;
+                Ok(format!("ran {} alert(s)", ran))
+            }
+        }
+    }
+}
+
+/// Parse a cron expression, accepting the usual 5-field form by running it at second 0.
+/// 5-field expressions number weekdays as crontab does (0 or 7 = Sunday, 1 = Monday);
+/// 6- and 7-field ones use the `cron` crate's numbering (1 = Sunday, 7 = Saturday).
+pub fn parse_schedule(expr: &str) -> Result<cron::Schedule, cron::error::Error> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    if let [minute, hour, day, month, weekday] = fields[..] {
+        let weekday = crontab_weekdays(weekday);
+        cron::Schedule::from_str(&format!("0 {} {} {} {} {}", minute, hour, day, month, weekday))
+    } else {
+        cron::Schedule::from_str(expr)
+    }
+}
+
+/// Rewrite a crontab day-of-week field for the `cron` crate, which counts Sunday as 1.
+/// Numeric items are expanded to the days they cover; names, `*` and `?` are kept,
+/// and anything malformed is passed through for the crate to reject.
+fn crontab_weekdays(field: &str) -> String {
+    if field == "*" || field == "?" {
+        return field.to_string();
+    }
+    let items: Vec<String> = field
+        .split(',')
+        .map(|item| {
+            let (base, step) = match item.split_once('/') {
+                Some((base, step)) => (base, step.parse::<u32>().ok().filter(|s| *s > 0)),
+                None => (item, Some(1)),
+            };
+            let range = if base == "*" {
+                Some((0, 6))
+            } else if let Some((from, to)) = base.split_once('-') {
+                from.parse().ok().zip(to.parse().ok())
+            } else {
+                // `n/step` runs from n to the end of the week
+                base.parse().ok().map(|from| (from, if item.contains('/') { 6 } else { from }))
+            };
+            match (range, step) {
+                (Some((from, to)), Some(step)) if from <= to && to <= 7 => (from..=to)
+                    .step_by(step as usize)
+                    .map(|day: u32| (day % 7 + 1).to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                _ => item.to_string(),
+            }
+        })
+        .collect();
+    items.join(",")
+}
+
+/// Refresh Tavily usage and the LLM providers' remaining limits; errors are logged.
+pub async fn sync_limits(state: &AppState) {
+    if let Err(e) = WebSearch::sync_tavily_usage(&state.http, &state.db).await {
+        tracing::error!("Sync Tavily limits error: {}", e);
+    }
+    // OpenRouter, Pollinations, etc.
+    if let Err(e) = state.llm_manager.refresh_llm_limits().await {
+        tracing::error!("Sync LLM limits error: {}", e);
+    }
+}
+
+/// Start one background task per enabled job. `SCHEDULER_ENABLED=false` turns them all off;
+/// the startup model fetch and Tavily sync in `main` run regardless.
+pub fn spawn(state: AppState) {
+    if std::env::var("SCHEDULER_ENABLED").is_ok_and(|v| v.eq_ignore_ascii_case("false")) {
+        tracing::info!("Scheduler disabled (SCHEDULER_ENABLED=false)");
+        return;
+    }
+
+    for job in Job::ALL {
+        let Some(schedule) = job.schedule() else {
+            continue;
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            while let Some(next) = schedule.upcoming(Utc).next() {
+                tracing::debug!("Scheduler: next {} run at {}", job.name(), next.to_rfc3339());
+                tokio::time::sleep((next - Utc::now()).to_std().unwrap_or_default()).await;
+                let started = Instant::now();
+                match job.run(&state).await {
+                    Ok(summary) => tracing::info!("Scheduler: {} finished in {:?}: {}", job.name(), started.elapsed(), summary),
+                    Err(e) => tracing::error!("Scheduler: {} failed: {}", job.name(), e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, TimeZone, Weekday};
+
+    /// Weekdays of the next `n` runs after Sunday 2024-06-02 00:00 UTC
+    fn next_weekdays(expr: &str, n: usize) -> Vec<Weekday> {
+        let start = Utc.with_ymd_and_hms(2024, 6, 2, 0, 0, 0).unwrap();
+        parse_schedule(expr).unwrap().after(&start).take(n).map(|t| t.weekday()).collect()
+    }
+
+    #[test]
+    fn crontab_weekday_range_runs_monday_to_friday() {
+        use Weekday::*;
+        assert_eq!(next_weekdays("0 9 * * 1-5", 6), [Mon, Tue, Wed, Thu, Fri, Mon]);
+    }
+
+    #[test]
+    fn crontab_sunday_is_zero_or_seven() {
+        assert_eq!(next_weekdays("30 8 * * 0", 2), [Weekday::Sun, Weekday::Sun]);
+        assert_eq!(next_weekdays("30 8 * * 7", 1), [Weekday::Sun]);
+        assert_eq!(next_weekdays("0 0 * * 5,6", 2), [Weekday::Fri, Weekday::Sat]);
+        assert_eq!(next_weekdays("0 0 * * */2", 4), [Weekday::Tue, Weekday::Thu, Weekday::Sat, Weekday::Sun]);
+    }
+
+    #[test]
+    fn names_and_six_field_expressions_are_unchanged() {
+        assert_eq!(next_weekdays("0 0 * * MON-FRI", 1), [Weekday::Mon]);
+        // With seconds the crate's own numbering applies: 2 = Monday
+        assert_eq!(next_weekdays("0 0 0 * * 2", 1), [Weekday::Mon]);
+        assert!(parse_schedule("0 0 * * 8").is_err());
+    }
+}
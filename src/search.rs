@@ -1,8 +1,15 @@
 use anyhow::Result;
 use scraper::{Html, Selector};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
-use crate::db::Database;
+use std::sync::OnceLock;
+use crate::circuit::{CircuitBreaker, ProviderHealth};
+use crate::db::{CachedPage, Database};
+use crate::documents::DocumentKind;
+use crate::domains::{on_domains, DomainRules};
+use crate::http::{HttpClients, BROWSER_USER_AGENT};
+use crate::llm::provider_disabled;
 
 #[derive(Debug, Clone)]
 pub struct SearchResult {
@@ -11,9 +18,258 @@ pub struct SearchResult {
     pub snippet: String,
 }
 
+/// Cached pages not refreshed for this long are dropped from `page_cache`
+pub const PAGE_CACHE_RETENTION_DAYS: i64 = 7;
+
+/// Stored web sources older than `SOURCE_MAX_AGE_DAYS` are refetched before use and
+/// dropped by the prune job. Unset or 0 keeps them forever.
+pub fn source_max_age() -> Option<chrono::Duration> {
+    std::env::var("SOURCE_MAX_AGE_DAYS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|days| *days > 0)
+        .map(chrono::Duration::days)
+}
+
+/// At most `SOURCE_MAX_ROWS` web sources are kept by the prune job, most recently fetched
+/// first. Unset or 0 means no cap.
+pub fn source_max_rows() -> Option<i64> {
+    std::env::var("SOURCE_MAX_ROWS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|rows| *rows > 0)
+}
+
+/// A fetched page: the extracted text plus the raw HTML it came from.
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    /// Final URL after redirects
+    pub url: String,
+    pub content: String,
+    /// `None` for PDFs, JSON and plain text
+    pub html: Option<String>,
+}
+
+/// How a fetched body is turned into text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageKind {
+    Html,
+    Pdf,
+    Json,
+    Text,
+}
+
+impl PageKind {
+    /// Lowercase MIME type without parameters.
+    fn mime(content_type: &str) -> String {
+        content_type.split(';').next().unwrap_or_default().trim().to_lowercase()
+    }
+
+    fn is_media(mime: &str) -> bool {
+        ["image/", "audio/", "video/", "font/"].iter().any(|prefix| mime.starts_with(prefix))
+    }
+
+    /// Classify by `Content-Type`, sniffing the body when the type is missing or
+    /// generic. `Err` carries the MIME type of unsupported bodies.
+    fn detect(content_type: Option<&str>, url: &str, body: &[u8]) -> std::result::Result<Self, String> {
+        let mime = content_type.map(Self::mime).unwrap_or_default();
+        let looks_like_pdf = body.starts_with(b"%PDF-");
+        match mime.as_str() {
+            "application/pdf" | "application/x-pdf" => Ok(Self::Pdf),
+            _ if looks_like_pdf => Ok(Self::Pdf),
+            "" | "text/html" | "application/xhtml+xml" => Ok(Self::Html),
+            "text/plain" | "text/markdown" | "text/x-markdown" | "text/csv" => Ok(Self::Text),
+            m if m == "application/json" || m.ends_with("+json") => Ok(Self::Json),
+            // XML, RSS and other text formats go through the tag-stripping HTML extractor
+            m if m.starts_with("text/") || m.ends_with("xml") => Ok(Self::Html),
+            "application/octet-stream" | "binary/octet-stream"
+                if url.to_lowercase().split(['?', '#']).next().is_some_and(|path| path.ends_with(".pdf")) => Ok(Self::Pdf),
+            other => Err(other.to_string()),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Pdf => "pdf",
+            Self::Json => "json",
+            Self::Text => "text",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "html" => Some(Self::Html),
+            "pdf" => Some(Self::Pdf),
+            "json" => Some(Self::Json),
+            "text" => Some(Self::Text),
+            _ => None,
+        }
+    }
+}
+
+/// Supported result regions: ISO 3166-1 alpha-2 code, display name and the
+/// DuckDuckGo `kl` region it maps to.
+pub const SEARCH_REGIONS: &[(&str, &str, &str)] = &[
+    ("AR", "Argentina", "ar-es"),
+    ("AT", "Austria", "at-de"),
+    ("AU", "Australia", "au-en"),
+    ("BE", "Belgium", "be-fr"),
+    ("BR", "Brazil", "br-pt"),
+    ("CA", "Canada", "ca-en"),
+    ("CH", "Switzerland", "ch-de"),
+    ("CN", "China", "cn-zh"),
+    ("DE", "Germany", "de-de"),
+    ("DK", "Denmark", "dk-da"),
+    ("ES", "Spain", "es-es"),
+    ("FI", "Finland", "fi-fi"),
+    ("FR", "France", "fr-fr"),
+    ("GB", "United Kingdom", "uk-en"),
+    ("IE", "Ireland", "ie-en"),
+    ("IN", "India", "in-en"),
+    ("IT", "Italy", "it-it"),
+    ("JP", "Japan", "jp-jp"),
+    ("KR", "South Korea", "kr-kr"),
+    ("MX", "Mexico", "mx-es"),
+    ("NL", "Netherlands", "nl-nl"),
+    ("NO", "Norway", "no-no"),
+    ("NZ", "New Zealand", "nz-en"),
+    ("PL", "Poland", "pl-pl"),
+    ("PT", "Portugal", "pt-pt"),
+    ("SE", "Sweden", "se-sv"),
+    ("SG", "Singapore", "sg-en"),
+    ("TR", "Turkey", "tr-tr"),
+    ("US", "United States", "us-en"),
+    ("VN", "Vietnam", "vn-vi"),
+    ("ZA", "South Africa", "za-en"),
+];
+
+/// Default number of results requested per search
+pub const DEFAULT_MAX_RESULTS: usize = 5;
+/// Upper bound for per-request result and fetch counts
+pub const MAX_RESULTS_LIMIT: usize = 20;
+
+/// Server-side ceiling on results per search. `MAX_RESULTS_PER_SEARCH` can lower it
+/// below `MAX_RESULTS_LIMIT`, never raise it.
+pub fn max_results_ceiling() -> usize {
+    env::var("MAX_RESULTS_PER_SEARCH")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .map_or(MAX_RESULTS_LIMIT, |n| n.min(MAX_RESULTS_LIMIT))
+}
+
+/// Freshness filter, mapped to each provider's native parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeRange {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl TimeRange {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "day" => Some(Self::Day),
+            "week" => Some(Self::Week),
+            "month" => Some(Self::Month),
+            "year" => Some(Self::Year),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+            Self::Year => "year",
+        }
+    }
+
+    pub fn days(&self) -> u32 {
+        match self {
+            Self::Day => 1,
+            Self::Week => 7,
+            Self::Month => 30,
+            Self::Year => 365,
+        }
+    }
+}
+
+/// Perplexity-style focus mode: which engines a search fans out to, how its results are
+/// filtered, and how the answer is framed (see `RAGSystem`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Focus {
+    News,
+    Academic,
+    Code,
+    Social,
+}
+
+impl Focus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::News => "news",
+            Self::Academic => "academic",
+            Self::Code => "code",
+            Self::Social => "social",
+        }
+    }
+
+    /// Dedicated providers searched alongside the general engine, when configured
+    fn providers(&self) -> &'static [&'static str] {
+        match self {
+            Self::Academic => &["arxiv"],
+            Self::Code => &["github", "stackoverflow"],
+            Self::News | Self::Social => &[],
+        }
+    }
+
+    /// Sites results must come from; the general engine's query is narrowed to them
+    fn sites(&self) -> &'static [&'static str] {
+        match self {
+            Self::Social => &["reddit.com", "news.ycombinator.com"],
+            Self::News | Self::Academic | Self::Code => &[],
+        }
+    }
+
+    /// SearXNG category to search instead of general
+    fn searxng_category(&self) -> &'static str {
+        match self {
+            Self::News => "news",
+            Self::Academic => "science",
+            Self::Code => "it",
+            Self::Social => "social media",
+        }
+    }
+}
+
+/// Per-search knobs passed through to the provider.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchOptions<'a> {
+    /// ISO country code from `SEARCH_REGIONS`; providers without a country
+    /// parameter ignore it.
+    pub region: Option<&'a str>,
+    pub max_results: usize,
+    /// Restrict results to allowlisted domains
+    pub allowed_only: bool,
+    pub time_range: Option<TimeRange>,
+    pub focus: Option<Focus>,
+}
+
+impl Default for SearchOptions<'_> {
+    fn default() -> Self {
+        Self { region: None, max_results: DEFAULT_MAX_RESULTS, allowed_only: false, time_range: None, focus: None }
+    }
+}
+
 #[async_trait::async_trait]
 pub trait SearchProvider: Send + Sync {
-    async fn search(&self, db: &Database, query: &str) -> Result<Vec<SearchResult>>;
+    async fn search(&self, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>>;
     fn name(&self) -> &str;
 }
 
@@ -25,15 +281,20 @@ impl SearchProvider for DuckDuckGoSearch {
         "DuckDuckGo"
     }
 
-    async fn search(&self, _db: &Database, query: &str) -> Result<Vec<SearchResult>> {
-        let url = format!("https://html.duckduckgo.com/html/?q={}", 
+    async fn search(&self, http: &HttpClients, _db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
+        let mut url = format!("https://html.duckduckgo.com/html/?q={}", 
             urlencoding::encode(query));
+        if let Some(kl) = options.region.and_then(|code| SEARCH_REGIONS.iter().find(|(c, _, _)| *c == code)).map(|(_, _, kl)| kl) {
+            url.push_str(&format!("&kl={}", kl));
+        }
+        // df takes the first letter: d, w, m or y
+        if let Some(range) = options.time_range {
+            url.push_str(&format!("&df={}", &range.as_str()[..1]));
+        }
         
-        let client = reqwest::Client::builder()
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36")
-            .build()?;
-        
-        let html = client.get(&url).send().await?.text().await?;
+        // The HTML endpoint serves a captcha to non-browser user agents
+        let request = http.search.get(&url).header(reqwest::header::USER_AGENT, BROWSER_USER_AGENT);
+        let html = crate::retry::send(self.name(), request).await?.text().await?;
         let document = Html::parse_document(&html);
         
         let result_selector = Selector::parse(".result").unwrap();
@@ -42,7 +303,7 @@ impl SearchProvider for DuckDuckGoSearch {
         
         let mut results = Vec::new();
         
-        for result in document.select(&result_selector).take(5) {
+        for result in document.select(&result_selector).take(options.max_results) {
             if let Some(title_elem) = result.select(&title_selector).next() {
                 let title = title_elem.text().collect::<String>();
                 let mut url = title_elem.value().attr("href")
@@ -111,20 +372,22 @@ impl SearchProvider for BraveSearch {
         "Brave Search"
     }
 
-    async fn search(&self, db: &Database, query: &str) -> Result<Vec<SearchResult>> {
+    async fn search(&self, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
         // Check rate limit (cost 1)
         if !db.check_search_rate_limit("search:brave", 1).await? {
             return Err(anyhow::anyhow!("Brave Search rate limit exceeded"));
         }
 
-        let client = reqwest::Client::new();
-        let response = client
+        let client = &http.search;
+        let request = client
             .get("https://api.search.brave.com/res/v1/web/search")
-            .query(&[("q", query), ("count", "5")])
+            .query(&[("q", query), ("count", &options.max_results.to_string())])
+            .query(&[("country", options.region)])
+            // Brave freshness: pd, pw, pm or py
+            .query(&[("freshness", options.time_range.map(|r| format!("p{}", &r.as_str()[..1])))])
             .header("X-Subscription-Token", &self.api_key)
-            .header("Accept", "application/json")
-            .send()
-            .await?;
+            .header("Accept", "application/json");
+        let response = crate::retry::send(self.name(), request).await?;
 
         // Parse headers for rate limits
         let remaining_header = response.headers().get("x-ratelimit-remaining")
@@ -141,7 +404,7 @@ impl SearchProvider for BraveSearch {
             if rem_parts.len() >= 2 && lim_parts.len() >= 2 {
                 if let (Ok(rem_month), Ok(lim_month)) = (rem_parts[1].parse::<i64>(), lim_parts[1].parse::<i64>()) {
                     let used_month = lim_month.saturating_sub(rem_month);
-                    let _ = db.update_search_limits("search:brave", Some(used_month), Some(lim_month), None).await;
+                    let _ = db.update_search_limits("search:brave", Some(used_month), Some(lim_month), None, None).await;
                 }
             }
         }
@@ -184,23 +447,32 @@ impl SearchProvider for TavilySearch {
         "Tavily"
     }
 
-    async fn search(&self, db: &Database, query: &str) -> Result<Vec<SearchResult>> {
+    async fn search(&self, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
         // Check rate limit (cost 1 for basic search)
         if !db.check_search_rate_limit("search:tavily", 1).await? {
             return Err(anyhow::anyhow!("Tavily rate limit exceeded"));
         }
 
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://api.tavily.com/search")
-            .json(&serde_json::json!({
-                "api_key": self.api_key,
-                "query": query,
-                "search_depth": "basic",
-                "max_results": 5
-            }))
-            .send()
-            .await?;
+        let client = &http.search;
+        let mut body = serde_json::json!({
+            "api_key": self.api_key,
+            "query": query,
+            "search_depth": "basic",
+            "max_results": options.max_results
+        });
+        // Tavily expects the lowercase country name rather than the ISO code
+        if let Some((_, name, _)) = options.region.and_then(|code| SEARCH_REGIONS.iter().find(|(c, _, _)| *c == code)) {
+            body["country"] = serde_json::json!(name.to_lowercase());
+        }
+        if options.focus == Some(Focus::News) {
+            body["topic"] = serde_json::json!("news");
+        }
+        // `days` only applies to the news topic; `time_range` covers general searches
+        if let Some(range) = options.time_range {
+            body["days"] = serde_json::json!(range.days());
+            body["time_range"] = serde_json::json!(range.as_str());
+        }
+        let response = crate::retry::send(self.name(), client.post("https://api.tavily.com/search").json(&body)).await?;
 
         if !response.status().is_success() {
             return Err(anyhow::anyhow!("Tavily API error: {}", response.status()));
@@ -240,10 +512,9 @@ impl SearchProvider for SearXNGSearch {
         "SearXNG"
     }
 
-    async fn search(&self, _db: &Database, query: &str) -> Result<Vec<SearchResult>> {
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
-            .build()?;
+    async fn search(&self, http: &HttpClients, _db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
+        let client = &http.search;
+        // SearXNG only filters by language, so the region is not forwarded
             
         let base = self.base_url.trim_end_matches('/');
         let url = if base.ends_with("/search") {
@@ -254,14 +525,48 @@ impl SearchProvider for SearXNGSearch {
         
         tracing::debug!("SearXNG URL: {}", url);
         
-        let response = client
+        let mut params = vec![("q", query.to_string()), ("format", "json".to_string())];
+        if let Some(focus) = options.focus {
+            params.push(("categories", focus.searxng_category().to_string()));
+        }
+        
+        // SEARXNG_SAFESEARCH: 0 (off), 1 (moderate), 2 (strict)
+        if let Ok(level) = env::var("SEARXNG_SAFESEARCH") {
+            match level.trim() {
+                "0" | "1" | "2" => params.push(("safesearch", level.trim().to_string())),
+                "" => {}
+                other => tracing::warn!("Ignoring invalid SEARXNG_SAFESEARCH '{}' (expected 0, 1 or 2)", other),
+            }
+        }
+        
+        // A per-query time range wins; otherwise SEARXNG_TIME_RANGE: day/week/month/year,
+        // or "auto" to restrict only time-sensitive queries
+        if let Some(range) = options.time_range {
+            params.push(("time_range", range.as_str().to_string()));
+        } else if let Ok(range) = env::var("SEARXNG_TIME_RANGE") {
+            let range = range.trim().to_lowercase();
+            let time_range = match range.as_str() {
+                "day" | "week" | "month" | "year" => Some(range.clone()),
+                "auto" => WebSearch::auto_time_range(query).map(str::to_string),
+                "" => None,
+                other => {
+                    tracing::warn!("Ignoring invalid SEARXNG_TIME_RANGE '{}'", other);
+                    None
+                }
+            };
+            if let Some(time_range) = time_range {
+                tracing::debug!("SearXNG time_range: {}", time_range);
+                params.push(("time_range", time_range));
+            }
+        }
+        
+        let request = client
             .get(&url)
-            .query(&[("q", query), ("format", "json")])
+            .query(&params)
             // Add headers to satisfy SearXNG bot detection
             .header("X-Forwarded-For", "127.0.0.1") 
-            .header("User-Agent", "w9-search/1.0")
-            .send()
-            .await?;
+            .header("User-Agent", "w9-search/1.0");
+        let response = crate::retry::send(self.name(), request).await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -278,7 +583,8 @@ impl SearchProvider for SearXNGSearch {
             e
         })?;
 
-        let results = searx_resp.results.into_iter().map(|r| SearchResult {
+        // SearXNG has no result-count parameter, so trim client-side
+        let results = searx_resp.results.into_iter().take(options.max_results).map(|r| SearchResult {
             title: r.title,
             url: r.url,
             snippet: r.content.unwrap_or_default(),
@@ -288,78 +594,943 @@ impl SearchProvider for SearXNGSearch {
     }
 }
 
+/// Wikipedia through the MediaWiki API. Free and keyless, so it also serves the
+/// `wikipedia_search` tool for encyclopedic questions.
+pub struct WikipediaSearch {
+    base_url: String,
+}
+
+/// One article from `WikipediaSearch::articles`: the intro or the whole text as plain text
+#[derive(Debug, Clone)]
+pub struct WikipediaArticle {
+    pub title: String,
+    pub url: String,
+    pub extract: String,
+}
+
+#[derive(Deserialize)]
+struct WikipediaResponse {
+    query: Option<WikipediaQuery>,
+}
+
+#[derive(Deserialize)]
+struct WikipediaQuery {
+    #[serde(default)]
+    pages: Vec<WikipediaPage>,
+}
+
+#[derive(Deserialize)]
+struct WikipediaPage {
+    title: String,
+    /// Rank in the search results; pages come back in page ID order
+    #[serde(default)]
+    index: usize,
+    fullurl: Option<String>,
+    #[serde(default)]
+    extract: String,
+}
+
+impl WikipediaSearch {
+    /// `WIKIPEDIA_BASE_URL` (e.g. a MediaWiki mirror), else the `WIKIPEDIA_LANGUAGE`
+    /// edition of Wikipedia (default "en").
+    pub fn from_env() -> Self {
+        let base_url = env::var("WIKIPEDIA_BASE_URL")
+            .ok()
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| {
+                let language = env::var("WIKIPEDIA_LANGUAGE").ok().filter(|l| !l.trim().is_empty());
+                format!("https://{}.wikipedia.org", language.as_deref().map_or("en", str::trim))
+            });
+        Self { base_url }
+    }
+
+    /// The best `limit` matches for `query`, best first, with their intro (`full`: the
+    /// whole article) as plain text. Redirects are followed.
+    pub async fn articles(&self, http: &HttpClients, query: &str, limit: usize, full: bool) -> Result<Vec<WikipediaArticle>> {
+        let limit = limit.to_string();
+        let mut params = vec![
+            ("action", "query"),
+            ("format", "json"),
+            ("formatversion", "2"),
+            ("generator", "search"),
+            ("gsrsearch", query),
+            ("gsrlimit", &limit),
+            ("prop", "extracts|info"),
+            ("inprop", "url"),
+            ("explaintext", "1"),
+            ("exlimit", &limit),
+            ("redirects", "1"),
+        ];
+        if !full {
+            params.push(("exintro", "1"));
+        }
+        // Wikimedia asks API clients to identify themselves
+        let request = http.search
+            .get(format!("{}/w/api.php", self.base_url))
+            .query(&params)
+            .header(reqwest::header::USER_AGENT, "w9-search/1.0");
+        let response = crate::retry::send(self.name(), request).await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Wikipedia API error: {}", response.status()));
+        }
+
+        let wiki_resp: WikipediaResponse = response.json().await?;
+        let mut pages = wiki_resp.query.map(|q| q.pages).unwrap_or_default();
+        pages.sort_by_key(|p| p.index);
+        Ok(pages.into_iter().map(|p| WikipediaArticle {
+            url: p.fullurl.unwrap_or_else(|| format!("{}/wiki/{}", self.base_url, urlencoding::encode(&p.title.replace(' ', "_")))),
+            title: p.title,
+            extract: p.extract,
+        }).collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for WikipediaSearch {
+    fn name(&self) -> &str {
+        "Wikipedia"
+    }
+
+    async fn search(&self, http: &HttpClients, _db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
+        // Articles aren't dated or regional, so neither option is forwarded
+        let articles = self.articles(http, query, options.max_results, false).await?;
+        Ok(articles.into_iter().map(|a| SearchResult {
+            snippet: a.extract.chars().take(500).collect(),
+            title: a.title,
+            url: a.url,
+        }).collect())
+    }
+}
+
+/// arXiv papers through its Atom API, for academic questions. Results link the abstract
+/// page and carry the authors, date, abstract and PDF link in the snippet.
+pub struct ArxivSearch {
+    base_url: String,
+}
+
+impl ArxivSearch {
+    /// `ARXIV_BASE_URL` (the API's query endpoint), defaulting to export.arxiv.org
+    pub fn from_env() -> Self {
+        let base_url = env::var("ARXIV_BASE_URL")
+            .ok()
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| "https://export.arxiv.org/api/query".to_string());
+        Self { base_url }
+    }
+
+    /// arXiv's query syntax: every word must appear somewhere in the paper's metadata,
+    /// optionally within a submission date window.
+    fn search_query(query: &str, time_range: Option<TimeRange>) -> String {
+        let terms: Vec<String> = query
+            .split(|c: char| !c.is_alphanumeric() && c != '-' && c != '.')
+            .filter(|t| !t.is_empty())
+            .map(|t| format!("all:{}", t))
+            .collect();
+        let mut search_query = terms.join(" AND ");
+        if let Some(range) = time_range {
+            let now = chrono::Utc::now();
+            let from = now - chrono::Duration::days(range.days() as i64);
+            search_query.push_str(&format!(
+                " AND submittedDate:[{} TO {}]",
+                from.format("%Y%m%d%H%M"),
+                now.format("%Y%m%d%H%M")
+            ));
+        }
+        search_query
+    }
+
+    fn parse_feed(xml: &str) -> Vec<SearchResult> {
+        // scraper copes with Atom well enough: the tags are unknown to HTML but nest as written
+        let document = Html::parse_document(xml);
+        let entry_selector = Selector::parse("entry").unwrap();
+        let field = |name: &str| Selector::parse(name).unwrap();
+        let (title_sel, id_sel, summary_sel, published_sel, author_sel, link_sel) =
+            (field("title"), field("id"), field("summary"), field("published"), field("author > name"), field("link"));
+        let text = |e: scraper::ElementRef, sel: &Selector| {
+            e.select(sel)
+                .next()
+                .map(|n| n.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" "))
+                .unwrap_or_default()
+        };
+
+        document.select(&entry_selector).filter_map(|entry| {
+            let title = text(entry, &title_sel);
+            let url = text(entry, &id_sel);
+            if title.is_empty() || !url.starts_with("http") {
+                return None;
+            }
+            let authors: Vec<String> = entry.select(&author_sel).map(|a| a.text().collect::<String>().trim().to_string()).collect();
+            let authors = match authors.len() {
+                0 => String::new(),
+                1..=3 => authors.join(", "),
+                _ => format!("{} et al.", authors[0]),
+            };
+            let published = text(entry, &published_sel);
+            let pdf = entry
+                .select(&link_sel)
+                .find(|l| l.value().attr("title") == Some("pdf") || l.value().attr("type") == Some("application/pdf"))
+                .and_then(|l| l.value().attr("href"));
+
+            let mut snippet = format!("{} ({})", authors, published.get(..10).unwrap_or(&published));
+            snippet.push_str(&format!(". {}", text(entry, &summary_sel)));
+            if let Some(pdf) = pdf {
+                snippet.push_str(&format!(" PDF: {}", pdf));
+            }
+            Some(SearchResult { title, url, snippet })
+        }).collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for ArxivSearch {
+    fn name(&self) -> &str {
+        "arXiv"
+    }
+
+    async fn search(&self, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
+        // arXiv asks clients to keep to about one request every three seconds
+        if !db.check_search_rate_limit("search:arxiv", 1).await? {
+            return Err(anyhow::anyhow!("arXiv rate limit exceeded"));
+        }
+
+        let search_query = Self::search_query(query, options.time_range);
+        if search_query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let request = http.search
+            .get(&self.base_url)
+            .query(&[
+                ("search_query", search_query.as_str()),
+                ("start", "0"),
+                ("max_results", &options.max_results.to_string()),
+                ("sortBy", "relevance"),
+            ])
+            .header(reqwest::header::USER_AGENT, "w9-search/1.0");
+        let response = crate::retry::send(self.name(), request).await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("arXiv API error: {}", response.status()));
+        }
+
+        let xml = response.text().await?;
+        Ok(Self::parse_feed(&xml).into_iter().take(options.max_results).collect())
+    }
+}
+
+/// GitHub's search API: repositories and issues, plus code when `GITHUB_TOKEN` is set
+/// (code search needs authentication). The lists are merged like a metasearch.
+pub struct GitHubSearch {
+    api_url: String,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubItems<T> {
+    items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct GitHubRepo {
+    full_name: String,
+    html_url: String,
+    description: Option<String>,
+    stargazers_count: u64,
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubIssue {
+    title: String,
+    html_url: String,
+    repository_url: String,
+    state: String,
+    comments: u64,
+    body: Option<String>,
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct GitHubCode {
+    path: String,
+    html_url: String,
+    repository: GitHubCodeRepo,
+    #[serde(default)]
+    text_matches: Vec<GitHubTextMatch>,
+}
+
+#[derive(Deserialize)]
+struct GitHubCodeRepo {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubTextMatch {
+    fragment: String,
+}
+
+impl GitHubSearch {
+    /// `GITHUB_API_URL` (e.g. a GitHub Enterprise server's `/api/v3`), defaulting to api.github.com
+    pub fn from_env() -> Self {
+        let api_url = env::var("GITHUB_API_URL")
+            .ok()
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| "https://api.github.com".to_string());
+        let token = env::var("GITHUB_TOKEN").ok().map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+        Self { api_url, token }
+    }
+
+    /// One search endpoint (`repositories`, `issues` or `code`). GitHub's remaining
+    /// search allowance is recorded from the response headers.
+    async fn items<T: serde::de::DeserializeOwned>(&self, http: &HttpClients, db: &Database, kind: &str, query: &str, per_page: usize) -> Result<Vec<T>> {
+        // Text matches give code results a snippet around the hit
+        let accept = if kind == "code" { "application/vnd.github.text-match+json" } else { "application/vnd.github+json" };
+        let mut request = http.search
+            .get(format!("{}/search/{}", self.api_url, kind))
+            .query(&[("q", query), ("per_page", &per_page.to_string())])
+            .header(reqwest::header::ACCEPT, accept)
+            .header(reqwest::header::USER_AGENT, "w9-search/1.0")
+            .header("X-GitHub-Api-Version", "2022-11-28");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = crate::retry::send(self.name(), request).await?;
+
+        let header = |name: &str| response.headers().get(name).and_then(|h| h.to_str().ok()).and_then(|v| v.parse::<i64>().ok());
+        if let (Some(limit), Some(remaining)) = (header("x-ratelimit-limit"), header("x-ratelimit-remaining")) {
+            let _ = db.update_search_limits("search:github", None, None, Some(limit.saturating_sub(remaining)), None).await;
+        }
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("GitHub API error ({} search): {}", kind, response.status()));
+        }
+        let items: GitHubItems<T> = response.json().await?;
+        Ok(items.items)
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for GitHubSearch {
+    fn name(&self) -> &str {
+        "GitHub"
+    }
+
+    async fn search(&self, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
+        let calls = if self.token.is_some() { 3 } else { 2 };
+        if !db.check_search_rate_limit("search:github", calls).await? {
+            return Err(anyhow::anyhow!("GitHub search rate limit exceeded"));
+        }
+
+        // Freshness maps to the last push for repositories and the last update for issues
+        let since = options.time_range.map(|range| {
+            (chrono::Utc::now() - chrono::Duration::days(range.days() as i64)).format("%Y-%m-%d").to_string()
+        });
+        let repo_query = since.as_ref().map_or_else(|| query.to_string(), |d| format!("{} pushed:>{}", query, d));
+        let issue_query = since.as_ref().map_or_else(|| query.to_string(), |d| format!("{} updated:>{}", query, d));
+        let n = options.max_results;
+
+        let (code, repos, issues) = tokio::join!(
+            async {
+                match self.token {
+                    Some(_) => self.items::<GitHubCode>(http, db, "code", query, n).await.map(Some),
+                    None => Ok(None),
+                }
+            },
+            self.items::<GitHubRepo>(http, db, "repositories", &repo_query, n),
+            self.items::<GitHubIssue>(http, db, "issues", &issue_query, n),
+        );
+
+        let mut lists = Vec::new();
+        let mut first_error = None;
+        match code {
+            Ok(Some(code)) => lists.push(code.into_iter().map(|c| SearchResult {
+                title: format!("{} in {}", c.path, c.repository.full_name),
+                url: c.html_url,
+                snippet: c.text_matches.into_iter().map(|m| m.fragment).collect::<Vec<_>>().join("\n...\n"),
+            }).collect()),
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("{}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+        match repos {
+            Ok(repos) => lists.push(repos.into_iter().map(|r| SearchResult {
+                snippet: format!(
+                    "{} ({} stars{})",
+                    r.description.unwrap_or_default(),
+                    r.stargazers_count,
+                    r.language.map(|l| format!(", {}", l)).unwrap_or_default()
+                ),
+                title: r.full_name,
+                url: r.html_url,
+            }).collect()),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+        match issues {
+            Ok(issues) => lists.push(issues.into_iter().map(|i| {
+                let kind = if i.pull_request.is_some() { "Pull request" } else { "Issue" };
+                let repo = i.repository_url.split("/repos/").nth(1).unwrap_or(&i.repository_url);
+                let body: String = i.body.unwrap_or_default().chars().take(500).collect();
+                SearchResult {
+                    title: i.title,
+                    url: i.html_url,
+                    snippet: format!("{} in {} ({}, {} comments): {}", kind, repo, i.state, i.comments, body.trim()),
+                }
+            }).collect()),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+        if let (true, Some(e)) = (lists.is_empty(), first_error) {
+            return Err(e);
+        }
+
+        let mut results = WebSearch::fuse(lists);
+        results.truncate(options.max_results);
+        Ok(results)
+    }
+}
+
+/// Stack Overflow (or another Stack Exchange site, `STACKEXCHANGE_SITE`) questions, with
+/// the accepted answer's text as the snippet when there is one.
+pub struct StackOverflowSearch {
+    key: Option<String>,
+    site: String,
+}
+
+const STACK_EXCHANGE_API: &str = "https://api.stackexchange.com/2.3";
+
+#[derive(Deserialize)]
+struct StackExchangeResponse<T> {
+    items: Vec<T>,
+    quota_max: Option<i64>,
+    quota_remaining: Option<i64>,
+    /// Seconds the API asks clients to wait before calling the same method again
+    backoff: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct StackQuestion {
+    title: String,
+    link: String,
+    score: i64,
+    answer_count: u64,
+    accepted_answer_id: Option<u64>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct StackAnswer {
+    answer_id: u64,
+    score: i64,
+    body: String,
+}
+
+impl StackOverflowSearch {
+    pub fn from_env() -> Self {
+        let key = env::var("STACKEXCHANGE_KEY").ok().map(|k| k.trim().to_string()).filter(|k| !k.is_empty());
+        let site = env::var("STACKEXCHANGE_SITE")
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "stackoverflow".to_string());
+        Self { key, site }
+    }
+
+    /// One API call; the daily quota left is recorded so it matches what the API counts.
+    async fn get<T: serde::de::DeserializeOwned>(&self, http: &HttpClients, db: &Database, method: &str, params: &[(&str, String)]) -> Result<Vec<T>> {
+        let mut request = http.search
+            .get(format!("{}/{}", STACK_EXCHANGE_API, method))
+            .query(params)
+            .query(&[("site", self.site.as_str())]);
+        if let Some(key) = &self.key {
+            request = request.query(&[("key", key)]);
+        }
+        let response = crate::retry::send(self.name(), request).await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("Stack Exchange API error: {}", response.status()));
+        }
+
+        let body: StackExchangeResponse<T> = response.json().await?;
+        if let (Some(max), Some(remaining)) = (body.quota_max, body.quota_remaining) {
+            let _ = db.update_search_limits("search:stackoverflow", None, None, None, Some(max.saturating_sub(remaining))).await;
+            if remaining < 20 {
+                tracing::warn!("Stack Exchange quota nearly used up: {}/{} left today", remaining, max);
+            }
+        }
+        if let Some(backoff) = body.backoff {
+            tracing::warn!("Stack Exchange asks to back off {}s before calling {} again", backoff, method);
+        }
+        Ok(body.items)
+    }
+
+    /// Plain text of the HTML the API returns for titles and answer bodies, keeping
+    /// paragraphs and code blocks on their own lines
+    fn html_text(html: &str) -> String {
+        let html = html.replace("</p>", "</p>\n").replace("</pre>", "</pre>\n").replace("</li>", "</li>\n");
+        Html::parse_fragment(&html).root_element().text().collect::<String>().trim().to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for StackOverflowSearch {
+    fn name(&self) -> &str {
+        "Stack Overflow"
+    }
+
+    async fn search(&self, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
+        // One call for the questions and one for their accepted answers
+        if !db.check_search_rate_limit("search:stackoverflow", 2).await? {
+            return Err(anyhow::anyhow!("Stack Exchange daily quota exceeded"));
+        }
+
+        let mut params = vec![
+            ("q", query.to_string()),
+            ("order", "desc".to_string()),
+            ("sort", "relevance".to_string()),
+            ("pagesize", options.max_results.to_string()),
+        ];
+        if let Some(range) = options.time_range {
+            let from = chrono::Utc::now() - chrono::Duration::days(range.days() as i64);
+            params.push(("fromdate", from.timestamp().to_string()));
+        }
+        let questions: Vec<StackQuestion> = self.get(http, db, "search/advanced", &params).await?;
+
+        let accepted: Vec<String> = questions.iter().filter_map(|q| q.accepted_answer_id).map(|id| id.to_string()).collect();
+        let answers: Vec<StackAnswer> = if accepted.is_empty() {
+            Vec::new()
+        } else {
+            let method = format!("answers/{}", accepted.join(";"));
+            self.get(http, db, &method, &[("filter", "withbody".to_string())]).await.unwrap_or_else(|e| {
+                tracing::warn!("Failed to load accepted answers: {}", e);
+                Vec::new()
+            })
+        };
+
+        Ok(questions.into_iter().map(|q| {
+            let answer = q.accepted_answer_id.and_then(|id| answers.iter().find(|a| a.answer_id == id));
+            let snippet = match answer {
+                Some(answer) => {
+                    let text: String = Self::html_text(&answer.body).chars().take(800).collect();
+                    format!("Accepted answer (score {}): {}", answer.score, text)
+                }
+                None => format!("Question score {}, {} answers, tagged {}", q.score, q.answer_count, q.tags.join(", ")),
+            };
+            SearchResult { title: Self::html_text(&q.title), url: q.link, snippet }
+        }).collect())
+    }
+}
+
 pub struct WebSearch;
 
+/// Circuit breaker for the search engines, keyed by `SearchProvider::name`
+fn search_health() -> &'static CircuitBreaker {
+    static HEALTH: OnceLock<CircuitBreaker> = OnceLock::new();
+    HEALTH.get_or_init(CircuitBreaker::default)
+}
+
 impl WebSearch {
-    pub async fn get_provider(name: Option<&str>) -> Box<dyn SearchProvider> {
+    /// Whether a query asks about something that changes over time (current office
+    /// holders, news, "latest" anything).
+    pub fn is_time_sensitive(query: &str) -> bool {
+        const TIME_SENSITIVE_KEYWORDS: [&str; 18] = [
+            "current", "today", "now", "present", "latest", "recent", 
+            "who is", "what is the current", "who are the current",
+            "president", "leader", "ceo", "chairman", "minister",
+            "happened today", "news", "breaking", "update"
+        ];
+        let query_lower = query.to_lowercase();
+        TIME_SENSITIVE_KEYWORDS.iter().any(|keyword| query_lower.contains(keyword))
+    }
+    
+    /// Freshness implied by the wording of a news-like question: the past day for
+    /// "today"/"breaking", the past week for "latest"/"news"/"this week".
+    pub fn fresh_time_range(query: &str) -> Option<TimeRange> {
+        let query_lower = query.to_lowercase();
+        if ["today", "breaking", "this morning", "tonight"].iter().any(|k| query_lower.contains(k)) {
+            Some(TimeRange::Day)
+        } else if ["news", "latest", "this week"].iter().any(|k| query_lower.contains(k)) {
+            Some(TimeRange::Week)
+        } else {
+            None
+        }
+    }
+
+    /// Time range for engines that support one: a week for news-like queries,
+    /// a month for other time-sensitive queries, none otherwise.
+    fn auto_time_range(query: &str) -> Option<&'static str> {
+        let query_lower = query.to_lowercase();
+        if ["news", "breaking", "today", "latest", "this week"].iter().any(|k| query_lower.contains(k)) {
+            Some("week")
+        } else if Self::is_time_sensitive(query) {
+            Some("month")
+        } else {
+            None
+        }
+    }
+
+    /// Value of a provider's config variable, unless it is empty or the provider is disabled
+    fn provider_config(provider: &str, var: &str) -> Option<String> {
+        if provider_disabled(provider) {
+            return None;
+        }
+        env::var(var).ok().filter(|v| !v.is_empty())
+    }
+
+    /// Search provider names accepted in requests and `SEARCH_PROVIDER_PRIORITY`
+    const PROVIDER_NAMES: [&'static str; 9] = ["searxng", "tavily", "brave", "duckduckgo", "ddg", "wikipedia", "arxiv", "github", "stackoverflow"];
+
+    /// Requested in place of a provider name to search every configured provider at once
+    const METASEARCH: &'static str = "all";
+
+    /// Canonical name of a requested search provider. `None` means automatic selection,
+    /// either because "auto" was asked for or because the name is unknown.
+    pub fn normalize_provider_name(name: Option<&str>) -> Option<String> {
+        let name = name?.trim().to_lowercase();
+        if name.is_empty() || name == "auto" {
+            return None;
+        }
+        if !Self::PROVIDER_NAMES.contains(&name.as_str()) && name != Self::METASEARCH {
+            tracing::warn!("Unknown search provider '{}', using automatic selection", name);
+            return None;
+        }
+        Some(name)
+    }
+
+    /// Order in which automatic selection tries providers: `SEARCH_PROVIDER_PRIORITY`
+    /// (comma-separated), defaulting to SearXNG -> Tavily -> Brave -> DDG.
+    fn search_priority() -> Vec<String> {
+        let configured: Vec<String> = env::var("SEARCH_PROVIDER_PRIORITY")
+            .unwrap_or_default()
+            .split(',')
+            .map(|p| p.trim().to_lowercase())
+            .filter(|p| {
+                let known = Self::PROVIDER_NAMES.contains(&p.as_str());
+                if !known && !p.is_empty() {
+                    tracing::warn!("Ignoring unknown provider '{}' in SEARCH_PROVIDER_PRIORITY", p);
+                }
+                known
+            })
+            .collect();
+        if configured.is_empty() {
+            ["searxng", "tavily", "brave", "ddg"].map(String::from).to_vec()
+        } else {
+            configured
+        }
+    }
+
+    /// The named provider, if it is configured and not disabled
+    fn configured_provider(name: &str) -> Option<Box<dyn SearchProvider>> {
+        match name {
+            "searxng" => Self::provider_config("searxng", "SEARXNG_BASE_URL")
+                .map(|url| Box::new(SearXNGSearch { base_url: url }) as Box<dyn SearchProvider>),
+            "tavily" => Self::provider_config("tavily", "TAVILY_API_KEY")
+                .map(|key| Box::new(TavilySearch { api_key: key }) as Box<dyn SearchProvider>),
+            "brave" => Self::provider_config("brave", "BRAVE_API_KEY")
+                .map(|key| Box::new(BraveSearch { api_key: key }) as Box<dyn SearchProvider>),
+            "duckduckgo" | "ddg" if !provider_disabled("ddg") => Some(Box::new(DuckDuckGoSearch)),
+            "wikipedia" if !provider_disabled("wikipedia") => Some(Box::new(WikipediaSearch::from_env())),
+            "arxiv" if !provider_disabled("arxiv") => Some(Box::new(ArxivSearch::from_env())),
+            "github" if !provider_disabled("github") => Some(Box::new(GitHubSearch::from_env())),
+            "stackoverflow" if !provider_disabled("stackoverflow") => Some(Box::new(StackOverflowSearch::from_env())),
+            _ => None,
+        }
+    }
+
+    /// The requested provider if configured, else the first healthy one in priority
+    /// order, else DuckDuckGo. `None` when DuckDuckGo is disabled too.
+    pub async fn get_provider(name: Option<&str>) -> Option<Box<dyn SearchProvider>> {
         // If a specific provider is requested, try to use it if configured
         if let Some(n) = name {
-            match n.to_lowercase().as_str() {
-                "searxng" => {
-                    if let Ok(url) = env::var("SEARXNG_BASE_URL") {
-                        if !url.is_empty() {
-                            return Box::new(SearXNGSearch { base_url: url });
-                        }
-                    }
-                },
-                "tavily" => {
-                    if let Ok(key) = env::var("TAVILY_API_KEY") {
-                        if !key.is_empty() {
-                            return Box::new(TavilySearch { api_key: key });
-                        }
-                    }
-                },
-                "brave" => {
-                    if let Ok(key) = env::var("BRAVE_API_KEY") {
-                        if !key.is_empty() {
-                            return Box::new(BraveSearch { api_key: key });
-                        }
-                    }
-                },
-                "duckduckgo" | "ddg" => return Box::new(DuckDuckGoSearch),
-                _ => {} // Fall through to auto
+            if let Some(provider) = Self::configured_provider(&n.to_lowercase()) {
+                return Some(provider);
             }
         }
 
-        // Auto logic (Priority: SearXNG -> Tavily -> Brave -> DDG)
-        if let Ok(url) = env::var("SEARXNG_BASE_URL") {
-            if !url.is_empty() {
-                return Box::new(SearXNGSearch { base_url: url });
+        // Auto logic: first configured provider in priority order that isn't failing
+        for n in Self::search_priority() {
+            if let Some(provider) = Self::configured_provider(&n) {
+                if !search_health().is_available(provider.name()) {
+                    tracing::info!("Skipping unhealthy search provider {}", provider.name());
+                    continue;
+                }
+                return Some(provider);
             }
         }
+        
+        // DuckDuckGo needs no credentials, so it is the last resort unless disabled
+        if provider_disabled("ddg") {
+            tracing::warn!("All search providers are disabled or unconfigured");
+            return None;
+        }
+        Some(Box::new(DuckDuckGoSearch))
+    }
 
-        if let Ok(key) = env::var("TAVILY_API_KEY") {
-            if !key.is_empty() {
-                return Box::new(TavilySearch { api_key: key });
+    /// Whether searches fan out to every configured provider: `search_provider=all`,
+    /// or automatic selection with `METASEARCH=true`.
+    fn is_metasearch(name: Option<&str>) -> bool {
+        match name {
+            Some(name) => name.eq_ignore_ascii_case(Self::METASEARCH),
+            None => env::var("METASEARCH").is_ok_and(|v| v.trim().eq_ignore_ascii_case("true")),
+        }
+    }
+
+    /// Engines a search runs on: the provider `get_provider` picks or, for metasearch,
+    /// every configured and healthy provider in `SEARCH_PROVIDER_PRIORITY` order.
+    /// Empty when no provider is available.
+    pub async fn engines(name: Option<&str>) -> Vec<Box<dyn SearchProvider>> {
+        if !Self::is_metasearch(name) {
+            return Self::get_provider(name).await.into_iter().collect();
+        }
+        let mut engines: Vec<Box<dyn SearchProvider>> = Vec::new();
+        for n in Self::search_priority() {
+            let Some(engine) = Self::configured_provider(&n) else { continue };
+            if engines.iter().any(|e| e.name() == engine.name()) {
+                continue;
+            }
+            if !search_health().is_available(engine.name()) {
+                tracing::info!("Skipping unhealthy search provider {}", engine.name());
+                continue;
             }
+            engines.push(engine);
         }
-        
-        if let Ok(key) = env::var("BRAVE_API_KEY") {
-             if !key.is_empty() {
-                return Box::new(BraveSearch { api_key: key });
+        if engines.is_empty() {
+            engines.extend(Self::get_provider(None).await);
+        }
+        engines
+    }
+
+    /// Validate a requested region (falling back to `DEFAULT_SEARCH_REGION`) against
+    /// `SEARCH_REGIONS`. Unknown codes are ignored.
+    pub fn resolve_region(requested: Option<&str>) -> Option<&'static str> {
+        let code = requested
+            .map(str::to_string)
+            .or_else(|| env::var("DEFAULT_SEARCH_REGION").ok())?
+            .trim()
+            .to_uppercase();
+        if code.is_empty() || code == "AUTO" {
+            return None;
+        }
+        let region = SEARCH_REGIONS.iter().find(|(c, _, _)| *c == code).map(|(c, _, _)| *c);
+        if region.is_none() {
+            tracing::warn!("Ignoring unknown search region '{}'", code);
+        }
+        region
+    }
+
+    /// `options.region` is validated here; `max_results` is clamped to `max_results_ceiling()`.
+    /// Results on blocked domains are dropped before the list is cut to `max_results`.
+    /// With `allowed_only`, the query is narrowed with `site:` operators and anything
+    /// outside the allowlist is dropped too.
+    ///
+    /// Metasearch and a focus's own providers run concurrently with the general engine;
+    /// their rankings are merged with reciprocal rank fusion, one result per normalized
+    /// URL. News defaults to the past week and social keeps to discussion sites. One
+    /// engine failing only loses its results, and in automatic selection the next
+    /// provider takes over (see `search_with_fallback`).
+    pub async fn search(http: &HttpClients, db: &Database, query: &str, provider: Option<&str>, options: SearchOptions<'_>) -> Result<Vec<SearchResult>> {
+        let rules = DomainRules::load(db).await;
+        let restrict = options.allowed_only && !rules.allowed.is_empty();
+        if options.allowed_only && !restrict {
+            tracing::warn!("allowed_domains_only requested but no allowed domains are configured; ignoring");
+        }
+        let focus = options.focus;
+        let options = SearchOptions {
+            region: Self::resolve_region(options.region),
+            max_results: options.max_results.clamp(1, max_results_ceiling()),
+            allowed_only: restrict,
+            time_range: options.time_range.or((focus == Some(Focus::News)).then_some(TimeRange::Week)),
+            focus,
+        };
+        let focus_sites = focus.map_or(&[][..], |f| f.sites());
+        let sites: Vec<&str> = if restrict {
+            rules.allowed.iter().map(String::as_str).collect()
+        } else {
+            focus_sites.to_vec()
+        };
+        let web_query = if sites.is_empty() {
+            query.to_string()
+        } else {
+            let sites: Vec<String> = sites.iter().map(|d| format!("site:{}", d)).collect();
+            format!("{} ({})", query, sites.join(" OR "))
+        };
+
+        // Dedicated providers take the plain query; `site:` operators mean nothing to them
+        let mut engines: Vec<_> = Self::engines(provider).await
+            .into_iter()
+            .map(|engine| (engine, web_query.clone()))
+            .collect();
+        for name in focus.map_or(&[][..], |f| f.providers()) {
+            let Some(engine) = Self::configured_provider(name) else { continue };
+            if engines.iter().any(|(e, _)| e.name() == engine.name()) {
+                continue;
+            }
+            if !search_health().is_available(engine.name()) {
+                tracing::info!("Skipping unhealthy search provider {}", engine.name());
+                continue;
             }
+            engines.push((engine, query.to_string()));
         }
-        
-        Box::new(DuckDuckGoSearch)
+        if engines.is_empty() {
+            return Err(anyhow::anyhow!("No search provider available"));
+        }
+        let names: Vec<&str> = engines.iter().map(|(e, _)| e.name()).collect();
+        tracing::info!(
+            "Using search provider: {} (region: {}, time range: {}, focus: {})",
+            names.join(" + "),
+            options.region.unwrap_or("any"),
+            options.time_range.map_or("any", |r| r.as_str()),
+            focus.map_or("none", |f| f.as_str())
+        );
+
+        let fallback = provider.is_none() && !Self::is_metasearch(None);
+        let rules = &rules;
+        let outcomes = futures::future::join_all(engines.iter().enumerate().map(|(idx, (engine, query))| async move {
+            if idx == 0 && fallback {
+                Self::search_with_fallback(engine.as_ref(), http, db, query, options, rules).await
+            } else {
+                Self::run_engine(engine.as_ref(), http, db, query, options, rules).await
+            }
+        }))
+        .await;
+        let mut lists = Vec::new();
+        let mut first_error = None;
+        for ((engine, _), outcome) in engines.iter().zip(outcomes) {
+            match outcome {
+                Ok(results) => lists.push(results),
+                Err(e) => {
+                    if engines.len() > 1 {
+                        tracing::warn!("{} failed: {}", engine.name(), e);
+                    }
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let (true, Some(e)) = (lists.is_empty(), first_error) {
+            return Err(e);
+        }
+        let mut results = Self::fuse(lists);
+        results.truncate(options.max_results);
+        Ok(results)
     }
 
+    /// Search one engine, recording the outcome with its circuit breaker, and drop
+    /// results the domain rules or the focus's sites exclude
+    async fn run_engine(engine: &dyn SearchProvider, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>, rules: &DomainRules) -> Result<Vec<SearchResult>> {
+        let results = engine.search(http, db, query, options).await;
+        match &results {
+            Ok(_) => search_health().record_success(engine.name()),
+            Err(_) => search_health().record_failure(engine.name()),
+        }
+        let results = results?;
 
-    pub async fn search(db: &Database, query: &str, provider: Option<&str>) -> Result<Vec<SearchResult>> {
-        let provider = Self::get_provider(provider).await;
-        tracing::info!("Using search provider: {}", provider.name());
-        provider.search(db, query).await
+        let focus_sites = options.focus.map_or(&[][..], |f| f.sites());
+        let total = results.len();
+        let results: Vec<SearchResult> = results.into_iter()
+            .filter(|r| !rules.is_blocked(&r.url) && (!options.allowed_only || rules.is_allowed(&r.url)))
+            .filter(|r| focus_sites.is_empty() || on_domains(&r.url, focus_sites))
+            .collect();
+        if results.len() < total {
+            tracing::info!("Domain rules removed {} of {} {} results", total - results.len(), total, engine.name());
+        }
+        Ok(results)
+    }
+
+    /// Search `first`, and while the provider in use fails or finds nothing the domain
+    /// rules let through, the next configured and healthy one in priority order. Each
+    /// hand-over is reported to the query as a status. The last provider's outcome is
+    /// returned when all come up empty.
+    async fn search_with_fallback(first: &dyn SearchProvider, http: &HttpClients, db: &Database, query: &str, options: SearchOptions<'_>, rules: &DomainRules) -> Result<Vec<SearchResult>> {
+        let mut tried = vec![first.name().to_string()];
+        let mut fallback: Option<Box<dyn SearchProvider>> = None;
+        loop {
+            let engine = fallback.as_deref().unwrap_or(first);
+            let outcome = Self::run_engine(engine, http, db, query, options, rules).await;
+            let reason = match &outcome {
+                Ok(results) if !results.is_empty() => return outcome,
+                Ok(_) => "found nothing",
+                Err(e) => {
+                    tracing::warn!("{} failed: {}", engine.name(), e);
+                    "failed"
+                }
+            };
+            let next = Self::search_priority()
+                .iter()
+                .filter_map(|name| Self::configured_provider(name))
+                .find(|e| !tried.iter().any(|t| t == e.name()) && search_health().is_available(e.name()));
+            let Some(next) = next else {
+                return outcome;
+            };
+            let notice = format!("{} {}, falling back to {}", engine.name(), reason, next.name());
+            tracing::warn!("{}", notice);
+            crate::retry::report(notice);
+            tried.push(next.name().to_string());
+            fallback = Some(next);
+        }
+    }
+
+    /// Reciprocal rank fusion: a result scores the sum of 1 / (RRF_K + rank) over the
+    /// engines that returned it, so agreement between engines outranks any single list.
+    /// Ties keep engine priority order, which interleaves the lists.
+    fn fuse(lists: Vec<Vec<SearchResult>>) -> Vec<SearchResult> {
+        const RRF_K: f64 = 60.0;
+        let mut fused: Vec<(SearchResult, f64)> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for results in lists {
+            for (rank, result) in results.into_iter().enumerate() {
+                let score = 1.0 / (RRF_K + rank as f64 + 1.0);
+                match positions.entry(Self::normalize_url(&result.url)) {
+                    std::collections::hash_map::Entry::Occupied(pos) => fused[*pos.get()].1 += score,
+                    std::collections::hash_map::Entry::Vacant(pos) => {
+                        pos.insert(fused.len());
+                        fused.push((result, score));
+                    }
+                }
+            }
+        }
+        fused.sort_by(|a, b| b.1.total_cmp(&a.1));
+        fused.into_iter().map(|(result, _)| result).collect()
+    }
+
+    /// Key under which engines' results count as the same page: scheme, `www.`, fragment,
+    /// trailing slash and tracking parameters don't matter.
+    fn normalize_url(url: &str) -> String {
+        let Ok(parsed) = url::Url::parse(url) else {
+            return url.trim().to_lowercase();
+        };
+        let host = parsed.host_str().unwrap_or_default().trim_start_matches("www.").to_lowercase();
+        let query: Vec<String> = parsed
+            .query_pairs()
+            .filter(|(k, _)| !k.starts_with("utm_") && !matches!(k.as_ref(), "fbclid" | "gclid" | "ref"))
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        let mut key = format!("{}{}", host, parsed.path().trim_end_matches('/'));
+        if !query.is_empty() {
+            key.push('?');
+            key.push_str(&query.join("&"));
+        }
+        key
     }
     
-    pub async fn sync_tavily_usage(db: &Database) -> Result<()> {
-        if let Ok(key) = env::var("TAVILY_API_KEY") {
-            if key.is_empty() { return Ok(()); }
-            
+    /// Search engines that have failed since their last successful search
+    pub fn provider_health() -> Vec<ProviderHealth> {
+        search_health().snapshot()
+    }
+
+    pub async fn sync_tavily_usage(http: &HttpClients, db: &Database) -> Result<()> {
+        if let Some(key) = Self::provider_config("tavily", "TAVILY_API_KEY") {
             tracing::info!("Syncing Tavily usage...");
-            let client = reqwest::Client::builder()
+            let response = http.search.get("https://api.tavily.com/usage")
                 .timeout(std::time::Duration::from_secs(30))
-                .build()?;
-            
-            let response = client.get("https://api.tavily.com/usage")
                 .header("Authorization", format!("Bearer {}", key))
                 .send()
                 .await?;
@@ -373,7 +1544,7 @@ impl WebSearch {
                     
                     if let (Some(u), Some(l)) = (usage, limit) {
                         tracing::info!("Tavily usage: {}/{}", u, l);
-                        db.update_search_limits("search:tavily", Some(u), Some(l), None).await?;
+                        db.update_search_limits("search:tavily", Some(u), Some(l), None, None).await?;
                     }
                 }
             } else {
@@ -383,8 +1554,30 @@ impl WebSearch {
         Ok(())
     }
     
-    pub async fn fetch_content(url: &str) -> Result<String> {
-        let normalized_url = if url.starts_with("//") {
+    /// Fetch a page and extract its readable text. HTML pages keep their raw HTML so
+    /// the extraction can be re-run later without re-fetching; PDFs, JSON and plain
+    /// text are converted by `Content-Type`, and other binary types are refused.
+    ///
+    /// Successful responses are cached in `page_cache`. Within `PAGE_CACHE_TTL_SECS`
+    /// (default 3600, 0 always revalidates) the cached copy is served as-is; after
+    /// that it is revalidated with `If-None-Match`/`If-Modified-Since` and reused on
+    /// a 304. `PAGE_CACHE_ENABLED=false` turns the cache off.
+    pub async fn fetch_content(http: &HttpClients, url: &str, db: &Database) -> Result<FetchedPage> {
+        Self::fetch_with(&http.fetch, url, db).await
+    }
+
+    /// `fetch_content` for URLs supplied by users or the model: the host, its resolved
+    /// addresses and every redirect hop must be public.
+    pub async fn fetch_public_content(http: &HttpClients, url: &str, db: &Database) -> Result<FetchedPage> {
+        let normalized_url = Self::normalize_fetch_url(url)?;
+        let parsed = url::Url::parse(&normalized_url).map_err(|e| anyhow::anyhow!("Invalid URL {}: {}", url, e))?;
+        crate::http::ensure_public_url(&parsed)?;
+        Self::fetch_with(&http.public_fetch, &normalized_url, db).await
+    }
+
+    /// Assume https for protocol-relative and scheme-less URLs
+    fn normalize_fetch_url(url: &str) -> Result<String> {
+        Ok(if url.starts_with("//") {
             format!("https:{}", url)
         } else if url.starts_with('/') {
             return Err(anyhow::anyhow!("Relative URL not supported: {}", url));
@@ -392,17 +1585,261 @@ impl WebSearch {
             format!("https://{}", url)
         } else {
             url.to_string()
+        })
+    }
+
+    async fn fetch_with(client: &reqwest::Client, url: &str, db: &Database) -> Result<FetchedPage> {
+        let normalized_url = Self::normalize_fetch_url(url)?;
+        
+        let rules = DomainRules::load(db).await;
+        if rules.is_blocked(&normalized_url) {
+            return Err(anyhow::anyhow!("{} is on a blocked domain", normalized_url));
+        }
+        
+        let cache_enabled = env::var("PAGE_CACHE_ENABLED")
+            .map(|v| !v.eq_ignore_ascii_case("false"))
+            .unwrap_or(true);
+        let cached = if cache_enabled {
+            db.get_cached_page(&normalized_url).await
+                .inspect_err(|e| tracing::warn!("Page cache lookup failed for {}: {}", normalized_url, e))
+                .ok()
+                .flatten()
+        } else {
+            None
         };
+        if let Some(page) = &cached {
+            let ttl = env::var("PAGE_CACHE_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(3600);
+            if chrono::Utc::now() - page.fetched_at < chrono::Duration::seconds(ttl) {
+                tracing::debug!("Serving {} from page cache", normalized_url);
+                return Ok(Self::page_from_cache(page.clone()));
+            }
+        }
         
         tracing::debug!("Fetching content from: {}", normalized_url);
         
-        let client = reqwest::Client::builder()
-            .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36")
-            .timeout(std::time::Duration::from_secs(10))
-            .build()?;
+        // reqwest transparently decompresses gzip/brotli bodies; advertise them explicitly
+        // since some servers misbehave without the header
+        let mut request = client.get(&normalized_url)
+            .header(reqwest::header::ACCEPT_ENCODING, "gzip, br");
+        if let Some(page) = &cached {
+            if let Some(etag) = &page.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &page.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let mut response = request.send().await?;
         
-        let html = client.get(&normalized_url).send().await?.text().await?;
-        let document = Html::parse_document(&html);
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(page) = cached {
+                tracing::debug!("{} not modified, reusing cached copy", normalized_url);
+                if let Err(e) = db.touch_cached_page(&normalized_url).await {
+                    tracing::warn!("Failed to refresh page cache entry for {}: {}", normalized_url, e);
+                }
+                return Ok(Self::page_from_cache(page));
+            }
+        }
+        let final_url = response.url().to_string();
+        if rules.is_blocked(&final_url) {
+            return Err(anyhow::anyhow!("{} redirected to blocked domain {}", normalized_url, final_url));
+        }
+        let header = |name: reqwest::header::HeaderName| response.headers().get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let etag = header(reqwest::header::ETAG);
+        let last_modified = header(reqwest::header::LAST_MODIFIED);
+        let content_type = header(reqwest::header::CONTENT_TYPE);
+        let cacheable = cache_enabled
+            && response.status().is_success()
+            && !header(reqwest::header::CACHE_CONTROL).is_some_and(|cc| cc.to_lowercase().contains("no-store"));
+        
+        // Images, audio and the like are refused before downloading the body
+        if let Some(mime) = content_type.as_deref().map(PageKind::mime).filter(|m| PageKind::is_media(m)) {
+            return Err(anyhow::anyhow!("Unsupported content type {} at {}", mime, final_url));
+        }
+        
+        // MAX_FETCH_BYTES caps the decoded body so huge pages can't exhaust memory
+        let max_bytes = env::var("MAX_FETCH_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(5 * 1024 * 1024);
+        if response.content_length().is_some_and(|len| len as usize > max_bytes) {
+            return Err(anyhow::anyhow!("Response from {} exceeds MAX_FETCH_BYTES ({} bytes)", normalized_url, max_bytes));
+        }
+        let mut body = Vec::new();
+        let mut truncated = false;
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > max_bytes {
+                tracing::warn!("Truncating {} at MAX_FETCH_BYTES ({} bytes)", normalized_url, max_bytes);
+                body.truncate(max_bytes);
+                truncated = true;
+                break;
+            }
+        }
+        
+        let kind = PageKind::detect(content_type.as_deref(), &final_url, &body)
+            .map_err(|mime| anyhow::anyhow!("Unsupported content type {} at {}", mime, final_url))?;
+        let text = match kind {
+            // A cut-off PDF can't be parsed at all
+            PageKind::Pdf if truncated => {
+                return Err(anyhow::anyhow!("PDF at {} exceeds MAX_FETCH_BYTES ({} bytes)", final_url, max_bytes));
+            }
+            PageKind::Pdf => crate::documents::extract_text(DocumentKind::Pdf, body).await?,
+            PageKind::Json => match serde_json::from_slice::<serde_json::Value>(&body) {
+                Ok(json) => serde_json::to_string_pretty(&json)?,
+                Err(_) => String::from_utf8_lossy(&body).into_owned(),
+            },
+            PageKind::Html | PageKind::Text => String::from_utf8_lossy(&body).into_owned(),
+        };
+        
+        if !Self::looks_like_text(&text) {
+            return Err(anyhow::anyhow!("Response from {} is not text (binary or undecoded content)", normalized_url));
+        }
+        
+        if cacheable {
+            let entry = CachedPage {
+                final_url: final_url.clone(),
+                html: text.clone(),
+                content_type: Some(kind.as_str().to_string()),
+                etag,
+                last_modified,
+                fetched_at: chrono::Utc::now(),
+            };
+            if let Err(e) = db.store_cached_page(&normalized_url, &entry, chrono::Duration::days(PAGE_CACHE_RETENTION_DAYS)).await {
+                tracing::warn!("Failed to cache {}: {}", normalized_url, e);
+            }
+        }
+        
+        Ok(Self::page_from_text(final_url, kind, text))
+    }
+
+    fn page_from_cache(page: CachedPage) -> FetchedPage {
+        let kind = page.content_type.as_deref().and_then(PageKind::parse).unwrap_or(PageKind::Html);
+        Self::page_from_text(page.final_url, kind, page.html)
+    }
+
+    fn page_from_text(url: String, kind: PageKind, text: String) -> FetchedPage {
+        match kind {
+            PageKind::Html => {
+                let content = Self::extract_content_for_url(&url, &text);
+                FetchedPage { url, content, html: Some(text) }
+            }
+            _ => FetchedPage { url, content: text.trim().to_string(), html: None },
+        }
+    }
+
+    /// Page title from `<title>`, falling back to the first `<h1>`.
+    pub fn extract_title(html: &str) -> Option<String> {
+        let document = Html::parse_document(html);
+        ["title", "h1"].iter()
+            .filter_map(|s| Selector::parse(s).ok())
+            .find_map(|selector| {
+                document.select(&selector).next()
+                    .map(|e| e.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" "))
+                    .filter(|t| !t.is_empty())
+            })
+    }
+
+    /// Absolute http(s) links on a page, resolved against `base_url`, without fragments, deduplicated.
+    pub fn extract_links(base_url: &str, html: &str) -> Vec<url::Url> {
+        let Ok(base) = url::Url::parse(base_url) else { return Vec::new() };
+        let document = Html::parse_document(html);
+        let Ok(selector) = Selector::parse("a[href]") else { return Vec::new() };
+        
+        let mut seen = std::collections::HashSet::new();
+        document.select(&selector)
+            .filter_map(|a| a.value().attr("href"))
+            .filter_map(|href| base.join(href).ok())
+            .filter(|u| u.scheme() == "http" || u.scheme() == "https")
+            .map(|mut u| {
+                u.set_fragment(None);
+                u
+            })
+            .filter(|u| seen.insert(u.to_string()))
+            .collect()
+    }
+
+    /// Reject bodies that decoded to garbage: a high share of replacement characters
+    /// or control bytes in the first few KB means binary or still-compressed data.
+    fn looks_like_text(body: &str) -> bool {
+        let sample: Vec<char> = body.chars().take(4096).collect();
+        if sample.is_empty() {
+            return true;
+        }
+        let suspicious = sample.iter()
+            .filter(|&&c| c == '\u{FFFD}' || (c.is_control() && !c.is_whitespace()))
+            .count();
+        suspicious * 10 < sample.len()
+    }
+
+    /// Per-domain CSS selectors from `DOMAIN_SELECTORS` (JSON object of domain -> selector).
+    /// Invalid selectors are logged and ignored when first loaded.
+    pub fn domain_selectors() -> &'static [(String, String)] {
+        static SELECTORS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+        SELECTORS.get_or_init(|| {
+            let raw = match env::var("DOMAIN_SELECTORS") {
+                Ok(raw) if !raw.trim().is_empty() => raw,
+                _ => return Vec::new(),
+            };
+            let map: HashMap<String, String> = match serde_json::from_str(&raw) {
+                Ok(map) => map,
+                Err(e) => {
+                    tracing::warn!("Ignoring DOMAIN_SELECTORS: not a JSON object of strings: {}", e);
+                    return Vec::new();
+                }
+            };
+            
+            let mut selectors = Vec::new();
+            for (domain, selector) in map {
+                if Selector::parse(&selector).is_err() {
+                    tracing::warn!("Ignoring invalid selector for {}: {}", domain, selector);
+                    continue;
+                }
+                let domain = domain.trim().trim_start_matches("www.").to_lowercase();
+                tracing::info!("Using content selector '{}' for {}", selector, domain);
+                selectors.push((domain, selector));
+            }
+            selectors
+        })
+    }
+
+    /// Extract content using the configured selector for the URL's domain, falling
+    /// back to the generic heuristic when no selector matches.
+    pub fn extract_content_for_url(url: &str, html: &str) -> String {
+        let host = url::Url::parse(url).ok()
+            .and_then(|u| u.host_str().map(|h| h.to_lowercase()));
+        
+        if let Some(host) = host {
+            let configured = Self::domain_selectors().iter()
+                .find(|(domain, _)| host == *domain || host.ends_with(&format!(".{}", domain)));
+            
+            if let Some((domain, selector_str)) = configured {
+                if let Ok(selector) = Selector::parse(selector_str) {
+                    let document = Html::parse_document(html);
+                    let blocks: Vec<String> = document.select(&selector)
+                        .map(|elem| elem.text().collect::<String>().trim().to_string())
+                        .filter(|text| !text.is_empty())
+                        .collect();
+                    
+                    if !blocks.is_empty() {
+                        return Self::truncate_content(blocks.join("\n\n"));
+                    }
+                    tracing::debug!("Selector for {} matched nothing on {}, using heuristic", domain, url);
+                }
+            }
+        }
+        
+        Self::extract_content(html)
+    }
+
+    /// Extract the main readable text from an HTML document.
+    pub fn extract_content(html: &str) -> String {
+        let document = Html::parse_document(html);
         
         // Positive selection: Look for article-like containers
         let main_selectors = ["article", "main", "#content", ".content", "#main", ".main", "body"];
@@ -468,9 +1905,11 @@ impl WebSearch {
         }
         
         // Join and clean
-        let mut content = extracted_blocks.join("\n\n");
-        
-        // Limit length safely
+        Self::truncate_content(extracted_blocks.join("\n\n"))
+    }
+    
+    /// Limit extracted content length on a char boundary
+    fn truncate_content(mut content: String) -> String {
         if content.len() > 15000 {
             let mut limit = 15000;
             while !content.is_char_boundary(limit) {
@@ -479,6 +1918,77 @@ impl WebSearch {
             content.truncate(limit);
         }
         
-        Ok(content)
+        content
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// `<html><head><title>Fixture</title></head><body><p>Compressed fixture page</p></body></html>`, gzipped
+    const GZIP_PAGE: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xb3, 0xc9, 0x28, 0xc9, 0xcd, 0xb1,
+        0xb3, 0xc9, 0x48, 0x4d, 0x4c, 0xb1, 0xb3, 0x29, 0xc9, 0x2c, 0xc9, 0x49, 0xb5, 0x73, 0xcb, 0xac,
+        0x28, 0x29, 0x2d, 0x4a, 0xb5, 0xd1, 0x87, 0x70, 0x6d, 0xf4, 0x21, 0x92, 0x49, 0xf9, 0x29, 0x95,
+        0x76, 0x36, 0x05, 0x76, 0xce, 0xf9, 0xb9, 0x05, 0x45, 0xa9, 0xc5, 0xc5, 0xa9, 0x29, 0x0a, 0x69,
+        0x10, 0x95, 0x0a, 0x05, 0x89, 0xe9, 0x40, 0xe5, 0x05, 0x40, 0xa5, 0x10, 0x45, 0xfa, 0x60, 0x43,
+        0x01, 0x49, 0x01, 0xe4, 0xcb, 0x5b, 0x00, 0x00, 0x00,
+    ];
+
+    /// Serve a single HTTP response with the given extra headers and body, returning its URL
+    async fn serve_once(headers: &'static str, body: &'static [u8]) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 4096];
+            let _ = socket.read(&mut request).await;
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+                body.len(), headers
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(body).await.unwrap();
+        });
+        format!("http://{}/page", addr)
+    }
+
+    async fn test_db() -> Database {
+        let path = env::temp_dir().join(format!("w9-search-test-{}.db", uuid::Uuid::new_v4()));
+        let db = Database::new(&format!("sqlite:{}", path.display())).await.unwrap();
+        db.migrate().await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn fetch_content_decodes_gzip_bodies() {
+        let url = serve_once("Content-Type: text/html\r\nContent-Encoding: gzip\r\n", GZIP_PAGE).await;
+        let http = HttpClients::from_env().unwrap();
+
+        let page = WebSearch::fetch_content(&http, &url, &test_db().await).await.unwrap();
+
+        let html = page.html.unwrap();
+        assert!(html.contains("<p>Compressed fixture page</p>"), "html: {}", html);
+    }
+
+    #[tokio::test]
+    async fn fetch_content_rejects_undecoded_binary() {
+        // Compressed bytes labelled as plain HTML, as a misconfigured server would send them
+        let url = serve_once("Content-Type: text/html\r\n", GZIP_PAGE).await;
+        let http = HttpClients::from_env().unwrap();
+
+        let err = WebSearch::fetch_content(&http, &url, &test_db().await).await.unwrap_err();
+
+        assert!(err.to_string().contains("is not text"), "error: {}", err);
+    }
+
+    #[test]
+    fn looks_like_text_flags_binary() {
+        assert!(WebSearch::looks_like_text("Plain text\twith tabs\nand newlines, café"));
+        assert!(WebSearch::looks_like_text(""));
+        assert!(!WebSearch::looks_like_text(&String::from_utf8_lossy(GZIP_PAGE)));
+        assert!(!WebSearch::looks_like_text("\u{0}\u{1}\u{2}\u{3}abc"));
     }
 }
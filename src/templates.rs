@@ -1,8 +1,105 @@
-use axum::{extract::State, response::Html};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Redirect, Response},
+};
 use maud::{html, Markup, DOCTYPE};
 use crate::AppState;
+use crate::llm::ProviderType;
+use std::collections::{HashMap, HashSet};
 
-pub async fn models(State(state): State<AppState>) -> Html<String> {
+/// Default CSP: allows the inline app script plus the jsdelivr (marked, mermaid)
+/// and Google Fonts CDNs the pages load from.
+const DEFAULT_CSP: &str = "default-src 'self'; \
+    script-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+    style-src 'self' 'unsafe-inline' https://fonts.googleapis.com; \
+    font-src 'self' https://fonts.gstatic.com; \
+    img-src 'self' data: https:; \
+    connect-src 'self'";
+
+/// Render a page with an explicit UTF-8 content type and basic security headers.
+/// The CSP can be overridden with `CONTENT_SECURITY_POLICY` (empty disables it).
+fn html_response(markup: Markup) -> Response {
+    let mut response = markup.into_string().into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+    let csp = std::env::var("CONTENT_SECURITY_POLICY").unwrap_or_else(|_| DEFAULT_CSP.to_string());
+    if !csp.trim().is_empty() {
+        match HeaderValue::from_str(csp.trim()) {
+            Ok(value) => {
+                headers.insert(header::CONTENT_SECURITY_POLICY, value);
+            }
+            Err(e) => tracing::warn!("Ignoring invalid CONTENT_SECURITY_POLICY: {}", e),
+        }
+    }
+
+    response
+}
+
+/// Sign-in page for multi-user mode; also offers account creation.
+pub async fn login() -> Response {
+    if !crate::auth::auth_enabled() {
+        return Redirect::to("/").into_response();
+    }
+
+    let markup: Markup = html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { "W9 Search - Log in" }
+                link rel="stylesheet" href="/static/style.css";
+                link rel="preconnect" href="https://fonts.googleapis.com";
+                link rel="preconnect" href="https://fonts.gstatic.com" crossorigin;
+                link href=(r#"https://fonts.googleapis.com/css2?family=JetBrains+Mono:wght@300;400;700&family=Space+Grotesk:wght@300;400;700&display=swap"#) rel="stylesheet";
+            }
+            body class="login-page" {
+                form id="login-form" class="login-card" {
+                    div class="logo" { "W9" }
+                    input type="text" id="username" placeholder="Username" autocomplete="username" required {}
+                    input type="password" id="password" placeholder="Password" autocomplete="current-password" required {}
+                    div id="login-error" class="error" {}
+                    div class="login-actions" {
+                        button type="submit" data-action="login" { "Log in" }
+                        button type="submit" data-action="register" class="secondary" { "Create account" }
+                    }
+                }
+                script {
+                    (maud::PreEscaped(r#"
+                    const form = document.getElementById('login-form');
+                    form.addEventListener('submit', async (e) => {
+                        e.preventDefault();
+                        const action = e.submitter?.dataset.action || 'login';
+                        const errorDiv = document.getElementById('login-error');
+                        errorDiv.textContent = '';
+                        const res = await fetch(`/api/auth/${action}`, {
+                            method: 'POST',
+                            headers: { 'Content-Type': 'application/json' },
+                            body: JSON.stringify({
+                                username: document.getElementById('username').value,
+                                password: document.getElementById('password').value
+                            })
+                        });
+                        if (res.ok) {
+                            window.location.href = '/';
+                        } else {
+                            const body = await res.json().catch(() => null);
+                            errorDiv.textContent = body?.error?.message || 'Login failed';
+                        }
+                    });
+                    "#))
+                }
+            }
+        }
+    };
+
+    html_response(markup)
+}
+
+pub async fn models(State(state): State<AppState>) -> Response {
     // Fetch models and limits
     let mut models = state.llm_manager.get_models().await;
     
@@ -17,6 +114,9 @@ pub async fn models(State(state): State<AppState>) -> Html<String> {
     });
 
     let metrics = state.db.get_all_provider_metrics().await.unwrap_or_default();
+    let stats = state.db.get_provider_stats().await.unwrap_or_default();
+    let mut health = state.llm_manager.provider_health();
+    health.extend(crate::search::WebSearch::provider_health());
 
     let markup: Markup = html! {
         (DOCTYPE)
@@ -37,6 +137,7 @@ pub async fn models(State(state): State<AppState>) -> Html<String> {
                         p class="subtitle" { "Models & Limits" }
                         nav {
                             a href="/" class="nav-link" { "← Back to Search" }
+                            a href="/admin/limits" class="nav-link" { "Edit Limits" }
                         }
                     }
 
@@ -113,6 +214,53 @@ pub async fn models(State(state): State<AppState>) -> Html<String> {
                         }
                     }
                     
+                    div class="section" {
+                        h2 { "Provider Health" }
+                        @if health.is_empty() {
+                            p { "All providers are healthy." }
+                        } @else {
+                            div class="grid-container" {
+                                @for provider in &health {
+                                    div class="metric-card" {
+                                        div class="metric-title" { (provider.provider) }
+                                        div class="meta-item" {
+                                            span class="label" { "Status:" }
+                                            @match provider.retry_in {
+                                                Some(wait) => span class="tag-paid" { (format!("Unhealthy, skipped for another {}s", wait.as_secs().max(1))) },
+                                                None => span { "Failing" },
+                                            }
+                                        }
+                                        div class="meta-item" {
+                                            span class="label" { "Failures:" }
+                                            span { (format!("{} in a row", provider.consecutive_failures)) }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    @if !stats.is_empty() {
+                        div class="section" {
+                            h2 { "Provider Performance" }
+                            div class="grid-container" {
+                                @for stat in &stats {
+                                    div class="metric-card" {
+                                        div class="metric-title" { (stat.provider) }
+                                        div class="meta-item" {
+                                            span class="label" { "Requests:" }
+                                            span { (format!("{} ({} errors)", stat.total_requests, stat.error_count)) }
+                                        }
+                                        div class="meta-item" {
+                                            span class="label" { "Averages:" }
+                                            span { (format!("avg latency {:.1}s, avg {:.0} tokens", stat.avg_latency_ms / 1000.0, stat.avg_tokens)) }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+
                     div class="section" {
                         h2 { "Available Models" }
                         div class="grid-container" {
@@ -146,10 +294,115 @@ pub async fn models(State(state): State<AppState>) -> Html<String> {
             }
         }
     };
-    Html(markup.into_string())
+    html_response(markup)
 }
 
-pub async fn index(State(state): State<AppState>) -> Html<String> {
+/// Editor for per-provider request limits, backed by `/api/admin/limits`.
+pub async fn admin_limits(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !crate::auth::is_admin(&state.db, &headers).await {
+        if crate::auth::auth_enabled() && crate::auth::session_user(&state.db, &headers).await.is_none() {
+            return Redirect::to("/login").into_response();
+        }
+        return (axum::http::StatusCode::FORBIDDEN, "Admin access required").into_response();
+    }
+
+    let limits = state.db.provider_limits().await.unwrap_or_default();
+    let field = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+
+    let markup: Markup = html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { "W9 Search - Provider Limits" }
+                link rel="stylesheet" href="/static/style.css";
+                link rel="preconnect" href="https://fonts.googleapis.com";
+                link rel="preconnect" href="https://fonts.gstatic.com" crossorigin;
+                link href=(r#"https://fonts.googleapis.com/css2?family=JetBrains+Mono:wght@300;400;700&family=Space+Grotesk:wght@300;400;700&display=swap"#) rel="stylesheet";
+            }
+            body {
+                div class="container" {
+                    header {
+                        h1 { "W9" }
+                        p class="subtitle" { "Provider Limits" }
+                        nav {
+                            a href="/models" class="nav-link" { "← Models & Limits" }
+                        }
+                    }
+
+                    div class="section" {
+                        h2 { "Request Limits" }
+                        p { "Leave a field empty to use the limit the provider reports, or the built-in default." }
+                        table class="limits-table" {
+                            thead {
+                                tr {
+                                    th { "Provider" }
+                                    th { "Per minute" }
+                                    th { "Per day" }
+                                    th { "Per month" }
+                                    th {}
+                                }
+                            }
+                            tbody {
+                                @for limit in &limits {
+                                    tr data-provider=(limit.provider) {
+                                        td { (limit.provider) }
+                                        td { input type="number" min="0" name="limit_min" value=(field(limit.override_min)) placeholder=(limit.limit_min) {} }
+                                        td { input type="number" min="0" name="limit_day" value=(field(limit.override_day)) placeholder=(limit.limit_day) {} }
+                                        td { input type="number" min="0" name="limit_month" value=(field(limit.override_month)) placeholder=(limit.limit_month) {} }
+                                        td {
+                                            button type="button" class="save-limits" { "Save" }
+                                            span class="limits-status" {}
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                script {
+                    (maud::PreEscaped(r#"
+                    document.querySelectorAll('.save-limits').forEach(button => {
+                        button.addEventListener('click', async () => {
+                            const row = button.closest('tr');
+                            const status = row.querySelector('.limits-status');
+                            const body = { provider: row.dataset.provider };
+                            row.querySelectorAll('input').forEach(input => {
+                                body[input.name] = input.value === '' ? null : Number(input.value);
+                            });
+                            status.textContent = '';
+                            const res = await fetch('/api/admin/limits', {
+                                method: 'PUT',
+                                headers: { 'Content-Type': 'application/json' },
+                                body: JSON.stringify(body)
+                            });
+                            const data = await res.json().catch(() => null);
+                            if (!res.ok) {
+                                status.textContent = data?.error?.message || 'Save failed';
+                                return;
+                            }
+                            row.querySelectorAll('input').forEach(input => {
+                                input.placeholder = data[input.name];
+                            });
+                            status.textContent = 'Saved';
+                        });
+                    });
+                    "#))
+                }
+            }
+        }
+    };
+
+    html_response(markup)
+}
+
+pub async fn index(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let auth_enabled = crate::auth::auth_enabled();
+    if auth_enabled && crate::auth::session_user(&state.db, &headers).await.is_none() {
+        return Redirect::to("/login").into_response();
+    }
+
     // Fetch models dynamically from LLMManager
     let mut models = state.llm_manager.get_models().await;
     
@@ -163,6 +416,17 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
         }
     });
 
+    // Providers over their daily limit, with hours until the window resets; each
+    // provider is looked up once however many models it serves
+    let providers: HashSet<&ProviderType> = models.iter().map(|m| &m.provider).collect();
+    let mut limited_providers: HashMap<ProviderType, i64> = HashMap::new();
+    for provider in providers {
+        if let Ok(Some(resets_at)) = state.db.day_limit_reset(provider).await {
+            let minutes = (resets_at - chrono::Utc::now()).num_minutes().max(0);
+            limited_providers.insert(provider.clone(), (minutes + 59) / 60);
+        }
+    }
+
     let markup: Markup = html! {
         (DOCTYPE)
         html lang="en" {
@@ -189,6 +453,10 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                     }
                     div class="sidebar-footer" {
                         a href="/models" class="nav-link" { "Models & Limits" }
+                        @if auth_enabled {
+                            " · "
+                            a href="#" id="logout-link" class="nav-link" { "Log out" }
+                        }
                     }
                 }
 
@@ -211,20 +479,74 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                                     span class="slider" {}
                                     span { "Web Search" }
                                 }
+                                label class="toggle-switch" {
+                                    input type="checkbox" id="research-mode-toggle" {}
+                                    span class="slider" {}
+                                    span { "Deep Research" }
+                                }
+                                label class="toggle-switch" {
+                                    input type="checkbox" id="keep-thinking-toggle" {}
+                                    span class="slider" {}
+                                    span { "Keep Thinking" }
+                                }
                             }
                             div class="control-group" {
                                 select id="model-select" {
                                     option value="auto" { "Auto (Smart)" }
                                     @for model in &models {
-                                        option value=(model.id) { (format!("{} ({})", model.name, model.provider)) }
+                                        @if let Some(hours) = limited_providers.get(&model.provider) {
+                                            option value=(model.id) disabled title=(format!("rate limited, resets in {}h", hours)) {
+                                                (format!("{} ({}) - rate limited", model.name, model.provider))
+                                            }
+                                        } @else {
+                                            option value=(model.id) { (format!("{} ({})", model.name, model.provider)) }
+                                        }
                                     }
                                 }
                                 select id="provider-select" {
                                     option value="auto" { "Auto Engine" }
+                                    option value="all" { "All Engines" }
                                     option value="searxng" { "SearXNG" }
                                     option value="tavily" { "Tavily" }
                                     option value="brave" { "Brave" }
                                     option value="ddg" { "DuckDuckGo" }
+                                    option value="wikipedia" { "Wikipedia" }
+                                    option value="arxiv" { "arXiv" }
+                                    option value="github" { "GitHub" }
+                                    option value="stackoverflow" { "Stack Overflow" }
+                                }
+                                select id="region-select" title="Search region" {
+                                    option value="" { "Any Region" }
+                                    @for (code, name, _) in crate::search::SEARCH_REGIONS {
+                                        option value=(code) { (name) }
+                                    }
+                                }
+                                select id="time-range-select" title="Result freshness" {
+                                    option value="" { "Any Time" }
+                                    option value="day" { "Past Day" }
+                                    option value="week" { "Past Week" }
+                                    option value="month" { "Past Month" }
+                                    option value="year" { "Past Year" }
+                                }
+                                select id="focus-select" title="Focus" {
+                                    option value="" { "All" }
+                                    option value="news" { "News" }
+                                    option value="academic" { "Academic" }
+                                    option value="code" { "Code" }
+                                    option value="social" { "Social" }
+                                }
+                            }
+                        }
+                        details class="advanced-options" {
+                            summary { "Advanced" }
+                            div class="control-group" {
+                                label class="number-input" {
+                                    span { "Results / search" }
+                                    input type="number" id="max-results-input" min="1" max="20" placeholder="5" {}
+                                }
+                                label class="number-input" {
+                                    span { "Pages to read" }
+                                    input type="number" id="max-fetch-input" min="1" max="20" placeholder="5" {}
                                 }
                             }
                         }
@@ -235,9 +557,22 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                     }
                 }
 
+                script {
+                    (maud::PreEscaped(format!(
+                        "const CITATION_MARKER = {};",
+                        serde_json::to_string(crate::rag::RAGSystem::citation_marker())
+                            .unwrap_or_else(|_| r#""[N]""#.to_string())
+                            .replace('<', "\\u003c")
+                    )))
+                }
                 script {
                     (maud::PreEscaped(r#"                    let currentThreadId = null;
                     let accumulatedSources = []; // Store sources for the current turn to look up for citations
+                    let accumulatedCitations = []; // The server's resolved citations for the current turn
+                    const HISTORY_PAGE_SIZE = 50;
+                    let loadedMessageCount = 0; // Messages of the current thread already rendered
+                    let hasOlderMessages = false;
+                    let loadingOlderMessages = false;
 
                     // --- Initialization ---
                     document.addEventListener('DOMContentLoaded', () => {
@@ -259,6 +594,7 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                                 div.className = 'thread-item';
                                 div.textContent = t.title || 'Untitled Chat';
                                 div.dataset.id = t.id;
+                                if (t.unread) div.classList.add('unread'); // an alert posted new results
                                 div.onclick = () => loadThread(t.id);
                                 list.appendChild(div);
                             });
@@ -275,6 +611,9 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                         `;
                         document.querySelectorAll('.thread-item').forEach(el => el.classList.remove('active'));
                         accumulatedSources = [];
+                        accumulatedCitations = [];
+                        loadedMessageCount = 0;
+                        hasOlderMessages = false;
                     };
 
                     async function loadThread(id) {
@@ -282,18 +621,25 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                         // Highlight in sidebar
                         document.querySelectorAll('.thread-item').forEach(el => {
                             el.classList.toggle('active', el.dataset.id === id);
+                            if (el.dataset.id === id) el.classList.remove('unread');
                         });
 
                         const container = document.getElementById('chat-container');
                         container.innerHTML = '<div class="loading">Loading history...</div>';
 
                         try {
-                            const res = await fetch(`/api/threads/${id}/messages`);
+                            const res = await fetch(`/api/threads/${id}/messages?limit=${HISTORY_PAGE_SIZE}`);
                             const messages = await res.json();
                             container.innerHTML = '';
                             
-                            // Replay messages
+                            // Replay the latest page; older messages load on scroll-up
                             messages.forEach(msg => appendMessage(msg.role, msg.content));
+                            const last = messages[messages.length - 1];
+                            if (last && last.role === 'assistant' && last.suggestions && last.suggestions.length) {
+                                addSuggestions(container.lastChild, last.suggestions);
+                            }
+                            loadedMessageCount = messages.length;
+                            hasOlderMessages = messages.length === HISTORY_PAGE_SIZE;
                             
                             scrollToBottom();
                         } catch (e) {
@@ -301,6 +647,45 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                         }
                     }
 
+                    async function loadOlderMessages() {
+                        if (!currentThreadId || !hasOlderMessages || loadingOlderMessages) return;
+                        loadingOlderMessages = true;
+                        const threadId = currentThreadId;
+                        const container = document.getElementById('chat-container');
+                        try {
+                            const res = await fetch(`/api/threads/${threadId}/messages?offset=${loadedMessageCount}&limit=${HISTORY_PAGE_SIZE}`);
+                            const messages = await res.json();
+                            if (threadId !== currentThreadId) return;
+
+                            // Prepend oldest-last so the page keeps chronological order, preserving scroll position
+                            const previousHeight = container.scrollHeight;
+                            const firstChild = container.firstChild;
+                            messages.forEach(msg => {
+                                const msgDiv = appendMessage(msg.role, msg.content).parentNode;
+                                container.insertBefore(msgDiv, firstChild);
+                            });
+                            container.scrollTop = container.scrollHeight - previousHeight;
+
+                            loadedMessageCount += messages.length;
+                            hasOlderMessages = messages.length === HISTORY_PAGE_SIZE;
+                        } catch (e) {
+                            console.error('Failed to load older messages', e);
+                        } finally {
+                            loadingOlderMessages = false;
+                        }
+                    }
+
+                    document.getElementById('chat-container').addEventListener('scroll', (e) => {
+                        if (e.target.scrollTop < 50) loadOlderMessages();
+                    });
+
+                    // --- Settings ---
+                    const keepThinkingToggle = document.getElementById('keep-thinking-toggle');
+                    keepThinkingToggle.checked = localStorage.getItem('keepThinkingVisible') === 'true';
+                    keepThinkingToggle.addEventListener('change', () => {
+                        localStorage.setItem('keepThinkingVisible', keepThinkingToggle.checked);
+                    });
+
                     // --- Chat Logic ---
                     function appendMessage(role, content) {
                         const container = document.getElementById('chat-container');
@@ -328,11 +713,115 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
 
                         msgDiv.appendChild(roleDiv);
                         msgDiv.appendChild(contentDiv);
+                        if (role === 'assistant' && content) {
+                            addCopyActions(msgDiv, contentDiv, content);
+                        }
                         container.appendChild(msgDiv);
                         scrollToBottom();
                         return contentDiv;
                     }
 
+                    // Keep the raw markdown in the DOM so it can be copied verbatim
+                    function addCopyActions(msgDiv, renderedEl, markdown) {
+                        renderedEl.dataset.markdown = markdown;
+
+                        const actions = document.createElement('div');
+                        actions.className = 'message-actions';
+
+                        const makeButton = (label, getText) => {
+                            const btn = document.createElement('button');
+                            btn.type = 'button';
+                            btn.className = 'copy-btn';
+                            btn.textContent = label;
+                            btn.onclick = async () => {
+                                try {
+                                    await navigator.clipboard.writeText(getText());
+                                    btn.textContent = 'Copied';
+                                } catch (e) {
+                                    btn.textContent = 'Copy failed';
+                                }
+                                setTimeout(() => { btn.textContent = label; }, 1500);
+                            };
+                            return btn;
+                        };
+
+                        actions.appendChild(makeButton('Copy markdown', () => renderedEl.dataset.markdown));
+                        actions.appendChild(makeButton('Copy text', () => renderedEl.innerText));
+                        msgDiv.appendChild(actions);
+                    }
+
+                    // Cited sources by default; the rest behind a "show all" expander
+                    function addSourcesPanel(msgDiv, sources, cited, citations) {
+                        if (!sources.length) return;
+                        const citedSet = new Set(cited || sources.map((_, i) => i + 1));
+
+                        const panel = document.createElement('div');
+                        panel.className = 'sources-panel';
+                        const list = document.createElement('ol');
+                        list.className = 'sources-list';
+
+                        sources.forEach((source, i) => {
+                            const item = document.createElement('li');
+                            item.value = i + 1;
+                            if (!citedSet.has(i + 1)) item.className = 'uncited';
+                            const link = document.createElement('a');
+                            link.href = source.url;
+                            link.target = '_blank';
+                            link.rel = 'noopener';
+                            link.textContent = source.title || source.url;
+                            item.appendChild(link);
+                            const citation = (citations || []).find(c => c.index === i + 1);
+                            if (citation && citation.snippet) {
+                                const snippet = document.createElement('div');
+                                snippet.className = 'source-snippet';
+                                snippet.textContent = citation.snippet;
+                                item.appendChild(snippet);
+                            }
+                            list.appendChild(item);
+                        });
+
+                        const header = document.createElement('div');
+                        header.className = 'sources-header';
+                        header.textContent = `Sources cited (${citedSet.size} of ${sources.length})`;
+                        panel.appendChild(header);
+                        panel.appendChild(list);
+
+                        if (citedSet.size < sources.length) {
+                            const toggle = document.createElement('button');
+                            toggle.type = 'button';
+                            toggle.className = 'copy-btn';
+                            toggle.textContent = `Show all ${sources.length} sources`;
+                            toggle.onclick = () => {
+                                const showing = panel.classList.toggle('show-all');
+                                toggle.textContent = showing ? 'Show cited only' : `Show all ${sources.length} sources`;
+                            };
+                            panel.appendChild(toggle);
+                        }
+                        msgDiv.appendChild(panel);
+                    }
+
+                    // Follow-up questions; clicking one asks it in the same thread
+                    function addSuggestions(msgDiv, suggestions) {
+                        const panel = document.createElement('div');
+                        panel.className = 'suggestions';
+                        const header = document.createElement('div');
+                        header.className = 'sources-header';
+                        header.textContent = 'Follow-up questions';
+                        panel.appendChild(header);
+                        suggestions.forEach(question => {
+                            const btn = document.createElement('button');
+                            btn.type = 'button';
+                            btn.className = 'suggestion-btn';
+                            btn.textContent = question;
+                            btn.onclick = () => {
+                                input.value = question;
+                                submitQuery();
+                            };
+                            panel.appendChild(btn);
+                        });
+                        msgDiv.appendChild(panel);
+                    }
+
                     function scrollToBottom() {
                         const container = document.getElementById('chat-container');
                         container.scrollTop = container.scrollHeight;
@@ -350,14 +839,25 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                     
                     document.getElementById('send-btn').onclick = submitQuery;
 
+                    const logoutLink = document.getElementById('logout-link');
+                    if (logoutLink) {
+                        logoutLink.onclick = async (e) => {
+                            e.preventDefault();
+                            await fetch('/api/auth/logout', { method: 'POST' });
+                            window.location.href = '/login';
+                        };
+                    }
+
                     async function submitQuery() {
                         const query = input.value.trim();
                         if (!query) return;
                         
                         input.value = '';
                         input.style.height = 'auto'; // Reset height
+                        document.querySelectorAll('.suggestions').forEach(el => el.remove());
                         
                         appendMessage('user', query);
+                        loadedMessageCount += 1;
                         const aiContentDiv = appendMessage('assistant', ''); 
                         
                         // Prepare thinking area
@@ -369,12 +869,32 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                         }
                         thinkingDiv.style.display = 'block';
 
+                        const progressBar = document.createElement('div');
+                        progressBar.className = 'query-progress';
+                        const progressFill = document.createElement('div');
+                        progressFill.className = 'query-progress-fill';
+                        progressBar.appendChild(progressFill);
+                        aiContentDiv.insertBefore(progressBar, thinkingDiv);
+
+                        // Per-message expand/collapse control for the thinking panel
+                        const thinkingToggle = document.createElement('button');
+                        thinkingToggle.type = 'button';
+                        thinkingToggle.className = 'thinking-toggle';
+                        thinkingToggle.textContent = 'Hide thinking';
+                        thinkingToggle.onclick = () => {
+                            const hidden = thinkingDiv.style.display === 'none';
+                            thinkingDiv.style.display = hidden ? 'block' : 'none';
+                            thinkingToggle.textContent = hidden ? 'Hide thinking' : 'Show thinking';
+                        };
+                        aiContentDiv.insertBefore(thinkingToggle, thinkingDiv);
+
                         // Actual Answer Container
                         const answerTextDiv = document.createElement('div');
                         answerTextDiv.className = 'answer-text';
                         aiContentDiv.appendChild(answerTextDiv);
 
                         accumulatedSources = []; 
+                        accumulatedCitations = [];
 
                         try {
                             const res = await fetch('/api/query/stream', {
@@ -383,8 +903,14 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                                 body: JSON.stringify({
                                     query,
                                     web_search_enabled: document.getElementById('web-search-toggle').checked,
+                                    research_mode: document.getElementById('research-mode-toggle').checked,
                                     model: document.getElementById('model-select').value,
                                     search_provider: document.getElementById('provider-select').value,
+                                    region: document.getElementById('region-select').value || null,
+                                    time_range: document.getElementById('time-range-select').value || null,
+                                    focus: document.getElementById('focus-select').value || null,
+                                    max_results: parseInt(document.getElementById('max-results-input').value) || null,
+                                    max_fetch: parseInt(document.getElementById('max-fetch-input').value) || null,
                                     thread_id: currentThreadId 
                                 })
                             });
@@ -393,6 +919,9 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                             const decoder = new TextDecoder();
                             let buffer = '';
                             let fullAnswer = '';
+                            let citedSources = null; // null until the server reports which sources were cited
+                            let suggestions = [];
+                            let stopBtn = null; // shown once the server reports the request ID
 
                             while (true) {
                                 const { done, value } = await reader.read();
@@ -413,6 +942,18 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                                                         currentThreadId = newId;
                                                         loadThreads(); 
                                                     }
+                                                } else if (event.data.startsWith('REQUEST_ID:')) {
+                                                    const requestId = event.data.split(':')[1];
+                                                    stopBtn = document.createElement('button');
+                                                    stopBtn.type = 'button';
+                                                    stopBtn.className = 'thinking-toggle';
+                                                    stopBtn.textContent = 'Stop';
+                                                    stopBtn.onclick = () => {
+                                                        stopBtn.disabled = true;
+                                                        stopBtn.textContent = 'Stopping...';
+                                                        fetch(`/api/query/${requestId}/cancel`, { method: 'POST' });
+                                                    };
+                                                    aiContentDiv.insertBefore(stopBtn, thinkingDiv);
                                                 } else {
                                                     const step = document.createElement('div');
                                                     step.className = 'thinking-step';
@@ -420,10 +961,23 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                                                     thinkingDiv.appendChild(step);
                                                     thinkingDiv.scrollTop = thinkingDiv.scrollHeight;
                                                 }
+                                            } else if (event.type === 'Progress') {
+                                                progressFill.style.width = `${Math.round(event.data * 100)}%`;
                                             } else if (event.type === 'Source') {
                                                 accumulatedSources.push(event.data);
+                                            } else if (event.type === 'CitedSources') {
+                                                citedSources = event.data;
+                                            } else if (event.type === 'Citations') {
+                                                accumulatedCitations = event.data;
+                                            } else if (event.type === 'Suggestions') {
+                                                suggestions = event.data;
+                                            } else if (event.type === 'Token') {
+                                                fullAnswer += event.data;
+                                                answerTextDiv.innerHTML = renderMarkdown(fullAnswer);
+                                                scrollToBottom();
                                             } else if (event.type === 'Answer') {
                                                 fullAnswer = event.data;
+                                                loadedMessageCount += 1;
                                                 answerTextDiv.innerHTML = renderMarkdown(fullAnswer);
                                                 setTimeout(() => {
                                                     const mermaidDivs = answerTextDiv.querySelectorAll('.mermaid');
@@ -435,18 +989,45 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                                                     });
                                                 }, 50);
                                                 scrollToBottom();
+                                            } else if (event.type === 'Warning') {
+                                                const step = document.createElement('div');
+                                                step.className = 'thinking-step warning';
+                                                step.textContent = '! ' + event.data;
+                                                thinkingDiv.appendChild(step);
+                                                thinkingDiv.scrollTop = thinkingDiv.scrollHeight;
+                                            } else if (event.type === 'Cancelled') {
+                                                const note = document.createElement('div');
+                                                note.className = 'thinking-step warning';
+                                                note.textContent = 'Query cancelled.';
+                                                answerTextDiv.appendChild(note);
                                             } else if (event.type === 'Error') {
-                                                answerTextDiv.innerHTML += `<div class="error">${event.data}</div>`;
+                                                const errorDiv = document.createElement('div');
+                                                errorDiv.className = 'error';
+                                                errorDiv.textContent = event.data;
+                                                answerTextDiv.appendChild(errorDiv);
                                             }
                                         } catch (e) { console.warn(e); }
                                     }
                                 }
                             }
                             
-                            // Collapse thinking after done
-                            thinkingDiv.style.display = 'none'; // Auto-hide thinking process
+                            progressBar.remove();
+                            if (stopBtn) stopBtn.remove();
+
+                            if (fullAnswer) {
+                                addSourcesPanel(aiContentDiv.parentNode, accumulatedSources, citedSources, accumulatedCitations);
+                                addCopyActions(aiContentDiv.parentNode, answerTextDiv, fullAnswer);
+                                if (suggestions.length) addSuggestions(aiContentDiv.parentNode, suggestions);
+                            }
+
+                            // Collapse thinking after done unless the user wants it kept visible
+                            if (!keepThinkingToggle.checked) {
+                                thinkingDiv.style.display = 'none';
+                                thinkingToggle.textContent = 'Show thinking';
+                            }
                             
                         } catch (e) {
+                            progressBar.remove();
                             answerTextDiv.innerHTML += `<div class="error">Error: ${e.message}</div>`;
                         }
                     }
@@ -457,7 +1038,12 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                         const tempDiv = document.createElement('div');
                         tempDiv.innerHTML = html;
                         
-                        const citationRegex = /\[(\d+)\]/g;
+                        // Built from the server's CITATION_MARKER, with N matching one source number
+                        // or a comma-separated list ([1, 2]); adjacent markers ([1][2]) match separately
+                        const citationRegex = new RegExp(
+                            CITATION_MARKER.split('N').map(part => part.replace(/[.*+?^${}()|[\]\\]/g, '\\$&')).join('(\\d+(?:\\s*,\\s*\\d+)*)'),
+                            'g'
+                        );
                         
                         function processTextNodes(node) {
                             if (node.nodeType === 3) {
@@ -465,32 +1051,41 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
                                 if (citationRegex.test(text)) {
                                     const fragment = document.createDocumentFragment();
                                     let lastIndex = 0;
-                                    text.replace(citationRegex, (match, num, offset) => {
+                                    text.replace(citationRegex, (match, list, offset) => {
                                         fragment.appendChild(document.createTextNode(text.substring(lastIndex, offset)));
                                         
-                                        const span = document.createElement('span');
-                                        span.className = 'citation';
-                                        span.textContent = `[${num}]`;
-                                        
-                                        const tooltip = document.createElement('div');
-                                        tooltip.className = 'citation-tooltip';
-                                        
-                                        const source = accumulatedSources[parseInt(num) - 1];
-                                        if (source) {
-                                            tooltip.innerHTML = `
-                                                <span class="citation-tooltip-title">${source.title}</span>
-                                                <span class="citation-tooltip-url">${source.url}</span>
-                                            `;
-                                            span.onclick = (e) => {
-                                                e.stopPropagation();
-                                                window.open(source.url, '_blank');
-                                            };
-                                        } else {
-                                            tooltip.textContent = `Source ${num}`;
-                                        }
-                                        
-                                        span.appendChild(tooltip);
-                                        fragment.appendChild(span);
+                                        list.split(',').map(n => n.trim()).forEach(num => {
+                                            const span = document.createElement('span');
+                                            span.className = 'citation';
+                                            span.textContent = CITATION_MARKER.replace('N', num);
+                                            
+                                            const tooltip = document.createElement('div');
+                                            tooltip.className = 'citation-tooltip';
+                                            
+                                            const citation = accumulatedCitations.find(c => c.index === parseInt(num));
+                                            const source = citation || accumulatedSources[parseInt(num) - 1];
+                                            if (source) {
+                                                tooltip.innerHTML = `
+                                                    <span class="citation-tooltip-title">${source.title}</span>
+                                                    <span class="citation-tooltip-url">${source.url}</span>
+                                                `;
+                                                if (citation && citation.snippet) {
+                                                    const snippet = document.createElement('span');
+                                                    snippet.className = 'citation-tooltip-snippet';
+                                                    snippet.textContent = citation.snippet;
+                                                    tooltip.appendChild(snippet);
+                                                }
+                                                span.onclick = (e) => {
+                                                    e.stopPropagation();
+                                                    window.open(source.url, '_blank');
+                                                };
+                                            } else {
+                                                tooltip.textContent = `Source ${num}`;
+                                            }
+                                            
+                                            span.appendChild(tooltip);
+                                            fragment.appendChild(span);
+                                        });
                                         lastIndex = offset + match.length;
                                     });
                                     fragment.appendChild(document.createTextNode(text.substring(lastIndex)));
@@ -520,5 +1115,5 @@ pub async fn index(State(state): State<AppState>) -> Html<String> {
             }
         }
     };
-    Html(markup.into_string())
+    html_response(markup)
 }
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::sync::OnceLock;
+
+use tiktoken_rs::CoreBPE;
+
+/// Context window assumed for models whose provider doesn't report `context_length`
+pub const DEFAULT_CONTEXT_LENGTH: usize = 8192;
+
+/// Fewest tokens worth giving a source; below this fewer sources are sent instead
+const MIN_SOURCE_TOKENS: usize = 150;
+
+/// Title, URL and separators around each source in the context block
+const SOURCE_HEADER_TOKENS: usize = 40;
+
+fn bpe() -> Option<&'static CoreBPE> {
+    static BPE: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    BPE.get_or_init(|| {
+        tiktoken_rs::cl100k_base()
+            .inspect_err(|e| tracing::warn!("Tokenizer unavailable, estimating token counts: {}", e))
+            .ok()
+    })
+    .as_ref()
+}
+
+/// Tokens in `text` under `cl100k_base`. Other providers' tokenizers differ somewhat,
+/// which the budget's reserves absorb.
+pub fn count(text: &str) -> usize {
+    match bpe() {
+        Some(bpe) => bpe.encode_ordinary(text).len(),
+        None => text.chars().count().div_ceil(4),
+    }
+}
+
+/// `text` cut to at most `max_tokens` tokens.
+pub fn truncate(text: &str, max_tokens: usize) -> String {
+    let Some(bpe) = bpe() else {
+        return text.chars().take(max_tokens * 4).collect();
+    };
+    let tokens = bpe.encode_ordinary(text);
+    if tokens.len() <= max_tokens {
+        return text.to_string();
+    }
+    // A cut inside a multi-byte character doesn't decode; back off a token or two
+    (0..4)
+        .filter_map(|back| bpe.decode(tokens[..max_tokens.saturating_sub(back)].to_vec()).ok())
+        .next()
+        .unwrap_or_else(|| text.chars().take(max_tokens * 4).collect())
+}
+
+/// How one query splits the selected model's context window between the answer, the
+/// fixed prompt, conversation history and sources.
+#[derive(Debug, Clone, Copy)]
+pub struct ContextBudget {
+    pub context_length: usize,
+    /// Held back for the completion: `max_tokens` when set, otherwise a quarter of the
+    /// window up to 4096
+    pub answer_tokens: usize,
+    /// What is left for history and sources
+    pub available_tokens: usize,
+    /// Most of `available_tokens` that verbatim history and its summary may use
+    pub history_tokens: usize,
+}
+
+impl ContextBudget {
+    pub fn new(context_length: Option<i64>, max_answer_tokens: Option<u32>, prompt_tokens: usize) -> Self {
+        let context_length = context_length
+            .and_then(|len| usize::try_from(len).ok())
+            .filter(|len| *len > 0)
+            .unwrap_or(DEFAULT_CONTEXT_LENGTH);
+        let answer_tokens = max_answer_tokens
+            .map(|t| t as usize)
+            .unwrap_or((context_length / 4).min(4096))
+            .min(context_length / 2);
+        let available_tokens = context_length.saturating_sub(answer_tokens + prompt_tokens);
+        let history_tokens = (available_tokens as f64 * history_share()) as usize;
+        Self { context_length, answer_tokens, available_tokens, history_tokens }
+    }
+
+    /// How many of `count` sources fit beside `history_used` tokens of history, and how
+    /// many tokens of content each may carry (at most `SOURCE_MAX_TOKENS`).
+    pub fn sources(&self, count: usize, history_used: usize) -> (usize, usize) {
+        if count == 0 {
+            return (0, 0);
+        }
+        let room = self.available_tokens.saturating_sub(history_used);
+        let kept = count.min(room / (MIN_SOURCE_TOKENS + SOURCE_HEADER_TOKENS)).max(1);
+        let per_source = (room / kept)
+            .saturating_sub(SOURCE_HEADER_TOKENS)
+            .clamp(MIN_SOURCE_TOKENS, source_max_tokens());
+        (kept, per_source)
+    }
+}
+
+/// `HISTORY_CONTEXT_SHARE` (default 0.25): share of the room left after the prompt and
+/// answer that conversation history may take.
+fn history_share() -> f64 {
+    std::env::var("HISTORY_CONTEXT_SHARE")
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| *v > 0.0 && *v <= 1.0)
+        .unwrap_or(0.25)
+}
+
+/// `SOURCE_MAX_TOKENS` (default 1000): longest excerpt of one source, even on large models.
+fn source_max_tokens() -> usize {
+    std::env::var("SOURCE_MAX_TOKENS")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|v| *v >= MIN_SOURCE_TOKENS)
+        .unwrap_or(1000)
+}
@@ -1,13 +1,359 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc, FixedOffset};
+use chrono::{DateTime, Utc, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use chrono_tz::{Tz, TZ_VARIANTS};
 use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use crate::db::Database;
+use crate::domains::DomainRules;
+use crate::http::HttpClients;
+use crate::llm::LLMManager;
+use crate::search::{Focus, SearchOptions, TimeRange, WebSearch, WikipediaSearch, DEFAULT_MAX_RESULTS};
+use crate::tokens;
 
 pub struct Tools;
 
+/// Handles for tools that reach outside the process, such as `web_search`.
+#[derive(Clone, Copy)]
+pub struct ToolContext<'a> {
+    pub db: &'a Database,
+    pub http: &'a HttpClients,
+    /// Embeddings for `query_knowledge_base`
+    pub llm_manager: &'a LLMManager,
+    /// Whose stored sources `query_knowledge_base` may see, besides shared ones
+    pub user_id: Option<i64>,
+    pub search_provider: Option<&'a str>,
+    pub region: Option<&'a str>,
+    /// The query is restricted to allowlisted domains
+    pub allowed_domains_only: bool,
+    /// Default freshness for `web_search` when the model doesn't pass one
+    pub time_range: Option<TimeRange>,
+    /// The query's focus mode, steering `web_search` like the initial searches
+    pub focus: Option<Focus>,
+}
+
+/// A function the model can call. Built-in tools and those of MCP servers are all
+/// registered in the `ToolRegistry` at startup.
+#[async_trait::async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    /// OpenAI-style definition: `{"type": "function", "function": {name, description, parameters}}`
+    fn definition(&self) -> Value;
+    async fn execute(&self, args: &Value, ctx: &ToolContext<'_>) -> Result<String>;
+}
+
+/// Every tool the model may be offered, in registration order. Queries can narrow it
+/// down with `QueryRequest.enabled_tools`.
+#[derive(Default)]
+pub struct ToolRegistry {
+    // Written while MCP servers connect in the background, read by every query
+    tools: RwLock<Vec<Arc<dyn Tool>>>,
+}
+
+impl ToolRegistry {
+    pub fn with_builtins() -> Self {
+        Self { tools: RwLock::new(Tools::builtin()) }
+    }
+
+    /// Add `tool`, replacing a registered tool of the same name.
+    pub fn register(&self, tool: Arc<dyn Tool>) {
+        let mut tools = self.tools.write().unwrap_or_else(|e| e.into_inner());
+        match tools.iter_mut().find(|t| t.name() == tool.name()) {
+            Some(existing) => {
+                tracing::warn!("Tool {} registered twice; keeping the newer one", tool.name());
+                *existing = tool;
+            }
+            None => tools.push(tool),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        let tools = self.tools.read().unwrap_or_else(|e| e.into_inner());
+        tools.iter().find(|t| t.name() == name).cloned()
+    }
+
+    /// Names in `requested` that no registered tool answers to
+    pub fn unknown<'a>(&self, requested: &'a [String]) -> Vec<&'a str> {
+        requested.iter().map(String::as_str).filter(|name| self.get(name).is_none()).collect()
+    }
+
+    /// Definitions to offer the model: every tool, or only those named in `enabled`.
+    pub fn definitions(&self, enabled: Option<&[String]>) -> Vec<Value> {
+        let tools = self.tools.read().unwrap_or_else(|e| e.into_inner());
+        tools
+            .iter()
+            .filter(|t| enabled.is_none_or(|names| names.iter().any(|n| n == t.name())))
+            .map(|t| t.definition())
+            .collect()
+    }
+
+    pub async fn execute(&self, name: &str, arguments: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        tracing::info!("Executing tool: {} with arguments: {}", name, serde_json::to_string(arguments).unwrap_or_default());
+
+        let result = match self.get(name) {
+            Some(tool) => tool.execute(arguments, ctx).await,
+            None => {
+                tracing::error!("Unknown tool requested: {}", name);
+                Err(anyhow::anyhow!("Unknown tool: {}", name))
+            }
+        };
+
+        match &result {
+            Ok(res) => tracing::info!("Tool {} executed successfully, result length: {}", name, res.len()),
+            Err(e) => tracing::error!("Tool {} execution failed: {}", name, e),
+        }
+
+        result
+    }
+}
+
+/// A built-in tool whose result depends only on its arguments: its definition and the
+/// function that runs it
+struct FnTool(Value, fn(&Value) -> Result<String>);
+
+#[async_trait::async_trait]
+impl Tool for FnTool {
+    fn name(&self) -> &str {
+        self.0["function"]["name"].as_str().unwrap_or_default()
+    }
+
+    fn definition(&self) -> Value {
+        self.0.clone()
+    }
+
+    async fn execute(&self, args: &Value, _ctx: &ToolContext<'_>) -> Result<String> {
+        (self.1)(args)
+    }
+}
+/// Geolocation through ip-api.com, over the shared HTTP client
+struct IpInfoTool;
+
+#[async_trait::async_trait]
+impl Tool for IpInfoTool {
+    fn name(&self) -> &str {
+        "ip_info"
+    }
+
+    fn definition(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": "ip_info",
+                "description": "Look up geolocation for a public IP address: country, region, city, timezone and network operator.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "ip": {
+                            "type": "string",
+                            "description": "IPv4 or IPv6 address (e.g., '8.8.8.8')"
+                        }
+                    },
+                    "required": ["ip"]
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        Tools::ip_info(args, ctx.http).await
+    }
+}
+
+/// `web_search`, run with the query's provider, region and domain restrictions
+struct WebSearchTool;
+
+#[async_trait::async_trait]
+impl Tool for WebSearchTool {
+    fn name(&self) -> &str {
+        "web_search"
+    }
+
+    fn definition(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": "web_search",
+                "description": "Search the web for a targeted query and get back titles, URLs and snippets. Use it for follow-up or multi-hop questions the provided sources don't answer.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "Search query (e.g., 'Rust 1.80 release date')"
+                        },
+                        "max_results": {
+                            "type": "integer",
+                            "description": "Number of results to return (1-10, default 5)"
+                        },
+                        "time_range": {
+                            "type": "string",
+                            "enum": ["day", "week", "month", "year"],
+                            "description": "Only return results from the past day, week, month or year"
+                        }
+                    },
+                    "required": ["query"]
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        Tools::web_search(args, ctx).await
+    }
+}
+
+/// `fetch_url`, for reading a page the model found in a snippet or search result
+struct FetchUrlTool;
+
+#[async_trait::async_trait]
+impl Tool for FetchUrlTool {
+    fn name(&self) -> &str {
+        "fetch_url"
+    }
+
+    fn definition(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": "fetch_url",
+                "description": "Fetch a web page (or PDF) and return its main text. Use it to read a page behind a search result or snippet before citing it.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "url": {
+                            "type": "string",
+                            "description": "Address of the page (e.g., 'https://doc.rust-lang.org/book/')"
+                        }
+                    },
+                    "required": ["url"]
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        Tools::fetch_url(args, ctx).await
+    }
+}
+
+/// `wikipedia_search`, for encyclopedic questions without spending paid search quota
+struct WikipediaTool;
+
+#[async_trait::async_trait]
+impl Tool for WikipediaTool {
+    fn name(&self) -> &str {
+        "wikipedia_search"
+    }
+
+    fn definition(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": "wikipedia_search",
+                "description": "Look up Wikipedia articles and get back their summaries, or the full text of the best match. Use it for encyclopedic facts about people, places, events and concepts.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "Article title or topic (e.g., 'Ada Lovelace')"
+                        },
+                        "full_article": {
+                            "type": "boolean",
+                            "description": "Return the whole text of the best matching article instead of summaries (default false)"
+                        },
+                        "max_results": {
+                            "type": "integer",
+                            "description": "Number of article summaries to return (1-5, default 3)"
+                        }
+                    },
+                    "required": ["query"]
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        Tools::wikipedia_search(args, ctx).await
+    }
+}
+
+/// `query_knowledge_base`, for stored sources from earlier research and ingested documents
+struct KnowledgeBaseTool;
+
+#[async_trait::async_trait]
+impl Tool for KnowledgeBaseTool {
+    fn name(&self) -> &str {
+        "query_knowledge_base"
+    }
+
+    fn definition(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": "query_knowledge_base",
+                "description": "Search the local knowledge base of previously stored pages and ingested documents. Use it to pull in past research or uploaded material the provided sources don't cover.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "What to look for (e.g., 'quarterly revenue 2023')"
+                        },
+                        "max_results": {
+                            "type": "integer",
+                            "description": "Number of sources to return (1-10, default 5)"
+                        }
+                    },
+                    "required": ["query"]
+                }
+            }
+        })
+    }
+
+    async fn execute(&self, args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        Tools::query_knowledge_base(args, ctx).await
+    }
+}
+
+/// Result cap for the `web_search` tool, kept small so results fit the context
+const WEB_SEARCH_MAX_RESULTS: usize = 10;
+
+/// Longest page text the `fetch_url` tool returns, in tokens
+const FETCH_URL_MAX_TOKENS: usize = 3000;
+
+/// Summary cap for the `wikipedia_search` tool
+const WIKIPEDIA_MAX_RESULTS: usize = 5;
+
+/// Longest article summary `wikipedia_search` returns, in tokens
+const WIKIPEDIA_SUMMARY_TOKENS: usize = 600;
+
+/// Result cap for the `query_knowledge_base` tool
+const KNOWLEDGE_BASE_MAX_RESULTS: usize = 10;
+
+/// Longest excerpt of each stored source `query_knowledge_base` returns, in tokens
+const KNOWLEDGE_BASE_EXCERPT_TOKENS: usize = 600;
+
+/// Common abbreviations the model tends to use instead of IANA names. They are
+/// ambiguous in general (IST, CST), so each maps to its most likely zone.
+const TIMEZONE_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("PST", "America/Los_Angeles"), ("PDT", "America/Los_Angeles"), ("PT", "America/Los_Angeles"),
+    ("MST", "America/Denver"), ("MDT", "America/Denver"), ("MT", "America/Denver"),
+    ("CST", "America/Chicago"), ("CDT", "America/Chicago"), ("CT", "America/Chicago"),
+    ("EST", "America/New_York"), ("EDT", "America/New_York"), ("ET", "America/New_York"),
+    ("BST", "Europe/London"), ("CET", "Europe/Paris"), ("CEST", "Europe/Paris"),
+    ("IST", "Asia/Kolkata"), ("JST", "Asia/Tokyo"), ("KST", "Asia/Seoul"),
+    ("AEST", "Australia/Sydney"), ("AEDT", "Australia/Sydney"),
+];
+
 impl Tools {
-    pub fn get_tools_definition() -> Vec<Value> {
+    /// The tools every deployment has, in the order they are offered to the model.
+    fn builtin() -> Vec<Arc<dyn Tool>> {
         vec![
-            json!({
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "get_current_date",
@@ -22,13 +368,13 @@ impl Tools {
                             },
                             "timezone": {
                                 "type": "string",
-                                "description": "Optional timezone (e.g., 'UTC', 'America/New_York', 'Europe/London'). Defaults to UTC."
+                                "description": "Optional IANA timezone or city (e.g., 'UTC', 'America/New_York', 'Europe/London', 'Tokyo'). Defaults to UTC."
                             }
                         }
                     }
                 }
-            }),
-            json!({
+            }), Self::get_current_date)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "get_current_time",
@@ -43,13 +389,13 @@ impl Tools {
                             },
                             "timezone": {
                                 "type": "string",
-                                "description": "Optional timezone (e.g., 'UTC', 'America/New_York'). Defaults to UTC."
+                                "description": "Optional IANA timezone or city (e.g., 'UTC', 'America/New_York', 'Asia/Tokyo', 'Tokyo'). Defaults to UTC."
                             }
                         }
                     }
                 }
-            }),
-            json!({
+            }), Self::get_current_time)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "calculate",
@@ -65,8 +411,8 @@ impl Tools {
                         "required": ["expression"]
                     }
                 }
-            }),
-            json!({
+            }), Self::calculate)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "format_date",
@@ -87,18 +433,18 @@ impl Tools {
                         "required": ["date"]
                     }
                 }
-            }),
-            json!({
+            }), Self::format_date)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "timezone_convert",
-                    "description": "Convert a time from one timezone to another.",
+                    "description": "Convert a time from one timezone to another, accounting for daylight saving time.",
                     "parameters": {
                         "type": "object",
                         "properties": {
                             "time": {
                                 "type": "string",
-                                "description": "Time string to convert"
+                                "description": "Time to convert: 'YYYY-MM-DD HH:MM[:SS]', 'HH:MM' (today in the source timezone), or RFC 3339"
                             },
                             "from_timezone": {
                                 "type": "string",
@@ -112,8 +458,8 @@ impl Tools {
                         "required": ["time", "from_timezone", "to_timezone"]
                     }
                 }
-            }),
-            json!({
+            }), Self::timezone_convert)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "generate_uuid",
@@ -129,8 +475,8 @@ impl Tools {
                         }
                     }
                 }
-            }),
-            json!({
+            }), Self::generate_uuid)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "hash_string",
@@ -151,8 +497,8 @@ impl Tools {
                         "required": ["text", "algorithm"]
                     }
                 }
-            }),
-            json!({
+            }), Self::hash_string)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "base64_encode",
@@ -168,8 +514,8 @@ impl Tools {
                         "required": ["text"]
                     }
                 }
-            }),
-            json!({
+            }), Self::base64_encode)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "base64_decode",
@@ -185,8 +531,8 @@ impl Tools {
                         "required": ["text"]
                     }
                 }
-            }),
-            json!({
+            }), Self::base64_decode)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "unit_convert",
@@ -210,8 +556,8 @@ impl Tools {
                         "required": ["value", "from_unit", "to_unit"]
                     }
                 }
-            }),
-            json!({
+            }), Self::unit_convert)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "extract_keywords",
@@ -231,8 +577,8 @@ impl Tools {
                         "required": ["text"]
                     }
                 }
-            }),
-            json!({
+            }), Self::extract_keywords)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "compare_values",
@@ -252,8 +598,8 @@ impl Tools {
                         "required": ["value1", "value2"]
                     }
                 }
-            }),
-            json!({
+            }), Self::compare_values)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "format_number",
@@ -278,8 +624,8 @@ impl Tools {
                         "required": ["number", "format"]
                     }
                 }
-            }),
-            json!({
+            }), Self::format_number)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "validate_url",
@@ -295,8 +641,8 @@ impl Tools {
                         "required": ["url"]
                     }
                 }
-            }),
-            json!({
+            }), Self::validate_url)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "days_between_dates",
@@ -316,8 +662,8 @@ impl Tools {
                         "required": ["date1"]
                     }
                 }
-            }),
-            json!({
+            }), Self::days_between_dates)),
+            Arc::new(FnTool(json!({
                 "type": "function",
                 "function": {
                     "name": "extract_entities",
@@ -341,42 +687,244 @@ impl Tools {
                         "required": ["text"]
                     }
                 }
-            }),
+            }), Self::extract_entities)),
+            Arc::new(FnTool(json!({
+                "type": "function",
+                "function": {
+                    "name": "percentage",
+                    "description": "Percentage math with an explanation: X% of Y, what percent X is of Y, or the percent change from X to Y.",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {
+                            "operation": {
+                                "type": "string",
+                                "enum": ["percent_of", "what_percent", "percent_change"],
+                                "description": "'percent_of' (a% of b), 'what_percent' (a is what percent of b), 'percent_change' (change from a to b)"
+                            },
+                            "a": {
+                                "type": "number",
+                                "description": "First operand (the percentage for 'percent_of', the part for 'what_percent', the old value for 'percent_change')"
+                            },
+                            "b": {
+                                "type": "number",
+                                "description": "Second operand (the base for 'percent_of' and 'what_percent', the new value for 'percent_change')"
+                            }
+                        },
+                        "required": ["operation", "a", "b"]
+                    }
+                }
+            }), Self::percentage)),
+            Arc::new(IpInfoTool),
+            Arc::new(FnTool(json!({
+                "type": "function",
+                "function": {
+                    "name": "http_info",
+                    "description": "Explain an HTTP status code (meaning and category) and/or an HTTP header (what it does). Provide at least one of the two.",
+                    "parameters": {
+                        "type": "object",
+                        "properties": {
+                            "status_code": {
+                                "type": "integer",
+                                "description": "HTTP status code (e.g., 404, 418)"
+                            },
+                            "header": {
+                                "type": "string",
+                                "description": "HTTP header name (e.g., 'Cache-Control'); case-insensitive"
+                            }
+                        }
+                    }
+                }
+            }), Self::http_info)),
+            Arc::new(WebSearchTool),
+            Arc::new(FetchUrlTool),
+            Arc::new(WikipediaTool),
+            Arc::new(KnowledgeBaseTool),
         ]
     }
 
-    pub fn execute_tool(name: &str, arguments: &Value) -> Result<String> {
-        tracing::info!("Executing tool: {} with arguments: {}", name, serde_json::to_string(arguments).unwrap_or_default());
-        
-        let result = match name {
-            "get_current_date" => Self::get_current_date(arguments),
-            "get_current_time" => Self::get_current_time(arguments),
-            "calculate" => Self::calculate(arguments),
-            "format_date" => Self::format_date(arguments),
-            "timezone_convert" => Self::timezone_convert(arguments),
-            "generate_uuid" => Self::generate_uuid(arguments),
-            "hash_string" => Self::hash_string(arguments),
-            "base64_encode" => Self::base64_encode(arguments),
-            "base64_decode" => Self::base64_decode(arguments),
-            "unit_convert" => Self::unit_convert(arguments),
-            "extract_keywords" => Self::extract_keywords(arguments),
-            "compare_values" => Self::compare_values(arguments),
-            "format_number" => Self::format_number(arguments),
-            "validate_url" => Self::validate_url(arguments),
-            "days_between_dates" => Self::days_between_dates(arguments),
-            "extract_entities" => Self::extract_entities(arguments),
-            _ => {
-                tracing::error!("Unknown tool requested: {}", name);
-                Err(anyhow::anyhow!("Unknown tool: {}", name))
-            },
+    async fn web_search(args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        let query = args.get("query")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|q| !q.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: query"))?;
+        let max_results = args.get("max_results")
+            .and_then(|v| v.as_u64())
+            .map_or(DEFAULT_MAX_RESULTS, |n| n as usize)
+            .clamp(1, WEB_SEARCH_MAX_RESULTS);
+
+        let time_range = args.get("time_range")
+            .and_then(|v| v.as_str())
+            .and_then(TimeRange::parse)
+            .or(ctx.time_range);
+
+        let options = SearchOptions { region: ctx.region, max_results, allowed_only: ctx.allowed_domains_only, time_range, focus: ctx.focus };
+        let results = WebSearch::search(ctx.http, ctx.db, query, ctx.search_provider, options).await?;
+        if results.is_empty() {
+            return Ok(format!("No web results found for '{}'.", query));
+        }
+
+        let mut output = format!("Web results for '{}':\n", query);
+        for (i, result) in results.iter().take(max_results).enumerate() {
+            output.push_str(&format!("\n{}. {}\n   URL: {}\n   {}\n", i + 1, result.title, result.url, result.snippet.trim()));
+        }
+        Ok(output)
+    }
+
+    /// Read a page for the model. Blocked domains are refused by `fetch_content`; on
+    /// queries restricted to allowlisted domains so is everything else. Addresses on the
+    /// local network are refused too, since the URL may come from untrusted page text.
+    async fn fetch_url(args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        let url = args.get("url")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: url"))?;
+        let normalized = if url.contains("://") { url.to_string() } else { format!("https://{}", url) };
+        let parsed = url::Url::parse(&normalized).map_err(|e| anyhow::anyhow!("Invalid URL {}: {}", url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Ok(format!("Only http(s) pages can be fetched, not {}", url));
+        }
+        if crate::http::ensure_public_url(&parsed).is_err() {
+            return Ok(format!("{} is not a public address and can't be fetched", url));
+        }
+        if ctx.allowed_domains_only {
+            let rules = DomainRules::load(ctx.db).await;
+            if !rules.allowed.is_empty() && !rules.is_allowed(&normalized) {
+                return Ok(format!("{} is outside the allowed domains for this query", url));
+            }
+        }
+
+        let page = WebSearch::fetch_public_content(ctx.http, &normalized, ctx.db).await?;
+        if page.content.trim().is_empty() {
+            return Ok(format!("No readable text found at {}", page.url));
+        }
+        let text = tokens::truncate(&page.content, FETCH_URL_MAX_TOKENS);
+        let truncated = if text.len() < page.content.len() { "\n\n[truncated]" } else { "" };
+        Ok(format!("Content of {}:\n\n{}{}", page.url, text.trim(), truncated))
+    }
+
+    /// Summaries of the best matching articles, or the full text of the best one. Like
+    /// `fetch_url`, it honours blocked domains and the query's allowlist restriction.
+    async fn wikipedia_search(args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        let query = args.get("query")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|q| !q.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: query"))?;
+        let full = args.get("full_article").and_then(|v| v.as_bool()).unwrap_or(false);
+        let max_results = if full {
+            1
+        } else {
+            args.get("max_results")
+                .and_then(|v| v.as_u64())
+                .map_or(3, |n| n as usize)
+                .clamp(1, WIKIPEDIA_MAX_RESULTS)
         };
-        
-        match &result {
-            Ok(res) => tracing::info!("Tool {} executed successfully, result length: {}", name, res.len()),
-            Err(e) => tracing::error!("Tool {} execution failed: {}", name, e),
+
+        let articles = WikipediaSearch::from_env().articles(ctx.http, query, max_results, full).await?;
+        let rules = DomainRules::load(ctx.db).await;
+        let restrict = ctx.allowed_domains_only && !rules.allowed.is_empty();
+        if articles.iter().any(|a| rules.is_blocked(&a.url) || (restrict && !rules.is_allowed(&a.url))) {
+            return Ok("Wikipedia is outside the allowed domains for this query".to_string());
         }
-        
-        result
+        if articles.is_empty() {
+            return Ok(format!("No Wikipedia articles found for '{}'.", query));
+        }
+
+        if full {
+            let article = &articles[0];
+            let text = tokens::truncate(&article.extract, FETCH_URL_MAX_TOKENS);
+            let truncated = if text.len() < article.extract.len() { "\n\n[truncated]" } else { "" };
+            return Ok(format!("Wikipedia: {}\nURL: {}\n\n{}{}", article.title, article.url, text.trim(), truncated));
+        }
+
+        let mut output = format!("Wikipedia articles for '{}':\n", query);
+        for (i, article) in articles.iter().enumerate() {
+            let summary = tokens::truncate(&article.extract, WIKIPEDIA_SUMMARY_TOKENS);
+            output.push_str(&format!("\n{}. {}\n   URL: {}\n   {}\n", i + 1, article.title, article.url, summary.trim()));
+        }
+        Ok(output)
+    }
+
+    /// Stored sources matching `query`: semantic matches first when embeddings are
+    /// configured, then keyword matches, without duplicates.
+    async fn query_knowledge_base(args: &Value, ctx: &ToolContext<'_>) -> Result<String> {
+        let query = args.get("query")
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|q| !q.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing required parameter: query"))?;
+        let max_results = args.get("max_results")
+            .and_then(|v| v.as_u64())
+            .map_or(DEFAULT_MAX_RESULTS, |n| n as usize)
+            .clamp(1, KNOWLEDGE_BASE_MAX_RESULTS);
+
+        let semantic = match ctx.llm_manager.semantic_search(query, max_results, ctx.user_id).await {
+            Ok(sources) => sources,
+            Err(e) => {
+                tracing::warn!("Semantic search for query_knowledge_base failed: {}, using keyword matches", e);
+                Vec::new()
+            }
+        };
+        let keyword = ctx.db.search_sources(query, max_results as i64, ctx.user_id).await?;
+
+        let mut seen = std::collections::HashSet::new();
+        let sources: Vec<_> = semantic
+            .into_iter()
+            .chain(keyword)
+            .filter(|s| seen.insert(s.id))
+            .take(max_results)
+            .collect();
+        if sources.is_empty() {
+            return Ok(format!("No stored sources match '{}'.", query));
+        }
+
+        let mut output = format!("Stored sources for '{}':\n", query);
+        for (i, source) in sources.iter().enumerate() {
+            let excerpt = tokens::truncate(&source.content, KNOWLEDGE_BASE_EXCERPT_TOKENS);
+            let truncated = if excerpt.len() < source.content.len() { " [...]" } else { "" };
+            output.push_str(&format!(
+                "\n{}. {}\n   URL: {}\n   Stored: {}\n   {}{}\n",
+                i + 1,
+                source.title,
+                source.url,
+                source.created_at.format("%Y-%m-%d"),
+                excerpt.trim(),
+                truncated
+            ));
+        }
+        Ok(output)
+    }
+
+    /// Resolve an IANA name (case-insensitive), a common abbreviation, or a bare
+    /// city such as "Tokyo" or "new york" to a timezone.
+    fn parse_timezone(name: &str) -> Result<Tz> {
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("utc") || name.eq_ignore_ascii_case("gmt") || name.eq_ignore_ascii_case("z") {
+            return Ok(Tz::UTC);
+        }
+        if let Ok(tz) = name.parse::<Tz>() {
+            return Ok(tz);
+        }
+        if let Some(tz) = TZ_VARIANTS.iter().find(|tz| tz.name().eq_ignore_ascii_case(name)) {
+            return Ok(*tz);
+        }
+        if let Some((_, iana)) = TIMEZONE_ABBREVIATIONS.iter().find(|(abbr, _)| abbr.eq_ignore_ascii_case(name)) {
+            return iana.parse::<Tz>().map_err(|e| anyhow::anyhow!("{}", e));
+        }
+        let city = name.replace(' ', "_");
+        TZ_VARIANTS.iter()
+            .find(|tz| tz.name().rsplit('/').next().is_some_and(|last| last.eq_ignore_ascii_case(&city)))
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Unknown timezone '{}'; use an IANA name such as 'Asia/Tokyo'", name))
+    }
+
+    fn timezone_arg(args: &Value, key: &str) -> Result<Tz> {
+        args.get(key)
+            .and_then(|v| v.as_str())
+            .filter(|v| !v.trim().is_empty())
+            .map_or(Ok(Tz::UTC), Self::parse_timezone)
     }
 
     fn get_current_date(args: &Value) -> Result<String> {
@@ -384,28 +932,14 @@ impl Tools {
             .and_then(|v| v.as_str())
             .unwrap_or("readable");
         
-        let timezone_str = args.get("timezone")
-            .and_then(|v| v.as_str())
-            .unwrap_or("UTC");
-        
-        let now = if timezone_str == "UTC" {
-            Utc::now()
-        } else {
-            // For simplicity, use UTC and note timezone in output
-            Utc::now()
-        };
+        let tz = Self::timezone_arg(args, "timezone")?;
+        let now = Utc::now().with_timezone(&tz);
         
         let result = match format {
             "iso" => now.format("%Y-%m-%d").to_string(),
             "readable" => now.format("%B %d, %Y").to_string(),
             "day_of_week" | "day_name" => now.format("%A").to_string(),
-            "full" => {
-                if timezone_str == "UTC" {
-                    now.format("%A, %B %d, %Y at %H:%M:%S UTC").to_string()
-                } else {
-                    format!("{} (timezone: {})", now.format("%A, %B %d, %Y at %H:%M:%S UTC"), timezone_str)
-                }
-            },
+            "full" => format!("{} ({})", now.format("%A, %B %d, %Y at %H:%M:%S %Z"), tz.name()),
             _ => now.format("%B %d, %Y").to_string(),
         };
         
@@ -417,24 +951,20 @@ impl Tools {
             .and_then(|v| v.as_str())
             .unwrap_or("24h");
         
-        let timezone_str = args.get("timezone")
-            .and_then(|v| v.as_str())
-            .unwrap_or("UTC");
-        
-        let now = Utc::now();
+        let tz = Self::timezone_arg(args, "timezone")?;
+        let now = Utc::now().with_timezone(&tz);
         
         let result = match format {
-            "12h" => now.format("%I:%M:%S %p UTC").to_string(),
-            "24h" => now.format("%H:%M:%S UTC").to_string(),
+            "12h" => now.format("%I:%M:%S %p %Z").to_string(),
             "iso" => now.to_rfc3339(),
-            "timestamp" => now.timestamp().to_string(),
-            _ => now.format("%H:%M:%S UTC").to_string(),
+            "timestamp" => return Ok(now.timestamp().to_string()),
+            _ => now.format("%H:%M:%S %Z").to_string(),
         };
         
-        if timezone_str != "UTC" {
-            Ok(format!("{} (requested timezone: {})", result, timezone_str))
-        } else {
+        if tz == Tz::UTC {
             Ok(result)
+        } else {
+            Ok(format!("{} in {} (UTC{})", result, tz.name(), now.format("%:z")))
         }
     }
 
@@ -501,24 +1031,67 @@ impl Tools {
     }
 
     fn timezone_convert(args: &Value) -> Result<String> {
-        // Simplified - for production, use chrono-tz
         let time_str = args.get("time")
             .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing 'time' parameter"))?;
+            .ok_or_else(|| anyhow::anyhow!("Missing 'time' parameter"))?
+            .trim();
         
-        let _from = args.get("from_timezone")
+        let from = args.get("from_timezone")
             .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing 'from_timezone' parameter"))?;
+            .ok_or_else(|| anyhow::anyhow!("Missing 'from_timezone' parameter"))
+            .and_then(Self::parse_timezone)?;
         
-        let _to = args.get("to_timezone")
+        let to = args.get("to_timezone")
             .and_then(|v| v.as_str())
-            .ok_or_else(|| anyhow::anyhow!("Missing 'to_timezone' parameter"))?;
+            .ok_or_else(|| anyhow::anyhow!("Missing 'to_timezone' parameter"))
+            .and_then(Self::parse_timezone)?;
+        
+        // An explicit offset wins over from_timezone; otherwise the time is local to from_timezone
+        let (instant, note) = if let Ok(dt) = DateTime::parse_from_rfc3339(time_str) {
+            (dt.with_timezone(&from), None)
+        } else {
+            let naive = Self::parse_local_time(time_str, from)?;
+            match from.from_local_datetime(&naive) {
+                LocalResult::Single(dt) => (dt, None),
+                // Clocks going back: the wall time happens twice, take the first
+                LocalResult::Ambiguous(earlier, _) => (earlier, Some(format!(
+                    "{} occurs twice in {} because of a DST change; using the first occurrence ({})",
+                    naive.format("%Y-%m-%d %H:%M"), from.name(), earlier.format("%Z")
+                ))),
+                LocalResult::None => anyhow::bail!(
+                    "{} does not exist in {} (skipped by a DST change)", naive.format("%Y-%m-%d %H:%M"), from.name()
+                ),
+            }
+        };
         
-        // Parse and convert (simplified - use chrono-tz for full support)
-        let dt = DateTime::parse_from_rfc3339(time_str)
-            .or_else(|_| time_str.parse::<DateTime<Utc>>().map(|dt| dt.with_timezone(&FixedOffset::east_opt(0).unwrap())))?;
+        let converted = instant.with_timezone(&to);
+        let mut output = format!(
+            "{} {} ({}) = {} {} ({})",
+            instant.format("%Y-%m-%d %H:%M:%S"), instant.format("%Z"), from.name(),
+            converted.format("%Y-%m-%d %H:%M:%S"), converted.format("%Z"), to.name()
+        );
+        if let Some(note) = note {
+            output.push_str(&format!("\nNote: {}", note));
+        }
+        Ok(output)
+    }
+
+    /// Wall-clock time without an offset: a full date and time, or a bare time of
+    /// day taken as today's date in `tz`.
+    fn parse_local_time(time_str: &str, tz: Tz) -> Result<NaiveDateTime> {
+        const DATETIME_FORMATS: [&str; 4] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+        const TIME_FORMATS: [&str; 4] = ["%H:%M:%S", "%H:%M", "%I:%M %p", "%I:%M%p"];
         
-        Ok(format!("Converted time: {} (Note: Full timezone conversion requires chrono-tz library)", dt.format("%Y-%m-%d %H:%M:%S")))
+        if let Some(dt) = DATETIME_FORMATS.iter().find_map(|f| NaiveDateTime::parse_from_str(time_str, f).ok()) {
+            return Ok(dt);
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(time_str, "%Y-%m-%d") {
+            return Ok(date.and_time(NaiveTime::MIN));
+        }
+        let time = TIME_FORMATS.iter()
+            .find_map(|f| NaiveTime::parse_from_str(&time_str.to_uppercase(), f).ok())
+            .ok_or_else(|| anyhow::anyhow!("Unrecognized time '{}'; use 'YYYY-MM-DD HH:MM' or 'HH:MM'", time_str))?;
+        Ok(Utc::now().with_timezone(&tz).date_naive().and_time(time))
     }
 
     fn generate_uuid(args: &Value) -> Result<String> {
@@ -656,21 +1229,26 @@ impl Tools {
             .and_then(|v| v.as_u64())
             .unwrap_or(10) as usize;
         
-        // Simple keyword extraction (for production, use proper NLP)
-        let words: Vec<&str> = text
+        let stopwords = Self::stopwords();
+        
+        // Normalize words: trim surrounding punctuation and lowercase for counting
+        let words: Vec<String> = text
             .split_whitespace()
+            .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
             .filter(|w| w.len() > 3) // Filter short words
+            .filter(|w| !stopwords.contains(w.as_str()))
             .collect();
         
         // Count word frequencies
         use std::collections::HashMap;
         let mut freq: HashMap<&str, usize> = HashMap::new();
         for word in &words {
-            *freq.entry(word).or_insert(0) += 1;
+            *freq.entry(word.as_str()).or_insert(0) += 1;
         }
         
         let mut keywords: Vec<(&str, usize)> = freq.into_iter().collect();
-        keywords.sort_by(|a, b| b.1.cmp(&a.1));
+        // Sort by frequency, then alphabetically so ties are stable
+        keywords.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
         keywords.truncate(max_keywords);
         
         let result: Vec<String> = keywords.iter()
@@ -680,6 +1258,40 @@ impl Tools {
         Ok(result.join(", "))
     }
 
+    /// Bundled English stopwords, extended with the comma-separated `KEYWORD_STOPWORDS` env var.
+    fn stopwords() -> std::collections::HashSet<String> {
+        const DEFAULT_STOPWORDS: &[&str] = &[
+            "a", "about", "above", "after", "again", "against", "all", "also", "an", "and", "any",
+            "are", "as", "at", "be", "because", "been", "before", "being", "below", "between",
+            "both", "but", "by", "can", "could", "did", "does", "doing", "down", "during", "each",
+            "even", "every", "few", "for", "from", "further", "had", "has", "have", "having", "he",
+            "her", "here", "hers", "herself", "him", "himself", "his", "how", "however", "i", "if",
+            "in", "into", "is", "it", "its", "itself", "just", "like", "many", "may", "might",
+            "more", "most", "much", "must", "my", "myself", "no", "nor", "not", "now", "of", "off",
+            "on", "once", "only", "or", "other", "ought", "our", "ours", "ourselves", "out", "over",
+            "own", "same", "shall", "she", "should", "since", "so", "some", "such", "than", "that",
+            "the", "their", "theirs", "them", "themselves", "then", "there", "these", "they",
+            "this", "those", "through", "to", "too", "under", "until", "up", "upon", "very", "was",
+            "we", "were", "what", "when", "where", "whether", "which", "while", "who", "whom",
+            "whose", "why", "will", "with", "within", "without", "would", "yet", "you", "your",
+            "yours", "yourself", "yourselves",
+        ];
+        
+        let mut stopwords: std::collections::HashSet<String> = DEFAULT_STOPWORDS.iter()
+            .map(|w| w.to_string())
+            .collect();
+        
+        if let Ok(extra) = std::env::var("KEYWORD_STOPWORDS") {
+            stopwords.extend(
+                extra.split(',')
+                    .map(|w| w.trim().to_lowercase())
+                    .filter(|w| !w.is_empty())
+            );
+        }
+        
+        stopwords
+    }
+
     fn compare_values(args: &Value) -> Result<String> {
         let value1 = args.get("value1")
             .and_then(|v| v.as_f64())
@@ -707,6 +1319,174 @@ impl Tools {
         }
     }
 
+    fn percentage(args: &Value) -> Result<String> {
+        let operation = args.get("operation")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Missing 'operation' parameter"))?;
+        let a = args.get("a")
+            .and_then(|v| v.as_f64())
+            .ok_or_else(|| anyhow::anyhow!("Missing 'a' parameter"))?;
+        let b = args.get("b")
+            .and_then(|v| v.as_f64())
+            .ok_or_else(|| anyhow::anyhow!("Missing 'b' parameter"))?;
+        
+        match operation {
+            "percent_of" => {
+                let result = a / 100.0 * b;
+                Ok(format!("{}\n{}% of {} = {} / 100 × {} = {}", result, a, b, a, b, result))
+            }
+            "what_percent" => {
+                if b == 0.0 {
+                    return Err(anyhow::anyhow!("Cannot compute a percentage of zero"));
+                }
+                let result = a / b * 100.0;
+                Ok(format!("{:.2}%\n{} is {:.2}% of {} ({} / {} × 100)", result, a, result, b, a, b))
+            }
+            "percent_change" => {
+                if a == 0.0 {
+                    return Err(anyhow::anyhow!("Percent change from zero is undefined"));
+                }
+                let result = (b - a) / a.abs() * 100.0;
+                let direction = if result > 0.0 { "increase" } else if result < 0.0 { "decrease" } else { "no change" };
+                Ok(format!("{:+.2}%\nFrom {} to {} is a {:.2}% {} (({} - {}) / |{}| × 100)",
+                    result, a, b, result.abs(), direction, b, a, a))
+            }
+            _ => Err(anyhow::anyhow!("Unsupported operation: {}", operation)),
+        }
+    }
+
+    fn http_info(args: &Value) -> Result<String> {
+        const STATUS_CODES: &[(u16, &str, &str)] = &[
+            (100, "Continue", "The server received the request headers; the client should send the body."),
+            (101, "Switching Protocols", "The server is switching to the protocol named in the Upgrade header (e.g., WebSocket)."),
+            (103, "Early Hints", "Preliminary headers (usually Link preloads) sent before the final response."),
+            (200, "OK", "The request succeeded."),
+            (201, "Created", "The request succeeded and a new resource was created, typically at the Location header."),
+            (202, "Accepted", "The request was accepted for processing, which has not completed yet."),
+            (204, "No Content", "The request succeeded and there is no body to return."),
+            (206, "Partial Content", "Only the byte range requested with a Range header is returned."),
+            (301, "Moved Permanently", "The resource has a new permanent URL, given in the Location header."),
+            (302, "Found", "The resource is temporarily at another URL; clients usually follow with GET."),
+            (303, "See Other", "The response is at another URL and must be fetched with GET."),
+            (304, "Not Modified", "The cached copy is still valid (conditional request with If-None-Match/If-Modified-Since)."),
+            (307, "Temporary Redirect", "Temporarily at another URL; the method and body must not change."),
+            (308, "Permanent Redirect", "Permanently at another URL; the method and body must not change."),
+            (400, "Bad Request", "The server cannot process the request due to malformed syntax or invalid input."),
+            (401, "Unauthorized", "Authentication is required or the supplied credentials are invalid."),
+            (402, "Payment Required", "Reserved; some APIs use it for exhausted quotas or billing problems."),
+            (403, "Forbidden", "The server understood the request but refuses to authorize it."),
+            (404, "Not Found", "The server has no resource at this URL."),
+            (405, "Method Not Allowed", "The HTTP method is not supported for this resource; see the Allow header."),
+            (406, "Not Acceptable", "No representation matches the request's Accept headers."),
+            (408, "Request Timeout", "The server timed out waiting for the request."),
+            (409, "Conflict", "The request conflicts with the current state of the resource."),
+            (410, "Gone", "The resource was removed permanently and will not come back."),
+            (411, "Length Required", "The request needs a Content-Length header."),
+            (412, "Precondition Failed", "A conditional header such as If-Match did not hold."),
+            (413, "Content Too Large", "The request body exceeds what the server will accept."),
+            (414, "URI Too Long", "The request URL is longer than the server will process."),
+            (415, "Unsupported Media Type", "The request body's Content-Type is not supported."),
+            (416, "Range Not Satisfiable", "The requested Range lies outside the resource."),
+            (418, "I'm a teapot", "An April Fools' joke from RFC 2324 (Hyper Text Coffee Pot Control Protocol): the server refuses to brew coffee because it is a teapot."),
+            (422, "Unprocessable Content", "The body is well-formed but semantically invalid (common for validation errors)."),
+            (425, "Too Early", "The server won't process a request that might be replayed (TLS early data)."),
+            (426, "Upgrade Required", "The client must switch to the protocol in the Upgrade header."),
+            (428, "Precondition Required", "The server requires the request to be conditional."),
+            (429, "Too Many Requests", "Rate limit exceeded; Retry-After may say when to try again."),
+            (431, "Request Header Fields Too Large", "The request headers are too large."),
+            (451, "Unavailable For Legal Reasons", "The resource is blocked for legal reasons, such as censorship or a court order."),
+            (500, "Internal Server Error", "The server hit an unexpected condition."),
+            (501, "Not Implemented", "The server does not support the functionality needed for the request."),
+            (502, "Bad Gateway", "A gateway or proxy got an invalid response from the upstream server."),
+            (503, "Service Unavailable", "The server is overloaded or down for maintenance; Retry-After may be set."),
+            (504, "Gateway Timeout", "A gateway or proxy did not get a timely response from the upstream server."),
+            (505, "HTTP Version Not Supported", "The HTTP version used in the request is not supported."),
+            (511, "Network Authentication Required", "The client must authenticate to gain network access (captive portals)."),
+        ];
+        const HEADERS: &[(&str, &str)] = &[
+            ("accept", "Request: media types the client can handle, with optional q-weights."),
+            ("accept-encoding", "Request: compression algorithms the client supports (gzip, br, ...)."),
+            ("accept-language", "Request: preferred natural languages for the response."),
+            ("access-control-allow-origin", "Response (CORS): which origins may read the response."),
+            ("access-control-allow-methods", "Response (CORS preflight): methods allowed for cross-origin requests."),
+            ("access-control-allow-headers", "Response (CORS preflight): request headers allowed for cross-origin requests."),
+            ("age", "Response: seconds the object has been in a proxy cache."),
+            ("allow", "Response: methods supported by the resource (sent with 405)."),
+            ("authorization", "Request: credentials for authenticating with the server (e.g., 'Bearer <token>')."),
+            ("cache-control", "Both: caching directives such as max-age, no-cache, no-store, private, public."),
+            ("connection", "Both: whether the connection stays open after the current transaction."),
+            ("content-disposition", "Response: whether content is shown inline or downloaded as an attachment, with a filename."),
+            ("content-encoding", "Both: compression applied to the body (gzip, br, deflate)."),
+            ("content-length", "Both: size of the body in bytes."),
+            ("content-security-policy", "Response: restricts which sources scripts, styles and other resources may load from."),
+            ("content-type", "Both: media type of the body (e.g., 'application/json; charset=utf-8')."),
+            ("cookie", "Request: cookies previously set by the server."),
+            ("etag", "Response: identifier for a specific version of a resource, used for conditional requests."),
+            ("expires", "Response: date after which the response is considered stale."),
+            ("host", "Request: domain name (and port) of the server being requested."),
+            ("if-modified-since", "Request: return the resource only if changed since this date, else 304."),
+            ("if-none-match", "Request: return the resource only if its ETag differs, else 304."),
+            ("last-modified", "Response: date the resource was last changed."),
+            ("location", "Response: URL to redirect to (3xx) or of a newly created resource (201)."),
+            ("origin", "Request: origin that initiated the request, used by CORS."),
+            ("range", "Request: byte range to return (answered with 206)."),
+            ("referer", "Request: address of the page that linked to the requested resource."),
+            ("retry-after", "Response: how long to wait before retrying (with 429 or 503)."),
+            ("server", "Response: software used by the origin server."),
+            ("set-cookie", "Response: sends a cookie to the client, with attributes like Secure, HttpOnly and SameSite."),
+            ("strict-transport-security", "Response (HSTS): forces browsers to use HTTPS for the domain for max-age seconds."),
+            ("transfer-encoding", "Both: encoding used to transfer the body, e.g. 'chunked'."),
+            ("upgrade", "Both: asks to switch protocols, e.g. to WebSocket."),
+            ("user-agent", "Request: identifies the client software."),
+            ("vary", "Response: request headers that affect the response, so caches key on them."),
+            ("www-authenticate", "Response: authentication scheme the client should use (sent with 401)."),
+            ("x-content-type-options", "Response: 'nosniff' stops browsers guessing the content type."),
+            ("x-forwarded-for", "Request: original client IP when passing through proxies."),
+            ("x-frame-options", "Response: whether the page may be embedded in frames (DENY, SAMEORIGIN)."),
+            ("x-ratelimit-limit", "Response (convention): request quota for the current window."),
+            ("x-ratelimit-remaining", "Response (convention): requests left in the current window."),
+        ];
+        
+        let status_code = args.get("status_code")
+            .and_then(|v| v.as_u64().or_else(|| v.as_str().and_then(|s| s.trim().parse().ok())));
+        let header = args.get("header")
+            .and_then(|v| v.as_str())
+            .map(|h| h.trim().trim_end_matches(':').to_lowercase())
+            .filter(|h| !h.is_empty());
+        
+        if status_code.is_none() && header.is_none() {
+            return Err(anyhow::anyhow!("Provide 'status_code' and/or 'header'"));
+        }
+        
+        let mut parts = Vec::new();
+        
+        if let Some(code) = status_code {
+            let category = match code {
+                100..=199 => "1xx Informational",
+                200..=299 => "2xx Success",
+                300..=399 => "3xx Redirection",
+                400..=499 => "4xx Client Error",
+                500..=599 => "5xx Server Error",
+                _ => return Err(anyhow::anyhow!("{} is not a valid HTTP status code (expected 100-599)", code)),
+            };
+            match STATUS_CODES.iter().find(|(c, _, _)| u64::from(*c) == code) {
+                Some((_, name, meaning)) => parts.push(format!("{} {} ({})
+{}", code, name, category, meaning)),
+                None => parts.push(format!("{} is not a standard status code; its category is {}", code, category)),
+            }
+        }
+        
+        if let Some(name) = header {
+            match HEADERS.iter().find(|(h, _)| *h == name) {
+                Some((_, description)) => parts.push(format!("{}: {}", name, description)),
+                None if name.starts_with("x-") => parts.push(format!("{}: not a standard header; 'X-' headers are custom or vendor-specific", name)),
+                None => parts.push(format!("{}: unknown header, no description available", name)),
+            }
+        }
+        
+        Ok(parts.join("\n\n"))
+    }
+
     fn format_number(args: &Value) -> Result<String> {
         let number = args.get("number")
             .and_then(|v| v.as_f64())
@@ -728,7 +1508,7 @@ impl Tools {
             "ordinal" => {
                 let n = number as i64;
                 let suffix = match n % 100 {
-                    11 | 12 | 13 => "th",
+                    11..=13 => "th",
                     _ => match n % 10 {
                         1 => "st",
                         2 => "nd",
@@ -762,6 +1542,54 @@ impl Tools {
         }
     }
 
+    async fn ip_info(args: &Value, http: &HttpClients) -> Result<String> {
+        let ip_str = args.get("ip")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow::anyhow!("Missing 'ip' parameter"))?
+            .trim();
+        
+        let ip = match IpAddr::from_str(ip_str) {
+            Ok(ip) => ip,
+            Err(_) => return Ok(format!("Invalid IP address: {}", ip_str)),
+        };
+        if !crate::http::is_public_ip(&ip) {
+            return Ok(format!("{} is a private, loopback or reserved address and has no public geolocation", ip));
+        }
+        
+        // Results are cached briefly to spare the free API's rate limit
+        const CACHE_TTL: Duration = Duration::from_secs(600);
+        static CACHE: OnceLock<Mutex<HashMap<IpAddr, (Instant, String)>>> = OnceLock::new();
+        let cache = CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+        if let Some((fetched, result)) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(&ip) {
+            if fetched.elapsed() < CACHE_TTL {
+                return Ok(result.clone());
+            }
+        }
+        
+        let resp: Value = http.search
+            .get(format!("http://ip-api.com/json/{}?fields=status,message,country,regionName,city,timezone,isp,query", ip))
+            .send()
+            .await?
+            .json()
+            .await?;
+        
+        if resp["status"].as_str() != Some("success") {
+            return Ok(format!("Lookup failed for {}: {}", ip, resp["message"].as_str().unwrap_or("unknown error")));
+        }
+        
+        let field = |name: &str| resp[name].as_str().filter(|v| !v.is_empty()).unwrap_or("N/A").to_string();
+        let result = format!(
+            "IP: {}\nCountry: {}\nRegion: {}\nCity: {}\nTimezone: {}\nISP: {}",
+            ip, field("country"), field("regionName"), field("city"), field("timezone"), field("isp")
+        );
+        
+        let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|_, (fetched, _)| fetched.elapsed() < CACHE_TTL);
+        cache.insert(ip, (Instant::now(), result.clone()));
+        
+        Ok(result)
+    }
+    
     fn days_between_dates(args: &Value) -> Result<String> {
         let date1_str = args.get("date1")
             .and_then(|v| v.as_str())
@@ -809,30 +1637,61 @@ impl Tools {
     }
 
     fn extract_entities(args: &Value) -> Result<String> {
+        static DATE: OnceLock<regex::Regex> = OnceLock::new();
+        static URL: OnceLock<regex::Regex> = OnceLock::new();
+        static CAPITALIZED: OnceLock<regex::Regex> = OnceLock::new();
+        // Sentence starters and function words that are capitalized without being names
+        const COMMON_WORDS: &[&str] = &[
+            "The", "This", "That", "These", "Those", "There", "Then", "They", "Their", "When",
+            "Where", "What", "Which", "While", "Who", "Why", "How", "And", "But", "For", "With",
+            "From", "However", "Also", "After", "Before", "Our", "You", "Your", "Its", "Some",
+            "Many", "Most", "Each", "Every", "All", "Any", "One", "Not", "Yes", "Here", "Now",
+        ];
+        
         let text = args.get("text")
             .and_then(|v| v.as_str())
             .ok_or_else(|| anyhow::anyhow!("Missing 'text' parameter"))?;
         
+        // Bound the work on huge inputs; entities past the cap are simply not reported
+        let max_chars = std::env::var("EXTRACT_ENTITIES_MAX_CHARS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(15000);
+        let text = match text.char_indices().nth(max_chars) {
+            Some((end, _)) => &text[..end],
+            None => text,
+        };
+        
         // Simple entity extraction (for production, use proper NLP library)
         let mut entities = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        // Byte ranges already reported, so capitalized words inside URLs aren't repeated
+        let mut covered = Vec::new();
         
         // Extract potential dates (YYYY-MM-DD, MM/DD/YYYY, etc.)
-        let date_pattern = regex::Regex::new(r"\d{4}-\d{2}-\d{2}|\d{1,2}/\d{1,2}/\d{4}").unwrap();
+        let date_pattern = DATE.get_or_init(|| regex::Regex::new(r"\d{4}-\d{2}-\d{2}|\d{1,2}/\d{1,2}/\d{4}").unwrap());
         for mat in date_pattern.find_iter(text) {
-            entities.push(format!("Date: {}", mat.as_str()));
+            if seen.insert(mat.as_str()) {
+                entities.push(format!("Date: {}", mat.as_str()));
+            }
         }
         
         // Extract URLs
-        let url_pattern = regex::Regex::new(r"https?://[^\s]+").unwrap();
+        let url_pattern = URL.get_or_init(|| regex::Regex::new(r"https?://[^\s]+").unwrap());
         for mat in url_pattern.find_iter(text) {
-            entities.push(format!("URL: {}", mat.as_str()));
+            covered.push(mat.range());
+            if seen.insert(mat.as_str()) {
+                entities.push(format!("URL: {}", mat.as_str()));
+            }
         }
         
         // Extract capitalized words (potential names/organizations)
-        let cap_pattern = regex::Regex::new(r"\b[A-Z][a-z]+(?:\s+[A-Z][a-z]+)*\b").unwrap();
+        let cap_pattern = CAPITALIZED.get_or_init(|| regex::Regex::new(r"\b[A-Z][a-z]+(?:\s+[A-Z][a-z]+)*\b").unwrap());
         for mat in cap_pattern.find_iter(text) {
             let word = mat.as_str();
-            if word.len() > 2 && !entities.iter().any(|e| e.contains(word)) {
+            let is_common = !word.contains(char::is_whitespace) && COMMON_WORDS.contains(&word);
+            let in_url = covered.iter().any(|r| r.start <= mat.start() && mat.end() <= r.end);
+            if word.len() > 2 && !is_common && !in_url && seen.insert(word) {
                 entities.push(format!("Potential entity: {}", word));
             }
         }
@@ -844,3 +1703,83 @@ impl Tools {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn extract_keywords_drops_stopwords_and_punctuation() {
+        let text = "The, cats and THE dogs. Cats chase dogs; the cats win!";
+        let result = Tools::extract_keywords(&json!({ "text": text })).unwrap();
+        assert_eq!(result, "cats (3x), dogs (2x), chase (1x)");
+    }
+
+    #[test]
+    fn keyword_stopwords_env_extends_defaults() {
+        std::env::set_var("KEYWORD_STOPWORDS", " Widget , ,gadget");
+        let stopwords = Tools::stopwords();
+        std::env::remove_var("KEYWORD_STOPWORDS");
+
+        assert!(stopwords.contains("widget"));
+        assert!(stopwords.contains("gadget"));
+        assert!(stopwords.contains("the"));
+        assert!(!stopwords.contains(""));
+    }
+
+    fn percentage(operation: &str, a: f64, b: f64) -> Result<String> {
+        Tools::percentage(&json!({ "operation": operation, "a": a, "b": b }))
+    }
+
+    #[test]
+    fn percentage_operations() {
+        assert!(percentage("percent_of", 15.0, 200.0).unwrap().starts_with("30\n"));
+        assert!(percentage("what_percent", 30.0, 200.0).unwrap().starts_with("15.00%\n"));
+
+        let increase = percentage("percent_change", 80.0, 100.0).unwrap();
+        assert!(increase.starts_with("+25.00%\n"), "{}", increase);
+        assert!(increase.contains("25.00% increase"));
+
+        let decrease = percentage("percent_change", 100.0, 80.0).unwrap();
+        assert!(decrease.starts_with("-20.00%\n"), "{}", decrease);
+        assert!(decrease.contains("20.00% decrease"));
+
+        // A negative base still reports the change relative to its magnitude
+        let from_negative = percentage("percent_change", -50.0, -25.0).unwrap();
+        assert!(from_negative.starts_with("+50.00%\n"), "{}", from_negative);
+
+        assert!(percentage("percent_change", 42.0, 42.0).unwrap().contains("no change"));
+    }
+
+    #[test]
+    fn percentage_rejects_zero_divisors() {
+        assert!(percentage("what_percent", 5.0, 0.0).is_err());
+        assert!(percentage("percent_change", 0.0, 10.0).is_err());
+        assert!(percentage("percent_of", 0.0, 0.0).is_ok());
+        assert!(percentage("ratio", 1.0, 2.0).is_err());
+    }
+
+    #[test]
+    fn extract_entities_caps_dedupes_and_skips_common_words() {
+        let sentence = "The meeting with Alice Smith on 2024-01-05 is at https://example.com/Agenda. However, Acme agreed. ";
+        let mut text = sentence.repeat(15000 / sentence.len() + 1);
+        assert!(text.chars().count() > 15000);
+        // Past the default 15k-char cap, so never reported
+        text.push_str("Zebediah Quux");
+
+        // Generous ceiling for debug builds; the scan itself takes milliseconds
+        let started = Instant::now();
+        let result = Tools::extract_entities(&json!({ "text": text })).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2), "extract_entities took {:?}", started.elapsed());
+        let lines: Vec<&str> = result.lines().collect();
+
+        assert_eq!(lines, vec![
+            "Date: 2024-01-05",
+            "URL: https://example.com/Agenda.",
+            "Potential entity: Alice Smith",
+            "Potential entity: Acme",
+        ]);
+        assert!(!result.contains("Zebediah"));
+    }
+}